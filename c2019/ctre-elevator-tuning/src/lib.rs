@@ -4,15 +4,26 @@ pub mod config {
     pub const SLAVE_TALON1: i32 = 4;
     pub const SLAVE_TALON2: i32 = 6;
     pub const LIMIT_SWITCH: i32 = 9;
+
+    pub const PEAK_CURRENT: i32 = 35;
+    pub const CONTINUOUS_CURRENT: i32 = 20;
+    pub const PEAK_CURRENT_DURATION_MS: i32 = 200;
 }
 use controls::const_unit;
 use controls::units as si;
 use controls::util::clamp;
 use ctre::motor_control::config::*;
 use ctre::motor_control::*;
+use hardware::fake::{Fake, FakeDigitalInput, FakeTalon};
+use hardware::io::{DigitalIn, Hardware, Rio, Talon};
+use hardware::record::{Playback, Recorded, Recording, Tape};
+use serde_derive::{Deserialize, Serialize};
+use tunables::{Registry, Tunable};
 use wpilib::{dio::DigitalInput, HalResult};
 
-#[derive(Copy, Clone, Debug)]
+pub mod sim;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LoopState {
     Unitialized,
     Panicking(u32),
@@ -21,16 +32,58 @@ pub enum LoopState {
 }
 
 #[derive(Debug)]
-pub struct Elevator {
-    mt: TalonSRX,
-    s1: TalonSRX,
-    s2: TalonSRX,
-    limit: DigitalInput,
+pub struct Elevator<H: Hardware = Rio> {
+    mt: H::Talon,
+    s1: H::Talon,
+    s2: H::Talon,
+    limit: H::DigitalInput,
     state: LoopState,
     goal: i32,         // encoder ticks
     zero_goal: i32,    // encoder ticks
     last_sent_sp: i32, // encoder ticks
     stage_track: StageTracker,
+    /// Indexed by slot
+    gains: [SlotGains; 2],
+}
+
+/// Live tunable gains for one of the master talon's slots
+#[derive(Debug, Clone)]
+struct SlotGains {
+    kp: Tunable,
+    kd: Tunable,
+    kf: Tunable,
+}
+
+impl SlotGains {
+    fn fixed(slot: &SlotConfiguration) -> Self {
+        Self {
+            kp: Tunable::fixed(slot.kP),
+            kd: Tunable::fixed(slot.kD),
+            kf: Tunable::fixed(slot.kF),
+        }
+    }
+
+    fn register(registry: &Registry, prefix: &str, slot: &SlotConfiguration) -> Self {
+        Self {
+            kp: registry.register(&format!("{}/kp", prefix), slot.kP),
+            kd: registry.register(&format!("{}/kd", prefix), slot.kD),
+            kf: registry.register(&format!("{}/kf", prefix), slot.kF),
+        }
+    }
+
+    /// Sends any gains that have changed to the talon, without waiting for it to confirm. Gains
+    /// the talon didn't take are sent again next time.
+    fn apply(&mut self, talon: &mut impl Talon, slot_idx: i32) -> ctre::Result<()> {
+        self.kp.apply(|kp| talon.config_kp(slot_idx, kp, 0))?;
+        self.kd.apply(|kd| talon.config_kd(slot_idx, kd, 0))?;
+        self.kf.apply(|kf| talon.config_kf(slot_idx, kf, 0))
+    }
+
+    fn assume_applied(&mut self) {
+        self.kp.assume_applied();
+        self.kd.assume_applied();
+        self.kf.assume_applied();
+    }
 }
 
 const RECT_PROF_PID_IDX: i32 = 0;
@@ -38,8 +91,8 @@ const RECT_PROF_PID_IDX: i32 = 0;
 // Exact value doesn't matter, should trail zero goal at some steady state distance provided is low enough
 // 20% power at 15cm
 const ZEROING_KP: f64 = 0.5 / (0.15 / METERS_PER_TICK.value_unsafe);
-// speed: -0.05m / s
-const ZEROING_SPEED_TICKS_PER_ITER: i32 = (-0.10 / METERS_PER_TICK.value_unsafe / 200.0) as i32;
+// speed: -0.10m / s
+const ZEROING_SPEED_TICKS_PER_SEC: f64 = -0.10 / METERS_PER_TICK.value_unsafe;
 const ZERO_CMD_MAX: f64 = 0.35;
 // TODO tune these
 const COMPLETION_THRESHOLD: si::Meter<f64> = const_unit!(0.03);
@@ -52,11 +105,34 @@ pub const METERS_PER_TICK: si::Meter<f64> = const_unit!(1.982 /*in*/ * 0.0254 *
 const STAGE_ONE_SLOT_IDX: i32 = 0;
 const STAGE_TWO_SLOT_IDX: i32 = 1;
 
+const STAGE_ONE_SLOT: SlotConfiguration = SlotConfiguration {
+    kP: 0.18,
+    kI: 0.0,
+    kD: 4.0,
+    kF: 0.04,
+    integralZone: 0,
+    allowableClosedloopError: 0,
+    maxIntegralAccumulator: 0.0,
+    closedLoopPeakOutput: 1.0,
+    closedLoopPeriod: 1,
+};
+const STAGE_TWO_SLOT: SlotConfiguration = SlotConfiguration {
+    kP: 0.23,
+    kI: 0.0,
+    kD: 2.0,
+    kF: 0.04,
+    integralZone: 0,
+    allowableClosedloopError: 0,
+    maxIntegralAccumulator: 0.0,
+    closedLoopPeakOutput: 1.0,
+    closedLoopPeriod: 1,
+};
+
 const GRAVITY_KF: f64 = 0.06;
 const STAGE_ONE_FRICTION_FF: f64 = 0.05;
 const STAGE_TWO_FRICTION_FF: f64 = 0.07;
 
-impl Elevator {
+impl<H: Hardware> Elevator<H> {
     pub const ZEROING_SPEED: si::MeterPerSecond<f64> = const_unit!(0.04);
     pub const MAX_HEIGHT: si::Meter<f64> = const_unit!(2.0);
     pub const MIN_HEIGHT: si::Meter<f64> = const_unit!(-0.02);
@@ -64,69 +140,149 @@ impl Elevator {
     pub const MIN_HEIGHT_TICKS: i32 = -1;
     // pub const TARGET_KF: f64 = 0.00004;
     pub const TARGET_KF: f64 = 0.0;
+    /// How often `iterate` is meant to run. Panicking counts loops, so lasts longer at a
+    /// slower rate.
     pub const DT: si::Second<f64> = const_unit!(1. / 200.);
     pub const KP: si::VoltPerMeter<f64> = const_unit!(300.0);
     pub const KD: si::VoltSecondPerMeter<f64> = const_unit!(30.);
     pub const KF: si::Volt<f64> = const_unit!(1.5);
 
+    /// Wraps already configured hardware. `s1` and `s2` should be following `mt`.
+    pub fn from_hardware(mt: H::Talon, s1: H::Talon, s2: H::Talon, limit: H::DigitalInput) -> Self {
+        Self {
+            mt,
+            s1,
+            s2,
+            limit,
+            state: LoopState::Unitialized,
+            goal: std::i32::MIN, // ticks
+            zero_goal: std::i32::MIN,
+            last_sent_sp: std::i32::MIN,
+            stage_track: StageTracker::zeroed(),
+            gains: [
+                SlotGains::fixed(&STAGE_ONE_SLOT),
+                SlotGains::fixed(&STAGE_TWO_SLOT),
+            ],
+        }
+    }
+
+    /// Makes the slot gains live tunable as `elevator/stage_one/*` and `elevator/stage_two/*`.
+    pub fn with_tuning(self, registry: &Registry) -> Self {
+        Self {
+            gains: [
+                SlotGains::register(registry, "elevator/stage_one", &STAGE_ONE_SLOT),
+                SlotGains::register(registry, "elevator/stage_two", &STAGE_TWO_SLOT),
+            ],
+            ..self
+        }
+    }
+
+    /// Records every device to `tape` as `elevator/*`, see `hardware::record`.
+    pub fn recorded(self, tape: &Tape) -> Elevator<Recording<H>> {
+        Elevator {
+            mt: Recorded::new(self.mt, "elevator/master", tape),
+            s1: Recorded::new(self.s1, "elevator/slave1", tape),
+            s2: Recorded::new(self.s2, "elevator/slave2", tape),
+            limit: Recorded::new(self.limit, "elevator/limit_switch", tape),
+            state: self.state,
+            goal: self.goal,
+            zero_goal: self.zero_goal,
+            last_sent_sp: self.last_sent_sp,
+            stage_track: self.stage_track,
+            gains: self.gains,
+        }
+    }
+}
+
+impl Elevator<Recording<Fake>> {
+    /// Fakes named like `recorded`'s devices, for replaying a recording.
+    pub fn replaying(playback: &mut Playback, tape: &Tape) -> Self {
+        Self::from_hardware(
+            playback.device(FakeTalon::new(), "elevator/master", tape),
+            playback.device(FakeTalon::new(), "elevator/slave1", tape),
+            playback.device(FakeTalon::new(), "elevator/slave2", tape),
+            playback.device(FakeDigitalInput::new(false), "elevator/limit_switch", tape),
+        )
+    }
+}
+
+/// The master talon's current limits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CurrentLimits {
+    /// Amps allowed before the talon starts limiting
+    pub peak: i32,
+    /// Amps allowed once the peak has lasted `peak_duration`
+    pub continuous: i32,
+    /// Milliseconds
+    pub peak_duration: i32,
+}
+
+impl Default for CurrentLimits {
+    fn default() -> Self {
+        Self {
+            peak: config::PEAK_CURRENT,
+            continuous: config::CONTINUOUS_CURRENT,
+            peak_duration: config::PEAK_CURRENT_DURATION_MS,
+        }
+    }
+}
+
+/// Configuration of the master talon. The followers only need to follow it.
+pub fn talon_config(limits: &CurrentLimits) -> TalonSRXConfig {
+    TalonSRXConfig {
+        primaryPID: TalonSRXPIDSetConfiguration {
+            selectedFeedbackSensor: FeedbackDevice::CTRE_MagEncoder_Relative,
+            ..Default::default()
+        },
+        base: BaseMotorConfig {
+            forwardSoftLimitThreshold: <Elevator>::MAX_HEIGHT_TICKS,
+            forwardSoftLimitEnable: true,
+            reverseSoftLimitThreshold: <Elevator>::MIN_HEIGHT_TICKS,
+            reverseSoftLimitEnable: true,
+            voltageCompSaturation: 12.0,
+            // Stage one slot
+            slot_0: STAGE_ONE_SLOT,
+            // stage two slot (more rigid)
+            slot_1: STAGE_TWO_SLOT,
+            motionCruiseVelocity: 12500,
+            motionAcceleration: 10000,
+            motionProfileTrajectoryPeriod: 0,
+            closedloopRamp: 0.1,
+            openloopRamp: 0.1,
+            ..Default::default()
+        },
+        continuousCurrentLimit: limits.continuous,
+        peakCurrentLimit: limits.peak,
+        peakCurrentDuration: limits.peak_duration,
+        ..Default::default()
+    }
+}
+
+impl Elevator {
     pub fn new() -> HalResult<Self> {
-        // TODO config the talons
-        let mut mt = TalonSRX::new(config::MASTER_TALON);
-        mt.config_all(
-            &TalonSRXConfig {
-                primaryPID: TalonSRXPIDSetConfiguration {
-                    selectedFeedbackSensor: FeedbackDevice::CTRE_MagEncoder_Relative,
-                    ..Default::default()
-                },
-                base: BaseMotorConfig {
-                    forwardSoftLimitThreshold: Self::MAX_HEIGHT_TICKS,
-                    forwardSoftLimitEnable: true,
-                    reverseSoftLimitThreshold: Self::MIN_HEIGHT_TICKS,
-                    reverseSoftLimitEnable: true,
-                    voltageCompSaturation: 12.0,
-                    // Stage one slot
-                    slot_0: SlotConfiguration {
-                        kP: 0.18,
-                        kI: 0.0,
-                        kD: 4.0,
-                        kF: 0.04,
-                        integralZone: 0,
-                        allowableClosedloopError: 0,
-                        maxIntegralAccumulator: 0.0,
-                        closedLoopPeakOutput: 1.0,
-                        closedLoopPeriod: 1,
-                    },
-                    // stage two slot (more rigid)
-                    slot_1: SlotConfiguration {
-                        kP: 0.23,
-                        kI: 0.0,
-                        kD: 2.0,
-                        kF: 0.04,
-                        integralZone: 0,
-                        allowableClosedloopError: 0,
-                        maxIntegralAccumulator: 0.0,
-                        closedLoopPeakOutput: 1.0,
-                        closedLoopPeriod: 1,
-                    },
-                    motionCruiseVelocity: 12500,
-                    motionAcceleration: 10000,
-                    motionProfileTrajectoryPeriod: 0,
-                    closedloopRamp: 0.1,
-                    openloopRamp: 0.1,
-                    ..Default::default()
-                },
-                continuousCurrentLimit: 20,
-                peakCurrentLimit: 35,
-                peakCurrentDuration: 200,
-                ..Default::default()
-            },
-            3000,
+        Self::with_ids(
+            config::MASTER_TALON,
+            [config::SLAVE_TALON1, config::SLAVE_TALON2],
+            config::LIMIT_SWITCH,
+            &CurrentLimits::default(),
         )
-        .expect("CONFIG ALL FAILED");
+    }
+
+    /// Like `new`, but for a robot wired or limited differently.
+    pub fn with_ids(
+        master_talon: i32,
+        slave_talons: [i32; 2],
+        limit_switch: i32,
+        limits: &CurrentLimits,
+    ) -> HalResult<Self> {
+        // TODO config the talons
+        let mut mt = TalonSRX::new(master_talon);
+        mt.config_all(&talon_config(limits), 3000)
+            .expect("CONFIG ALL FAILED");
         mt.enable_current_limit(true);
-        mt.select_profile_slot(STAGE_ONE_SLOT_IDX, RECT_PROF_PID_IDX);
+        MotorController::select_profile_slot(&mut mt, STAGE_ONE_SLOT_IDX, RECT_PROF_PID_IDX);
         mt.override_limit_switches_enable(true);
-        mt.override_soft_limits_enable(false); // enabled after zeroing
+        MotorController::override_soft_limits_enable(&mut mt, false); // enabled after zeroing
         mt.enable_voltage_compensation(true);
         mt.set_status_frame_period(StatusFrameEnhanced::Status_2_Feedback0, 10, 20);
         mt.set_status_frame_period(StatusFrameEnhanced::Status_10_MotionMagic, 10, 20);
@@ -135,41 +291,39 @@ impl Elevator {
         mt.set_sensor_phase(true);
         mt.set_neutral_mode(NeutralMode::Brake);
 
-        let mut s1 = TalonSRX::new(config::SLAVE_TALON1);
+        let mut s1 = TalonSRX::new(slave_talons[0]);
         s1.follow(&mt, FollowerType::PercentOutput)
             .expect("COULD NOT FOLLOW");
-        let mut s2 = TalonSRX::new(config::SLAVE_TALON2);
+        let mut s2 = TalonSRX::new(slave_talons[1]);
         s2.follow(&mt, FollowerType::PercentOutput)
             .expect("COULD NOT FOLLOW");
 
-        Ok(Self {
+        Ok(Self::from_hardware(
             mt,
             s1,
             s2,
-            limit: DigitalInput::new(config::LIMIT_SWITCH)?,
-            state: LoopState::Unitialized,
-            goal: std::i32::MIN, // ticks
-            zero_goal: std::i32::MIN,
-            last_sent_sp: std::i32::MIN,
-            stage_track: StageTracker::zeroed(),
-        })
+            DigitalInput::new(limit_switch)?,
+        ))
     }
 }
 
-impl Elevator {
-    pub fn iterate(&mut self) -> ctre::Result<()> {
+impl<H: Hardware> Elevator<H> {
+    /// Runs one loop, `dt` after the last.
+    pub fn iterate(&mut self, dt: si::Second<f64>) -> ctre::Result<()> {
+        let [stage_one, stage_two] = &mut self.gains;
+        stage_one.apply(&mut self.mt, STAGE_ONE_SLOT_IDX)?;
+        stage_two.apply(&mut self.mt, STAGE_TWO_SLOT_IDX)?;
         match self.state {
             LoopState::Unitialized => {
                 // TODO handle
                 self.state = LoopState::Zeroing;
                 self.zero_goal = self.mt.get_selected_sensor_position(RECT_PROF_PID_IDX)?;
                 self.mt.override_soft_limits_enable(false);
-                println!("ZERO TICKS PER: {}", ZEROING_SPEED_TICKS_PER_ITER);
-                return self.iterate();
+                return self.iterate(dt);
             }
             LoopState::Panicking(0) => {
                 self.state = LoopState::Unitialized;
-                return self.iterate();
+                return self.iterate(dt);
             }
             LoopState::Panicking(ticks) => {
                 self.state = LoopState::Panicking(ticks - 1);
@@ -191,11 +345,12 @@ impl Elevator {
                     Ok(true) => {
                         let pos = self.mt.get_selected_sensor_position(RECT_PROF_PID_IDX)?;
                         let cmd = ZEROING_KP * f64::from(self.zero_goal - pos);
+                        let step = (ZEROING_SPEED_TICKS_PER_SEC * *(dt / si::S)) as i32;
                         // stay in stasis around CMD_MAX for cases when robot disabled
                         if cmd.abs() < ZERO_CMD_MAX {
-                            self.zero_goal += ZEROING_SPEED_TICKS_PER_ITER;
+                            self.zero_goal += step;
                         } else {
-                            self.zero_goal -= ZEROING_SPEED_TICKS_PER_ITER;
+                            self.zero_goal -= step;
                         }
                         let cmd = clamp(cmd, -ZERO_CMD_MAX, ZERO_CMD_MAX);
                         // println!("Zero Info: pos: {}, goal: {}, CMD {}", pos, self.zero_goal, cmd);
@@ -220,7 +375,7 @@ impl Elevator {
                             DemandType::Neutral,
                             0.0,
                         )?;
-                        return self.iterate();
+                        return self.iterate(dt);
                     }
                 }
             }
@@ -257,6 +412,10 @@ impl Elevator {
     }
 
     pub fn is_holding(&self) -> ctre::Result<bool> {
+        // nothing sent yet, and the subtraction below would overflow
+        if self.last_sent_sp == std::i32::MIN {
+            return Ok(false);
+        }
         Ok(self.last_sent_sp == self.goal
             && (self.mt.get_selected_sensor_position(RECT_PROF_PID_IDX)? - self.last_sent_sp).abs()
                 < COMPLETION_THRESHOLD_TICKS)
@@ -288,6 +447,40 @@ impl Elevator {
         self.state = LoopState::Unitialized;
         self.state()
     }
+
+    /// Where the control loop is, for a replay to pick up from.
+    pub fn snapshot(&self) -> ElevatorSnapshot {
+        ElevatorSnapshot {
+            state: self.state,
+            goal: self.goal,
+            zero_goal: self.zero_goal,
+            last_sent_sp: self.last_sent_sp,
+            stage_track: self.stage_track.clone(),
+        }
+    }
+
+    /// Picks up the control loop from a snapshot. The gains count as already sent, since they
+    /// always are by then.
+    pub fn restore(&mut self, snapshot: &ElevatorSnapshot) {
+        self.state = snapshot.state;
+        self.goal = snapshot.goal;
+        self.zero_goal = snapshot.zero_goal;
+        self.last_sent_sp = snapshot.last_sent_sp;
+        self.stage_track = snapshot.stage_track.clone();
+        for gains in &mut self.gains {
+            gains.assume_applied();
+        }
+    }
+}
+
+/// An [`Elevator`]'s control loop between loops, see `Elevator::snapshot`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElevatorSnapshot {
+    state: LoopState,
+    goal: i32,
+    zero_goal: i32,
+    last_sent_sp: i32,
+    stage_track: StageTracker,
 }
 const PANIC_TICKS: u32 = 400;
 const PANIC_COMMAND: f64 = 0.3;
@@ -316,7 +509,7 @@ impl Stage {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StageTracker {
     stage_two_pos: i32,
     old_pos_ticks: i32,
@@ -368,3 +561,127 @@ impl StageTracker {
         self.old_pos_ticks = new_pos;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hardware::fake::*;
+
+    const DT: si::Second<f64> = <Elevator<Fake>>::DT;
+
+    fn fake_elevator() -> (Elevator<Fake>, FakeTalon, FakeDigitalInput) {
+        let mt = FakeTalon::new();
+        // limit is normally closed, so true means not pressed
+        let limit = FakeDigitalInput::new(true);
+        let elev = Elevator::<Fake>::from_hardware(
+            mt.clone(),
+            FakeTalon::new(),
+            FakeTalon::new(),
+            limit.clone(),
+        );
+        (elev, mt, limit)
+    }
+
+    #[test]
+    fn zeroes_on_limit() {
+        let (mut elev, mt, limit) = fake_elevator();
+        mt.state().position = 5000;
+
+        elev.iterate(DT).unwrap();
+        assert_eq!(elev.state(), LoopState::Zeroing);
+        assert!(!mt.state().soft_limits_enabled);
+
+        // the zeroing goal walks down, so the carriage should be pulled down but gently
+        for _ in 0..50 {
+            elev.iterate(DT).unwrap();
+        }
+        assert_eq!(mt.state().mode, ControlMode::PercentOutput);
+        assert!(mt.state().demand0 < 0.0);
+        assert!(mt.state().demand0 >= -ZERO_CMD_MAX);
+
+        limit.set(false);
+        elev.iterate(DT).unwrap();
+        assert_eq!(elev.state(), LoopState::Running);
+        assert_eq!(mt.state().position, 0);
+        assert!(mt.state().soft_limits_enabled);
+        assert_eq!(mt.state().mode, ControlMode::MotionMagic);
+        assert_eq!(mt.state().closed_loop_target, 0);
+    }
+
+    #[test]
+    fn zeroes_at_the_same_speed_whatever_the_rate() {
+        let (mut elev, mt, _limit) = fake_elevator();
+        mt.state().position = 5000;
+        elev.iterate(DT).unwrap();
+        let start = elev.zero_goal;
+        for _ in 0..20 {
+            elev.iterate(DT).unwrap();
+        }
+        let fast = start - elev.zero_goal;
+        for _ in 0..10 {
+            elev.iterate(DT * 2.0).unwrap();
+        }
+        let slow = start - elev.zero_goal - fast;
+        assert!(fast > 0);
+        assert!((fast - slow).abs() <= 10, "{} vs {}", fast, slow);
+    }
+
+    #[test]
+    fn panic_rezeroes() {
+        let (mut elev, mt, _limit) = fake_elevator();
+        elev.iterate(DT).unwrap();
+        assert_eq!(elev.try_init_panic(), LoopState::Panicking(PANIC_TICKS));
+
+        for _ in 0..PANIC_TICKS {
+            elev.iterate(DT).unwrap();
+            assert!((mt.state().demand0 - PANIC_COMMAND).abs() < std::f64::EPSILON);
+        }
+        elev.iterate(DT).unwrap();
+        assert_eq!(elev.state(), LoopState::Zeroing);
+    }
+
+    #[test]
+    fn panic_only_while_zeroing() {
+        let (mut elev, _mt, limit) = fake_elevator();
+        limit.set(false);
+        elev.iterate(DT).unwrap();
+        assert_eq!(elev.state(), LoopState::Running);
+        assert_eq!(elev.try_init_panic(), LoopState::Running);
+    }
+
+    #[test]
+    fn tuned_gains_reach_talon() {
+        let (elev, mt, _limit) = fake_elevator();
+        let registry = Registry::new();
+        let mut elev = elev.with_tuning(&registry);
+        elev.iterate(DT).unwrap();
+        let gains = mt.state().gains;
+        assert!((gains[STAGE_TWO_SLOT_IDX as usize].kp - STAGE_TWO_SLOT.kP).abs() < 1e-9);
+
+        registry.set("elevator/stage_one/kd", 2.5).unwrap();
+        elev.iterate(DT).unwrap();
+        let gains = mt.state().gains;
+        assert!((gains[STAGE_ONE_SLOT_IDX as usize].kd - 2.5).abs() < 1e-9);
+        assert!((gains[STAGE_ONE_SLOT_IDX as usize].kp - STAGE_ONE_SLOT.kP).abs() < 1e-9);
+    }
+
+    #[test]
+    fn picks_up_from_a_snapshot() {
+        let (mut elev, mt, _limit) = fake_elevator();
+        mt.state().position = 5000;
+        for _ in 0..20 {
+            elev.iterate(DT).unwrap();
+        }
+        let (mut copy, copy_mt, _copy_limit) = fake_elevator();
+        copy.restore(&elev.snapshot());
+        assert_eq!(copy.snapshot(), elev.snapshot());
+
+        copy_mt.state().position = 5000;
+        elev.iterate(DT).unwrap();
+        copy.iterate(DT).unwrap();
+        assert_eq!(copy.snapshot(), elev.snapshot());
+        assert!((copy_mt.state().demand0 - mt.state().demand0).abs() < 1e-9);
+        // the robot sent the gains long before
+        assert_eq!(copy_mt.state().gains, [hardware::fake::SlotGains::default(); 4]);
+    }
+}
@@ -12,6 +12,7 @@ fn main() {
     let axis = wpilib::ds::JoystickAxis::new(1).unwrap();
     let mut elev = Elevator::new().expect("ELEVATOR");
     RobotBase::start_competition();
+    let mut last = std::time::Instant::now();
     loop {
         match ds.robot_state() {
             wpilib::ds::RobotState::Disabled => {
@@ -21,10 +22,12 @@ fn main() {
         }
         std::thread::sleep(std::time::Duration::from_millis(50));
         let sp = (ds.stick_axis(joy, axis).unwrap_or(0.0) * -1.0 / 2.0 + 0.5) as f64
-            * Elevator::MAX_HEIGHT;
+            * <Elevator>::MAX_HEIGHT;
         elev.set_goal(sp);
         println!("sp {}", sp);
-        elev.iterate().expect("ITER FAILED");
+        let dt = last.elapsed().as_secs_f64() * controls::units::S;
+        last = std::time::Instant::now();
+        elev.iterate(dt).expect("ITER FAILED");
         println!("{:?}", elev.state());
     }
 }
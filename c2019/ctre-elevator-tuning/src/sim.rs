@@ -0,0 +1,440 @@
+//! A physics model of the two-stage elevator for running [`Elevator`] in a
+//! [`controls::SimulationHarness`] off the robot.
+//!
+//! Nothing here is measured. The plant is derived from the feedforwards the real loop runs with
+//! (slot kF, `GRAVITY_KF`, the friction FFs), so a gain that behaves here is at least consistent
+//! with the assumptions already baked into the robot code.
+use crate::{
+    talon_config, CurrentLimits, Elevator, LoopState, Stage, StageTracker, GRAVITY_KF,
+    METERS_PER_TICK, STAGE_ONE_SLOT, STAGE_TWO_SLOT_IDX,
+};
+use controls::const_unit;
+use controls::units as si;
+use controls::util::clamp;
+use controls::{HarnessAble, StateShim};
+use ctre::motor_control::config::TalonSRXConfig;
+use ctre::motor_control::{ControlMode, DemandType};
+use hardware::fake::{Fake, FakeDigitalInput, FakeTalon};
+use serde_derive::Serialize;
+use std::f64::consts::PI;
+
+// Vex 775pro
+const MOTOR_STALL_TORQUE: f64 = 0.71; // N*m
+const MOTOR_STALL_CURRENT: f64 = 134.0; // A
+const MOTOR_FREE_SPEED: f64 = 18730.0 / 60.0 * 2.0 * PI; // rad/s
+const MOTOR_RESISTANCE: f64 = 12.0 / MOTOR_STALL_CURRENT; // Ohm
+const MOTOR_KT: f64 = MOTOR_STALL_TORQUE / MOTOR_STALL_CURRENT; // N*m / A
+const MOTOR_KV: f64 = MOTOR_FREE_SPEED / 12.0; // rad/s / V
+/// The master and both followers
+const MOTOR_COUNT: f64 = 3.0;
+
+const VOLTAGE_COMP: f64 = 12.0;
+const SPOOL_RADIUS: f64 = 1.982 /*in*/ * 0.0254 / 2.0; // m
+/// Carriage speed per volt of back-EMF, chosen so the slot kF is exactly the voltage needed to
+/// hold a velocity.
+const SPEED_PER_VOLT: f64 =
+    1023.0 / (STAGE_ONE_SLOT.kF * VOLTAGE_COMP) * 10.0 * METERS_PER_TICK.value_unsafe; // m/s / V
+const GEAR_RATIO: f64 = MOTOR_KV * SPOOL_RADIUS / SPEED_PER_VOLT;
+/// Force on the carriage per volt applied to a stalled gearbox
+const NEWTONS_PER_VOLT: f64 = MOTOR_COUNT * MOTOR_KT / MOTOR_RESISTANCE * GEAR_RATIO / SPOOL_RADIUS;
+const GRAVITY_FORCE: f64 = GRAVITY_KF * VOLTAGE_COMP * NEWTONS_PER_VOLT; // N
+const MASS: f64 = GRAVITY_FORCE / 9.81; // kg
+
+const BOTTOM_STOP: f64 = StageTracker::LOWEST_POS_TICKS as f64 * METERS_PER_TICK.value_unsafe;
+/// Where the first stage to move runs out of travel
+const STAGE_STOP: f64 = StageTracker::HIGHEST_POS_TICKS as f64 * METERS_PER_TICK.value_unsafe;
+/// A little past the forward soft limit
+const TOP_STOP: f64 = (<Elevator>::MAX_HEIGHT_TICKS + 500) as f64 * METERS_PER_TICK.value_unsafe;
+
+/// The physical state of the elevator.
+#[derive(Debug, Copy, Clone)]
+pub struct ElevatorState {
+    /// Height of the carriage above its lowest point
+    pub pos: si::Meter<f64>,
+    pub vel: si::MeterPerSecond<f64>,
+    /// Travel taken up by the stage that moves first. The other stage only moves once this hits
+    /// one of its stops, mirroring [`StageTracker`].
+    pub stage_one_travel: si::Meter<f64>,
+}
+
+impl ElevatorState {
+    pub fn at_rest(pos: si::Meter<f64>) -> Self {
+        let pos = clamp(*(pos / si::M), BOTTOM_STOP, TOP_STOP);
+        Self {
+            pos: pos * si::M,
+            vel: 0.0 * si::MPS,
+            stage_one_travel: clamp(pos, BOTTOM_STOP, STAGE_STOP) * si::M,
+        }
+    }
+
+    fn moving_stage(&self, direction: f64) -> Stage {
+        let travel = *(self.stage_one_travel / si::M);
+        if (travel >= STAGE_STOP && direction > 0.0) || (travel <= BOTTOM_STOP && direction < 0.0) {
+            Stage::Two
+        } else {
+            Stage::One
+        }
+    }
+
+    fn step(self, volts: f64, dt: f64) -> Self {
+        let pos = *(self.pos / si::M);
+        let mut vel = *(self.vel / si::MPS);
+
+        let motor_speed = vel / SPOOL_RADIUS * GEAR_RATIO;
+        let current = (volts - motor_speed / MOTOR_KV) / MOTOR_RESISTANCE;
+        let drive = MOTOR_COUNT * MOTOR_KT * current * GEAR_RATIO / SPOOL_RADIUS - GRAVITY_FORCE;
+
+        let moving = vel.abs() > std::f64::EPSILON;
+        let direction = if moving { vel } else { drive };
+        let friction = self.moving_stage(direction).ff() * VOLTAGE_COMP * NEWTONS_PER_VOLT;
+        if moving || drive.abs() > friction {
+            let new_vel = vel + (drive - friction * direction.signum()) / MASS * dt;
+            // friction can stop the carriage, but not reverse it
+            vel = if moving && new_vel.signum() != vel.signum() {
+                0.0
+            } else {
+                new_vel
+            };
+        }
+
+        let mut new_pos = pos + vel * dt;
+        if new_pos <= BOTTOM_STOP {
+            new_pos = BOTTOM_STOP;
+            vel = vel.max(0.0);
+        } else if new_pos >= TOP_STOP {
+            new_pos = TOP_STOP;
+            vel = vel.min(0.0);
+        }
+        let travel = *(self.stage_one_travel / si::M) + new_pos - pos;
+
+        Self {
+            pos: new_pos * si::M,
+            vel: vel * si::MPS,
+            stage_one_travel: clamp(travel, BOTTOM_STOP, STAGE_STOP) * si::M,
+        }
+    }
+}
+
+/// The elevator plant. The control response is the voltage across the motors.
+#[derive(Debug)]
+pub struct ElevatorPlant;
+
+impl HarnessAble for ElevatorPlant {
+    type State = ElevatorState;
+    type ControlResponse = si::Volt<f64>;
+    type LogData = ElevatorLog;
+
+    fn sim_time(mut s: ElevatorState, r: si::Volt<f64>, dur: si::Second<f64>) -> ElevatorState {
+        let steps = (*(dur / Self::SIMUL_DT)).round() as u32;
+        let dt = *(Self::SIMUL_DT / si::S);
+        for _ in 0..steps {
+            s = s.step(*(r / si::V), dt);
+        }
+        s
+    }
+
+    const SIMUL_DT: si::Second<f64> = const_unit!(0.0001);
+    /// The talon closed loop period
+    const CONTROL_DT: si::Second<f64> = const_unit!(0.001);
+}
+
+#[derive(Debug, Serialize)]
+pub struct ElevatorLog {
+    time: f64,
+    pos: f64,
+    vel: f64,
+    volts: f64,
+    /// NaN until the elevator has zeroed
+    sp: f64,
+}
+
+/// Runs the real [`Elevator`] logic against an [`ElevatorPlant`].
+///
+/// The elevator talks to a [`FakeTalon`]; this shim stands in for the talon firmware, running the
+/// motion magic profile and PIDF every [`ElevatorPlant::CONTROL_DT`] from the gains in a
+/// `TalonSRXConfig`. [`Elevator::iterate`] runs at its own, slower rate.
+pub struct ElevatorShim {
+    elevator: Elevator<Fake>,
+    talon: FakeTalon,
+    limit: FakeDigitalInput,
+    config: TalonSRXConfig,
+    iterate_every: u32,
+    periods: u32,
+    /// Physical position when the sensor was last written, ticks
+    last_phys: i32,
+    last_sensor: i32,
+    sensor_offset: i32,
+    last_mode: ControlMode,
+    profile_pos: f64, // ticks
+    profile_vel: f64, // ticks per second
+    last_err: f64,    // ticks
+    output: f64,      // percent
+}
+
+impl ElevatorShim {
+    /// Uses the gains the robot runs. The encoder starts off reading `sensor_offset` more than the
+    /// physical position, as it would after a power cycle with the elevator raised.
+    pub fn new(sensor_offset: i32) -> Self {
+        Self::with_config(talon_config(&CurrentLimits::default()), sensor_offset)
+    }
+
+    /// Runs the talon model with `config` instead, for trying out new gains.
+    pub fn with_config(config: TalonSRXConfig, sensor_offset: i32) -> Self {
+        let talon = FakeTalon::new();
+        let limit = FakeDigitalInput::new(true);
+        let elevator = Elevator::<Fake>::from_hardware(
+            talon.clone(),
+            FakeTalon::new(),
+            FakeTalon::new(),
+            limit.clone(),
+        );
+        Self {
+            elevator,
+            talon,
+            limit,
+            config,
+            iterate_every: (*(<Elevator>::DT / ElevatorPlant::CONTROL_DT)).round() as u32,
+            periods: 0,
+            last_phys: 0,
+            last_sensor: sensor_offset,
+            sensor_offset,
+            last_mode: ControlMode::Disabled,
+            profile_pos: 0.0,
+            profile_vel: 0.0,
+            last_err: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn elevator(&self) -> &Elevator<Fake> {
+        &self.elevator
+    }
+
+    pub fn elevator_mut(&mut self) -> &mut Elevator<Fake> {
+        &mut self.elevator
+    }
+
+    pub fn talon(&self) -> &FakeTalon {
+        &self.talon
+    }
+
+    fn update_sensors(&mut self, state: ElevatorState) {
+        let phys = (*(state.pos / METERS_PER_TICK)).round() as i32;
+        let mut talon = self.talon.state();
+        // the elevator has rezeroed the encoder since our last write
+        if talon.position != self.last_sensor {
+            self.sensor_offset = talon.position - self.last_phys;
+        }
+        talon.position = phys + self.sensor_offset;
+        talon.velocity = (*(state.vel / METERS_PER_TICK * si::S) / 10.0).round() as i32;
+        self.last_phys = phys;
+        self.last_sensor = talon.position;
+        // normally closed, pressed by the carriage sitting on the bottom hardstop
+        self.limit.set(*(state.pos / si::M) > BOTTOM_STOP);
+    }
+
+    /// One period of the talon firmware, returning percent output.
+    fn run_talon(&mut self) -> f64 {
+        let dt = *(ElevatorPlant::CONTROL_DT / si::S);
+        let mut talon = self.talon.state();
+        let sensor = f64::from(talon.position);
+        let (target, ramp) = match talon.mode {
+            ControlMode::PercentOutput => (talon.demand0, self.config.base.openloopRamp),
+            ControlMode::MotionMagic => {
+                if self.last_mode != ControlMode::MotionMagic {
+                    self.profile_pos = sensor;
+                    self.profile_vel = f64::from(talon.velocity) * 10.0;
+                    self.last_err = 0.0;
+                }
+                let cruise = f64::from(self.config.base.motionCruiseVelocity) * 10.0;
+                let accel = f64::from(self.config.base.motionAcceleration) * 10.0;
+                let remaining = talon.demand0 - self.profile_pos;
+                let stopping_vel = (2.0 * accel * remaining.abs()).sqrt() * remaining.signum();
+                self.profile_vel += clamp(
+                    clamp(stopping_vel, -cruise, cruise) - self.profile_vel,
+                    -accel * dt,
+                    accel * dt,
+                );
+                self.profile_pos += self.profile_vel * dt;
+                if (talon.demand0 - self.profile_pos).signum() != remaining.signum() {
+                    self.profile_pos = talon.demand0;
+                    self.profile_vel = 0.0;
+                }
+                talon.closed_loop_target = self.profile_pos.round() as i32;
+
+                let slot = if talon.profile_slot == STAGE_TWO_SLOT_IDX {
+                    &self.config.base.slot_1
+                } else {
+                    &self.config.base.slot_0
+                };
+                let err = self.profile_pos - sensor;
+                let pidf = slot.kP * err
+                    + slot.kD * (err - self.last_err)
+                    + slot.kF * self.profile_vel / 10.0;
+                self.last_err = err;
+                let ff = match talon.demand1_type {
+                    DemandType::ArbitraryFeedForward => talon.demand1,
+                    _ => 0.0,
+                };
+                let peak = slot.closedLoopPeakOutput;
+                (
+                    clamp(pidf / 1023.0, -peak, peak) + ff,
+                    self.config.base.closedloopRamp,
+                )
+            }
+            // anything else the elevator doesn't use
+            _ => (0.0, 0.0),
+        };
+        self.last_mode = talon.mode;
+
+        let mut target = clamp(target, -1.0, 1.0);
+        if talon.soft_limits_enabled {
+            let base = &self.config.base;
+            if (base.forwardSoftLimitEnable
+                && target > 0.0
+                && talon.position >= base.forwardSoftLimitThreshold)
+                || (base.reverseSoftLimitEnable
+                    && target < 0.0
+                    && talon.position <= base.reverseSoftLimitThreshold)
+            {
+                target = 0.0;
+            }
+        }
+        if ramp > 0.0 {
+            let step = dt / ramp;
+            self.output += clamp(target - self.output, -step, step);
+        } else {
+            self.output = target;
+        }
+        self.output
+    }
+}
+
+impl StateShim<ElevatorPlant> for ElevatorShim {
+    fn update(&mut self, state: ElevatorState) -> si::Volt<f64> {
+        self.update_sensors(state);
+        if self.periods == 0 {
+            self.elevator
+                .iterate(<Elevator>::DT)
+                .expect("Fake talon failed");
+        }
+        self.periods = (self.periods + 1) % self.iterate_every;
+        self.run_talon() * VOLTAGE_COMP * si::V
+    }
+
+    fn log_dat(
+        &mut self,
+        state: ElevatorState,
+        response: si::Volt<f64>,
+        time: si::Second<f64>,
+    ) -> ElevatorLog {
+        let sp = match self.elevator.state() {
+            LoopState::Running => f64::from(self.elevator.goal) * METERS_PER_TICK.value_unsafe,
+            _ => std::f64::NAN,
+        };
+        ElevatorLog {
+            time: *(time / si::S),
+            pos: *(state.pos / si::M),
+            vel: *(state.vel / si::MPS),
+            volts: *(response / si::V),
+            sp,
+        }
+    }
+
+    fn assert(&mut self, state: ElevatorState) {
+        if self.elevator.state() == LoopState::Running {
+            assert!(
+                *(state.pos / si::M) < TOP_STOP,
+                "Elevator hit the top hardstop while running"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{COMPLETION_THRESHOLD, PANIC_TICKS, STAGE_ONE_FRICTION_FF};
+    use controls::SimulationHarness;
+    use hardware::io::Talon;
+
+    fn harness(
+        start: si::Meter<f64>,
+        sensor_offset: i32,
+    ) -> SimulationHarness<ElevatorPlant, ElevatorShim> {
+        SimulationHarness::new(
+            ElevatorShim::new(sensor_offset),
+            ElevatorState::at_rest(start),
+            10,
+        )
+    }
+
+    fn sensor_error(h: &SimulationHarness<ElevatorPlant, ElevatorShim>, s: ElevatorState) -> i32 {
+        h.shim().talon().get_selected_sensor_position(0).unwrap()
+            - (*(s.pos / METERS_PER_TICK)).round() as i32
+    }
+
+    #[test]
+    fn plant_matches_feedforwards() {
+        let hold = GRAVITY_KF * VOLTAGE_COMP * si::V;
+        let s = ElevatorPlant::sim_time(ElevatorState::at_rest(0.5 * si::M), hold, 1.0 * si::S);
+        assert!((*(s.pos / si::M) - 0.5).abs() < 1e-6);
+
+        let cruise = 1.0 * si::MPS;
+        let volts = (GRAVITY_KF + STAGE_ONE_FRICTION_FF) * VOLTAGE_COMP * si::V
+            + cruise / (SPEED_PER_VOLT * si::MPS / si::V);
+        let s = ElevatorState {
+            vel: cruise,
+            ..ElevatorState::at_rest(0.1 * si::M)
+        };
+        let s = ElevatorPlant::sim_time(s, volts, 0.2 * si::S);
+        assert!((*((s.vel - cruise) / si::MPS)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zeroes_from_unknown_position() {
+        let mut h = harness(0.6 * si::M, 12345);
+        let s = h.run_time(10.0 * si::S);
+        assert_eq!(h.shim().elevator().state(), LoopState::Running);
+        assert!(s.pos < COMPLETION_THRESHOLD);
+        assert!(sensor_error(&h, s).abs() < 50);
+    }
+
+    #[test]
+    fn moves_between_stages() {
+        let mut h = harness(0.0 * si::M, 0);
+        h.run_time(0.1 * si::S);
+        assert_eq!(h.shim().elevator().state(), LoopState::Running);
+
+        h.shim_mut().elevator_mut().set_goal(1.4 * si::M);
+        let s = h.run_time(3.0 * si::S);
+        assert!(h.shim().elevator().is_holding().unwrap());
+        // the second stage has to move to get this high
+        assert!(*((s.pos - s.stage_one_travel) / si::M) > 1.4 - STAGE_STOP - 0.01);
+
+        h.shim_mut().elevator_mut().set_goal(0.3 * si::M);
+        let s = h.run_time(3.0 * si::S);
+        assert!(h.shim().elevator().is_holding().unwrap());
+        // the first stage gives up all its travel before the second moves
+        assert!(*(s.stage_one_travel / si::M) < 0.01);
+    }
+
+    #[test]
+    fn panic_lifts_then_rezeroes() {
+        let mut h = harness(0.3 * si::M, -5000);
+        let before = h.run_time(0.5 * si::S);
+        assert_eq!(h.shim().elevator().state(), LoopState::Zeroing);
+        assert_eq!(
+            h.shim_mut().elevator_mut().try_init_panic(),
+            LoopState::Panicking(PANIC_TICKS)
+        );
+
+        let s = h.run_time(1.0 * si::S);
+        assert!(s.pos > before.pos);
+
+        let s = h.run_time(20.0 * si::S);
+        assert_eq!(h.shim().elevator().state(), LoopState::Running);
+        assert!(s.pos < COMPLETION_THRESHOLD);
+        assert!(sensor_error(&h, s).abs() < 50);
+    }
+}
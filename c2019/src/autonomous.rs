@@ -0,0 +1,515 @@
+//! Autonomous routines, run by the controller while the robot is in autonomous.
+//!
+//! A routine is an [`Action`] built from the ones in [`actions`]: driving, turning, moving the
+//! elevator, intaking, waiting, and groups of those in sequence or in parallel. Every loop the
+//! controller hands the routine a [`Robot`] with the latest pose and what the superstructure said,
+//! and sends whatever commands it gave. Drivers pick the routine from a chooser on the dashboard
+//! before the match, from the [`routines::ROUTINES`].
+use crate::config::autonomous::*;
+use crate::subsystems::drive::{Instruction as DriveCmd, Pose};
+use crate::subsystems::superstructure::{Feedback, Instruction as SsCmd, Status};
+use crate::subsystems::telemetry::Log;
+use crate::subsystems::vision::Target;
+use networktables::{Table, Value};
+use std::fmt::Debug;
+use std::time::Instant;
+
+pub mod actions;
+pub mod ramsete;
+pub mod routines;
+pub mod trajectory;
+
+use routines::{Routine, ROUTINES};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Running,
+    Done,
+}
+
+pub trait Action: Debug {
+    /// Runs one loop, starting with the first loop the action runs, until it's done.
+    fn update(&mut self, robot: &mut Robot) -> Progress;
+
+    /// Called instead of the next update if the action is cut short, because its group finished
+    /// first or autonomous ended. Should stop whatever it was moving.
+    fn cancel(&mut self, _robot: &mut Robot) {}
+}
+
+/// What an action can see of the robot in one loop, and the commands it gives.
+#[derive(Debug)]
+pub struct Robot<'a> {
+    pub now: Instant,
+    /// None until the drive has published a pose
+    pub pose: Option<Pose>,
+    /// The superstructure's latest, if it's said and is still up
+    pub status: Option<&'a Status>,
+    /// What the superstructure said since the last loop
+    pub heard: &'a [Feedback],
+    /// The TX1's latest target, if it's fresh
+    pub target: Option<Target>,
+    /// The heading the routine began on, which routines' headings are from
+    pub start_heading: f64,
+    pub log: &'a Log,
+    drive: Vec<DriveCmd>,
+    superstructure: Vec<SsCmd>,
+}
+
+impl<'a> Robot<'a> {
+    pub fn new(
+        now: Instant,
+        pose: Option<Pose>,
+        status: Option<&'a Status>,
+        heard: &'a [Feedback],
+        log: &'a Log,
+    ) -> Self {
+        Self {
+            now,
+            pose,
+            status,
+            heard,
+            target: None,
+            start_heading: 0.0,
+            log,
+            drive: Vec::new(),
+            superstructure: Vec::new(),
+        }
+    }
+
+    pub fn drive(&mut self, cmd: DriveCmd) {
+        self.drive.push(cmd);
+    }
+
+    pub fn superstructure(&mut self, cmd: SsCmd) {
+        self.superstructure.push(cmd);
+    }
+
+    /// Whether the superstructure said it finished `cmd` since the last loop.
+    pub fn completed(&self, cmd: SsCmd) -> bool {
+        self.heard.contains(&Feedback::Completed(cmd))
+    }
+
+    /// Whether the superstructure refused `cmd` since the last loop.
+    pub fn rejected(&self, cmd: SsCmd) -> bool {
+        self.heard.iter().any(|feedback| match feedback {
+            Feedback::Rejected(rejection) => rejection.command == cmd,
+            _ => false,
+        })
+    }
+
+    /// The commands given this loop, in order.
+    pub fn commands(self) -> (Vec<DriveCmd>, Vec<SsCmd>) {
+        (self.drive, self.superstructure)
+    }
+}
+
+/// Picks a routine when autonomous starts, and runs it until it's done or autonomous ends.
+#[derive(Debug)]
+pub struct Autonomous {
+    routines: &'static [Routine],
+    /// Where the chooser is, if there's a dashboard
+    table: Option<Table>,
+    running: Option<(&'static str, Box<dyn Action>)>,
+    /// The heading the running routine began on, from the first pose it got
+    start_heading: Option<f64>,
+}
+
+impl Autonomous {
+    pub fn new() -> Self {
+        Self {
+            routines: ROUTINES,
+            table: None,
+            running: None,
+            start_heading: None,
+        }
+    }
+
+    pub fn with_routines(self, routines: &'static [Routine]) -> Self {
+        Self { routines, ..self }
+    }
+
+    /// Offers the routines in a chooser on the dashboard, like a `SendableChooser`.
+    pub fn with_dashboard(self, table: Table) -> Self {
+        let names = self.routines.iter().map(|r| r.name.to_string()).collect();
+        table.set(&format!("{}/.type", CHOOSER), "String Chooser");
+        table.set(&format!("{}/options", CHOOSER), Value::StringArray(names));
+        table.set(&format!("{}/default", CHOOSER), DEFAULT_ROUTINE);
+        table.set(&format!("{}/active", CHOOSER), DEFAULT_ROUTINE);
+        Self {
+            table: Some(table),
+            ..self
+        }
+    }
+
+    /// The routine chosen on the dashboard, or the default.
+    pub fn selected(&self) -> &'static str {
+        let chosen = self
+            .table
+            .as_ref()
+            .and_then(|table| table.get(&format!("{}/selected", CHOOSER)));
+        let name = match &chosen {
+            Some(Value::String(name)) => name.as_str(),
+            _ => DEFAULT_ROUTINE,
+        };
+        self.routines
+            .iter()
+            .find(|r| r.name == name)
+            .or_else(|| self.routines.iter().find(|r| r.name == DEFAULT_ROUTINE))
+            .map_or("", |r| r.name)
+    }
+
+    /// Builds the selected routine, to run from the next loop.
+    pub fn begin(&mut self, log: &Log) {
+        let name = self.selected();
+        if let Some(routine) = self.routines.iter().find(|r| r.name == name) {
+            log.event("autonomous", format!("running {}", name));
+            if let Some(table) = &self.table {
+                table.set(&format!("{}/active", CHOOSER), name);
+            }
+            self.running = Some((name, (routine.build)()));
+            self.start_heading = None;
+        }
+    }
+
+    /// Runs a loop of the routine, if there's one still going.
+    pub fn iterate(&mut self, robot: &mut Robot) {
+        if let Some((name, action)) = &mut self.running {
+            if let Some(pose) = robot.pose {
+                robot.start_heading = *self.start_heading.get_or_insert(pose.heading);
+            }
+            if action.update(robot) == Progress::Done {
+                robot.log.event("autonomous", format!("{} done", name));
+                self.running = None;
+            }
+        }
+    }
+
+    /// Cancels the routine if it's still going.
+    pub fn end(&mut self, robot: &mut Robot) {
+        if let Some((name, mut action)) = self.running.take() {
+            robot.log.event("autonomous", format!("{} cut short", name));
+            action.cancel(robot);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+}
+
+impl Default for Autonomous {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::actions::*;
+    use super::ramsete::Ramsete;
+    use super::trajectory::{Constraints, Trajectory};
+    use super::*;
+    use crate::subsystems::controller::recording::{ControlsTape, ReplayedControls};
+    use crate::subsystems::controller::Bindings;
+    use crate::subsystems::drive::sim::{DrivePlant, DriveShim, DriveState, GEOMETRY};
+    use crate::subsystems::drive::Gear;
+    use crate::subsystems::superstructure::{
+        Superstructure, SuperstructureHardware, UserElevatorHeights,
+    };
+    use crate::subsystems::telemetry::{queue, Entry, Payload, Record};
+    use controls::units::*;
+    use controls::SimulationHarness;
+    use crossbeam_channel::{unbounded, Receiver};
+    use ctre_elevator_tuning::Elevator;
+    use hardware::fake::{Fake, FakeAnalogInput, FakeDigitalInput, FakeSolenoid, FakeTalon};
+    use interp_map::pose::{wrap_angle, Pose2d};
+    use std::f64::consts::PI;
+    use std::time::Duration;
+
+    const LOOP: Duration = Duration::from_millis(20);
+
+    /// The controller's bindings, with the drive simulated and the superstructure on fake
+    /// hardware, its elevator reaching every setpoint instantly.
+    struct Sim {
+        bindings: Bindings<ReplayedControls>,
+        drive: SimulationHarness<DrivePlant, DriveShim>,
+        drive_commands: Receiver<DriveCmd>,
+        state: DriveState,
+        superstructure: Superstructure<Fake>,
+        /// The channel's gates, from the intake up
+        gates: [FakeDigitalInput; 3],
+        elevator: FakeTalon,
+        start: Instant,
+        time: Duration,
+        log: Receiver<Entry>,
+    }
+
+    impl Sim {
+        fn new() -> Self {
+            let (drive_send, drive_commands) = unbounded();
+            let (superstructure_send, superstructure_commands) = unbounded();
+            let (feedback_send, feedback) = unbounded();
+            let (log, entries) = queue(4096, 0);
+            let gates = [
+                FakeDigitalInput::new(true),
+                FakeDigitalInput::new(true),
+                FakeDigitalInput::new(true),
+            ];
+            let elevator = FakeTalon::new();
+            let hardware = SuperstructureHardware::<Fake> {
+                intake_talon: FakeTalon::new(),
+                outtake_talon: FakeTalon::new(),
+                intake_solenoid: FakeSolenoid::new(),
+                climb_solenoid: FakeSolenoid::new(),
+                hatch_extend: FakeSolenoid::new(),
+                hatch_outtake: FakeSolenoid::new(),
+                gates: (gates[0].clone(), gates[1].clone(), gates[2].clone()),
+                pressure: FakeAnalogInput::new(2.5),
+                elevator: Elevator::from_hardware(
+                    elevator.clone(),
+                    FakeTalon::new(),
+                    FakeTalon::new(),
+                    FakeDigitalInput::new(false),
+                ),
+            };
+            let superstructure = Superstructure::from_hardware(hardware, superstructure_commands)
+                .unwrap()
+                .with_feedback(feedback_send);
+            let mut drive = DriveShim::new();
+            drive.execute(DriveCmd::GearShift(Gear::Low));
+            Self {
+                bindings: Bindings::new(
+                    ReplayedControls::new(&ControlsTape::new()),
+                    drive_send,
+                    superstructure_send,
+                )
+                .with_log(log)
+                .with_feedback(feedback),
+                drive: SimulationHarness::new(drive, DriveState::at_rest(), 1000),
+                drive_commands,
+                state: DriveState::at_rest(),
+                superstructure,
+                gates,
+                elevator,
+                start: Instant::now(),
+                time: Duration::from_secs(0),
+                log: entries,
+            }
+        }
+
+        /// Runs the controller, then the drive and superstructure, for one controller loop.
+        fn step(&mut self, act: impl FnOnce(&mut Robot)) {
+            let pose = Some(self.drive.shim().pose());
+            self.bindings.act(pose, None, self.start + self.time, act);
+            for cmd in self.drive_commands.try_iter() {
+                self.drive.shim_mut().execute(cmd);
+            }
+            self.state = self.drive.run_time(LOOP.as_secs_f64() * S);
+            self.superstructure.iterate(LOOP);
+            let mut elevator = self.elevator.state();
+            elevator.position = elevator.demand0 as i32;
+            self.time += LOOP;
+        }
+
+        /// Runs `autonomous` until it's done, returning how long it took.
+        fn run(&mut self, autonomous: &mut Autonomous, limit: Duration) -> Option<Duration> {
+            autonomous.begin(&Log::discard());
+            let begin = self.time;
+            while self.time - begin < limit {
+                self.step(|robot| autonomous.iterate(robot));
+                if !autonomous.is_running() {
+                    return Some(self.time - begin);
+                }
+            }
+            None
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.log
+                .try_iter()
+                .filter_map(|entry| match entry.payload {
+                    Payload::Record(Record::EventRecord(event)) => Some(event.message),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    fn routine(name: &'static str, build: fn() -> Box<dyn Action>) -> &'static [Routine] {
+        Box::leak(vec![Routine { name, build }].into_boxed_slice())
+    }
+
+    #[test]
+    fn drives_and_turns() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            Box::new(Sequence::new(vec![
+                Box::new(DriveDistance::new(2.0 * M)),
+                Box::new(TurnTo::new(PI / 2.0)),
+                Box::new(DriveDistance::new(1.0 * M)),
+            ]))
+        }));
+        let took = sim.run(&mut autonomous, Duration::from_secs(10));
+        assert!(took.is_some(), "still at {:?}", sim.state);
+        assert!((*(sim.state.x / M) - 2.0).abs() < 0.15, "{:?}", sim.state);
+        assert!((*(sim.state.y / M) - 1.0).abs() < 0.15, "{:?}", sim.state);
+        assert!(
+            (sim.state.heading - PI / 2.0).abs() < 0.1,
+            "{:?}",
+            sim.state
+        );
+    }
+
+    #[test]
+    fn turns_from_the_heading_it_began_on() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new()
+            .with_routines(routine(DEFAULT_ROUTINE, || Box::new(TurnTo::new(PI / 2.0))));
+        sim.run(&mut autonomous, Duration::from_secs(5)).unwrap();
+        sim.run(&mut autonomous, Duration::from_secs(5)).unwrap();
+        assert!(
+            wrap_angle(sim.state.heading - PI).abs() < 0.1,
+            "{:?}",
+            sim.state
+        );
+    }
+
+    #[test]
+    fn follows_trajectories_from_where_it_is() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            let trajectory = Trajectory::through(
+                &[Pose2d::new(0.0, 0.0, 0.0), Pose2d::new(2.0, 1.0, 0.0)],
+                &Constraints {
+                    wheel_width: GEOMETRY.wheel_width,
+                    ..Constraints::default()
+                },
+            );
+            Box::new(Sequence::new(vec![
+                Box::new(TurnTo::new(PI / 2.0)),
+                Box::new(
+                    FollowTrajectory::new(trajectory)
+                        .with_ramsete(Ramsete::new().with_wheel_width(GEOMETRY.wheel_width)),
+                ),
+            ]))
+        }));
+        let took = sim.run(&mut autonomous, Duration::from_secs(10));
+        assert!(took.is_some(), "still at {:?}", sim.state);
+        // planned from the origin, but started facing left
+        assert!((*(sim.state.x / M) + 1.0).abs() < 0.1, "{:?}", sim.state);
+        assert!((*(sim.state.y / M) - 2.0).abs() < 0.1, "{:?}", sim.state);
+        assert!(
+            (sim.state.heading - PI / 2.0).abs() < 0.1,
+            "{:?}",
+            sim.state
+        );
+    }
+
+    #[test]
+    fn waits_for_the_superstructure() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            Box::new(Sequence::new(vec![
+                Box::new(SetElevator::new(UserElevatorHeights::Med)),
+                Box::new(Parallel::all(vec![
+                    Box::new(IntakeBall::new()),
+                    Box::new(Wait::new(Duration::from_millis(200))),
+                ])),
+            ]))
+        }));
+        autonomous.begin(&Log::discard());
+        for _ in 0..20 {
+            sim.step(|robot| autonomous.iterate(robot));
+        }
+        assert!(autonomous.is_running(), "no ball yet");
+        sim.gates[0].set(false);
+        sim.step(|robot| autonomous.iterate(robot));
+        sim.step(|robot| autonomous.iterate(robot));
+        assert!(!autonomous.is_running());
+        assert_eq!(sim.events(), vec![format!("{} done", DEFAULT_ROUTINE)]);
+    }
+
+    #[test]
+    fn waits_for_a_ball_to_load_before_moving_the_elevator() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            Box::new(Sequence::new(vec![
+                Box::new(IntakeBall::new()),
+                Box::new(SetElevator::new(UserElevatorHeights::Cargo)),
+            ]))
+        }));
+        autonomous.begin(&Log::discard());
+        for i in 0..50 {
+            sim.gates[0].set(i != 5);
+            sim.gates[1].set(i != 10);
+            sim.gates[2].set(i < 15);
+            sim.step(|robot| autonomous.iterate(robot));
+        }
+        assert!(!autonomous.is_running());
+        assert_eq!(sim.events(), vec![format!("{} done", DEFAULT_ROUTINE)]);
+    }
+
+    #[test]
+    fn gives_up_on_rejected_commands_and_timeouts() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            Box::new(Sequence::new(vec![
+                Box::new(timeout(IntakeBall::new(), Duration::from_millis(100))),
+                Box::new(Parallel::race(vec![
+                    Box::new(IntakeBall::new()),
+                    Box::new(Sequence::new(vec![
+                        Box::new(Wait::new(Duration::from_millis(100))),
+                        // can't move the elevator while intaking
+                        Box::new(SetElevator::new(UserElevatorHeights::High)),
+                    ])),
+                ])),
+            ]))
+        }));
+        let took = sim.run(&mut autonomous, Duration::from_secs(1)).unwrap();
+        assert!(took >= Duration::from_millis(200));
+        assert_eq!(
+            sim.events(),
+            vec![
+                "superstructure SetElevatorHeight(High) can't be done in Intaking".to_string(),
+                format!("{} done", DEFAULT_ROUTINE),
+            ]
+        );
+        assert_eq!(sim.bindings.sent().1, vec!["BallIntake(false)".to_string()]);
+    }
+
+    #[test]
+    fn ending_cancels_the_routine() {
+        let mut sim = Sim::new();
+        let mut autonomous = Autonomous::new().with_routines(routine(DEFAULT_ROUTINE, || {
+            Box::new(Parallel::all(vec![
+                Box::new(DriveDistance::new(5.0 * M)),
+                Box::new(IntakeBall::new()),
+            ]))
+        }));
+        autonomous.begin(&Log::discard());
+        for _ in 0..10 {
+            sim.step(|robot| autonomous.iterate(robot));
+        }
+        sim.step(|robot| autonomous.end(robot));
+        assert!(!autonomous.is_running());
+        let (drive, superstructure) = sim.bindings.sent();
+        assert_eq!(drive, vec!["Velocity(0.0 m*s^-1, 0.0 m*s^-1)".to_string()]);
+        assert_eq!(superstructure, vec!["BallIntake(false)".to_string()]);
+    }
+
+    #[test]
+    fn selects_from_the_dashboard() {
+        let table = Table::new();
+        let autonomous = Autonomous::new().with_dashboard(table.clone());
+        assert_eq!(autonomous.selected(), DEFAULT_ROUTINE);
+        match table.get(&format!("{}/options", CHOOSER)) {
+            Some(Value::StringArray(names)) => assert_eq!(names.len(), ROUTINES.len()),
+            other => panic!("no options: {:?}", other),
+        }
+        let other = ROUTINES.iter().find(|r| r.name != DEFAULT_ROUTINE).unwrap();
+        table.set(&format!("{}/selected", CHOOSER), other.name);
+        assert_eq!(autonomous.selected(), other.name);
+        table.set(&format!("{}/selected", CHOOSER), "Win the match");
+        assert_eq!(autonomous.selected(), DEFAULT_ROUTINE);
+    }
+}
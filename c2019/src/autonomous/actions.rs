@@ -0,0 +1,345 @@
+//! The actions routines are built from.
+use super::ramsete::Ramsete;
+use super::trajectory::Trajectory;
+use super::{Action, Progress, Robot};
+use crate::config::autonomous::*;
+use crate::subsystems::drive::{Instruction as DriveCmd, Pose};
+use crate::subsystems::superstructure::{Instruction as SsCmd, Mode, UserElevatorHeights};
+use controls::units::*;
+use controls::util::clamp;
+use interp_map::pose::{wrap_angle, Pose2d};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+fn stop(robot: &mut Robot) {
+    robot.drive(DriveCmd::Velocity(0.0 * MPS, 0.0 * MPS));
+}
+
+fn settled(pose: &Pose) -> bool {
+    (*(pose.velocity / MPS)).abs() < *(SETTLED_SPEED / MPS)
+        && (*(pose.angular_velocity * S)).abs() < SETTLED_TURN_RATE
+}
+
+/// Drives straight for a distance, backwards if it's negative, holding the heading it started on.
+#[derive(Debug)]
+pub struct DriveDistance {
+    distance: Meter<f64>,
+    speed: MeterPerSecond<f64>,
+    start: Option<Pose>,
+}
+
+impl DriveDistance {
+    pub fn new(distance: Meter<f64>) -> Self {
+        Self {
+            distance,
+            speed: DRIVE_SPEED,
+            start: None,
+        }
+    }
+
+    pub fn with_speed(self, speed: MeterPerSecond<f64>) -> Self {
+        Self { speed, ..self }
+    }
+}
+
+impl Action for DriveDistance {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let pose = match robot.pose {
+            Some(pose) => pose,
+            None => return Progress::Running,
+        };
+        let start = *self.start.get_or_insert(pose);
+        let (sin, cos) = start.heading.sin_cos();
+        let travelled = (pose.x - start.x) * cos + (pose.y - start.y) * sin;
+        let remaining = self.distance - travelled;
+        if (*(remaining / M)).abs() < *(DRIVE_TOLERANCE / M) && settled(&pose) {
+            stop(robot);
+            return Progress::Done;
+        }
+        let speed = clamp(remaining * DRIVE_KP, -self.speed, self.speed);
+        let turn = DRIVE_HEADING_KP * wrap_angle(start.heading - pose.heading);
+        robot.drive(DriveCmd::Velocity(speed - turn, speed + turn));
+        Progress::Running
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        stop(robot);
+    }
+}
+
+/// Turns in place to face a heading, in radians from the one the routine began on.
+#[derive(Debug)]
+pub struct TurnTo {
+    heading: f64,
+}
+
+impl TurnTo {
+    pub fn new(heading: f64) -> Self {
+        Self { heading }
+    }
+}
+
+impl Action for TurnTo {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let pose = match robot.pose {
+            Some(pose) => pose,
+            None => return Progress::Running,
+        };
+        let error = wrap_angle(robot.start_heading + self.heading - pose.heading);
+        if error.abs() < TURN_TOLERANCE && settled(&pose) {
+            stop(robot);
+            return Progress::Done;
+        }
+        let speed = clamp(TURN_KP * error, -TURN_SPEED, TURN_SPEED);
+        robot.drive(DriveCmd::Velocity(-speed, speed));
+        Progress::Running
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        stop(robot);
+    }
+}
+
+/// Follows a trajectory, planned as if from the origin, from wherever the robot is when it starts.
+#[derive(Debug)]
+pub struct FollowTrajectory {
+    planned: Trajectory,
+    ramsete: Ramsete,
+    /// Where the trajectory actually starts, and when
+    started: Option<(Trajectory, Instant)>,
+}
+
+impl FollowTrajectory {
+    pub fn new(trajectory: Trajectory) -> Self {
+        Self {
+            planned: trajectory,
+            ramsete: Ramsete::new(),
+            started: None,
+        }
+    }
+
+    pub fn with_ramsete(self, ramsete: Ramsete) -> Self {
+        Self { ramsete, ..self }
+    }
+}
+
+impl Action for FollowTrajectory {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let pose = match robot.pose {
+            Some(pose) => pose,
+            None => return Progress::Running,
+        };
+        let planned = &self.planned;
+        let now = robot.now;
+        let (trajectory, start) = self.started.get_or_insert_with(|| {
+            let origin = Pose2d::new(*(pose.x / M), *(pose.y / M), pose.heading);
+            (planned.relative_to(&origin), now)
+        });
+        let elapsed = now.duration_since(*start).as_secs_f64() * S;
+        if elapsed >= trajectory.duration() {
+            stop(robot);
+            return Progress::Done;
+        }
+        let goal = trajectory.sample(elapsed);
+        robot.drive(self.ramsete.calculate(&pose, &goal));
+        Progress::Running
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        stop(robot);
+    }
+}
+
+/// Sends the superstructure a command, and waits for it to be completed. One that's rejected is
+/// given up on, and the controller logs why.
+#[derive(Debug)]
+struct Request {
+    command: SsCmd,
+    sent: bool,
+}
+
+impl Request {
+    fn new(command: SsCmd) -> Self {
+        Self {
+            command,
+            sent: false,
+        }
+    }
+
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        if !self.sent {
+            robot.superstructure(self.command);
+            self.sent = true;
+            return Progress::Running;
+        }
+        if robot.rejected(self.command) || robot.completed(self.command) {
+            Progress::Done
+        } else {
+            Progress::Running
+        }
+    }
+}
+
+/// Moves the elevator, and waits for it to get there. A ball on its way up to the carriage has
+/// to get there first, since the superstructure won't move the elevator until it does.
+#[derive(Debug)]
+pub struct SetElevator(Request);
+
+impl SetElevator {
+    pub fn new(height: UserElevatorHeights) -> Self {
+        SetElevator(Request::new(SsCmd::SetElevatorHeight(height)))
+    }
+}
+
+impl Action for SetElevator {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let loading = robot.status.map_or(false, |s| s.mode == Mode::Loading);
+        if !self.0.sent && loading {
+            return Progress::Running;
+        }
+        self.0.update(robot)
+    }
+}
+
+/// Runs the intake until a ball is in.
+#[derive(Debug)]
+pub struct IntakeBall(Request);
+
+impl IntakeBall {
+    pub fn new() -> Self {
+        IntakeBall(Request::new(SsCmd::BallIntake(true)))
+    }
+}
+
+impl Default for IntakeBall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Action for IntakeBall {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        self.0.update(robot)
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        if self.0.sent {
+            robot.superstructure(SsCmd::BallIntake(false));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Wait {
+    duration: Duration,
+    until: Option<Instant>,
+}
+
+impl Wait {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            until: None,
+        }
+    }
+}
+
+impl Action for Wait {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let duration = self.duration;
+        let until = *self.until.get_or_insert_with(|| robot.now + duration);
+        if robot.now >= until {
+            Progress::Done
+        } else {
+            Progress::Running
+        }
+    }
+}
+
+/// Runs actions one after another. The next starts in the same loop the last one finished.
+#[derive(Debug)]
+pub struct Sequence(VecDeque<Box<dyn Action>>);
+
+impl Sequence {
+    pub fn new(actions: Vec<Box<dyn Action>>) -> Self {
+        Sequence(actions.into())
+    }
+}
+
+impl Action for Sequence {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        while let Some(action) = self.0.front_mut() {
+            match action.update(robot) {
+                Progress::Running => return Progress::Running,
+                Progress::Done => self.0.pop_front(),
+            };
+        }
+        Progress::Done
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        if let Some(action) = self.0.front_mut() {
+            action.cancel(robot);
+        }
+    }
+}
+
+/// Runs actions at the same time, until they're all done, or until the first is done if it's a
+/// race. Actions later in the list win if they tell a subsystem different things.
+#[derive(Debug)]
+pub struct Parallel {
+    /// None once done
+    actions: Vec<Option<Box<dyn Action>>>,
+    race: bool,
+}
+
+impl Parallel {
+    pub fn all(actions: Vec<Box<dyn Action>>) -> Self {
+        Self {
+            actions: actions.into_iter().map(Some).collect(),
+            race: false,
+        }
+    }
+
+    /// Cancels the rest as soon as one is done.
+    pub fn race(actions: Vec<Box<dyn Action>>) -> Self {
+        Self {
+            race: true,
+            ..Self::all(actions)
+        }
+    }
+}
+
+impl Action for Parallel {
+    fn update(&mut self, robot: &mut Robot) -> Progress {
+        let mut any_done = false;
+        for slot in &mut self.actions {
+            if let Some(action) = slot {
+                if action.update(robot) == Progress::Done {
+                    *slot = None;
+                    any_done = true;
+                }
+            }
+        }
+        if self.race && any_done {
+            self.cancel(robot);
+            self.actions.clear();
+        }
+        if self.actions.iter().all(Option::is_none) {
+            Progress::Done
+        } else {
+            Progress::Running
+        }
+    }
+
+    fn cancel(&mut self, robot: &mut Robot) {
+        for action in self.actions.iter_mut().flatten() {
+            action.cancel(robot);
+        }
+    }
+}
+
+/// Gives up on `action` after `duration`.
+pub fn timeout(action: impl Action + 'static, duration: Duration) -> Parallel {
+    Parallel::race(vec![Box::new(action), Box::new(Wait::new(duration))])
+}
@@ -0,0 +1,165 @@
+//! Following a [`Trajectory`] with the Ramsete controller.
+//!
+//! The trajectory's own speed and curvature drive the robot, and the controller adds corrections
+//! for the error from where it should be, in the robot's frame. It converges from errors in any
+//! direction, including sideways, which a differential drive can't correct directly.
+//!
+//! See "Control of Wheeled Mobile Robots: An Experimental Overview", section 8.5.2.
+use super::trajectory::State;
+use crate::config::autonomous::*;
+use crate::config::drive::DRIVE_BASE_WHEEL_WIDTH;
+use crate::subsystems::drive::{Instruction, Pose};
+use controls::units::*;
+use interp_map::pose::wrap_angle;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ramsete {
+    b: f64,
+    zeta: f64,
+    wheel_width: Meter<f64>,
+}
+
+impl Ramsete {
+    pub fn new() -> Self {
+        Self {
+            b: RAMSETE_B,
+            zeta: RAMSETE_ZETA,
+            wheel_width: DRIVE_BASE_WHEEL_WIDTH,
+        }
+    }
+
+    pub fn with_gains(self, b: f64, zeta: f64) -> Self {
+        Self { b, zeta, ..self }
+    }
+
+    /// Replaces the wheel width in `config::drive`
+    #[allow(dead_code)]
+    pub fn with_wheel_width(self, wheel_width: Meter<f64>) -> Self {
+        Self {
+            wheel_width,
+            ..self
+        }
+    }
+
+    /// Wheel speeds to take the robot at `pose` to `goal`.
+    pub fn calculate(&self, pose: &Pose, goal: &State) -> Instruction {
+        let (sin, cos) = pose.heading.sin_cos();
+        let (dx, dy) = (
+            *((goal.pose.x * M - pose.x) / M),
+            *((goal.pose.y * M - pose.y) / M),
+        );
+        let error_x = dx * cos + dy * sin;
+        let error_y = -dx * sin + dy * cos;
+        let error_heading = wrap_angle(goal.pose.heading - pose.heading);
+
+        let velocity = *(goal.velocity / MPS);
+        let angular_velocity = velocity * goal.curvature;
+        let gain = 2.0 * self.zeta * (angular_velocity.powi(2) + self.b * velocity.powi(2)).sqrt();
+        // sin(x) / x, which is 1 at 0
+        let sinc = if error_heading.abs() < 1e-9 {
+            1.0
+        } else {
+            error_heading.sin() / error_heading
+        };
+
+        let velocity = velocity * error_heading.cos() + gain * error_x;
+        let angular_velocity = angular_velocity
+            + gain * error_heading
+            + self.b * *(goal.velocity / MPS) * sinc * error_y;
+        let turn = angular_velocity * *(self.wheel_width / M) / 2.0;
+        Instruction::Velocity((velocity - turn) * MPS, (velocity + turn) * MPS)
+    }
+}
+
+impl Default for Ramsete {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::trajectory::{Constraints, Trajectory};
+    use super::*;
+    use crate::subsystems::drive::sim::{DrivePlant, DriveShim, DriveState, GEOMETRY};
+    use crate::subsystems::drive::Gear;
+    use controls::SimulationHarness;
+    use interp_map::pose::Pose2d;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn distance(state: &DriveState, pose: &Pose2d) -> f64 {
+        (*(state.x / M) - pose.x).hypot(*(state.y / M) - pose.y)
+    }
+
+    /// Follows `trajectory` on a simulated drive, commanding it every 20ms like the controller.
+    /// Returns the final state, and the furthest the robot strayed from where it should have been.
+    fn follow(trajectory: &Trajectory, ramsete: Option<Ramsete>) -> (DriveState, f64) {
+        let mut drive = DriveShim::new();
+        drive.execute(Instruction::GearShift(Gear::Low));
+        let mut harness = SimulationHarness::<DrivePlant, _>::new(drive, DriveState::at_rest(), 20);
+        let period = 0.02 * S;
+        let mut time = 0.0 * S;
+        let mut state = DriveState::at_rest();
+        let mut worst = 0.0f64;
+        // and a little longer to settle
+        while time < trajectory.duration() + 0.5 * S {
+            let goal = trajectory.sample(time);
+            let instruction = match ramsete {
+                Some(ramsete) => ramsete
+                    .with_wheel_width(GEOMETRY.wheel_width)
+                    .calculate(&harness.shim().pose(), &goal),
+                // open loop, from the trajectory's speed and curvature alone
+                None => {
+                    let turn = goal.velocity * goal.curvature * GEOMETRY.wheel_width / M / 2.0;
+                    Instruction::Velocity(goal.velocity - turn, goal.velocity + turn)
+                }
+            };
+            harness.shim_mut().execute(instruction);
+            state = harness.run_time(period);
+            time += period;
+            worst = worst.max(distance(&state, &goal.pose));
+        }
+        (state, worst)
+    }
+
+    fn s_curve() -> Trajectory {
+        Trajectory::through(
+            &[
+                Pose2d::new(0.0, 0.0, 0.0),
+                Pose2d::new(2.0, 1.0, 0.0),
+                Pose2d::new(3.5, -0.5, -FRAC_PI_2),
+            ],
+            &Constraints {
+                wheel_width: GEOMETRY.wheel_width,
+                ..Constraints::default()
+            },
+        )
+    }
+
+    #[test]
+    fn follows_an_s_curve() {
+        let trajectory = s_curve();
+        let end = trajectory.states().last().unwrap().pose;
+        let (state, worst) = follow(&trajectory, Some(Ramsete::new()));
+        assert!(distance(&state, &end) < 0.05, "ended at {:?}", state);
+        assert!(
+            wrap_angle(state.heading - end.heading).abs() < 0.05,
+            "ended at {:?}",
+            state
+        );
+        assert!(worst < 0.1, "strayed up to {}m", worst);
+    }
+
+    #[test]
+    fn corrects_what_open_loop_misses() {
+        let trajectory = s_curve();
+        let (_, closed) = follow(&trajectory, Some(Ramsete::new()));
+        let (_, open) = follow(&trajectory, None);
+        assert!(
+            closed < open,
+            "strayed up to {}m closed loop, {}m open loop",
+            closed,
+            open
+        );
+    }
+}
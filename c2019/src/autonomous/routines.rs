@@ -0,0 +1,72 @@
+//! The routines drivers can choose from. Headings are from the one the robot started on, so
+//! routines are written for starting on the right side of the HAB, and mirrored for the left.
+use super::actions::*;
+use super::Action;
+use crate::subsystems::superstructure::UserElevatorHeights;
+use controls::units::*;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// A routine's name on the dashboard, and how to build it.
+#[derive(Debug, Copy, Clone)]
+pub struct Routine {
+    pub name: &'static str,
+    pub build: fn() -> Box<dyn Action>,
+}
+
+pub const ROUTINES: &[Routine] = &[
+    Routine {
+        name: "Do nothing",
+        build: nothing,
+    },
+    Routine {
+        name: "Cross the line",
+        build: cross_the_line,
+    },
+    Routine {
+        name: "Depot ball (right)",
+        build: depot_ball_right,
+    },
+    Routine {
+        name: "Depot ball (left)",
+        build: depot_ball_left,
+    },
+];
+
+fn nothing() -> Box<dyn Action> {
+    Box::new(Sequence::new(vec![]))
+}
+
+/// Drives off the HAB, far enough to clear the line.
+fn cross_the_line() -> Box<dyn Action> {
+    Box::new(DriveDistance::new(3.0 * M))
+}
+
+/// Drives off the HAB to the depot on `side` (1 for right, -1 for left), picks up a ball and
+/// raises it to the cargo ship's height on the way back.
+fn depot_ball(side: f64) -> Box<dyn Action> {
+    Box::new(Sequence::new(vec![
+        Box::new(DriveDistance::new(2.5 * M)),
+        Box::new(TurnTo::new(-side * PI / 2.0)),
+        Box::new(timeout(
+            Parallel::race(vec![
+                Box::new(IntakeBall::new()),
+                Box::new(DriveDistance::new(1.0 * M).with_speed(0.5 * MPS)),
+            ]),
+            Duration::from_secs(4),
+        )),
+        Box::new(TurnTo::new(0.0)),
+        Box::new(Parallel::all(vec![
+            Box::new(DriveDistance::new(1.0 * M)),
+            Box::new(SetElevator::new(UserElevatorHeights::Cargo)),
+        ])),
+    ]))
+}
+
+fn depot_ball_right() -> Box<dyn Action> {
+    depot_ball(1.0)
+}
+
+fn depot_ball_left() -> Box<dyn Action> {
+    depot_ball(-1.0)
+}
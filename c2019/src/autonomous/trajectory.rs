@@ -0,0 +1,401 @@
+//! Smooth paths through waypoints, timed to the drivetrain's limits.
+//!
+//! Each pair of waypoints is joined by a quintic Hermite spline, leaving in the direction of one
+//! and arriving in the direction of the next with no sideways acceleration at either end. The
+//! splines are sampled finely enough to treat as straight between samples, then timed with a
+//! forward pass that accelerates as hard as allowed and a backward pass that brakes in time for
+//! every slower stretch after it.
+use crate::config::autonomous::*;
+use crate::config::drive::DRIVE_BASE_WHEEL_WIDTH;
+use controls::units::*;
+use interp_map::pose::{wrap_angle, Pose2d};
+use interp_map::Interpolatable;
+
+/// How far apart samples of a spline may be, before timing
+const MAX_SAMPLE_DISTANCE: f64 = 0.05; // m
+const MAX_SAMPLE_TURN: f64 = 0.05; // rad
+/// Below this, a spline parameter interval isn't split further
+const MIN_SAMPLE_INTERVAL: f64 = 1.0 / 4096.0;
+
+/// Limits a trajectory is timed to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Constraints {
+    /// Of the outside wheel, so the robot slows through tight turns
+    pub max_speed: MeterPerSecond<f64>,
+    pub max_acceleration: MeterPerSecond2<f64>,
+    pub max_centripetal: MeterPerSecond2<f64>,
+    pub wheel_width: Meter<f64>,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Self {
+            max_speed: TRAJECTORY_MAX_SPEED,
+            max_acceleration: TRAJECTORY_MAX_ACCELERATION,
+            max_centripetal: TRAJECTORY_MAX_CENTRIPETAL,
+            wheel_width: DRIVE_BASE_WHEEL_WIDTH,
+        }
+    }
+}
+
+/// Where the robot should be at a point in time, and how it should be moving.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct State {
+    /// Since the start of the trajectory
+    pub time: Second<f64>,
+    pub pose: Pose2d,
+    pub velocity: MeterPerSecond<f64>,
+    /// Until the next state
+    pub acceleration: MeterPerSecond2<f64>,
+    /// Radians per meter, positive turning left
+    pub curvature: f64,
+}
+
+/// One dimension of a quintic Hermite spline, as polynomial coefficients from the constant up.
+#[derive(Debug, Copy, Clone)]
+struct Quintic([f64; 6]);
+
+impl Quintic {
+    /// Through `p0` and `p1`, with first derivatives `v0` and `v1` and second derivatives zero.
+    fn new(p0: f64, v0: f64, p1: f64, v1: f64) -> Self {
+        Quintic([
+            p0,
+            v0,
+            0.0,
+            -10.0 * p0 - 6.0 * v0 - 4.0 * v1 + 10.0 * p1,
+            15.0 * p0 + 8.0 * v0 + 7.0 * v1 - 15.0 * p1,
+            -6.0 * p0 - 3.0 * v0 - 3.0 * v1 + 6.0 * p1,
+        ])
+    }
+
+    /// Value, first and second derivatives at `t` in [0, 1]
+    fn at(&self, t: f64) -> (f64, f64, f64) {
+        let c = &self.0;
+        let p = c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * c[5]))));
+        let d = c[1] + t * (2.0 * c[2] + t * (3.0 * c[3] + t * (4.0 * c[4] + t * 5.0 * c[5])));
+        let dd = 2.0 * c[2] + t * (6.0 * c[3] + t * (12.0 * c[4] + t * 20.0 * c[5]));
+        (p, d, dd)
+    }
+}
+
+/// A point along a spline, before it's timed.
+#[derive(Debug, Copy, Clone)]
+struct PathPoint {
+    pose: Pose2d,
+    curvature: f64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Spline {
+    x: Quintic,
+    y: Quintic,
+}
+
+impl Spline {
+    fn between(from: &Pose2d, to: &Pose2d) -> Self {
+        // how hard the spline pulls toward each waypoint's heading; scaling with the distance
+        // keeps the shape the same whatever the size
+        let scale = 1.2 * (to.x - from.x).hypot(to.y - from.y);
+        let (from_sin, from_cos) = from.heading.sin_cos();
+        let (to_sin, to_cos) = to.heading.sin_cos();
+        Self {
+            x: Quintic::new(from.x, scale * from_cos, to.x, scale * to_cos),
+            y: Quintic::new(from.y, scale * from_sin, to.y, scale * to_sin),
+        }
+    }
+
+    fn at(&self, t: f64) -> PathPoint {
+        let (x, dx, ddx) = self.x.at(t);
+        let (y, dy, ddy) = self.y.at(t);
+        let speed = dx.hypot(dy);
+        PathPoint {
+            pose: Pose2d::new(x, y, dy.atan2(dx)),
+            curvature: (dx * ddy - dy * ddx) / (speed * speed * speed),
+        }
+    }
+
+    /// Appends points after `t0` up to and including `t1`, closer together than the sample limits.
+    fn sample(&self, t0: f64, start: &PathPoint, t1: f64, out: &mut Vec<PathPoint>) {
+        let end = self.at(t1);
+        let distance = (end.pose.x - start.pose.x).hypot(end.pose.y - start.pose.y);
+        let turn = wrap_angle(end.pose.heading - start.pose.heading).abs();
+        if (distance > MAX_SAMPLE_DISTANCE || turn > MAX_SAMPLE_TURN)
+            && t1 - t0 > MIN_SAMPLE_INTERVAL
+        {
+            let mid_t = (t0 + t1) / 2.0;
+            let mid = self.at(mid_t);
+            self.sample(t0, start, mid_t, out);
+            self.sample(mid_t, &mid, t1, out);
+        } else {
+            out.push(end);
+        }
+    }
+}
+
+/// A path through waypoints, timed so the robot can follow it.
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    states: Vec<State>,
+}
+
+impl Trajectory {
+    /// Plans a path starting and ending at rest, passing through each waypoint facing along its
+    /// heading.
+    ///
+    /// # Panics
+    /// With fewer than two waypoints, or two in the same place one after the other.
+    pub fn through(waypoints: &[Pose2d], constraints: &Constraints) -> Self {
+        assert!(waypoints.len() >= 2, "a trajectory needs two waypoints");
+        let mut path = vec![Spline::between(&waypoints[0], &waypoints[1]).at(0.0)];
+        for pair in waypoints.windows(2) {
+            assert!(
+                (pair[1].x - pair[0].x).hypot(pair[1].y - pair[0].y) > 0.0,
+                "repeated waypoint {:?}",
+                pair[0]
+            );
+            let spline = Spline::between(&pair[0], &pair[1]);
+            let start = *path.last().unwrap();
+            spline.sample(0.0, &start, 1.0, &mut path);
+        }
+        Self::time(&path, constraints)
+    }
+
+    fn time(path: &[PathPoint], constraints: &Constraints) -> Self {
+        let max_speed = *(constraints.max_speed / MPS);
+        let max_acceleration = *(constraints.max_acceleration / MPS2);
+        let max_centripetal = *(constraints.max_centripetal / MPS2);
+        let half_width = *(constraints.wheel_width / M) / 2.0;
+
+        let distances: Vec<f64> = path
+            .windows(2)
+            .map(|pair| (pair[1].pose.x - pair[0].pose.x).hypot(pair[1].pose.y - pair[0].pose.y))
+            .collect();
+        let mut speeds: Vec<f64> = path
+            .iter()
+            .map(|point| {
+                let curvature = point.curvature.abs();
+                let wheels = max_speed / (1.0 + curvature * half_width);
+                let centripetal = (max_centripetal / curvature).sqrt();
+                wheels.min(centripetal)
+            })
+            .collect();
+
+        let last = speeds.len() - 1;
+        speeds[0] = 0.0;
+        speeds[last] = 0.0;
+        for i in 1..speeds.len() {
+            let reachable =
+                (speeds[i - 1].powi(2) + 2.0 * max_acceleration * distances[i - 1]).sqrt();
+            speeds[i] = speeds[i].min(reachable);
+        }
+        for i in (0..last).rev() {
+            let stoppable = (speeds[i + 1].powi(2) + 2.0 * max_acceleration * distances[i]).sqrt();
+            speeds[i] = speeds[i].min(stoppable);
+        }
+
+        let mut time = 0.0;
+        let states = path
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let acceleration = if i < last {
+                    (speeds[i + 1].powi(2) - speeds[i].powi(2)) / (2.0 * distances[i])
+                } else {
+                    0.0
+                };
+                let state = State {
+                    time: time * S,
+                    pose: point.pose,
+                    velocity: speeds[i] * MPS,
+                    acceleration: acceleration * MPS2,
+                    curvature: point.curvature,
+                };
+                if i < last {
+                    time += 2.0 * distances[i] / (speeds[i] + speeds[i + 1]);
+                }
+                state
+            })
+            .collect();
+        Self { states }
+    }
+
+    pub fn duration(&self) -> Second<f64> {
+        self.states.last().unwrap().time
+    }
+
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    /// The state `time` into the trajectory, held at the ends outside of it.
+    pub fn sample(&self, time: Second<f64>) -> State {
+        let next = self.states.iter().position(|state| state.time > time);
+        let (before, after) = match next {
+            Some(0) => return self.states[0],
+            Some(i) => (&self.states[i - 1], &self.states[i]),
+            None => return *self.states.last().unwrap(),
+        };
+        // constant acceleration between states
+        let dt = time - before.time;
+        let velocity = before.velocity + before.acceleration * dt;
+        let travelled = *((before.velocity * dt + before.acceleration * dt * dt / 2.0) / M);
+        let distance = (after.pose.x - before.pose.x).hypot(after.pose.y - before.pose.y);
+        let percent = travelled / distance;
+        State {
+            time,
+            pose: Pose2d::interp(before.pose, after.pose, percent),
+            velocity,
+            acceleration: before.acceleration,
+            curvature: f64::interp(before.curvature, after.curvature, percent),
+        }
+    }
+
+    /// This trajectory, starting from `origin` rather than from where it was planned.
+    pub fn relative_to(&self, origin: &Pose2d) -> Self {
+        let (sin, cos) = origin.heading.sin_cos();
+        let states = self
+            .states
+            .iter()
+            .map(|state| {
+                let pose = &state.pose;
+                State {
+                    pose: Pose2d::new(
+                        origin.x + pose.x * cos - pose.y * sin,
+                        origin.y + pose.x * sin + pose.y * cos,
+                        origin.heading + pose.heading,
+                    ),
+                    ..*state
+                }
+            })
+            .collect();
+        Self { states }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::drive::sim::GEOMETRY;
+    use controls::approx::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn constraints() -> Constraints {
+        Constraints {
+            wheel_width: GEOMETRY.wheel_width,
+            ..Constraints::default()
+        }
+    }
+
+    fn s_curve() -> Trajectory {
+        Trajectory::through(
+            &[
+                Pose2d::new(0.0, 0.0, 0.0),
+                Pose2d::new(2.0, 1.0, 0.0),
+                Pose2d::new(4.0, 0.0, -FRAC_PI_2),
+            ],
+            &constraints(),
+        )
+    }
+
+    #[test]
+    fn passes_through_waypoints() {
+        let trajectory = s_curve();
+        let states = trajectory.states();
+        for waypoint in &[
+            Pose2d::new(0.0, 0.0, 0.0),
+            Pose2d::new(2.0, 1.0, 0.0),
+            Pose2d::new(4.0, 0.0, -FRAC_PI_2),
+        ] {
+            let closest = states
+                .iter()
+                .map(|state| &state.pose)
+                .min_by(|a, b| {
+                    let dist = |p: &&Pose2d| (p.x - waypoint.x).hypot(p.y - waypoint.y);
+                    dist(a).partial_cmp(&dist(b)).unwrap()
+                })
+                .unwrap();
+            assert_abs_diff_eq!(closest.x, waypoint.x, epsilon = 1e-9);
+            assert_abs_diff_eq!(closest.y, waypoint.y, epsilon = 1e-9);
+            assert_abs_diff_eq!(
+                wrap_angle(closest.heading - waypoint.heading),
+                0.0,
+                epsilon = 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn respects_constraints() {
+        let constraints = constraints();
+        let trajectory = s_curve();
+        let states = trajectory.states();
+        assert_abs_diff_eq!(*(states[0].velocity / MPS), 0.0);
+        assert_abs_diff_eq!(*(states.last().unwrap().velocity / MPS), 0.0);
+        let half_width = *(constraints.wheel_width / M) / 2.0;
+        for pair in states.windows(2) {
+            let state = &pair[0];
+            let speed = *(state.velocity / MPS);
+            let outside = speed * (1.0 + state.curvature.abs() * half_width);
+            assert!(outside <= *(constraints.max_speed / MPS) + 1e-9);
+            assert!(
+                speed * speed * state.curvature.abs()
+                    <= *(constraints.max_centripetal / MPS2) + 1e-9
+            );
+            assert!(
+                (*(state.acceleration / MPS2)).abs()
+                    <= *(constraints.max_acceleration / MPS2) + 1e-9
+            );
+            assert!(pair[1].time > state.time);
+        }
+    }
+
+    #[test]
+    fn turns_through_backwards() {
+        // the same arc, facing forwards and backwards, where the heading wraps around
+        let forwards = Trajectory::through(
+            &[Pose2d::new(0.0, 0.0, 0.3), Pose2d::new(3.0, 0.0, -0.3)],
+            &constraints(),
+        );
+        let backwards = Trajectory::through(
+            &[
+                Pose2d::new(0.0, 0.0, PI - 0.3),
+                Pose2d::new(-3.0, 0.0, 0.3 - PI),
+            ],
+            &constraints(),
+        );
+        assert_eq!(forwards.states().len(), backwards.states().len());
+    }
+
+    #[test]
+    fn samples_between_states() {
+        let trajectory = s_curve();
+        let duration = trajectory.duration();
+        assert_eq!(trajectory.sample(-1.0 * S), trajectory.states()[0]);
+        assert_eq!(
+            trajectory.sample(duration + 1.0 * S),
+            *trajectory.states().last().unwrap()
+        );
+
+        // distance covered by integrating the sampled speed matches the path's length
+        let length: f64 = trajectory
+            .states()
+            .windows(2)
+            .map(|pair| (pair[1].pose.x - pair[0].pose.x).hypot(pair[1].pose.y - pair[0].pose.y))
+            .sum();
+        let dt = 0.001;
+        let steps = (*(duration / S) / dt) as usize;
+        let travelled: f64 = (0..steps)
+            .map(|i| *(trajectory.sample(i as f64 * dt * S).velocity / MPS) * dt)
+            .sum();
+        assert_abs_diff_eq!(travelled, length, epsilon = 0.01);
+    }
+
+    #[test]
+    fn moves_to_another_origin() {
+        let trajectory = s_curve().relative_to(&Pose2d::new(1.0, 2.0, FRAC_PI_2));
+        let end = trajectory.states().last().unwrap().pose;
+        assert_abs_diff_eq!(end.x, 1.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(end.y, 6.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(end.heading, 0.0, epsilon = 1e-9);
+    }
+}
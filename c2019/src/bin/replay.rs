@@ -0,0 +1,61 @@
+//! Reruns a match log against the current code, on a laptop, and prints every loop where the
+//! robot's logic now does something different.
+//!
+//! ```sh
+//! scp -r lvuser@10.1.14.2:/home/lvuser/telemetry/3 .
+//! scp lvuser@10.1.14.2:/home/lvuser/tunables.toml .
+//! cargo run --bin replay -- 3 tunables.toml
+//! ```
+//!
+//! Copy the whole session directory. If its oldest files were deleted, or frames were dropped, each
+//! subsystem's replay starts again from its next snapshot.
+use c2019::replay::replay;
+use c2019::subsystems::telemetry::read_frames;
+use std::env;
+use std::process;
+use tunables::Registry;
+
+const USAGE: &str = "usage: replay <session directory> [tunables.toml]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (dir, tunables) = match args.as_slice() {
+        [dir] => (dir, None),
+        [dir, tunables] => (dir, Some(tunables)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2)
+        }
+    };
+
+    let registry = Registry::new();
+    if let Some(path) = tunables {
+        if let Err(e) = registry.load(path) {
+            eprintln!("Could not load {}: {}", path, e);
+            process::exit(1)
+        }
+    }
+    let log = read_frames(dir).unwrap_or_else(|e| {
+        eprintln!("Could not read the match log in {}: {}", dir, e);
+        process::exit(1)
+    });
+    if log.skipped > 0 {
+        println!(
+            "Skipped {} loops missing from the log or waiting on a snapshot",
+            log.skipped
+        );
+    }
+
+    let mismatches = replay(&log.frames, &registry);
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    println!(
+        "Replayed {} loops, {} came out differently",
+        log.frames.len(),
+        mismatches.len()
+    );
+    if !mismatches.is_empty() {
+        process::exit(1)
+    }
+}
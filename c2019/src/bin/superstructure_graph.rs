@@ -0,0 +1,29 @@
+//! Prints the superstructure's state machine, for teaching drivers what each button does when.
+//!
+//! ```sh
+//! cargo run --bin superstructure_graph -- dot | dot -Tpng > superstructure.png
+//! cargo run --bin superstructure_graph -- mermaid
+//! ```
+use c2019::subsystems::superstructure::machine::{dot, mermaid};
+use std::env;
+use std::process;
+
+const USAGE: &str = "usage: superstructure_graph <dot|mermaid>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let graph = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["dot"] => dot(),
+        ["mermaid"] => mermaid(),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2)
+        }
+    };
+    print!("{}", graph);
+}
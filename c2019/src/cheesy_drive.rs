@@ -4,7 +4,9 @@
 /// turn-in-place maneuvers.
 // Translated from code released by FRC254 under the MIT license
 use controls::util::clamp;
+use serde_derive::{Deserialize, Serialize};
 use std::f64::consts::PI;
+use tunables::{Registry, Tunable};
 
 const THROTTLE_DEADBAND: f64 = 0.02;
 const WHEEL_DEADBAND: f64 = 0.02;
@@ -27,28 +29,94 @@ const QUICSTOP_DEADBAND: f64 = 0.5;
 const QUICSTOP_WEIGHT: f64 = 0.1;
 const QUICSTOP_SCALAR: f64 = 5.0;
 
+macro_rules! gains {
+    ($($field:ident: $default:ident,)*) => {
+        /// Every constant above, live tunable as `cheesy/<name>`.
+        #[derive(Debug, Clone)]
+        pub struct Gains {
+            $($field: Tunable,)*
+        }
+
+        impl Default for Gains {
+            fn default() -> Self {
+                Self {
+                    $($field: Tunable::fixed($default),)*
+                }
+            }
+        }
+
+        impl Gains {
+            pub fn register(registry: &Registry) -> Self {
+                Self {
+                    $($field: registry.register(concat!("cheesy/", stringify!($field)), $default),)*
+                }
+            }
+        }
+    };
+}
+
+gains! {
+    throttle_deadband: THROTTLE_DEADBAND,
+    wheel_deadband: WHEEL_DEADBAND,
+    high_wheel_non_linearity: HIGH_WHEEL_NON_LINEARITY,
+    low_wheel_non_linearity: LOW_WHEEL_NON_LINEARITY,
+    high_neg_inertia_scalar: HIGH_NEG_INERTIA_SCALAR,
+    low_neg_inertia_threshold: LOW_NEG_INERTIA_THRESHOLD,
+    low_neg_inertia_turn_scalar: LOW_NEG_INERTIA_TURN_SCALAR,
+    low_neg_inertia_close_scalar: LOW_NEG_INERTIA_CLOSE_SCALAR,
+    low_neg_inertia_far_scalar: LOW_NEG_INERTIA_FAR_SCALAR,
+    high_sensitivity: HIGH_SENSITIVITY,
+    low_sensitivity: LOW_SENSITIITY,
+    quick_stop_deadband: QUICSTOP_DEADBAND,
+    quick_stop_weight: QUICSTOP_WEIGHT,
+    quick_stop_scalar: QUICSTOP_SCALAR,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct DriveSignal {
     pub l: f64,
     pub r: f64,
 }
 
-#[derive(Debug)]
+/// What [`CheesyDrive`] carries from one loop to the next
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheesySnapshot {
+    old_wheel: f64,
+    quick_stop_accumlator: f64,
+    neg_inertia_accumlator: f64,
+}
+
+#[derive(Debug, Default)]
 pub struct CheesyDrive {
     old_wheel: f64,
     quick_stop_accumlator: f64,
     neg_inertia_accumlator: f64,
+    gains: Gains,
 }
 
 impl CheesyDrive {
     pub fn new() -> Self {
-        Self {
-            old_wheel: 0.0,
-            quick_stop_accumlator: 0.0,
-            neg_inertia_accumlator: 0.0,
+        Self::default()
+    }
+
+    pub fn with_gains(self, gains: Gains) -> Self {
+        Self { gains, ..self }
+    }
+
+    pub fn snapshot(&self) -> CheesySnapshot {
+        CheesySnapshot {
+            old_wheel: self.old_wheel,
+            quick_stop_accumlator: self.quick_stop_accumlator,
+            neg_inertia_accumlator: self.neg_inertia_accumlator,
         }
     }
 
+    pub fn restore(&mut self, snapshot: &CheesySnapshot) {
+        self.old_wheel = snapshot.old_wheel;
+        self.quick_stop_accumlator = snapshot.quick_stop_accumlator;
+        self.neg_inertia_accumlator = snapshot.neg_inertia_accumlator;
+    }
+
     pub fn cheesy_drive(
         &mut self,
         throttle: f64,
@@ -56,21 +124,22 @@ impl CheesyDrive {
         is_quick_turn: bool,
         is_high_gear: bool,
     ) -> DriveSignal {
-        let mut wheel = handle_deadband(wheel, WHEEL_DEADBAND);
-        let throttle = handle_deadband(throttle, THROTTLE_DEADBAND);
+        let gains = &self.gains;
+        let mut wheel = handle_deadband(wheel, gains.wheel_deadband.get());
+        let throttle = handle_deadband(throttle, gains.throttle_deadband.get());
 
         let neg_inertia = wheel - self.old_wheel;
         self.old_wheel = wheel;
 
         let wheel_non_linearity;
         if is_high_gear {
-            wheel_non_linearity = HIGH_WHEEL_NON_LINEARITY;
+            wheel_non_linearity = gains.high_wheel_non_linearity.get();
             let denominator = f64::sin(PI / 2.0 * wheel_non_linearity);
             // Apply a sin function that's scaled to make it feel better.
             wheel = f64::sin(PI / 2.0 * wheel_non_linearity * wheel) / denominator;
             wheel = f64::sin(PI / 2.0 * wheel_non_linearity * wheel) / denominator;
         } else {
-            wheel_non_linearity = LOW_WHEEL_NON_LINEARITY;
+            wheel_non_linearity = gains.low_wheel_non_linearity.get();
             let denominator = f64::sin(PI / 2.0 * wheel_non_linearity);
             // Apply a sin function that's scaled to make it feel better.
             wheel = f64::sin(PI / 2.0 * wheel_non_linearity * wheel) / denominator;
@@ -87,21 +156,21 @@ impl CheesyDrive {
         // Negative inertia!
         let neg_inertia_scalar;
         if is_high_gear {
-            neg_inertia_scalar = HIGH_NEG_INERTIA_SCALAR;
-            sensitivity = HIGH_SENSITIVITY;
+            neg_inertia_scalar = gains.high_neg_inertia_scalar.get();
+            sensitivity = gains.high_sensitivity.get();
         } else {
             if wheel * neg_inertia > 0.0 {
                 // If we are moving away from 0.0, aka, trying to get more wheel.
-                neg_inertia_scalar = LOW_NEG_INERTIA_TURN_SCALAR;
+                neg_inertia_scalar = gains.low_neg_inertia_turn_scalar.get();
             } else {
                 // Otherwise, we areDriveSignal attempting to go back to 0.0.
-                if f64::abs(wheel) > LOW_NEG_INERTIA_THRESHOLD {
-                    neg_inertia_scalar = LOW_NEG_INERTIA_FAR_SCALAR;
+                if f64::abs(wheel) > gains.low_neg_inertia_threshold.get() {
+                    neg_inertia_scalar = gains.low_neg_inertia_far_scalar.get();
                 } else {
-                    neg_inertia_scalar = LOW_NEG_INERTIA_CLOSE_SCALAR;
+                    neg_inertia_scalar = gains.low_neg_inertia_close_scalar.get();
                 }
             }
-            sensitivity = LOW_SENSITIITY;
+            sensitivity = gains.low_sensitivity.get();
         }
         let neg_inertia_power = neg_inertia * neg_inertia_scalar;
         self.neg_inertia_accumlator += neg_inertia_power;
@@ -118,10 +187,10 @@ impl CheesyDrive {
 
         // Quickturn!
         if is_quick_turn {
-            if f64::abs(linear_power) < QUICSTOP_DEADBAND {
-                let alpha = QUICSTOP_WEIGHT;
+            if f64::abs(linear_power) < gains.quick_stop_deadband.get() {
+                let alpha = gains.quick_stop_weight.get();
                 self.quick_stop_accumlator = (1.0 - alpha) * self.quick_stop_accumlator
-                    + alpha * clamp(wheel, -1.0, 1.0) * QUICSTOP_SCALAR;
+                    + alpha * clamp(wheel, -1.0, 1.0) * gains.quick_stop_scalar.get();
             }
             over_power = 1.0;
             angular_power = wheel;
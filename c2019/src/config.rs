@@ -1,12 +1,34 @@
 //! Module contains configuration variables.
 //! These should use `dimensioned` units wherever applicable, and contain units in the name otherwise.
+//!
+//! Anything that differs between robots (IDs, channels, current limits) is read at startup through
+//! [`robot::RobotConfig`], and the consts here are only its defaults.
 
 use ::controls::const_unit;
 use ::controls::units::*;
 use std::time::Duration;
 
-/// How long to sleep before checking for messages and other periodic tasks.
-pub const SUBSYSTEM_SLEEP_TIME: Duration = Duration::from_millis(5);
+pub mod robot;
+
+/// How often the drive and superstructure loop. `Elevator::DT` assumes this.
+pub const SUBSYSTEM_PERIOD: Duration = Duration::from_millis(5);
+
+/// Realtime scheduling for subsystem loops, see `subsystems::periodic`. `None` leaves a loop at
+/// normal priority.
+pub mod periodic {
+    use crate::subsystems::periodic::Realtime;
+
+    /// The loops share the second core, leaving the first to the controller, telemetry and
+    /// the HAL
+    pub const DRIVE: Option<Realtime> = Some(Realtime {
+        priority: 10,
+        cpu: Some(1),
+    });
+    pub const SUPERSTRUCTURE: Option<Realtime> = Some(Realtime {
+        priority: 10,
+        cpu: Some(1),
+    });
+}
 
 pub mod drive {
     use super::*;
@@ -42,6 +64,71 @@ pub mod drive {
     /// Communication timeout for setting the talon configurations
     pub const TALON_CFG_TO_MS: i32 = 10;
 
+    /// Closed loop velocity control, per gear. PID gains are in talon native units and run on
+    /// the talons; the feedforward is computed on the rio and sent as an arbitrary feedforward.
+    pub mod velocity {
+        use super::*;
+
+        pub mod low_gear {
+            use super::*;
+
+            pub const SLOT_IDX: i32 = 0;
+            pub const KP: f64 = 1.0; // TODO tune
+            pub const KI: f64 = 0.0;
+            pub const KD: f64 = 0.0;
+            /// Voltage needed to get the robot moving
+            pub const KS: Volt<f64> = const_unit!(0.3); // TODO characterize
+            pub const KV: VoltSecondPerMeter<f64> = const_unit!(4.2); // TODO characterize
+            pub const KA: VoltSecondSquaredPerMeter<f64> = const_unit!(0.93); // TODO characterize
+        }
+
+        pub mod high_gear {
+            use super::*;
+
+            pub const SLOT_IDX: i32 = 1;
+            pub const KP: f64 = 1.0; // TODO tune
+            pub const KI: f64 = 0.0;
+            pub const KD: f64 = 0.0;
+            /// Voltage needed to get the robot moving
+            pub const KS: Volt<f64> = const_unit!(0.3); // TODO characterize
+            pub const KV: VoltSecondPerMeter<f64> = const_unit!(2.0); // TODO characterize
+            pub const KA: VoltSecondSquaredPerMeter<f64> = const_unit!(1.74); // TODO characterize
+        }
+
+        /// Setpoints further apart than this aren't differenced for the acceleration feedforward
+        pub const MAX_SETPOINT_INTERVAL: Duration = Duration::from_millis(100);
+    }
+
+    /// Fusing the gyro and encoder headings, see `subsystems::drive::heading`.
+    pub mod heading {
+        use super::*;
+
+        /// How long the complementary filter trusts the gyro before deferring to the encoders
+        pub const COMPLEMENTARY_TIME_CONSTANT: Second<f64> = const_unit!(2.0);
+
+        // TODO measure these
+        /// Gyro angle random walk, rad^2/s
+        pub const GYRO_VARIANCE: f64 = 1e-5;
+        /// How quickly the gyro bias wanders, rad^2/s^3
+        pub const BIAS_VARIANCE: f64 = 1e-8;
+        /// Heading error from the encoders, rad^2
+        pub const ENCODER_VARIANCE: f64 = 1e-1;
+        /// Uncertainty of the gyro bias at startup, rad^2/s^2
+        pub const INITIAL_BIAS_VARIANCE: f64 = 1e-4;
+    }
+
+    /// Distribution of pose estimates, see `subsystems::drive::pose_service`.
+    pub mod poses {
+        use super::*;
+
+        /// Poses a subscriber can fall behind before missing some
+        pub const SUBSCRIBER_CAPACITY: usize = 16;
+        /// How far back poses are kept for latency compensation
+        pub const HISTORY_AGE: Duration = Duration::from_secs(1);
+        /// Upper bound on poses kept, in case the drive loop runs much faster than expected
+        pub const HISTORY_LENGTH: usize = 1000;
+    }
+
     /// Gear shifter for the drive base.
     pub mod shifter {
         /// Gear shifter solenoid channel ID for high gear
@@ -50,6 +137,111 @@ pub mod drive {
     }
 }
 
+/// Watching over subsystem threads, see `subsystems::supervisor`.
+pub mod supervisor {
+    use super::*;
+
+    /// How often the supervisor checks on subsystems, and logs their health
+    pub const CHECK_PERIOD: Duration = Duration::from_millis(100);
+    /// A subsystem that goes this long without looping is stalled, and is made safe
+    pub const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+    /// Wait before restarting a subsystem that panicked, so one that panics on startup doesn't
+    /// spin
+    pub const RESTART_DELAY: Duration = Duration::from_millis(500);
+    /// Times a subsystem is restarted before it's left stopped
+    pub const MAX_RESTARTS: usize = 3;
+}
+
+/// Live tuning over the network, see `subsystems::tuning`.
+pub mod tuning {
+    /// UDP port the tuning server listens on. Must be in the FMS's open range, 5800-5810.
+    pub const PORT: u16 = 5805;
+    /// Where tuned values are saved, and loaded from at startup
+    pub const SAVE_PATH: &str = "/home/lvuser/tunables.toml";
+}
+
+/// The NetworkTables server for dashboards, see `subsystems::dashboard`.
+pub mod dashboard {
+    use super::*;
+
+    /// What dashboards see the server as
+    pub const IDENTITY: &str = "c2019";
+    /// How often clients are sent what changed, ntcore's default
+    pub const UPDATE_PERIOD: Duration = Duration::from_millis(100);
+    /// Where telemetry records are published, so they show up on Shuffleboard's SmartDashboard tab
+    pub const TABLE: &str = "/SmartDashboard";
+}
+
+/// Targets from the TX1, see `subsystems::vision`.
+pub mod vision {
+    use super::*;
+
+    /// UDP port the TX1 sends `copcomp::c2019::Packet`s to
+    pub const PORT: u16 = 5808;
+    /// Targets seen longer ago than this are stale
+    pub const MAX_AGE: Duration = Duration::from_millis(250);
+    /// From capture to arriving at the RIO, for the fastest frames. Slower frames are measured
+    /// against these.
+    pub const MIN_LATENCY: Duration = Duration::from_millis(30); // TODO measure
+    /// How long to wait before receiving again after the socket fails
+    pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    /// Camera intrinsics, in pixels. TODO calibrate
+    pub const FOCAL_LENGTH_X: f64 = 560.0;
+    pub const FOCAL_LENGTH_Y: f64 = 560.0;
+    pub const CENTER_X: f64 = 320.0;
+    pub const CENTER_Y: f64 = 240.0;
+
+    /// Where the camera is on the robot: forward and left of the middle of the drive base, and
+    /// up from the floor. TODO measure
+    pub const CAMERA_X: Meter<f64> = const_unit!(0.3);
+    pub const CAMERA_Y: Meter<f64> = const_unit!(0.0);
+    pub const CAMERA_HEIGHT: Meter<f64> = const_unit!(0.5);
+    /// Radians up from level
+    pub const CAMERA_PITCH: f64 = 0.2;
+    /// Center of the hatch targets, up from the floor
+    pub const TARGET_HEIGHT: Meter<f64> = const_unit!(31.4 /*in*/ * 0.0254);
+
+    /// Turning toward a target while the driver holds the assist, see
+    /// `subsystems::controller::assist`. Percent output per radian off the target
+    pub const ASSIST_KP: f64 = 0.8; // TODO tune
+    /// Percent output per radian per second turning, to keep from overshooting
+    pub const ASSIST_KD: f64 = 0.05; // TODO tune
+    /// Most percent output the assist turns with
+    pub const ASSIST_MAX_TURN: f64 = 0.5;
+}
+
+/// Telemetry logging, see `subsystems::telemetry`.
+pub mod telemetry {
+    use super::*;
+
+    /// Each boot logs to a new numbered directory in here
+    pub const DIR: &str = "/home/lvuser/telemetry";
+    /// How many boots' logs to keep
+    pub const MAX_SESSIONS: usize = 5;
+    /// Size at which a log file is closed and the next one started
+    pub const MAX_FILE_BYTES: u64 = 1024 * 1024;
+    /// How many files of each kind of record to keep per boot. The oldest are deleted first.
+    pub const MAX_FILES: usize = 8;
+    /// How many match log files to keep from before the robot was last enabled, which covers a
+    /// robot left on for several minutes before its match. Files from after it was enabled are all
+    /// kept.
+    pub const MAX_FRAME_FILES: usize = 48;
+    /// Loops between each subsystem's snapshots in the match log. A replay of a log whose oldest
+    /// files were deleted starts each subsystem at its first snapshot.
+    pub const KEYFRAME_PERIOD: u32 = 500;
+    /// Entries waiting to be written. Past this, new records are dropped and counted rather than
+    /// blocking the subsystem.
+    pub const QUEUE_LENGTH: usize = 4096;
+    /// Room for match log frames on top of `QUEUE_LENGTH`, about half a minute of them, past which
+    /// they're dropped and counted too. This caps the queue's memory if the writer gets stuck.
+    pub const FRAME_QUEUE_LENGTH: usize = 8192;
+    /// How often written records are flushed to disk
+    pub const FLUSH_PERIOD: Duration = Duration::from_secs(1);
+    /// Niceness of the writer thread, so it only gets the CPU the control loops don't use
+    pub const NICENESS: i32 = 19;
+}
+
 pub mod superstructure {
     // TODO find
     pub const GATE1: i32 = 8;
@@ -62,8 +254,17 @@ pub mod superstructure {
         pub const SLAVE_TALON1: i32 = 4;
         pub const SLAVE_TALON2: i32 = 6;
         pub const LIMIT_SWITCH: i32 = 9;
+
+        /// Amps allowed before the master talon starts limiting
+        pub const PEAK_CURRENT: i32 = 35;
+        /// Amps allowed once the peak has lasted `PEAK_CURRENT_DURATION_MS`
+        pub const CONTINUOUS_CURRENT: i32 = 20;
+        pub const PEAK_CURRENT_DURATION_MS: i32 = 200;
     }
 
+    /// Analog input channel of the pressure sensor
+    pub const PRESSURE_SENSOR: i32 = 3;
+
     // TODO ids
     pub const INTAKE_SOLENOID: i32 = 1;
     pub const CHANNEL_TALON: i32 = 9;
@@ -75,8 +276,56 @@ pub mod superstructure {
         // COMPETITION
         pub const EXTEND_PNEUMATICS_ID: i32 = 6;
         pub const OUTTAKE_PNEUMATICS_ID: i32 = 7;
-        // PRACTICE
-        // pub const EXTEND_PNEUMATICS_ID: i32 = 7;
-        // pub const OUTTAKE_PNEUMATICS_ID: i32 = 6;
+
+        /// The practice robot has the hatch solenoids swapped
+        pub mod practice {
+            pub const EXTEND_PNEUMATICS_ID: i32 = 7;
+            pub const OUTTAKE_PNEUMATICS_ID: i32 = 6;
+        }
     }
 }
+
+/// Autonomous routines, see `autonomous`.
+pub mod autonomous {
+    use super::*;
+
+    /// Top speed driving a distance
+    pub const DRIVE_SPEED: MeterPerSecond<f64> = const_unit!(1.5);
+    /// Speed per meter left to drive, so the robot slows into the end
+    pub const DRIVE_KP: Hertz<f64> = const_unit!(2.0);
+    /// Difference in wheel speeds per radian off the heading driving started on
+    pub const DRIVE_HEADING_KP: MeterPerSecond<f64> = const_unit!(1.0);
+    pub const DRIVE_TOLERANCE: Meter<f64> = const_unit!(0.05);
+
+    /// Top wheel speed turning in place
+    pub const TURN_SPEED: MeterPerSecond<f64> = const_unit!(1.0);
+    /// Wheel speed per radian left to turn
+    pub const TURN_KP: MeterPerSecond<f64> = const_unit!(1.5);
+    /// Radians
+    pub const TURN_TOLERANCE: f64 = 0.05;
+
+    /// An action that stops the robot is done once it's slower than this
+    pub const SETTLED_SPEED: MeterPerSecond<f64> = const_unit!(0.1);
+    /// Radians per second, see `SETTLED_SPEED`
+    pub const SETTLED_TURN_RATE: f64 = 0.1;
+
+    /// Limits planned trajectories are timed to, see `autonomous::trajectory`. The top speed is
+    /// of the outside wheel, so it also slows the robot through tight turns.
+    pub const TRAJECTORY_MAX_SPEED: MeterPerSecond<f64> = const_unit!(2.0);
+    pub const TRAJECTORY_MAX_ACCELERATION: MeterPerSecond2<f64> = const_unit!(2.0);
+    /// Keeps the robot from sliding or tipping on curves
+    pub const TRAJECTORY_MAX_CENTRIPETAL: MeterPerSecond2<f64> = const_unit!(1.5);
+
+    /// Ramsete gains, see `autonomous::ramsete`. B is in rad^2/m^2, larger converges harder;
+    /// ZETA is the damping ratio, between 0 and 1.
+    pub const RAMSETE_B: f64 = 2.0;
+    pub const RAMSETE_ZETA: f64 = 0.7;
+
+    /// The routine's chooser on the dashboard
+    pub const CHOOSER: &str = "/SmartDashboard/Autonomous";
+    /// Run when nothing was chosen
+    pub const DEFAULT_ROUTINE: &str = "Cross the line";
+    /// Moving either stick further than this cancels the routine and hands the robot to the
+    /// driver
+    pub const TAKEOVER_DEADBAND: f64 = 0.1;
+}
@@ -0,0 +1,617 @@
+//! Per-robot configuration, read from a TOML file on the rio at startup.
+//!
+//! Everything a robot could be wired differently has a default from the consts in `config`, so the
+//! file only needs the values that differ. The file is layered: the selected profile's defaults,
+//! then the top level sections, then the section for the selected profile.
+//!
+//! ```toml
+//! # overridden by the ROBOT_PROFILE environment variable
+//! profile = "practice"
+//!
+//! [drive]
+//! peak_current = "60 A"
+//! peak_current_duration = "0.2 s"
+//!
+//! [practice.superstructure.hatch]
+//! extend_solenoid = 7
+//! ```
+//!
+//! Currents and durations can be bare numbers (amps and milliseconds) or strings with a unit.
+use super::{drive, superstructure};
+use ctre_elevator_tuning::CurrentLimits;
+use serde::de::{self, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml::value::{Table, Value};
+
+/// Where the config file lives on the rio. A missing file means all defaults.
+pub const CONFIG_PATH: &str = "/home/lvuser/robot.toml";
+/// Environment variable naming the profile, which beats the one in the file
+pub const PROFILE_VAR: &str = "ROBOT_PROFILE";
+
+/// Highest device ID on the CAN bus
+const MAX_CAN_ID: i32 = 62;
+/// Channels on the PCM
+const MAX_SOLENOID: i32 = 7;
+/// Onboard DIO plus the MXP
+const MAX_DIO: i32 = 25;
+/// Onboard analog inputs plus the MXP
+const MAX_ANALOG: i32 = 7;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Profile {
+    Competition,
+    Practice,
+}
+
+impl Profile {
+    const ALL: [Profile; 2] = [Profile::Competition, Profile::Practice];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Competition => "competition",
+            Profile::Practice => "practice",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, ConfigError> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|profile| profile.name() == name.trim())
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))
+    }
+
+    /// The profile named by [`PROFILE_VAR`], if it's set.
+    pub fn from_env() -> Result<Option<Self>, ConfigError> {
+        std::env::var(PROFILE_VAR)
+            .ok()
+            .map(|name| Self::from_name(&name))
+            .transpose()
+    }
+
+    /// The robot as wired, with no config file.
+    pub fn defaults(self) -> RobotConfig {
+        let config = RobotConfig::default();
+        match self {
+            Profile::Competition => config,
+            Profile::Practice => RobotConfig {
+                superstructure: SuperstructureConfig {
+                    hatch: HatchConfig {
+                        extend_solenoid: superstructure::hatch::practice::EXTEND_PNEUMATICS_ID,
+                        outtake_solenoid: superstructure::hatch::practice::OUTTAKE_PNEUMATICS_ID,
+                    },
+                    ..config.superstructure
+                },
+                ..config
+            },
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Competition
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    UnknownProfile(String),
+    /// Two devices given the same ID on one bus
+    Collision {
+        bus: &'static str,
+        id: i32,
+        devices: (&'static str, &'static str),
+    },
+    OutOfRange {
+        bus: &'static str,
+        device: &'static str,
+        id: i32,
+    },
+    /// Current limits that can't be applied as given
+    CurrentLimit(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Could not read robot config: {}", e),
+            ConfigError::Toml(e) => write!(f, "Bad robot config: {}", e),
+            ConfigError::UnknownProfile(name) => write!(f, "No robot profile named {:?}", name),
+            ConfigError::Collision { bus, id, devices } => {
+                write!(f, "{} and {} are both {} {}", devices.0, devices.1, bus, id)
+            }
+            ConfigError::OutOfRange { bus, device, id } => {
+                write!(f, "{} has no {} {}", device, bus, id)
+            }
+            ConfigError::CurrentLimit(e) => write!(f, "Bad current limits: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RobotConfig {
+    pub drive: DriveConfig,
+    pub superstructure: SuperstructureConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveConfig {
+    pub left_master: i32,
+    pub left_slave: i32,
+    pub right_master: i32,
+    pub right_slave: i32,
+    /// Amps allowed before the talons start limiting
+    #[serde(deserialize_with = "amps")]
+    pub peak_current: i32,
+    /// Amps allowed once the peak has lasted `peak_current_duration`
+    #[serde(deserialize_with = "amps")]
+    pub continuous_current: i32,
+    #[serde(deserialize_with = "millis")]
+    pub peak_current_duration: i32,
+    pub shifter_solenoid: i32,
+    /// Solenoid output for high gear
+    pub high_gear: bool,
+}
+
+impl Default for DriveConfig {
+    fn default() -> Self {
+        Self {
+            left_master: drive::LEFT_MASTER,
+            left_slave: drive::LEFT_SLAVE,
+            right_master: drive::RIGHT_MASTER,
+            right_slave: drive::RIGHT_SLAVE,
+            peak_current: drive::CURRENT_LIMIT_THRESHOLD,
+            continuous_current: drive::CURRENT_LIMIT,
+            peak_current_duration: drive::CURRENT_LIMIT_DURATION_MS,
+            shifter_solenoid: drive::shifter::SOLENOID_CHANNEL,
+            high_gear: drive::shifter::HIGH_GEAR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuperstructureConfig {
+    /// Channel beam breaks
+    pub gates: [i32; 3],
+    pub intake_solenoid: i32,
+    pub channel_talon: i32,
+    pub outtake_talon: i32,
+    pub climb_solenoid: i32,
+    /// Analog input
+    pub pressure_sensor: i32,
+    pub elevator: ElevatorConfig,
+    pub hatch: HatchConfig,
+}
+
+impl Default for SuperstructureConfig {
+    fn default() -> Self {
+        Self {
+            gates: [
+                superstructure::GATE1,
+                superstructure::GATE2,
+                superstructure::GATE3,
+            ],
+            intake_solenoid: superstructure::INTAKE_SOLENOID,
+            channel_talon: superstructure::CHANNEL_TALON,
+            outtake_talon: superstructure::OUTTAKE_TALON,
+            climb_solenoid: superstructure::CLIMB_SOLENOID,
+            pressure_sensor: superstructure::PRESSURE_SENSOR,
+            elevator: ElevatorConfig::default(),
+            hatch: HatchConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ElevatorConfig {
+    pub master_talon: i32,
+    pub slave_talons: [i32; 2],
+    pub limit_switch: i32,
+    /// Amps allowed before the master talon starts limiting
+    #[serde(deserialize_with = "amps")]
+    pub peak_current: i32,
+    /// Amps allowed once the peak has lasted `peak_current_duration`
+    #[serde(deserialize_with = "amps")]
+    pub continuous_current: i32,
+    #[serde(deserialize_with = "millis")]
+    pub peak_current_duration: i32,
+}
+
+impl Default for ElevatorConfig {
+    fn default() -> Self {
+        use superstructure::elevator::*;
+        Self {
+            master_talon: MASTER_TALON,
+            slave_talons: [SLAVE_TALON1, SLAVE_TALON2],
+            limit_switch: LIMIT_SWITCH,
+            peak_current: PEAK_CURRENT,
+            continuous_current: CONTINUOUS_CURRENT,
+            peak_current_duration: PEAK_CURRENT_DURATION_MS,
+        }
+    }
+}
+
+impl ElevatorConfig {
+    pub fn current_limits(&self) -> CurrentLimits {
+        CurrentLimits {
+            peak: self.peak_current,
+            continuous: self.continuous_current,
+            peak_duration: self.peak_current_duration,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HatchConfig {
+    pub extend_solenoid: i32,
+    pub outtake_solenoid: i32,
+}
+
+impl Default for HatchConfig {
+    fn default() -> Self {
+        Self {
+            extend_solenoid: superstructure::hatch::EXTEND_PNEUMATICS_ID,
+            outtake_solenoid: superstructure::hatch::OUTTAKE_PNEUMATICS_ID,
+        }
+    }
+}
+
+impl RobotConfig {
+    /// Reads [`CONFIG_PATH`] if it exists, for the profile from the environment or the file.
+    pub fn load() -> Result<(Profile, Self), ConfigError> {
+        let profile = Profile::from_env()?;
+        if !Path::new(CONFIG_PATH).exists() {
+            let profile = profile.unwrap_or_default();
+            return Ok((profile, profile.defaults()));
+        }
+        Self::from_toml(&fs::read_to_string(CONFIG_PATH)?, profile)
+    }
+
+    /// Parses and validates a config file. `profile` overrides the one named in the file.
+    pub fn from_toml(text: &str, profile: Option<Profile>) -> Result<(Profile, Self), ConfigError> {
+        let mut file: Table = toml::from_str(text)?;
+        let named = match file.remove("profile") {
+            Some(Value::String(name)) => Some(Profile::from_name(&name)?),
+            Some(other) => return Err(ConfigError::UnknownProfile(other.to_string())),
+            None => None,
+        };
+        let profile = profile.or(named).unwrap_or_default();
+        let mut sections = HashMap::new();
+        for &p in &Profile::ALL {
+            if let Some(section) = file.remove(p.name()) {
+                sections.insert(p, section);
+            }
+        }
+
+        // the defaults are plain data, so serializing them can't fail
+        let mut config = Value::try_from(profile.defaults()).expect("Unserializable defaults");
+        merge(&mut config, Value::Table(file));
+        if let Some(section) = sections.remove(&profile) {
+            merge(&mut config, section);
+        }
+        let config: Self = config.try_into()?;
+        config.validate()?;
+        Ok((profile, config))
+    }
+
+    /// Checks that every ID and channel exists and is only used once per bus, and the current
+    /// limits are consistent.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let drive = &self.drive;
+        let sstruct = &self.superstructure;
+        let elevator = &sstruct.elevator;
+        check_bus(
+            "CAN ID",
+            MAX_CAN_ID,
+            &[
+                ("drive.left_master", drive.left_master),
+                ("drive.left_slave", drive.left_slave),
+                ("drive.right_master", drive.right_master),
+                ("drive.right_slave", drive.right_slave),
+                ("superstructure.channel_talon", sstruct.channel_talon),
+                ("superstructure.outtake_talon", sstruct.outtake_talon),
+                (
+                    "superstructure.elevator.master_talon",
+                    elevator.master_talon,
+                ),
+                (
+                    "superstructure.elevator.slave_talons[0]",
+                    elevator.slave_talons[0],
+                ),
+                (
+                    "superstructure.elevator.slave_talons[1]",
+                    elevator.slave_talons[1],
+                ),
+            ],
+        )?;
+        check_bus(
+            "solenoid channel",
+            MAX_SOLENOID,
+            &[
+                ("drive.shifter_solenoid", drive.shifter_solenoid),
+                ("superstructure.intake_solenoid", sstruct.intake_solenoid),
+                ("superstructure.climb_solenoid", sstruct.climb_solenoid),
+                (
+                    "superstructure.hatch.extend_solenoid",
+                    sstruct.hatch.extend_solenoid,
+                ),
+                (
+                    "superstructure.hatch.outtake_solenoid",
+                    sstruct.hatch.outtake_solenoid,
+                ),
+            ],
+        )?;
+        check_bus(
+            "DIO channel",
+            MAX_DIO,
+            &[
+                ("superstructure.gates[0]", sstruct.gates[0]),
+                ("superstructure.gates[1]", sstruct.gates[1]),
+                ("superstructure.gates[2]", sstruct.gates[2]),
+                (
+                    "superstructure.elevator.limit_switch",
+                    elevator.limit_switch,
+                ),
+            ],
+        )?;
+        check_bus(
+            "analog input",
+            MAX_ANALOG,
+            &[("superstructure.pressure_sensor", sstruct.pressure_sensor)],
+        )?;
+
+        if drive.continuous_current <= 0 {
+            return Err(ConfigError::CurrentLimit(
+                "drive.continuous_current must be positive",
+            ));
+        }
+        if drive.peak_current < drive.continuous_current {
+            return Err(ConfigError::CurrentLimit(
+                "drive.peak_current is below drive.continuous_current",
+            ));
+        }
+        if drive.peak_current_duration < 0 {
+            return Err(ConfigError::CurrentLimit(
+                "drive.peak_current_duration is negative",
+            ));
+        }
+        if elevator.continuous_current <= 0 {
+            return Err(ConfigError::CurrentLimit(
+                "superstructure.elevator.continuous_current must be positive",
+            ));
+        }
+        if elevator.peak_current < elevator.continuous_current {
+            return Err(ConfigError::CurrentLimit(
+                "superstructure.elevator.peak_current is below its continuous_current",
+            ));
+        }
+        if elevator.peak_current_duration < 0 {
+            return Err(ConfigError::CurrentLimit(
+                "superstructure.elevator.peak_current_duration is negative",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn check_bus(
+    bus: &'static str,
+    max: i32,
+    devices: &[(&'static str, i32)],
+) -> Result<(), ConfigError> {
+    for (i, &(device, id)) in devices.iter().enumerate() {
+        if id < 0 || id > max {
+            return Err(ConfigError::OutOfRange { bus, device, id });
+        }
+        if let Some(&(other, _)) = devices[..i].iter().find(|&&(_, other)| other == id) {
+            return Err(ConfigError::Collision {
+                bus,
+                id,
+                devices: (other, device),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Overwrites `base` with `overrides`, key by key through nested tables.
+fn merge(base: &mut Value, overrides: Value) {
+    match (base.as_table_mut(), overrides) {
+        (Some(base), Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (_, overrides) => *base = overrides,
+    }
+}
+
+/// A bare number in the field's unit, or a string with an explicit unit.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(f64),
+    Text(String),
+}
+
+/// Reads a `Quantity` as a whole number of the first of `units`, which are scale factors to it.
+fn quantity<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    units: &[(&str, f64)],
+) -> Result<i32, D::Error> {
+    let value = match serde::Deserialize::deserialize(deserializer)? {
+        Quantity::Number(value) => value,
+        Quantity::Text(text) => {
+            let split = text
+                .find(|c: char| c.is_alphabetic())
+                .unwrap_or_else(|| text.len());
+            let (number, unit) = text.split_at(split);
+            let number: f64 = number.trim().parse().map_err(|_| {
+                de::Error::custom(format!("{:?} doesn't start with a number", text))
+            })?;
+            let scale = units
+                .iter()
+                .find(|&&(name, _)| name == unit.trim())
+                .map(|&(_, scale)| scale)
+                .ok_or_else(|| {
+                    let names: Vec<_> = units.iter().map(|&(name, _)| name).collect();
+                    de::Error::custom(format!("{:?} isn't in {}", text, names.join(" or ")))
+                })?;
+            number * scale
+        }
+    };
+    let rounded = value.round();
+    if (value - rounded).abs() > 1e-9 || rounded.abs() > f64::from(i32::max_value()) {
+        return Err(de::Error::custom(format!(
+            "{} {} isn't a whole number",
+            value, units[0].0
+        )));
+    }
+    Ok(rounded as i32)
+}
+
+fn amps<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    quantity(deserializer, &[("A", 1.0)])
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    quantity(deserializer, &[("ms", 1.0), ("s", 1000.0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        for &profile in &Profile::ALL {
+            profile.defaults().validate().unwrap();
+        }
+        let practice = Profile::Practice.defaults().superstructure.hatch;
+        let competition = Profile::Competition.defaults().superstructure.hatch;
+        assert_eq!(practice.extend_solenoid, competition.outtake_solenoid);
+        assert_eq!(practice.outtake_solenoid, competition.extend_solenoid);
+    }
+
+    #[test]
+    fn empty_file_is_defaults() {
+        let (profile, config) = RobotConfig::from_toml("", None).unwrap();
+        assert_eq!(profile, Profile::Competition);
+        assert_eq!(config, RobotConfig::default());
+    }
+
+    #[test]
+    fn layers_profile_sections() {
+        let text = r#"
+            profile = "practice"
+            [drive]
+            left_master = 20
+            [practice.drive]
+            right_master = 21
+            [competition.drive]
+            right_master = 22
+        "#;
+        let (profile, config) = RobotConfig::from_toml(text, None).unwrap();
+        assert_eq!(profile, Profile::Practice);
+        assert_eq!(config.drive.left_master, 20);
+        assert_eq!(config.drive.right_master, 21);
+        // untouched values keep the profile's defaults
+        assert_eq!(
+            config.superstructure,
+            Profile::Practice.defaults().superstructure
+        );
+
+        let (profile, config) = RobotConfig::from_toml(text, Some(Profile::Competition)).unwrap();
+        assert_eq!(profile, Profile::Competition);
+        assert_eq!(config.drive.right_master, 22);
+        assert_eq!(config.superstructure, SuperstructureConfig::default());
+    }
+
+    #[test]
+    fn reads_units() {
+        let text = r#"
+            [drive]
+            peak_current = "50 A"
+            continuous_current = 40
+            peak_current_duration = "0.25 s"
+        "#;
+        let (_, config) = RobotConfig::from_toml(text, None).unwrap();
+        assert_eq!(config.drive.peak_current, 50);
+        assert_eq!(config.drive.continuous_current, 40);
+        assert_eq!(config.drive.peak_current_duration, 250);
+
+        let wrong_unit = "[drive]\npeak_current = \"50 ms\"";
+        assert!(RobotConfig::from_toml(wrong_unit, None).is_err());
+        let fractional = "[drive]\npeak_current_duration = \"0.0001 s\"";
+        assert!(RobotConfig::from_toml(fractional, None).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let collision = "[drive]\nleft_slave = 12";
+        match RobotConfig::from_toml(collision, None) {
+            Err(ConfigError::Collision { id: 12, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        let out_of_range = "[superstructure.hatch]\nextend_solenoid = 8";
+        match RobotConfig::from_toml(out_of_range, None) {
+            Err(ConfigError::OutOfRange { id: 8, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        for limits in &[
+            "[drive]\npeak_current = 30",
+            "[superstructure.elevator]\npeak_current = \"15 A\"",
+        ] {
+            match RobotConfig::from_toml(limits, None) {
+                Err(ConfigError::CurrentLimit(_)) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+        let pressure_sensor = "[superstructure]\npressure_sensor = 8";
+        match RobotConfig::from_toml(pressure_sensor, None) {
+            Err(ConfigError::OutOfRange { id: 8, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        // typos shouldn't be silently ignored
+        assert!(RobotConfig::from_toml("[drive]\nleft_mastr = 1", None).is_err());
+        assert!(RobotConfig::from_toml("profile = \"pratice\"", None).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+//! The 2019 robot. `main.rs` wires the subsystems together on the RIO; the `replay` binary reruns
+//! their logic on a laptop from a recorded match.
+#[macro_use]
+extern crate debug_stub_derive;
+
+pub mod autonomous;
+pub mod cheesy_drive;
+pub mod config;
+pub mod replay;
+pub mod subsystems;
+
+pub trait OkPrint<T> {
+    fn print(self) -> T;
+    fn ok_print(self);
+}
+
+impl<T, E: std::fmt::Debug> OkPrint<Result<T, E>> for Result<T, E> {
+    fn print(self) -> Self {
+        match self {
+            Err(ref e) => println!("Err at {}:{}:{}: {:?}", file!(), line!(), column!(), e),
+            Ok(_) => (),
+        }
+        self
+    }
+
+    fn ok_print(self) {
+        self.print().ok();
+    }
+}
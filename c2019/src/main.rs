@@ -1,44 +1,33 @@
-#[macro_use]
-extern crate debug_stub_derive;
-
-mod cheesy_drive;
-mod config;
-mod subsystems;
-
-use crate::subsystems::Subsystem;
+use c2019::autonomous::Autonomous;
+use c2019::config::{
+    self,
+    robot::{Profile, RobotConfig},
+};
+use c2019::subsystems::{self, Subsystem};
+use c2019::OkPrint;
 use wpilib::ds::*;
 use wpilib::RobotBase;
 
-use bus::Bus;
 use crossbeam_channel::unbounded;
+use ctre::motor_control::{MotorController, TalonSRX};
+use hardware::record::Tape;
+use networktables::Table;
 use std::env;
 use std::thread;
+use subsystems::controller::recording::{ControlsTape, RecordedControls};
 use subsystems::controller::*;
+use subsystems::dashboard::Dashboard;
 use subsystems::drive::*;
 use subsystems::superstructure::*;
+use subsystems::supervisor::{Supervised, Supervisor};
+use subsystems::telemetry::{Log, Telemetry};
+use subsystems::tuning::Tuning;
+use subsystems::vision::{TargetPublisher, Vision};
+use tunables::Registry;
 
 #[macro_use]
 extern crate std;
 
-pub trait OkPrint<T> {
-    fn print(self) -> T;
-    fn ok_print(self);
-}
-
-impl<T, E: std::fmt::Debug> OkPrint<Result<T, E>> for Result<T, E> {
-    fn print(self) -> Self {
-        match self {
-            Err(ref e) => println!("Err at {}:{}:{}: {:?}", file!(), line!(), column!(), e),
-            Ok(_) => (),
-        }
-        self
-    }
-
-    fn ok_print(self) {
-        self.print().ok();
-    }
-}
-
 pub mod built_info {
     // The file has been placed there by the build script.
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -55,42 +44,149 @@ fn main() {
         built_info::OPT_LEVEL
     );
 
-    let bus = Bus::new(0);
+    let (profile, config) = RobotConfig::load().unwrap_or_else(|e| {
+        // the wiring has to come from somewhere, and the profile's defaults are known good
+        let profile = Profile::from_env().ok().and_then(|p| p).unwrap_or_default();
+        println!("{}, using the {} defaults", e, profile);
+        (profile, profile.defaults())
+    });
+    println!("Robot profile: {}\nConfig: {:#?}", profile, config);
+    let RobotConfig {
+        drive: drive_config,
+        superstructure: sstruct_config,
+    } = config;
+
+    let tunables = Registry::new();
+    tunables.load(config::tuning::SAVE_PATH).ok_print();
+
+    let dashboard = Table::new();
+    let (telemetry, log) = match Telemetry::new() {
+        Ok((telemetry, log)) => (Some(telemetry.with_dashboard(dashboard.clone())), log),
+        Err(e) => {
+            println!("Could not start telemetry: {}", e);
+            (None, Log::discard())
+        }
+    };
+    let mut supervisor = Supervisor::new(log.clone());
+    if let Some(telemetry) = telemetry {
+        supervisor = supervisor.supervise(Supervised::once("Telemetry", telemetry));
+    }
+
+    let poses = PosePublisher::new();
+    let autonomous = Autonomous::new().with_dashboard(dashboard.clone());
+    let pose_handle = poses.handle();
+    let targets = TargetPublisher::new();
+    let target_handle = targets.handle();
     let (drive_send, drive_recv) = unbounded();
     let (super_send, super_recv) = unbounded();
+    let (feedback_send, feedback_recv) = unbounded();
     let base = RobotBase::new().unwrap();
 
     // let comp = wpilib::pneumatics::Compressor::new().unwrap();
     // comp.stop();
 
-    thread::Builder::new()
-        .name("Drive".to_string())
-        .spawn(move || {
-            let drive = Drive::new(bus, drive_recv);
-            println!("drive: {:#?}", drive);
-            drive.run();
-        })
-        .unwrap();
+    let drive_talons = [drive_config.left_master, drive_config.right_master];
+    let drive_tunables = tunables.clone();
+    let drive_log = log.clone();
+    let drive_targets = target_handle.clone();
+    let drive = Supervised::looping("Drive", move |heartbeat| {
+        // anything sent while it was down is stale
+        drive_recv.try_iter().for_each(drop);
+        let drive = Drive::new(
+            &drive_config,
+            poses.clone(),
+            drive_recv.clone(),
+            drive_log.clone(),
+        )
+        .with_tuning(&drive_tunables)
+        .with_vision(drive_targets.clone())
+        .with_heartbeat(heartbeat);
+        println!("drive: {:#?}", drive);
+        drive.run();
+    })
+    .with_safe(move || neutral(&drive_talons));
+
+    let sstruct_talons = [
+        sstruct_config.channel_talon,
+        sstruct_config.outtake_talon,
+        sstruct_config.elevator.master_talon,
+    ];
+    let sstruct_tunables = tunables.clone();
+    let sstruct_log = log.clone();
+    let sstruct = Supervised::looping("SStruct", move |heartbeat| {
+        super_recv.try_iter().for_each(drop);
+        let tape = Tape::new();
+        let hardware = SuperstructureHardware::new(&sstruct_config)
+            .unwrap()
+            .recorded(&tape);
+        let sstruct = Superstructure::from_hardware(hardware, super_recv.clone())
+            .unwrap()
+            .with_tuning(&sstruct_tunables)
+            .with_log(sstruct_log.clone())
+            .with_feedback(feedback_send.clone())
+            .with_recording(tape)
+            .with_heartbeat(heartbeat);
+        println!("sstruct: {:#?}", sstruct);
+        sstruct.run();
+    })
+    .with_safe(move || neutral(&sstruct_talons));
+    supervisor = supervisor.supervise(drive).supervise(sstruct);
+
+    match Tuning::new(tunables.clone()) {
+        Ok(tuning) => {
+            let tuning = tuning.with_log(log.clone());
+            supervisor = supervisor.supervise(Supervised::once("Tuning", tuning))
+        }
+        Err(e) => println!("Could not start the tuning server: {}", e),
+    }
+
+    match Vision::new(targets) {
+        Ok(vision) => {
+            let vision = vision.with_log(log.clone());
+            supervisor = supervisor.supervise(Supervised::once("Vision", vision))
+        }
+        Err(e) => println!("Could not start vision: {}", e),
+    }
+
+    match Dashboard::new(dashboard) {
+        Ok(dashboard) => {
+            let dashboard = dashboard.with_log(log.clone());
+            supervisor = supervisor.supervise(Supervised::once("Dashboard", dashboard))
+        }
+        Err(e) => println!("Could not start the dashboard server: {}", e),
+    }
 
     thread::Builder::new()
-        .name("SStruct".to_string())
-        .spawn(move || {
-            // TODO log
-            let sstruct = Superstructure::new(super_recv).unwrap();
-            println!("sstruct: {:#?}", sstruct);
-            sstruct.run();
-        })
+        .name("Supervisor".to_string())
+        .spawn(move || supervisor.run())
         .unwrap();
 
     let lj = JoystickPort::new(0).unwrap();
     let rj = JoystickPort::new(1).unwrap();
     let oi = JoystickPort::new(2).unwrap();
-    let controls = StandardControls::new(base.make_ds(), lj, rj, oi).unwrap();
+    let controls_tape = ControlsTape::new();
+    let controls = RecordedControls::new(
+        StandardControls::new(base.make_ds(), lj, rj, oi).unwrap(),
+        &controls_tape,
+    );
 
     RobotBase::start_competition();
 
     // NOTE: All new control bindings or functions should be added in subsystems/controller/mod.rs
-    let controller = Controller::new(controls, drive_send, super_send, base.make_ds());
+    let controller = Controller::new(controls, drive_send, super_send, base.make_ds())
+        .with_tuning(&tunables)
+        .with_log(log)
+        .with_feedback(feedback_recv)
+        .with_recording(controls_tape)
+        .with_autonomous(autonomous, pose_handle)
+        .with_vision(target_handle);
     println!("controller: {:#?}", controller);
     controller.run();
 }
+
+/// Stops the talons of a subsystem that died or stalled, which might have left them running.
+fn neutral(talons: &[i32]) {
+    for &id in talons {
+        TalonSRX::new(id).neutral_output();
+    }
+}
@@ -0,0 +1,396 @@
+//! Rerunning the robot's logic from a match log, to reproduce what happened on the field.
+//!
+//! The controller and superstructure each log a [`Frame`] every loop with everything their logic
+//! read: the driver's controls and robot state, the commands received, and the hardware's
+//! readings (see `hardware::record`). Replaying builds the same logic on fake hardware, feeds each
+//! frame back in, and compares what it does with what the robot did. A mismatch means the code
+//! changed since the match, or something wasn't recorded.
+//!
+//! Every so often a subsystem's frame also carries a snapshot of its logic. Replaying restores it,
+//! so a log that's lost its start, or frames in the middle, picks up again from there.
+//!
+//! Tuned values aren't in the log, so replay against the robot's saved tunables, and expect
+//! mismatches after anything was tuned mid-match.
+use crate::subsystems::controller::{
+    recording::{ControllerFrame, ControlsTape, ReplayedControls},
+    Bindings,
+};
+use crate::subsystems::drive::Instruction as DriveCmd;
+use crate::subsystems::superstructure::{
+    Instruction as SsCmd, Superstructure, SuperstructureFrame, SuperstructureHardware,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use hardware::fake::Fake;
+use hardware::record::{Playback, Recording, Tape};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use tunables::Registry;
+
+/// One loop of one subsystem, from the match log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    Controller(ControllerFrame),
+    Superstructure(SuperstructureFrame),
+}
+
+impl Frame {
+    /// Which subsystem logged it.
+    pub fn subsystem(&self) -> &'static str {
+        match self {
+            Frame::Controller(_) => "controller",
+            Frame::Superstructure(_) => "superstructure",
+        }
+    }
+
+    /// Whether it has a snapshot to replay from.
+    pub fn is_keyframe(&self) -> bool {
+        match self {
+            Frame::Controller(frame) => frame.snapshot.is_some(),
+            Frame::Superstructure(frame) => frame.snapshot.is_some(),
+        }
+    }
+
+    /// Whether the robot was enabled, for frames that know.
+    pub fn enabled(&self) -> Option<bool> {
+        match self {
+            Frame::Controller(frame) => Some(frame.enabled()),
+            Frame::Superstructure(_) => None,
+        }
+    }
+}
+
+/// A loop where the replay did something different from the robot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// Seconds since the robot code started
+    pub time: f64,
+    pub subsystem: &'static str,
+    /// What the robot did, and what the replay did, as printed by `Debug`
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}s {}:\n  robot:  {}\n  replay: {}",
+            self.time, self.subsystem, self.expected, self.actual
+        )
+    }
+}
+
+/// The superstructure on fakes named like the robot's recorded hardware.
+#[derive(Debug)]
+pub struct SuperstructureReplay {
+    superstructure: Superstructure<Recording<Fake>>,
+    playback: Playback,
+    tape: Tape,
+    commands: Sender<SsCmd>,
+}
+
+impl SuperstructureReplay {
+    pub fn new(registry: &Registry) -> Self {
+        let tape = Tape::new();
+        let mut playback = Playback::new();
+        let hardware = SuperstructureHardware::replaying(&mut playback, &tape);
+        let (commands, receiver) = unbounded();
+        let superstructure = Superstructure::from_hardware(hardware, receiver)
+            .expect("Fake hardware failed")
+            .with_tuning(registry);
+        Self {
+            superstructure,
+            playback,
+            tape,
+            commands,
+        }
+    }
+
+    /// Reruns one loop, returning the outputs if they differ from the recording's.
+    pub fn step(&mut self, frame: &SuperstructureFrame) -> Option<Mismatch> {
+        if let Some(snapshot) = &frame.snapshot {
+            self.superstructure.restore(snapshot);
+            // anything sent before picking up, like setting up the solenoids, was long done
+            self.tape.take();
+        }
+        self.playback.apply(&frame.hardware);
+        for &command in &frame.commands {
+            self.commands.send(command).unwrap();
+        }
+        self.superstructure.iterate(frame.dt);
+        let outputs = self.tape.take().outputs;
+        if outputs == frame.hardware.outputs {
+            None
+        } else {
+            Some(Mismatch {
+                time: 0.0,
+                subsystem: "superstructure",
+                expected: format!("{:?}", frame.hardware.outputs),
+                actual: format!("{:?}", outputs),
+            })
+        }
+    }
+}
+
+/// The controller's bindings, reading the recorded controls.
+#[derive(Debug)]
+pub struct ControllerReplay {
+    bindings: Bindings<ReplayedControls>,
+    controls: ControlsTape,
+    drive: Receiver<DriveCmd>,
+    superstructure: Receiver<SsCmd>,
+}
+
+impl ControllerReplay {
+    pub fn new(registry: &Registry) -> Self {
+        let controls = ControlsTape::new();
+        let (drive_send, drive) = unbounded();
+        let (superstructure_send, superstructure) = unbounded();
+        let bindings = Bindings::new(
+            ReplayedControls::new(&controls),
+            drive_send,
+            superstructure_send,
+        )
+        .with_tuning(registry);
+        Self {
+            bindings,
+            controls,
+            drive,
+            superstructure,
+        }
+    }
+
+    /// Reruns one loop, returning the commands sent if they differ from the recording's. Loops
+    /// while disabled didn't run the bindings, so there's nothing to rerun.
+    pub fn step(&mut self, frame: &ControllerFrame) -> Option<Mismatch> {
+        if let Some(snapshot) = &frame.snapshot {
+            self.bindings.restore(snapshot);
+        }
+        let controls = frame.controls?;
+        self.controls.set(controls);
+        self.bindings.aim(frame.aim);
+        self.bindings.iterate();
+        // only what was sent matters, and the receivers have to outlive the bindings
+        self.drive.try_iter().for_each(drop);
+        self.superstructure.try_iter().for_each(drop);
+        let sent = self.bindings.sent();
+        let expected = (frame.drive.clone(), frame.superstructure.clone());
+        if sent == expected {
+            None
+        } else {
+            Some(Mismatch {
+                time: 0.0,
+                subsystem: "controller",
+                expected: format!("{:?}", expected),
+                actual: format!("{:?}", sent),
+            })
+        }
+    }
+}
+
+/// Reruns a match log, returning every loop that came out differently. Each subsystem's frames
+/// should start from the robot code starting, or from a snapshot.
+pub fn replay(frames: &[(f64, Frame)], registry: &Registry) -> Vec<Mismatch> {
+    let mut controller = ControllerReplay::new(registry);
+    let mut superstructure = SuperstructureReplay::new(registry);
+    frames
+        .iter()
+        .filter_map(|(time, frame)| {
+            let mismatch = match frame {
+                Frame::Controller(frame) => controller.step(frame),
+                Frame::Superstructure(frame) => superstructure.step(frame),
+            };
+            mismatch.map(|mismatch| Mismatch {
+                time: *time,
+                ..mismatch
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::telemetry::KEYFRAME_PERIOD;
+    use crate::subsystems::controller::assist::Aim;
+    use crate::subsystems::controller::recording::{ControlsFrame, RecordedControls};
+    use crate::subsystems::superstructure::UserElevatorHeights;
+    use crate::subsystems::telemetry::{queue, Log, Payload};
+    use ctre_elevator_tuning::Elevator;
+    use hardware::fake::{FakeAnalogInput, FakeDigitalInput, FakeSolenoid, FakeTalon};
+    use hardware::record::Output;
+    use std::time::Duration;
+
+    fn frames(receiver: &Receiver<crate::subsystems::telemetry::Entry>) -> Vec<(f64, Frame)> {
+        receiver
+            .try_iter()
+            .filter_map(|entry| match entry.payload {
+                Payload::Frame(_, frame) => Some((entry.time.as_secs_f64(), frame)),
+                Payload::Record(_) => None,
+            })
+            .collect()
+    }
+
+    /// A superstructure logging frames to `log`, with its command queue, first gate and elevator
+    /// master talon.
+    fn recorded_superstructure(
+        log: Log,
+    ) -> (
+        Superstructure<Recording<Fake>>,
+        Sender<SsCmd>,
+        FakeDigitalInput,
+        FakeTalon,
+    ) {
+        let tape = Tape::new();
+        let gate = FakeDigitalInput::new(true);
+        let elevator_master = FakeTalon::new();
+        let hardware = SuperstructureHardware::<Fake> {
+            intake_talon: FakeTalon::new(),
+            outtake_talon: FakeTalon::new(),
+            intake_solenoid: FakeSolenoid::new(),
+            climb_solenoid: FakeSolenoid::new(),
+            hatch_extend: FakeSolenoid::new(),
+            hatch_outtake: FakeSolenoid::new(),
+            gates: (
+                gate.clone(),
+                FakeDigitalInput::new(true),
+                FakeDigitalInput::new(true),
+            ),
+            pressure: FakeAnalogInput::new(2.5),
+            elevator: Elevator::from_hardware(
+                elevator_master.clone(),
+                FakeTalon::new(),
+                FakeTalon::new(),
+                FakeDigitalInput::new(false),
+            ),
+        };
+        let (commands, receiver) = unbounded();
+        let robot = Superstructure::from_hardware(hardware.recorded(&tape), receiver)
+            .unwrap()
+            .with_log(log)
+            .with_recording(tape);
+        (robot, commands, gate, elevator_master)
+    }
+
+    #[test]
+    fn replays_superstructure() {
+        let (log, receiver) = queue(4096, 4096);
+        let (mut robot, commands, gate, elevator_master) = recorded_superstructure(log);
+        commands.send(SsCmd::BallIntake(true)).unwrap();
+        commands
+            .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Med))
+            .unwrap();
+        for i in 0..50 {
+            gate.set(i < 20);
+            elevator_master.state().position = i * 100;
+            // zeroing depends on the time between loops
+            robot.iterate(Duration::from_millis(4 + (i % 3) as u64));
+        }
+
+        let mut frames = frames(&receiver);
+        assert_eq!(frames.len(), 50);
+        let registry = Registry::new();
+        assert_eq!(replay(&frames, &registry), Vec::new());
+
+        match &mut frames[30].1 {
+            Frame::Superstructure(frame) => frame.hardware.outputs.push((
+                "superstructure/climb_solenoid".to_string(),
+                Output::Solenoid(true),
+            )),
+            frame => panic!("Not a superstructure frame: {:?}", frame),
+        }
+        let mismatches = replay(&frames, &registry);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].subsystem, "superstructure");
+        assert_eq!(mismatches[0].time.to_bits(), frames[30].0.to_bits());
+    }
+
+    #[test]
+    fn replays_from_a_snapshot() {
+        let (log, receiver) = queue(4096, 4096);
+        let (mut robot, commands, gate, elevator_master) = recorded_superstructure(log);
+        let period = KEYFRAME_PERIOD as i32;
+        commands.send(SsCmd::BallIntake(true)).unwrap();
+        for i in 0..2 * period {
+            // a ball comes in, and heads up with the elevator across the snapshot
+            gate.set(i < 100 || i >= 150);
+            if i == period - 10 {
+                commands
+                    .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Med))
+                    .unwrap();
+            }
+            elevator_master.state().position = (i * 37) % 20000;
+            robot.iterate(Duration::from_millis(5));
+        }
+
+        // as if everything before the first snapshot was deleted
+        let frames = frames(&receiver);
+        let start = frames
+            .iter()
+            .position(|(_, frame)| frame.is_keyframe())
+            .unwrap();
+        assert_eq!(start, KEYFRAME_PERIOD as usize - 1);
+        let registry = Registry::new();
+        assert_eq!(replay(&frames[start..], &registry), Vec::new());
+
+        // starting from scratch instead goes its own way
+        let mut unsnapped = frames[start..].to_vec();
+        if let Frame::Superstructure(frame) = &mut unsnapped[0].1 {
+            frame.snapshot = None;
+        }
+        assert!(!replay(&unsnapped, &registry).is_empty());
+    }
+
+    #[test]
+    fn replays_controller() {
+        let driver = ControlsTape::new();
+        let controls_tape = ControlsTape::new();
+        let (drive_send, _drive) = unbounded();
+        let (superstructure_send, _superstructure) = unbounded();
+        let mut robot = Bindings::new(
+            RecordedControls::new(ReplayedControls::new(&driver), &controls_tape),
+            drive_send,
+            superstructure_send,
+        );
+
+        let mut frames = Vec::new();
+        for i in 0..20 {
+            driver.set(ControlsFrame {
+                throttle: 0.5,
+                wheel: -0.25,
+                ball_intake: i % 4 == 0,
+                elevator_high: i == 7,
+                vision_assist: i >= 10,
+                ..ControlsFrame::default()
+            });
+            // the target comes and goes
+            let aim = if i % 3 == 0 {
+                None
+            } else {
+                Some(Aim {
+                    error: f64::from(i) * 0.01,
+                    turn_rate: 0.0,
+                })
+            };
+            robot.aim(aim);
+            robot.iterate();
+            let (drive, superstructure) = robot.sent();
+            let frame = ControllerFrame {
+                robot_state: "Teleop".to_string(),
+                controls: Some(controls_tape.get()),
+                drive,
+                superstructure,
+                aim,
+                snapshot: None,
+            };
+            frames.push((f64::from(i) * 0.02, Frame::Controller(frame)));
+        }
+
+        let registry = Registry::new();
+        assert_eq!(replay(&frames, &registry), Vec::new());
+        if let Frame::Controller(frame) = &mut frames[7].1 {
+            frame.superstructure.clear();
+        }
+        assert_eq!(replay(&frames, &registry).len(), 1);
+    }
+}
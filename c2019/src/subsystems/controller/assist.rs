@@ -0,0 +1,200 @@
+//! Lining up on a vision target while the driver holds the assist.
+//!
+//! A target is located from where the robot was when the frame was captured, not where it is
+//! when the target arrives, so turning toward it doesn't overshoot by however far the robot
+//! turned in the meantime. The turn takes the wheel's place, while the driver keeps the throttle.
+//! It skips Cheesy Drive, whose shaping of the wheel would distort it.
+use crate::cheesy_drive::DriveSignal;
+use crate::config::vision::*;
+use crate::subsystems::drive::Pose;
+use crate::subsystems::vision::camera::Location;
+use controls::units::*;
+use controls::util::clamp;
+use interp_map::pose::wrap_angle;
+use serde_derive::{Deserialize, Serialize};
+
+/// Which way to turn to face the target. Recorded in the match log, since it depends on more
+/// than the controls.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aim {
+    /// Radians, counterclockwise from where the robot faces now
+    pub error: f64,
+    /// Radians per second the robot is turning, counterclockwise
+    pub turn_rate: f64,
+}
+
+impl Aim {
+    /// Aims at a target at `location` from the robot at `then`, for the robot at `now`.
+    pub fn new(location: &Location, then: &Pose, now: &Pose) -> Self {
+        let (x, y) = then.locate(location);
+        let bearing = (*((y - now.y) / M)).atan2(*((x - now.x) / M));
+        Self {
+            error: wrap_angle(bearing - now.heading),
+            turn_rate: *(now.angular_velocity * S),
+        }
+    }
+}
+
+/// Drives at `throttle`, turning toward `aim`.
+pub fn steer(throttle: f64, aim: &Aim) -> DriveSignal {
+    let turn = clamp(
+        ASSIST_KP * aim.error - ASSIST_KD * aim.turn_rate,
+        -ASSIST_MAX_TURN,
+        ASSIST_MAX_TURN,
+    );
+    DriveSignal {
+        l: clamp(throttle - turn, -1.0, 1.0),
+        r: clamp(throttle + turn, -1.0, 1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::recording::{ControlsFrame, ControlsTape, ReplayedControls};
+    use super::super::Bindings;
+    use super::*;
+    use crate::subsystems::drive::sim::{DrivePlant, DriveShim, DriveState};
+    use crate::subsystems::drive::Instruction as DriveCmd;
+    use controls::SimulationHarness;
+    use crossbeam_channel::unbounded;
+    use std::collections::VecDeque;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn pose(x: f64, y: f64, heading: f64) -> Pose {
+        let mut pose = Pose::origin();
+        pose.x = x * M;
+        pose.y = y * M;
+        pose.heading = heading;
+        pose
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn aims_from_where_the_target_was_seen() {
+        let seen = Location {
+            bearing: 0.5,
+            range: 2.0 * M,
+        };
+        let then = pose(0.0, 0.0, 0.0);
+        assert_close(Aim::new(&seen, &then, &then).error, 0.5);
+        // turned most of the way since
+        assert_close(Aim::new(&seen, &then, &pose(0.0, 0.0, 0.4)).error, 0.1);
+        // drove up level with it
+        let level = pose(2.0 * 0.5f64.cos(), 0.0, 0.0);
+        assert_close(Aim::new(&seen, &then, &level).error, FRAC_PI_2);
+    }
+
+    #[test]
+    fn turns_toward_the_target() {
+        let aim = Aim {
+            error: 0.1,
+            turn_rate: 0.0,
+        };
+        let signal = steer(0.3, &aim);
+        assert!(signal.r > signal.l);
+        assert_close(signal.l + signal.r, 0.6);
+        // slows the turn as it gets there
+        let turning = steer(
+            0.3,
+            &Aim {
+                turn_rate: 1.0,
+                ..aim
+            },
+        );
+        assert!(turning.r - turning.l < signal.r - signal.l);
+        let far = steer(
+            0.0,
+            &Aim {
+                error: 3.0,
+                turn_rate: 0.0,
+            },
+        );
+        assert_close(far.r, ASSIST_MAX_TURN);
+    }
+
+    fn held(throttle: f64, wheel: f64, vision_assist: bool) -> ControlsFrame {
+        ControlsFrame {
+            throttle,
+            wheel,
+            vision_assist,
+            ..ControlsFrame::default()
+        }
+    }
+
+    #[test]
+    fn only_while_held_and_seeing_a_target() {
+        let tape = ControlsTape::new();
+        let (drive_send, drive) = unbounded();
+        let mut bindings = Bindings::new(ReplayedControls::new(&tape), drive_send, unbounded().0);
+        let aim = Aim {
+            error: 0.2,
+            turn_rate: 0.0,
+        };
+        let mut sent = |frame, aim| {
+            tape.set(frame);
+            bindings.aim(aim);
+            bindings.iterate();
+            format!("{:?}", drive.try_iter().last().unwrap())
+        };
+
+        let manual = sent(held(0.5, 0.0, false), Some(aim));
+        assert_eq!(manual, format!("{:?}", DriveCmd::Percentage(0.5, 0.5)));
+        let signal = steer(0.5, &aim);
+        assert_eq!(
+            sent(held(0.5, 0.0, true), Some(aim)),
+            format!("{:?}", DriveCmd::Percentage(signal.l, signal.r))
+        );
+        // lost the target
+        assert_eq!(sent(held(0.5, 0.0, true), None), manual);
+    }
+
+    /// Where the TX1 would see a target at `(x, y)` on the field from `pose`.
+    fn look(pose: &Pose, x: f64, y: f64) -> Location {
+        let (dx, dy) = (x - *(pose.x / M), y - *(pose.y / M));
+        Location {
+            bearing: dy.atan2(dx) - pose.heading,
+            range: dx.hypot(dy) * M,
+        }
+    }
+
+    #[test]
+    fn lines_up_despite_latency() {
+        let tape = ControlsTape::new();
+        tape.set(held(0.0, 0.0, true));
+        let (drive_send, drive) = unbounded();
+        let mut bindings = Bindings::new(ReplayedControls::new(&tape), drive_send, unbounded().0);
+        let mut harness =
+            SimulationHarness::<DrivePlant, _>::new(DriveShim::new(), DriveState::at_rest(), 100);
+        let target = (3.0f64, 1.5f64);
+        let facing = target.1.atan2(target.0);
+
+        // frames arrive 100ms after they're captured
+        let mut poses: VecDeque<Pose> = VecDeque::new();
+        let mut worst_overshoot = 0.0f64;
+        let mut state = DriveState::at_rest();
+        for _ in 0..100 {
+            let now = harness.shim().pose();
+            poses.push_back(now);
+            if poses.len() > 5 {
+                poses.pop_front();
+            }
+            let then = poses.front().unwrap();
+            bindings.aim(Some(Aim::new(&look(then, target.0, target.1), then, &now)));
+            bindings.iterate();
+            for cmd in drive.try_iter() {
+                harness.shim_mut().execute(cmd);
+            }
+            state = harness.run_time(0.02 * S);
+            worst_overshoot = worst_overshoot.max(state.heading - facing);
+        }
+        assert!((state.heading - facing).abs() < 0.03, "{:?}", state);
+        assert!(
+            worst_overshoot < 0.05,
+            "overshot by up to {}",
+            worst_overshoot
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use serde_derive::{Deserialize, Serialize};
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Edge {
     Falling,
@@ -35,7 +37,7 @@ impl Edge {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct EdgeDetector {
     last_value: bool,
 }
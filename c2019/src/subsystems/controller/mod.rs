@@ -1,27 +1,238 @@
+pub mod assist;
 pub mod edge_detect;
+pub mod recording;
 #[allow(dead_code)]
 pub mod xbox;
 
 use super::{
-    drive::{Gear, Instruction as DriveCmd},
-    superstructure, Subsystem,
+    drive::{pose_service::PoseHandle, Gear, Instruction as DriveCmd, Pose},
+    superstructure,
+    telemetry::Log,
+    vision::{Target, TargetHandle},
+    Subsystem,
 };
-use crate::cheesy_drive::CheesyDrive;
-use crossbeam_channel::Sender;
-use superstructure::{HatchPneumaticExt, Instruction as SsCmd, UserElevatorHeights};
+use crate::autonomous::{Autonomous, Robot};
+use crate::cheesy_drive::{CheesyDrive, Gains};
+use crate::config::autonomous::TAKEOVER_DEADBAND;
+use crate::config::telemetry::KEYFRAME_PERIOD;
+use crate::replay::Frame;
+use assist::Aim;
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use recording::{BindingsSnapshot, ControllerFrame, ControlsTape};
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::time::Instant;
+use superstructure::{
+    Feedback, HatchPneumaticExt, Instruction as SsCmd, Status, UserElevatorHeights,
+};
+use tunables::Registry;
 use wpilib::ds::*;
 
 type Drive = Sender<DriveCmd>;
 type Superstructure = Sender<SsCmd>;
 
-#[allow(dead_code)]
+/// A subsystem's command queue, remembering what was sent for the match log.
+///
+/// If the subsystem's thread dies, its end of the queue goes with it. The robot carries on
+/// without it, and the rest of its commands are dropped.
 #[derive(Debug)]
-pub struct Controller<'a, T: Controls> {
+struct Outbox<C> {
+    name: &'static str,
+    sender: Sender<C>,
+    sent: RefCell<Vec<String>>,
+    link: Cell<Link>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Link {
+    Up,
+    /// Found down, and not logged yet
+    Lost,
+    Down,
+}
+
+impl<C: Debug> Outbox<C> {
+    fn new(name: &'static str, sender: Sender<C>) -> Self {
+        Self {
+            name,
+            sender,
+            sent: RefCell::new(Vec::new()),
+            link: Cell::new(Link::Up),
+        }
+    }
+
+    fn send(&self, cmd: C) {
+        // still recorded, so replays match what the driver asked for
+        self.sent.borrow_mut().push(format!("{:?}", cmd));
+        if self.is_up() && self.sender.send(cmd).is_err() {
+            self.lose();
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.link.get() == Link::Up
+    }
+
+    fn lose(&self) {
+        if self.is_up() {
+            self.link.set(Link::Lost);
+        }
+    }
+
+    /// Logs the subsystem going down, once.
+    fn report(&self, log: &Log) {
+        if self.link.get() == Link::Lost {
+            log.event(
+                "controller",
+                format!("{} stopped, no longer sending it commands", self.name),
+            );
+            self.link.set(Link::Down);
+        }
+    }
+
+    fn clear(&self) {
+        self.sent.borrow_mut().clear();
+    }
+
+    fn sent(&self) -> Vec<String> {
+        self.sent.borrow().clone()
+    }
+}
+
+/// Turns the driver's controls into commands for the other subsystems, one loop at a time. Kept
+/// apart from the [`Controller`] so it can run without a driver station, when replaying.
+#[derive(Debug)]
+pub struct Bindings<T: Controls> {
     controls: EdgeWrapper<T>,
     cheesy: CheesyDrive,
-    drive: Drive,
-    superstructure: Superstructure,
+    drive: Outbox<DriveCmd>,
+    superstructure: Outbox<SsCmd>,
+    /// How the superstructure's commands went
+    feedback: Option<Receiver<Feedback>>,
+    /// What the superstructure last said it was doing
+    status: Option<Status>,
+    /// Where the vision assist turns, if it has a target
+    aim: Option<Aim>,
+    /// Whether the vision assist turned last loop
+    assisted: bool,
+    log: Log,
+}
+
+impl<T: Controls> Bindings<T> {
+    pub fn new(controls: T, drive: Drive, superstructure: Superstructure) -> Self {
+        Self {
+            controls: EdgeWrapper::new(controls),
+            cheesy: CheesyDrive::new(),
+            drive: Outbox::new("drive", drive),
+            superstructure: Outbox::new("superstructure", superstructure),
+            feedback: None,
+            status: None,
+            aim: None,
+            assisted: false,
+            log: Log::discard(),
+        }
+    }
+
+    /// Makes the Cheesy Drive constants live tunable.
+    pub fn with_tuning(self, registry: &Registry) -> Self {
+        Self {
+            cheesy: self.cheesy.with_gains(Gains::register(registry)),
+            ..self
+        }
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+
+    /// Hears from the superstructure on `feedback`.
+    pub fn with_feedback(self, feedback: Receiver<Feedback>) -> Self {
+        Self {
+            feedback: Some(feedback),
+            ..self
+        }
+    }
+
+    /// Sets where the vision assist turns until it's next set, or that it has no target.
+    pub fn aim(&mut self, aim: Option<Aim>) {
+        self.aim = aim;
+    }
+
+    /// The superstructure's status, as of its last loop, or None if it hasn't said or is down.
+    pub fn superstructure_status(&self) -> Option<&Status> {
+        self.status.as_ref()
+    }
+
+    /// Whether the drive and the superstructure are still running.
+    pub fn alive(&self) -> (bool, bool) {
+        (self.drive.is_up(), self.superstructure.is_up())
+    }
+
+    /// Whether the driver is moving either stick, wanting the robot back from autonomous.
+    pub fn taking_over(&mut self) -> bool {
+        let throttle = self.controls.throttle();
+        let wheel = self.controls.wheel();
+        throttle.abs() > TAKEOVER_DEADBAND || wheel.abs() > TAKEOVER_DEADBAND
+    }
+
+    /// Commands sent to the drive and the superstructure by the last loop.
+    pub fn sent(&self) -> (Vec<String>, Vec<String>) {
+        (self.drive.sent(), self.superstructure.sent())
+    }
+
+    pub fn snapshot(&self) -> BindingsSnapshot {
+        BindingsSnapshot {
+            edges: self.controls.detectors(),
+            cheesy: self.cheesy.snapshot(),
+            assisted: self.assisted,
+        }
+    }
+
+    /// Picks up from a snapshot, as if the loops before it had run here.
+    pub fn restore(&mut self, snapshot: &BindingsSnapshot) {
+        self.controls.restore(&snapshot.edges);
+        self.cheesy.restore(&snapshot.cheesy);
+        self.assisted = snapshot.assisted;
+    }
+
+    /// Runs a loop of something other than the driver, like an autonomous routine, and sends
+    /// the commands it gives.
+    pub fn act(
+        &mut self,
+        pose: Option<Pose>,
+        target: Option<Target>,
+        now: Instant,
+        act: impl FnOnce(&mut Robot),
+    ) {
+        self.drive.clear();
+        self.superstructure.clear();
+        let heard = self.hear();
+        let mut robot = Robot::new(now, pose, self.status.as_ref(), &heard, &self.log);
+        robot.target = target;
+        act(&mut robot);
+        let (drive, superstructure) = robot.commands();
+        for cmd in drive {
+            self.drive.send(cmd);
+        }
+        for cmd in superstructure {
+            self.superstructure.send(cmd);
+        }
+        self.drive.report(&self.log);
+        self.superstructure.report(&self.log);
+    }
+}
+
+#[derive(Debug)]
+pub struct Controller<'a, T: Controls> {
+    bindings: Bindings<T>,
     ds: DriverStation<'a>,
+    /// What `controls` read, if they're recorded
+    tape: Option<ControlsTape>,
+    autonomous: Autonomous,
+    /// Where the drive thinks the robot is, for autonomous and the vision assist
+    poses: Option<PoseHandle>,
+    /// What the TX1 sees
+    targets: Option<TargetHandle>,
 }
 
 impl<'a, T: Controls> Controller<'a, T> {
@@ -32,126 +243,287 @@ impl<'a, T: Controls> Controller<'a, T> {
         ds: DriverStation<'a>,
     ) -> Self {
         Self {
-            controls: EdgeWrapper::new(controls),
-            cheesy: CheesyDrive::new(),
-            drive,
-            superstructure,
+            bindings: Bindings::new(controls, drive, superstructure),
             ds,
+            tape: None,
+            autonomous: Autonomous::new(),
+            poses: None,
+            targets: None,
+        }
+    }
+
+    /// Makes the Cheesy Drive constants live tunable.
+    pub fn with_tuning(self, registry: &Registry) -> Self {
+        Self {
+            bindings: self.bindings.with_tuning(registry),
+            ..self
+        }
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self {
+            bindings: self.bindings.with_log(log),
+            ..self
+        }
+    }
+
+    pub fn with_feedback(self, feedback: Receiver<Feedback>) -> Self {
+        Self {
+            bindings: self.bindings.with_feedback(feedback),
+            ..self
+        }
+    }
+
+    /// Logs a frame for the replay tool every loop. The controls should be
+    /// `recording::RecordedControls` on the same tape.
+    pub fn with_recording(self, tape: ControlsTape) -> Self {
+        Self {
+            tape: Some(tape),
+            ..self
+        }
+    }
+
+    /// Runs routines from `autonomous` in autonomous, driving by the poses from `poses`.
+    pub fn with_autonomous(self, autonomous: Autonomous, poses: PoseHandle) -> Self {
+        Self {
+            autonomous,
+            poses: Some(poses),
+            ..self
+        }
+    }
+
+    /// Lets autonomous and the vision assist see targets from `targets`. The assist also needs
+    /// the poses from `with_autonomous`.
+    pub fn with_vision(self, targets: TargetHandle) -> Self {
+        Self {
+            targets: Some(targets),
+            ..self
         }
     }
+
+    /// Where the vision assist should turn, locating the latest target from where the robot was
+    /// when its frame was captured.
+    fn aim(&self) -> Option<Aim> {
+        let poses = self.poses.as_ref()?;
+        let target = self.targets.as_ref()?.fresh(Instant::now())?;
+        let then = poses.at(target.captured)?;
+        let now = poses.latest()?.pose;
+        Some(Aim::new(&target.location, &then, &now))
+    }
+
+    /// Runs a loop of the driver's bindings, with the vision assist.
+    fn drive(&mut self) {
+        let aim = self.aim();
+        self.bindings.aim(aim);
+        self.bindings.iterate();
+    }
+
+    fn act(&mut self, act: impl FnOnce(&mut Autonomous, &mut Robot)) {
+        let now = Instant::now();
+        let pose = self
+            .poses
+            .as_ref()
+            .and_then(PoseHandle::latest)
+            .map(|timed| timed.pose);
+        let target = self.targets.as_ref().and_then(|targets| targets.fresh(now));
+        let autonomous = &mut self.autonomous;
+        self.bindings
+            .act(pose, target, now, |robot| act(autonomous, robot));
+    }
 }
 
 impl<'a, T: Controls> Subsystem for Controller<'a, T> {
     fn run(mut self) {
+        let mut last = RobotState::Disabled;
+        let mut loops = 0u32;
         loop {
             self.ds.wait_for_data();
-            match self.ds.robot_state() {
-                RobotState::Disabled => {
-                    continue;
+            loops += 1;
+            let snapshot = if self.tape.is_some() && loops % KEYFRAME_PERIOD == 0 {
+                Some(self.bindings.snapshot())
+            } else {
+                None
+            };
+            let state = self.ds.robot_state();
+            if state == RobotState::Autonomous && last != RobotState::Autonomous {
+                self.autonomous.begin(&self.bindings.log);
+            }
+            let driven = match state {
+                RobotState::Disabled => false,
+                RobotState::Autonomous => {
+                    if self.autonomous.is_running() && self.bindings.taking_over() {
+                        self.bindings
+                            .log
+                            .event("controller", "driver took over from autonomous");
+                        self.act(|autonomous, robot| autonomous.end(robot));
+                    }
+                    if self.autonomous.is_running() {
+                        self.act(|autonomous, robot| autonomous.iterate(robot));
+                        false
+                    } else {
+                        // the routine's done, or there wasn't one, so the driver has it
+                        self.drive();
+                        true
+                    }
+                }
+                _ => {
+                    if last == RobotState::Autonomous {
+                        self.act(|autonomous, robot| autonomous.end(robot));
+                    }
+                    self.drive();
+                    true
                 }
-                _ => {}
+            };
+            if state == RobotState::Disabled && last == RobotState::Autonomous {
+                self.act(|autonomous, robot| autonomous.end(robot));
             }
+            last = state;
+            if let Some(tape) = &self.tape {
+                let (drive, superstructure) = if state == RobotState::Disabled {
+                    Default::default()
+                } else {
+                    self.bindings.sent()
+                };
+                // loops run by an autonomous routine have no controls to replay
+                self.bindings.log.frame(Frame::Controller(ControllerFrame {
+                    robot_state: format!("{:?}", state),
+                    controls: if driven { Some(tape.get()) } else { None },
+                    drive,
+                    superstructure,
+                    aim: if driven { self.bindings.aim } else { None },
+                    snapshot,
+                }));
+            }
+        }
+    }
+}
 
-            // TODO add logging to all this
-
-            // DRIVE
-            let wheel = self.controls.wheel();
-            let throttle = self.controls.throttle();
-            let quick_turn = self.controls.quick_turn_raw();
-            let high_gear = self.controls.high_gear_raw();
-            // TODO user input
-            let signal = self
-                .cheesy
-                .cheesy_drive(throttle, wheel, quick_turn, high_gear);
-            self.drive
-                .send(DriveCmd::Percentage(signal.l, signal.r))
-                .expect("DT disconnected: ");
-            // TODO log
-            self.controls.high_gear().sig_send_val(
-                DriveCmd::GearShift(Gear::High),
-                DriveCmd::GearShift(Gear::Low),
-                |cmd| {
-                    self.drive.send(cmd).expect("DT disconnected");
-                },
-            );
-
-            // SUPERSTRUCTURE
+impl<T: Controls> Bindings<T> {
+    /// Reads the controls once and sends whatever they ask for.
+    pub fn iterate(&mut self) {
+        self.drive.clear();
+        self.superstructure.clear();
+        self.hear();
 
-            // TODO log
-            if self.controls.abort_ball_intake().rising() {
-                self.superstructure
-                    .send(SsCmd::ForceAbortBall)
-                    .expect("SS disconnected");
-            }
-            self.controls.ball_intake().sig_send(|is_intk| {
-                println!("SENT {}", is_intk);
-                self.superstructure
-                    .send(SsCmd::BallIntake(is_intk))
-                    .expect("SS disconnected");
-            });
-            self.controls.outtake_ball().sig_send(|is_outk| {
-                self.superstructure
-                    .send(SsCmd::BallOuttake(is_outk))
-                    .expect("SS disconnected");
-            });
-            self.controls.ball_unjam().sig_send(|is_unjam| {
-                self.superstructure
-                    .send(SsCmd::Unjam(is_unjam))
-                    .expect("SS disconnected");
-            });
-            self.controls.hatch_extend().sig_send_val(
-                SsCmd::HatchExtend(HatchPneumaticExt::Extended),
-                SsCmd::HatchExtend(HatchPneumaticExt::Retracted),
-                |cmd| {
-                    self.superstructure.send(cmd).expect("SS disconnected");
-                },
-            );
-            self.controls.hatch_outtake().sig_send_val(
-                SsCmd::HatchOuttake(HatchPneumaticExt::Extended),
-                SsCmd::HatchOuttake(HatchPneumaticExt::Retracted),
-                |cmd| {
-                    self.superstructure.send(cmd).expect("SS disconnected");
-                },
-            );
-            if self.controls.elevator_low().rising() {
-                self.superstructure
-                    .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Low))
-                    .expect("SS disconnected");
-            }
-            if self.controls.elevator_med().rising() {
-                self.superstructure
-                    .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Med))
-                    .expect("SS disconnected");
-            }
-            if self.controls.elevator_high().rising() {
-                self.superstructure
-                    .send(SsCmd::SetElevatorHeight(
-                        superstructure::UserElevatorHeights::High,
-                    ))
-                    .expect("SS disconnected");
-            }
-            if self.controls.elevator_cargo().rising() {
-                self.superstructure
-                    .send(SsCmd::SetElevatorHeight(
-                        superstructure::UserElevatorHeights::Cargo,
-                    ))
-                    .expect("SS disconnected");
-            }
-            self.controls.climb().sig_send(|is_climb_ext| {
-                println!("SENT {}", is_climb_ext);
-                self.superstructure
-                    .send(SsCmd::Climb(is_climb_ext))
-                    .expect("SS disconnected");
-            });
-            if self.controls.begin_elevator_panic().rising() {
-                self.superstructure
-                    .send(SsCmd::BeginElevatorPanic)
-                    .expect("SS disconnected");
+        // DRIVE
+        let wheel = self.controls.wheel();
+        let throttle = self.controls.throttle();
+        let quick_turn = self.controls.quick_turn_raw();
+        let high_gear = self.controls.high_gear_raw();
+        let assisting = self.controls.vision_assist_raw();
+        // TODO user input
+        let signal = match self.aim.filter(|_| assisting) {
+            Some(aim) => assist::steer(throttle, &aim),
+            None => {
+                if assisting && self.assisted {
+                    self.log
+                        .event("controller", "vision assist lost the target");
+                }
+                self.cheesy
+                    .cheesy_drive(throttle, wheel, quick_turn, high_gear)
             }
-            if self.controls.force_zero().rising() {
-                self.superstructure
-                    .send(SsCmd::ForceElevatorZero)
-                    .expect("SS disconnected");
+        };
+        self.assisted = assisting && self.aim.is_some();
+        self.drive.send(DriveCmd::Percentage(signal.l, signal.r));
+        self.controls.high_gear().sig_send_val(
+            DriveCmd::GearShift(Gear::High),
+            DriveCmd::GearShift(Gear::Low),
+            |cmd| {
+                self.log.event("controller", format!("{:?}", cmd));
+                self.drive.send(cmd);
+            },
+        );
+
+        // SUPERSTRUCTURE
+
+        if self.controls.abort_ball_intake().rising() {
+            self.log.event("controller", "abort ball intake");
+            self.superstructure.send(SsCmd::ForceAbortBall);
+        }
+        self.controls.ball_intake().sig_send(|is_intk| {
+            self.log
+                .event("controller", format!("ball intake {}", is_intk));
+            self.superstructure.send(SsCmd::BallIntake(is_intk));
+        });
+        self.controls.outtake_ball().sig_send(|is_outk| {
+            self.superstructure.send(SsCmd::BallOuttake(is_outk));
+        });
+        self.controls.ball_unjam().sig_send(|is_unjam| {
+            self.superstructure.send(SsCmd::Unjam(is_unjam));
+        });
+        self.controls.hatch_extend().sig_send_val(
+            SsCmd::HatchExtend(HatchPneumaticExt::Extended),
+            SsCmd::HatchExtend(HatchPneumaticExt::Retracted),
+            |cmd| {
+                self.superstructure.send(cmd);
+            },
+        );
+        self.controls.hatch_outtake().sig_send_val(
+            SsCmd::HatchOuttake(HatchPneumaticExt::Extended),
+            SsCmd::HatchOuttake(HatchPneumaticExt::Retracted),
+            |cmd| {
+                self.superstructure.send(cmd);
+            },
+        );
+        if self.controls.elevator_low().rising() {
+            self.superstructure
+                .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Low));
+        }
+        if self.controls.elevator_med().rising() {
+            self.superstructure
+                .send(SsCmd::SetElevatorHeight(UserElevatorHeights::Med));
+        }
+        if self.controls.elevator_high().rising() {
+            self.superstructure.send(SsCmd::SetElevatorHeight(
+                superstructure::UserElevatorHeights::High,
+            ));
+        }
+        if self.controls.elevator_cargo().rising() {
+            self.superstructure.send(SsCmd::SetElevatorHeight(
+                superstructure::UserElevatorHeights::Cargo,
+            ));
+        }
+        self.controls.climb().sig_send(|is_climb_ext| {
+            self.log
+                .event("controller", format!("climb {}", is_climb_ext));
+            self.superstructure.send(SsCmd::Climb(is_climb_ext));
+        });
+        if self.controls.begin_elevator_panic().rising() {
+            self.superstructure.send(SsCmd::BeginElevatorPanic);
+        }
+        if self.controls.force_zero().rising() {
+            self.superstructure.send(SsCmd::ForceElevatorZero);
+        }
+
+        self.drive.report(&self.log);
+        self.superstructure.report(&self.log);
+    }
+
+    /// Catches up on what the superstructure said since the last loop, returning all of it.
+    fn hear(&mut self) -> Vec<Feedback> {
+        let mut heard = Vec::new();
+        let feedback = match &self.feedback {
+            Some(feedback) => feedback,
+            None => return heard,
+        };
+        loop {
+            match feedback.try_recv() {
+                Ok(feedback) => {
+                    match &feedback {
+                        Feedback::Status(status) => self.status = Some(status.clone()),
+                        Feedback::Rejected(rejection) => self
+                            .log
+                            .event("controller", format!("superstructure {}", rejection)),
+                        Feedback::Accepted(_) | Feedback::Completed(_) => (),
+                    }
+                    heard.push(feedback);
+                }
+                Err(TryRecvError::Empty) => return heard,
+                Err(TryRecvError::Disconnected) => {
+                    self.superstructure.lose();
+                    self.status = None;
+                    return heard;
+                }
             }
         }
     }
@@ -184,6 +556,19 @@ macro_rules! wrapper_fields {
                 }
             )*
 
+            pub fn detectors(&self) -> Vec<EdgeDetector> {
+                vec![$(self.$x.clone(),)*]
+            }
+
+            pub fn restore(&mut self, detectors: &[EdgeDetector]) {
+                let mut detectors = detectors.iter();
+                $(
+                    if let Some(detector) = detectors.next() {
+                        self.$x = detector.clone();
+                    }
+                )*
+            }
+
             paste::item! {
                 $(
                     pub fn [<$x _raw>] (&mut self) -> bool {
@@ -216,7 +601,8 @@ mod _wrapper {
         elevator_cargo,
         climb,
         begin_elevator_panic,
-        force_zero
+        force_zero,
+        vision_assist
     }
 
     impl<T: Controls> EdgeWrapper<T> {
@@ -250,6 +636,8 @@ pub trait Controls {
     fn climb(&mut self) -> bool;
     fn begin_elevator_panic(&mut self) -> bool;
     fn force_zero(&mut self) -> bool;
+    /// Held to turn toward the vision target
+    fn vision_assist(&mut self) -> bool;
 }
 
 #[derive(Debug)]
@@ -335,6 +723,9 @@ impl<'a> Controls for StandardControls<'a> {
     fn force_zero(&mut self) -> bool {
         get_button(&self.ds, self.oi, 9)
     }
+    fn vision_assist(&mut self) -> bool {
+        get_button(&self.ds, self.right, 1)
+    }
 }
 fn get_button(ds: &DriverStation<'_>, port: JoystickPort, num: u8) -> bool {
     ds.stick_button(port, num).unwrap_or(false)
@@ -345,3 +736,97 @@ fn get_axis(ds: &DriverStation<'_>, port: JoystickPort, axis: JoystickAxis) -> f
 fn get_pov(ds: &DriverStation<'_>, port: JoystickPort, pov: JoystickPOV) -> i16 {
     ds.stick_pov(port, pov).unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::recording::{ControlsFrame, ControlsTape, ReplayedControls};
+    use super::*;
+    use crate::subsystems::superstructure::Mode;
+    use crate::subsystems::telemetry::{queue, Payload, Record};
+    use crossbeam_channel::unbounded;
+
+    #[test]
+    fn carries_on_without_a_dead_subsystem() {
+        let controls = ControlsTape::new();
+        let (drive_send, drive) = unbounded();
+        let (superstructure_send, superstructure) = unbounded();
+        let (feedback_send, feedback) = unbounded();
+        let (log, entries) = queue(64, 0);
+        let mut bindings = Bindings::new(
+            ReplayedControls::new(&controls),
+            drive_send,
+            superstructure_send,
+        )
+        .with_log(log)
+        .with_feedback(feedback);
+
+        let status = Status {
+            mode: Mode::Hatch,
+            goal: superstructure::GoalState::Ball(superstructure::BallGoalHeight::None),
+            balls: 0,
+            fault: None,
+        };
+        feedback_send
+            .send(Feedback::Status(status.clone()))
+            .unwrap();
+        bindings.iterate();
+        assert_eq!(bindings.superstructure_status(), Some(&status));
+        assert_eq!(bindings.alive(), (true, true));
+
+        // the superstructure's thread died
+        drop((superstructure, feedback_send));
+        for i in 0..3 {
+            controls.set(ControlsFrame {
+                ball_intake: i % 2 == 0,
+                ..ControlsFrame::default()
+            });
+            bindings.iterate();
+        }
+        assert_eq!(bindings.alive(), (true, false));
+        assert_eq!(bindings.superstructure_status(), None);
+        assert_eq!(drive.try_iter().count(), 4);
+        assert_eq!(
+            bindings.sent().1,
+            vec!["BallIntake(true)".to_string()],
+            "still recorded for the match log"
+        );
+
+        let events: Vec<String> = entries
+            .try_iter()
+            .filter_map(|entry| match entry.payload {
+                Payload::Record(Record::EventRecord(event)) => Some(event.message),
+                _ => None,
+            })
+            .filter(|message| message.contains("stopped"))
+            .collect();
+        assert_eq!(
+            events,
+            vec!["superstructure stopped, no longer sending it commands".to_string()]
+        );
+    }
+
+    #[test]
+    fn takes_over_on_the_sticks() {
+        let controls = ControlsTape::new();
+        let (drive_send, _drive) = unbounded();
+        let (superstructure_send, _superstructure) = unbounded();
+        let mut bindings = Bindings::new(
+            ReplayedControls::new(&controls),
+            drive_send,
+            superstructure_send,
+        );
+
+        controls.set(ControlsFrame {
+            throttle: TAKEOVER_DEADBAND / 2.0,
+            // buttons alone leave autonomous be
+            ball_intake: true,
+            ..ControlsFrame::default()
+        });
+        assert!(!bindings.taking_over());
+        controls.set(ControlsFrame {
+            wheel: -0.5,
+            ..ControlsFrame::default()
+        });
+        assert!(bindings.taking_over());
+    }
+}
@@ -0,0 +1,137 @@
+//! Recording the driver's controls for the match log, and playing them back.
+use super::assist::Aim;
+use super::edge_detect::EdgeDetector;
+use super::Controls;
+use crate::cheesy_drive::CheesySnapshot;
+use serde_derive::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Declares a frame with a field per control, and the [`Controls`] that record to and play back
+/// from it.
+macro_rules! controls_frame {
+    ($($control:ident: $ty:ty,)*) => {
+        /// Every control, as read in one loop. Controls missing from older logs weren't held.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct ControlsFrame {
+            $(pub $control: $ty,)*
+        }
+
+        impl<T: Controls> Controls for RecordedControls<T> {
+            $(
+                fn $control(&mut self) -> $ty {
+                    let value = self.inner.$control();
+                    self.tape.0.borrow_mut().$control = value;
+                    value
+                }
+            )*
+        }
+
+        impl Controls for ReplayedControls {
+            $(
+                fn $control(&mut self) -> $ty {
+                    self.0.get().$control
+                }
+            )*
+        }
+    };
+}
+
+controls_frame! {
+    throttle: f64,
+    wheel: f64,
+    high_gear: bool,
+    quick_turn: bool,
+    ball_intake: bool,
+    abort_ball_intake: bool,
+    outtake_ball: bool,
+    ball_unjam: bool,
+    hatch_extend: bool,
+    hatch_outtake: bool,
+    elevator_low: bool,
+    elevator_med: bool,
+    elevator_high: bool,
+    elevator_cargo: bool,
+    climb: bool,
+    begin_elevator_panic: bool,
+    force_zero: bool,
+    vision_assist: bool,
+}
+
+/// The latest value of every control. Clones share the same frame.
+#[derive(Debug, Clone, Default)]
+pub struct ControlsTape(Rc<RefCell<ControlsFrame>>);
+
+impl ControlsTape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> ControlsFrame {
+        *self.0.borrow()
+    }
+
+    pub fn set(&self, frame: ControlsFrame) {
+        *self.0.borrow_mut() = frame;
+    }
+}
+
+/// Controls that note every value they read on a tape.
+#[derive(Debug)]
+pub struct RecordedControls<T: Controls> {
+    inner: T,
+    tape: ControlsTape,
+}
+
+impl<T: Controls> RecordedControls<T> {
+    pub fn new(inner: T, tape: &ControlsTape) -> Self {
+        Self {
+            inner,
+            tape: tape.clone(),
+        }
+    }
+}
+
+/// Controls that read whatever frame was last put on the tape.
+#[derive(Debug)]
+pub struct ReplayedControls(ControlsTape);
+
+impl ReplayedControls {
+    pub fn new(tape: &ControlsTape) -> Self {
+        ReplayedControls(tape.clone())
+    }
+}
+
+/// One loop of the controller, for replaying.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControllerFrame {
+    pub robot_state: String,
+    /// `None` while disabled, when the controls aren't read
+    pub controls: Option<ControlsFrame>,
+    /// Commands sent to the drive and superstructure, as printed by `Debug`
+    pub drive: Vec<String>,
+    pub superstructure: Vec<String>,
+    /// What the vision assist would have turned toward, if it had a target
+    #[serde(default)]
+    pub aim: Option<Aim>,
+    /// Taken before the loop, every `KEYFRAME_PERIOD` loops
+    #[serde(default)]
+    pub snapshot: Option<BindingsSnapshot>,
+}
+
+/// The bindings between loops, for a replay to pick up from partway through a match log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindingsSnapshot {
+    /// Each button's edge detector, in the order `EdgeWrapper` declares them
+    pub(super) edges: Vec<EdgeDetector>,
+    pub(super) cheesy: CheesySnapshot,
+    pub(super) assisted: bool,
+}
+
+impl ControllerFrame {
+    /// Whether the robot was running, rather than disabled or e-stopped.
+    pub fn enabled(&self) -> bool {
+        self.robot_state != "Disabled" && self.robot_state != "EStop"
+    }
+}
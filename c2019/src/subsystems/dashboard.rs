@@ -0,0 +1,160 @@
+//! Shows the robot's state on Shuffleboard or Glass, over NetworkTables.
+//!
+//! Every telemetry record is published as `/SmartDashboard/<kind>/<field>`, by the telemetry
+//! thread so the control loops never wait on a dashboard. Health records go under
+//! `/SmartDashboard/health/<subsystem>`, and the drive's pose and the vision target it located also
+//! go in a `Field2d` widget at `/SmartDashboard/Field`.
+use super::telemetry::{Log, Record};
+use super::Subsystem;
+use crate::config::dashboard::*;
+use networktables::server::{Server, PORT};
+use networktables::Table;
+use std::io;
+use std::net::TcpListener;
+
+/// Puts a record's fields in the table.
+pub fn publish(table: &Table, record: &Record) {
+    // every subsystem's health gets its own entries
+    let path = match record {
+        Record::HealthRecord(health) => format!("{}/{}/{}", TABLE, record.kind(), health.subsystem),
+        _ => format!("{}/{}", TABLE, record.kind()),
+    };
+    for (field, value) in record.fields() {
+        table.set(&format!("{}/{}", path, field), value);
+    }
+    if let Record::DriveRecord(drive) = record {
+        table.set(&format!("{}/Field/.type", TABLE), "Field2d");
+        let robot = vec![drive.x_m, drive.y_m, drive.heading_rad.to_degrees()];
+        table.set(&format!("{}/Field/Robot", TABLE), robot);
+        // an empty pose hides the target
+        let target = if drive.target_x_m.is_nan() {
+            vec![]
+        } else {
+            vec![drive.target_x_m, drive.target_y_m, 0.0]
+        };
+        table.set(&format!("{}/Field/Target", TABLE), target);
+    }
+}
+
+#[derive(Debug)]
+pub struct Dashboard {
+    server: Server,
+    listener: TcpListener,
+    log: Log,
+}
+
+impl Dashboard {
+    pub fn new(table: Table) -> io::Result<Self> {
+        Ok(Self {
+            server: Server::new(table, IDENTITY, UPDATE_PERIOD),
+            listener: TcpListener::bind(("0.0.0.0", PORT))?,
+            log: Log::discard(),
+        })
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+}
+
+impl Subsystem for Dashboard {
+    fn run(self) {
+        let log = self.log.clone();
+        let dropped = move |message| log.event("dashboard", message);
+        if let Err(e) = self.server.serve(self.listener, dropped) {
+            self.log
+                .event("dashboard", format!("Dashboard server stopped: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::telemetry::{DriveRecord, HealthRecord, SuperstructureRecord};
+    use networktables::Value;
+
+    #[test]
+    fn publishes_records() {
+        let table = Table::new();
+        let drive = DriveRecord {
+            x_m: 1.5,
+            y_m: -0.5,
+            heading_rad: std::f64::consts::PI,
+            velocity_mps: 0.0,
+            angular_velocity_radps: 0.0,
+            high_gear: true,
+            mode: "velocity",
+            left_demand: 0.0,
+            right_demand: 0.0,
+            target_x_m: 3.0,
+            target_y_m: 1.0,
+        };
+        publish(&table, &drive.clone().into());
+        let superstructure = SuperstructureRecord {
+            elevator_state: "Holding".to_string(),
+            elevator_goal_m: 0.5,
+            mode: "Loading".to_string(),
+            channel: "Queued".to_string(),
+            balls: 2,
+            intake_pct: 0.0,
+            outtake_pct: 0.0,
+            intake_extended: false,
+            pressure_psi: 110.5,
+        };
+        publish(&table, &superstructure.into());
+
+        assert_eq!(
+            table.get("/SmartDashboard/drive/high_gear"),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/drive/mode"),
+            Some(Value::String("velocity".to_string()))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/Field/Robot"),
+            Some(Value::DoubleArray(vec![1.5, -0.5, 180.0]))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/Field/Target"),
+            Some(Value::DoubleArray(vec![3.0, 1.0, 0.0]))
+        );
+        let lost = DriveRecord {
+            target_x_m: std::f64::NAN,
+            target_y_m: std::f64::NAN,
+            ..drive
+        };
+        publish(&table, &lost.into());
+        assert_eq!(
+            table.get("/SmartDashboard/Field/Target"),
+            Some(Value::DoubleArray(vec![]))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/superstructure/channel"),
+            Some(Value::String("Queued".to_string()))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/superstructure/balls"),
+            Some(Value::Double(2.0))
+        );
+        assert_eq!(
+            table.get("/SmartDashboard/superstructure/pressure_psi"),
+            Some(Value::Double(110.5))
+        );
+
+        let health = HealthRecord {
+            subsystem: "Drive",
+            state: "Running".to_string(),
+            restarts: 1,
+            overruns: 0,
+            worst_jitter_ms: 0.5,
+            mean_jitter_ms: 0.1,
+        };
+        publish(&table, &health.into());
+        assert_eq!(
+            table.get("/SmartDashboard/health/Drive/restarts"),
+            Some(Value::Double(1.0))
+        );
+    }
+}
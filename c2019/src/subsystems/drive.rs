@@ -1,32 +1,50 @@
-use std::thread;
+use std::time::Instant;
 
-use bus::Bus;
 use crossbeam_channel::Receiver;
 use ctre::motor_control::config::*;
 use ctre::motor_control::*;
-use lazy_static::lazy_static;
+use hardware::io::{Gyro, Hardware, Rio, SolenoidOut, Talon};
+use interp_map::pose::Pose2d;
+use interp_map::Interpolatable;
 use navx::AHRS;
+use tunables::{Registry, Tunable};
 use wpilib::pneumatics::Solenoid;
 
 use controls::const_unit;
 use controls::units::*;
 
 use crate::config::drive::*;
+use crate::config::robot::DriveConfig;
 use crate::OkPrint;
 
+use super::periodic::Periodic;
+use super::supervisor::Heartbeat;
+use super::telemetry::{DriveRecord, Log};
+use super::vision::camera::Location;
+use super::vision::TargetHandle;
 use super::Subsystem;
 
+pub mod heading;
+pub mod pose_service;
+#[cfg(test)]
+pub(crate) mod sim;
+
+pub use self::pose_service::PosePublisher;
+
+use heading::{HeadingFilter, Kalman};
+
 #[derive(Debug, Copy, Clone)]
 pub enum Gear {
     High,
     Low,
 }
 
-impl From<Gear> for bool {
-    fn from(gear: Gear) -> Self {
-        match gear {
-            Gear::High => shifter::HIGH_GEAR,
-            Gear::Low => !shifter::HIGH_GEAR,
+impl Gear {
+    /// What to set the shifter solenoid to, given its output for high gear
+    fn shifter_output(self, high_gear: bool) -> bool {
+        match self {
+            Gear::High => high_gear,
+            Gear::Low => !high_gear,
         }
     }
 }
@@ -38,7 +56,56 @@ pub struct Pose {
     pub heading: f64,
     pub velocity: MeterPerSecond<f64>,
     pub angular_velocity: Hertz<f64>,
-    distance_accumulated: Meter<f64>,
+    left_distance: Meter<f64>,
+    right_distance: Meter<f64>,
+}
+
+impl Pose {
+    pub fn origin() -> Self {
+        Pose {
+            x: 0.0 * M,
+            y: 0.0 * M,
+            heading: 0.0,
+            velocity: 0.0 * M / S,
+            angular_velocity: 0.0 / S,
+            left_distance: 0.0 * M,
+            right_distance: 0.0 * M,
+        }
+    }
+
+    /// Where something at `location` from the robot at this pose is on the field.
+    pub fn locate(&self, location: &Location) -> (Meter<f64>, Meter<f64>) {
+        let direction = self.heading + location.bearing;
+        (
+            self.x + location.range * direction.cos(),
+            self.y + location.range * direction.sin(),
+        )
+    }
+}
+
+impl Interpolatable for Pose {
+    /// Follows the arc between the two poses rather than the straight line
+    fn interp(low: Self, high: Self, percent: f64) -> Self {
+        let rigid = |pose: &Pose| Pose2d::new(*(pose.x / M), *(pose.y / M), pose.heading);
+        let between = Pose2d::interp(rigid(&low), rigid(&high), percent);
+        Pose {
+            x: between.x * M,
+            y: between.y * M,
+            heading: between.heading,
+            velocity: Interpolatable::interp(low.velocity, high.velocity, percent),
+            angular_velocity: Interpolatable::interp(
+                low.angular_velocity,
+                high.angular_velocity,
+                percent,
+            ),
+            left_distance: Interpolatable::interp(low.left_distance, high.left_distance, percent),
+            right_distance: Interpolatable::interp(
+                low.right_distance,
+                high.right_distance,
+                percent,
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -49,158 +116,584 @@ pub enum Instruction {
     Percentage(f64, f64),
 }
 
+// debug_stub_derive can't parse `dyn`
+type BoxedHeadingFilter = Box<dyn HeadingFilter + Send>;
+
 #[derive(DebugStub)]
-pub struct Drive {
-    l_mstr: TalonSRX,
-    r_mstr: TalonSRX,
-    _l_slave: TalonSRX,
-    _r_slave: TalonSRX,
-    // #[debug_stub = "Opaque(AHRS)"]
-    // ahrs: AHRS,
-    gear_shifter: Solenoid,
+pub struct Drive<H: Hardware = Rio> {
+    l_mstr: H::Talon,
+    r_mstr: H::Talon,
+    _l_slave: H::Talon,
+    _r_slave: H::Talon,
+    #[debug_stub = "Opaque(Gyro)"]
+    gyro: H::Gyro,
+    #[debug_stub = "Opaque(HeadingFilter)"]
+    heading_filter: BoxedHeadingFilter,
+    gear_shifter: H::Solenoid,
+    /// Shifter solenoid output for high gear
+    high_gear: bool,
+    gear: Gear,
+    geometry: Geometry,
+    gains: VelocityGains,
+    /// Last velocity setpoint, for the acceleration feedforward
+    last_setpoint: Option<(MeterPerSecond<f64>, MeterPerSecond<f64>, Instant)>,
     receiver: Receiver<Instruction>,
-    #[debug_stub = "Opaque(Bus<Pose>)"]
-    broadcaster: Bus<Pose>,
+    poses: PosePublisher,
+    /// Where vision targets come from, to put them on the field
+    targets: Option<TargetHandle>,
+    log: Log,
+    /// Last control mode and demands sent to the talons, for telemetry
+    output: (&'static str, f64, f64),
+    heartbeat: Heartbeat,
 }
 
-lazy_static! {
-    static ref DEFAULT_CONFIG: TalonSRXConfig = TalonSRXConfig {
+fn talon_config(config: &DriveConfig) -> TalonSRXConfig {
+    TalonSRXConfig {
         base: BaseMotorConfig {
+            voltageCompSaturation: 12.0,
             voltageMeasurementFilter: 5,
             velocityMeasurementPeriod: VelocityMeasPeriod::Period_5Ms,
+            slot_0: SlotConfiguration {
+                kP: velocity::low_gear::KP,
+                kI: velocity::low_gear::KI,
+                kD: velocity::low_gear::KD,
+                kF: 0.0,
+                integralZone: 0,
+                allowableClosedloopError: 0,
+                maxIntegralAccumulator: 0.0,
+                closedLoopPeakOutput: 1.0,
+                closedLoopPeriod: 1,
+            },
+            slot_1: SlotConfiguration {
+                kP: velocity::high_gear::KP,
+                kI: velocity::high_gear::KI,
+                kD: velocity::high_gear::KD,
+                kF: 0.0,
+                integralZone: 0,
+                allowableClosedloopError: 0,
+                maxIntegralAccumulator: 0.0,
+                closedLoopPeakOutput: 1.0,
+                closedLoopPeriod: 1,
+            },
             ..Default::default()
         },
         forwardLimitSwitchSource: LimitSwitchSource::Deactivated,
         reverseLimitSwitchSource: LimitSwitchSource::Deactivated,
-        peakCurrentLimit: CURRENT_LIMIT_THRESHOLD,
-        peakCurrentDuration: CURRENT_LIMIT_DURATION_MS,
-        continuousCurrentLimit: CURRENT_LIMIT,
+        peakCurrentLimit: config.peak_current,
+        peakCurrentDuration: config.peak_current_duration,
+        continuousCurrentLimit: config.continuous_current,
         ..Default::default()
-    };
+    }
+}
+/// Every device the drive owns, already configured.
+#[derive(DebugStub)]
+pub struct DriveHardware<H: Hardware> {
+    pub l_mstr: H::Talon,
+    pub r_mstr: H::Talon,
+    pub l_slave: H::Talon,
+    pub r_slave: H::Talon,
+    #[debug_stub = "Opaque(Gyro)"]
+    pub gyro: H::Gyro,
+    pub gear_shifter: H::Solenoid,
+}
+
+/// How the wheels' motion shows up in the encoders, and turns the robot.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Geometry {
+    pub meters_per_tick: Meter<f64>,
+    pub wheel_width: Meter<f64>,
+}
+
+impl Geometry {
+    /// Converts to talon native units, ticks per 100ms
+    fn to_native_velocity(&self, velocity: MeterPerSecond<f64>) -> f64 {
+        *(velocity / self.meters_per_tick * S) / 10.0
+    }
+
+    /// Converts back from talon native units
+    fn velocity_from_native(&self, ticks: i32) -> MeterPerSecond<f64> {
+        f64::from(ticks) * 10.0 * self.meters_per_tick / S
+    }
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            meters_per_tick: ENCODER_METERS_PER_TICK,
+            wheel_width: DRIVE_BASE_WHEEL_WIDTH,
+        }
+    }
+}
+
+/// Velocity feedforward for one gear
+#[derive(Debug, Copy, Clone)]
+struct Feedforward {
+    ks: Volt<f64>,
+    kv: VoltSecondPerMeter<f64>,
+    ka: VoltSecondSquaredPerMeter<f64>,
+}
+
+/// Velocity gains for one gear: PID on the talons, feedforward on the rio
+#[derive(Debug, Clone)]
+struct GearGains {
+    slot_idx: i32,
+    kp: Tunable,
+    ki: Tunable,
+    kd: Tunable,
+    /// Feedforward in volts, volt seconds per meter and volt seconds squared per meter
+    ks: Tunable,
+    kv: Tunable,
+    ka: Tunable,
+}
+
+impl GearGains {
+    fn new(gear: Gear, mut tunable: impl FnMut(&str, f64) -> Tunable) -> Self {
+        use velocity::*;
+        let kv_unit = V * S / M;
+        let ka_unit = V * S * S / M;
+        match gear {
+            Gear::Low => Self {
+                slot_idx: low_gear::SLOT_IDX,
+                kp: tunable("kp", low_gear::KP),
+                ki: tunable("ki", low_gear::KI),
+                kd: tunable("kd", low_gear::KD),
+                ks: tunable("ks", *(low_gear::KS / V)),
+                kv: tunable("kv", *(low_gear::KV / kv_unit)),
+                ka: tunable("ka", *(low_gear::KA / ka_unit)),
+            },
+            Gear::High => Self {
+                slot_idx: high_gear::SLOT_IDX,
+                kp: tunable("kp", high_gear::KP),
+                ki: tunable("ki", high_gear::KI),
+                kd: tunable("kd", high_gear::KD),
+                ks: tunable("ks", *(high_gear::KS / V)),
+                kv: tunable("kv", *(high_gear::KV / kv_unit)),
+                ka: tunable("ka", *(high_gear::KA / ka_unit)),
+            },
+        }
+    }
+
+    fn feedforward(&self) -> Feedforward {
+        Feedforward {
+            ks: self.ks.get() * V,
+            kv: self.kv.get() * V * S / M,
+            ka: self.ka.get() * V * S * S / M,
+        }
+    }
+
+    /// Sends any PID gains that have changed to both talons, without waiting for them to confirm.
+    /// Gains either talon didn't take are sent again next time.
+    fn apply<T: Talon>(&mut self, left: &mut T, right: &mut T) -> ctre::Result<()> {
+        let slot = self.slot_idx;
+        self.kp.apply(|kp| {
+            left.config_kp(slot, kp, 0)?;
+            right.config_kp(slot, kp, 0)
+        })?;
+        self.ki.apply(|ki| {
+            left.config_ki(slot, ki, 0)?;
+            right.config_ki(slot, ki, 0)
+        })?;
+        self.kd.apply(|kd| {
+            left.config_kd(slot, kd, 0)?;
+            right.config_kd(slot, kd, 0)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VelocityGains {
+    low: GearGains,
+    high: GearGains,
+}
+
+impl VelocityGains {
+    fn fixed() -> Self {
+        Self {
+            low: GearGains::new(Gear::Low, |_, value| Tunable::fixed(value)),
+            high: GearGains::new(Gear::High, |_, value| Tunable::fixed(value)),
+        }
+    }
+
+    fn register(registry: &Registry) -> Self {
+        let tunable = |gear| {
+            move |name: &str, value| registry.register(&format!("drive/{}/{}", gear, name), value)
+        };
+        Self {
+            low: GearGains::new(Gear::Low, tunable("low_gear")),
+            high: GearGains::new(Gear::High, tunable("high_gear")),
+        }
+    }
+
+    fn for_gear(&self, gear: Gear) -> &GearGains {
+        match gear {
+            Gear::Low => &self.low,
+            Gear::High => &self.high,
+        }
+    }
+}
+
+impl Feedforward {
+    fn calculate(
+        &self,
+        velocity: MeterPerSecond<f64>,
+        acceleration: MeterPerSecond2<f64>,
+    ) -> Volt<f64> {
+        let velocity_mps = *(velocity / MPS);
+        // f64::signum is never zero
+        let direction = if velocity_mps.abs() < 1e-3 {
+            0.0
+        } else {
+            velocity_mps.signum()
+        };
+        self.ks * direction + self.kv * velocity + self.ka * acceleration
+    }
 }
+
 trait TypedQuadrature {
-    fn pos(&self) -> ctre::Result<Meter<f64>>;
-    fn vel(&self) -> ctre::Result<MeterPerSecond<f64>>;
+    fn pos(&self, geometry: &Geometry) -> ctre::Result<Meter<f64>>;
+    fn vel(&self, geometry: &Geometry) -> ctre::Result<MeterPerSecond<f64>>;
 }
 
-impl TypedQuadrature for TalonSRX {
-    fn pos(&self) -> ctre::Result<Meter<f64>> {
+impl<T: Talon> TypedQuadrature for T {
+    fn pos(&self, geometry: &Geometry) -> ctre::Result<Meter<f64>> {
         self.get_quadrature_position()
-            .and_then(|ticks| Ok(f64::from(ticks) * crate::config::drive::ENCODER_METERS_PER_TICK))
+            .map(|ticks| f64::from(ticks) * geometry.meters_per_tick)
     }
 
-    fn vel(&self) -> ctre::Result<MeterPerSecond<f64>> {
-        self.get_quadrature_velocity().and_then(|ticks| {
-            Ok(f64::from(ticks) * crate::config::drive::ENCODER_METERS_PER_TICK / S)
-        })
+    fn vel(&self, geometry: &Geometry) -> ctre::Result<MeterPerSecond<f64>> {
+        self.get_quadrature_velocity()
+            .map(|ticks| geometry.velocity_from_native(ticks))
     }
 }
 
-impl Drive {
+impl<H: Hardware> Drive<H> {
     #[allow(dead_code)]
-    fn config_talons<T>(&mut self, f: impl Fn(&mut TalonSRX) -> T) -> (T, T) {
+    fn config_talons<T>(&mut self, f: impl Fn(&mut H::Talon) -> T) -> (T, T) {
         (f(&mut self.l_mstr), f(&mut self.r_mstr))
     }
 
-    /// Generates the next pose from the previous pose and current gyro data
-    fn generate_pose(&self, previous: &Pose) -> Pose {
-        // let new_heading: f64 = self.ahrs.yaw().into();
-        let new_heading = 0.0;
-        let angle = (new_heading + previous.heading) / 2.0;
+    /// Generates the next pose from the previous pose, the encoders and the gyro
+    fn generate_pose(&mut self, previous: &Pose, dt: Second<f64>) -> Pose {
+        let (log, geometry) = (&self.log, &self.geometry);
+        let left_distance = log_err(log, "reading the left position", self.l_mstr.pos(geometry))
+            .unwrap_or(previous.left_distance);
+        let right_distance = log_err(log, "reading the right position", self.r_mstr.pos(geometry))
+            .unwrap_or(previous.right_distance);
 
-        //TODO log errors handling here
-        let left_distance = self.l_mstr.pos().unwrap_or(const_unit!(0.));
-        let right_distance = self.r_mstr.pos().unwrap_or(const_unit!(0.));
+        let left_velocity = log_err(log, "reading the left velocity", self.l_mstr.vel(geometry))
+            .unwrap_or(const_unit!(0.));
+        let right_velocity = log_err(log, "reading the right velocity", self.r_mstr.vel(geometry))
+            .unwrap_or(const_unit!(0.));
 
-        let left_velocity = self.l_mstr.vel().unwrap_or(const_unit!(0.));
-        let right_velocity = self.l_mstr.vel().unwrap_or(const_unit!(0.));
+        let left_delta = left_distance - previous.left_distance;
+        let right_delta = right_distance - previous.right_distance;
+        let wheel_width = geometry.wheel_width;
+        let encoder_delta = *((right_delta - left_delta) / wheel_width);
+        let heading = self
+            .heading_filter
+            .update(self.gyro.heading(), encoder_delta, dt);
 
-        let distance = (left_distance + right_distance) / 2.0 - previous.distance_accumulated;
+        let angle = (heading + previous.heading) / 2.0;
+        let distance = (left_delta + right_delta) / 2.0;
 
         Pose {
             x: previous.x + distance * angle.cos(),
             y: previous.y + distance * angle.sin(),
-            heading: new_heading,
+            heading,
             velocity: (left_velocity + right_velocity) / 2.0,
-            angular_velocity: (right_velocity - left_velocity) / DRIVE_BASE_WHEEL_WIDTH,
-            distance_accumulated: (left_distance + right_distance) / 2.0,
+            angular_velocity: (right_velocity - left_velocity) / wheel_width,
+            left_distance,
+            right_distance,
         }
     }
 
-    pub fn new(broadcaster: Bus<Pose>, receiver: Receiver<Instruction>) -> Self {
-        // TODO log errors
-        let mut l_mstr = TalonSRX::new(LEFT_MASTER);
-        let mut l_slave = TalonSRX::new(LEFT_SLAVE);
-        l_mstr.config_all(&DEFAULT_CONFIG, TALON_CFG_TO_MS).ok();
-        // .expect("Unable to configure left_master side!")
-        l_slave.config_all(&DEFAULT_CONFIG, TALON_CFG_TO_MS).ok();
-        // .expect("Unable to configure left_slave side!");
+    /// Replaces the default Kalman heading filter
+    #[allow(dead_code)]
+    pub fn with_heading_filter(self, filter: impl HeadingFilter + Send + 'static) -> Self {
+        Self {
+            heading_filter: Box::new(filter),
+            ..self
+        }
+    }
+
+    /// Makes the velocity gains live tunable as `drive/low_gear/*` and `drive/high_gear/*`.
+    pub fn with_tuning(self, registry: &Registry) -> Self {
+        Self {
+            gains: VelocityGains::register(registry),
+            ..self
+        }
+    }
+
+    /// Replaces the geometry in `config::drive`
+    #[allow(dead_code)]
+    pub fn with_geometry(self, geometry: Geometry) -> Self {
+        Self { geometry, ..self }
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+
+    pub fn with_heartbeat(self, heartbeat: Heartbeat) -> Self {
+        Self { heartbeat, ..self }
+    }
+
+    /// Records where the latest fresh target from `targets` is on the field, so vision can be
+    /// checked against odometry.
+    pub fn with_vision(self, targets: TargetHandle) -> Self {
+        Self {
+            targets: Some(targets),
+            ..self
+        }
+    }
+
+    /// Where the latest fresh target is on the field, from where the robot was when its frame
+    /// was captured.
+    fn locate_target(&self, now: Instant) -> Option<(Meter<f64>, Meter<f64>)> {
+        let target = self.targets.as_ref()?.fresh(now)?;
+        let then = self.poses.handle().at(target.captured)?;
+        Some(then.locate(&target.location))
+    }
+
+    fn record(&self, pose: &Pose, now: Instant) -> DriveRecord {
+        let (mode, left_demand, right_demand) = self.output;
+        let (target_x_m, target_y_m) = self
+            .locate_target(now)
+            .map_or((std::f64::NAN, std::f64::NAN), |(x, y)| {
+                (*(x / M), *(y / M))
+            });
+        DriveRecord {
+            x_m: *(pose.x / M),
+            y_m: *(pose.y / M),
+            heading_rad: pose.heading,
+            velocity_mps: *(pose.velocity / (M / S)),
+            angular_velocity_radps: *(pose.angular_velocity * S),
+            high_gear: match self.gear {
+                Gear::High => true,
+                Gear::Low => false,
+            },
+            mode,
+            left_demand,
+            right_demand,
+            target_x_m,
+            target_y_m,
+        }
+    }
+
+    /// Sends newly tuned PID gains to the talons
+    fn update_gains(&mut self) {
+        let VelocityGains { low, high } = &mut self.gains;
+        let (left, right) = (&mut self.l_mstr, &mut self.r_mstr);
+        let applied = low
+            .apply(left, right)
+            .and_then(|()| high.apply(left, right));
+        log_err(&self.log, "tuning the velocity gains", applied);
+    }
+
+    /// Sets which shifter output is high gear, for robots plumbed differently
+    pub fn with_high_gear(mut self, high_gear: bool) -> Self {
+        self.high_gear = high_gear;
+        self.gear_shifter
+            .set(self.gear.shifter_output(high_gear))
+            .ok_print();
+        self
+    }
+
+    fn execute(&mut self, instruction: Instruction, now: Instant) {
+        match instruction {
+            Instruction::GearShift(g) => {
+                self.gear = g;
+                self.last_setpoint = None;
+                let shifted = self.gear_shifter.set(g.shifter_output(self.high_gear));
+                log_err(&self.log, "shifting gears", shifted);
+            }
+            Instruction::Percentage(lpct, rpct) => {
+                self.last_setpoint = None;
+                self.output = ("percent", lpct, rpct);
+                self.l_mstr
+                    .set(ControlMode::PercentOutput, lpct, DemandType::Neutral, 0.0)
+                    .ok_print();
+                self.r_mstr
+                    .set(ControlMode::PercentOutput, rpct, DemandType::Neutral, 0.0)
+                    .ok_print();
+            }
+            Instruction::Velocity(left, right) => {
+                let (l_acc, r_acc) = match self.last_setpoint {
+                    // setpoints sent together can't be differenced
+                    Some((last_l, last_r, then))
+                        if now > then
+                            && now.duration_since(then) <= velocity::MAX_SETPOINT_INTERVAL =>
+                    {
+                        let dt = now.duration_since(then).as_secs_f64() * S;
+                        ((left - last_l) / dt, (right - last_r) / dt)
+                    }
+                    _ => (0.0 * M / S / S, 0.0 * M / S / S),
+                };
+                self.last_setpoint = Some((left, right, now));
+                self.output = ("velocity", *(left / (M / S)), *(right / (M / S)));
+
+                let gains = self.gains.for_gear(self.gear);
+                let ff = gains.feedforward();
+                let slot = gains.slot_idx;
+                let left_native = self.geometry.to_native_velocity(left);
+                let right_native = self.geometry.to_native_velocity(right);
+                Self::set_velocity(
+                    &mut self.l_mstr,
+                    slot,
+                    left_native,
+                    ff.calculate(left, l_acc),
+                );
+                Self::set_velocity(
+                    &mut self.r_mstr,
+                    slot,
+                    right_native,
+                    ff.calculate(right, r_acc),
+                );
+            }
+        }
+    }
+
+    /// Sets a velocity in talon native units
+    fn set_velocity(talon: &mut H::Talon, slot: i32, setpoint: f64, ff: Volt<f64>) {
+        talon.select_profile_slot(slot, 0).ok_print();
+        talon
+            .set(
+                ControlMode::Velocity,
+                setpoint,
+                DemandType::ArbitraryFeedForward,
+                *(ff / (12.0 * V)),
+            )
+            .ok_print();
+    }
+
+    pub fn from_hardware(
+        hw: DriveHardware<H>,
+        poses: PosePublisher,
+        receiver: Receiver<Instruction>,
+    ) -> Self {
+        let mut gear_shifter = hw.gear_shifter;
+        gear_shifter
+            .set(Gear::Low.shifter_output(shifter::HIGH_GEAR))
+            .ok_print();
+        Drive {
+            l_mstr: hw.l_mstr,
+            r_mstr: hw.r_mstr,
+            _l_slave: hw.l_slave,
+            _r_slave: hw.r_slave,
+            gyro: hw.gyro,
+            heading_filter: Box::new(Kalman::default()),
+            gear_shifter,
+            high_gear: shifter::HIGH_GEAR,
+            gear: Gear::Low,
+            geometry: Geometry::default(),
+            gains: VelocityGains::fixed(),
+            last_setpoint: None,
+            receiver,
+            poses,
+            targets: None,
+            log: Log::discard(),
+            output: ("none", 0.0, 0.0),
+            heartbeat: Heartbeat::new(),
+        }
+    }
+}
+
+impl Drive {
+    /// Configures the drive's hardware, logging anything that goes wrong to `log`, and then logs
+    /// there as it runs.
+    pub fn new(
+        config: &DriveConfig,
+        poses: PosePublisher,
+        receiver: Receiver<Instruction>,
+        log: Log,
+    ) -> Self {
+        let talon_config = talon_config(config);
+        let mut l_mstr = TalonSRX::new(config.left_master);
+        let mut l_slave = TalonSRX::new(config.left_slave);
+        let configured = l_mstr.config_all(&talon_config, TALON_CFG_TO_MS);
+        log_err(&log, "configuring the left master", configured);
+        let configured = l_slave.config_all(&talon_config, TALON_CFG_TO_MS);
+        log_err(&log, "configuring the left slave", configured);
         l_slave
             .follow(&l_mstr, FollowerType::PercentOutput)
             .unwrap();
         l_mstr.set_inverted(true);
         l_slave.set_inverted(true);
+        // the velocity feedforward is in volts
+        l_mstr.enable_voltage_compensation(true);
+        l_slave.enable_voltage_compensation(true);
 
-        let mut r_mstr = TalonSRX::new(RIGHT_MASTER);
-        let mut r_slave = TalonSRX::new(RIGHT_SLAVE);
-        r_mstr.config_all(&DEFAULT_CONFIG, TALON_CFG_TO_MS).ok();
-        // .expect("Unable to configure right_master side!");
-        r_slave.config_all(&DEFAULT_CONFIG, TALON_CFG_TO_MS).ok();
-        // .expect("Unable to configure right_slave side!");
+        let mut r_mstr = TalonSRX::new(config.right_master);
+        let mut r_slave = TalonSRX::new(config.right_slave);
+        let configured = r_mstr.config_all(&talon_config, TALON_CFG_TO_MS);
+        log_err(&log, "configuring the right master", configured);
+        let configured = r_slave.config_all(&talon_config, TALON_CFG_TO_MS);
+        log_err(&log, "configuring the right slave", configured);
         r_slave
             .follow(&r_mstr, FollowerType::PercentOutput)
             .unwrap();
+        r_mstr.enable_voltage_compensation(true);
+        r_slave.enable_voltage_compensation(true);
 
-        Drive {
-            l_mstr,
-            r_mstr,
-            _l_slave: l_slave,
-            _r_slave: r_slave,
-            // ahrs: AHRS::from_spi_minutiae(wpilib::spi::Port::MXP, 500_000, 60),
-            // TODO log errors
-            gear_shifter: Solenoid::new(shifter::SOLENOID_CHANNEL)
-                .expect("Unable to create gear shifter!"),
+        // the drive panics without it, and the supervisor starts it again
+        let gear_shifter = log_err(
+            &log,
+            "creating the gear shifter",
+            Solenoid::new(config.shifter_solenoid),
+        )
+        .expect("Unable to create gear shifter!");
+        Self::from_hardware(
+            DriveHardware {
+                l_mstr,
+                r_mstr,
+                l_slave,
+                r_slave,
+                gyro: AHRS::from_spi_minutiae(wpilib::spi::Port::MXP, 500_000, 60),
+                gear_shifter,
+            },
+            poses,
             receiver,
-            broadcaster,
+        )
+        .with_log(log)
+        .with_high_gear(config.high_gear)
+    }
+}
+
+/// Records an error the drive carries on through, returning the value if there wasn't one.
+fn log_err<T, E: std::fmt::Debug>(log: &Log, doing: &str, result: Result<T, E>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log.event("drive", format!("{}: {:?}", doing, e));
+            None
         }
     }
 }
 
-impl Subsystem for Drive {
+impl<H: Hardware> Subsystem for Drive<H> {
     fn run(mut self) {
-        let mut latest_pose = Pose {
-            x: 0.0 * M,
-            y: 0.0 * M,
-            heading: 0.0,
-            velocity: 0.0 * M / S,
-            angular_velocity: 0.0 / S,
-            distance_accumulated: 0.0 * M,
-        };
+        if let Some(realtime) = crate::config::periodic::DRIVE {
+            if let Err(e) = realtime.apply() {
+                self.log
+                    .event("drive", format!("Could not make the drive realtime: {}", e));
+            }
+        }
+        let mut latest_pose = Pose::origin();
+        let mut periodic = Periodic::new(crate::config::SUBSYSTEM_PERIOD);
 
         loop {
-            thread::sleep(crate::config::SUBSYSTEM_SLEEP_TIME);
-
-            for item in self.receiver.try_iter() {
-                match item {
-                    Instruction::GearShift(g) => self
-                        .gear_shifter
-                        .set(g.into())
-                        // TODO log
-                        .ok_print(),
-                    Instruction::Percentage(lpct, rpct) => {
-                        self.l_mstr
-                            .set(ControlMode::PercentOutput, lpct, DemandType::Neutral, 0.0)
-                            .ok_print();
-                        self.r_mstr
-                            .set(ControlMode::PercentOutput, rpct, DemandType::Neutral, 0.0)
-                            .ok_print();
-                    }
-                    Instruction::Velocity(_left, _right) => unimplemented!(),
-                }
+            let tick = periodic.wait();
+
+            self.update_gains();
+            while let Ok(item) = self.receiver.try_recv() {
+                self.execute(item, Instant::now());
             }
 
-            latest_pose = self.generate_pose(&latest_pose);
-            self.broadcaster.broadcast(latest_pose);
+            let now = Instant::now();
+            latest_pose = self.generate_pose(&latest_pose, tick.dt.as_secs_f64() * S);
+            self.poses.publish(latest_pose, now);
+            self.log.record(self.record(&latest_pose, now));
+            self.heartbeat.beat(&tick);
         }
     }
 }
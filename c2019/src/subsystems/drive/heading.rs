@@ -0,0 +1,173 @@
+//! Combining the gyro with the heading implied by the encoders.
+//!
+//! The gyro is smooth and doesn't care about wheel slip, but its bias integrates into a heading
+//! error that grows without bound. The encoders don't drift while the wheels grip, but any slip
+//! shows up directly as heading error. Each filter here trusts the gyro over short periods and the
+//! encoders over long ones.
+use crate::config::drive::heading::*;
+use controls::units::*;
+
+pub trait HeadingFilter {
+    /// Takes the latest (continuous) gyro heading and the change in heading measured by the
+    /// encoders since the last update, and returns the new heading estimate.
+    fn update(&mut self, gyro_heading: f64, encoder_delta: f64, dt: Second<f64>) -> f64;
+}
+
+/// Trusts the gyro completely.
+#[derive(Debug, Default)]
+pub struct GyroOnly;
+
+impl HeadingFilter for GyroOnly {
+    fn update(&mut self, gyro_heading: f64, _encoder_delta: f64, _dt: Second<f64>) -> f64 {
+        gyro_heading
+    }
+}
+
+/// Integrates the gyro, pulling the result towards the encoder heading with time constant `tau`.
+#[derive(Debug)]
+pub struct Complementary {
+    tau: Second<f64>,
+    estimate: f64,
+    encoder_heading: f64,
+    last_gyro: Option<f64>,
+}
+
+impl Complementary {
+    pub fn new(tau: Second<f64>) -> Self {
+        Self {
+            tau,
+            estimate: 0.0,
+            encoder_heading: 0.0,
+            last_gyro: None,
+        }
+    }
+}
+
+impl Default for Complementary {
+    fn default() -> Self {
+        Self::new(COMPLEMENTARY_TIME_CONSTANT)
+    }
+}
+
+impl HeadingFilter for Complementary {
+    fn update(&mut self, gyro_heading: f64, encoder_delta: f64, dt: Second<f64>) -> f64 {
+        let gyro_delta = gyro_heading - self.last_gyro.unwrap_or(gyro_heading);
+        self.last_gyro = Some(gyro_heading);
+        self.encoder_heading += encoder_delta;
+
+        let alpha = *(self.tau / (self.tau + dt));
+        self.estimate = alpha * (self.estimate + gyro_delta) + (1.0 - alpha) * self.encoder_heading;
+        self.estimate
+    }
+}
+
+/// Estimates heading and gyro bias together, predicting with the gyro and correcting with the
+/// encoder heading.
+#[derive(Debug)]
+pub struct Kalman {
+    /// Heading, gyro bias (rad/s)
+    state: [f64; 2],
+    covariance: [[f64; 2]; 2],
+    encoder_heading: f64,
+    last_gyro: Option<f64>,
+}
+
+impl Default for Kalman {
+    fn default() -> Self {
+        Self {
+            state: [0.0, 0.0],
+            covariance: [[0.0, 0.0], [0.0, INITIAL_BIAS_VARIANCE]],
+            encoder_heading: 0.0,
+            last_gyro: None,
+        }
+    }
+}
+
+impl Kalman {
+    #[allow(dead_code)]
+    pub fn bias(&self) -> Hertz<f64> {
+        self.state[1] / S
+    }
+}
+
+impl HeadingFilter for Kalman {
+    fn update(&mut self, gyro_heading: f64, encoder_delta: f64, dt: Second<f64>) -> f64 {
+        let dt = *(dt / S);
+        let gyro_delta = gyro_heading - self.last_gyro.unwrap_or(gyro_heading);
+        self.last_gyro = Some(gyro_heading);
+        self.encoder_heading += encoder_delta;
+
+        // predict: x = F x, P = F P F' + Q with F = [[1, -dt], [0, 1]]
+        let [heading, bias] = self.state;
+        self.state = [heading + gyro_delta - bias * dt, bias];
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        self.covariance = [
+            [
+                p00 - dt * (p01 + p10) + dt * dt * p11 + GYRO_VARIANCE * dt,
+                p01 - dt * p11,
+            ],
+            [p10 - dt * p11, p11 + BIAS_VARIANCE * dt],
+        ];
+
+        // correct against the encoder heading, H = [1, 0]
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let innovation = self.encoder_heading - self.state[0];
+        let s = p00 + ENCODER_VARIANCE;
+        let k = [p00 / s, p10 / s];
+        self.state[0] += k[0] * innovation;
+        self.state[1] += k[1] * innovation;
+        self.covariance = [
+            [(1.0 - k[0]) * p00, (1.0 - k[0]) * p01],
+            [p10 - k[1] * p00, p11 - k[1] * p01],
+        ];
+        self.state[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f64 = 0.005;
+
+    /// Runs a filter for `secs` with a stationary robot and a biased gyro, returning the heading
+    fn stationary(filter: &mut impl HeadingFilter, bias: f64, secs: f64) -> f64 {
+        let mut estimate = 0.0;
+        for i in 0..(secs / DT) as usize {
+            estimate = filter.update(bias * i as f64 * DT, 0.0, DT * S);
+        }
+        estimate
+    }
+
+    #[test]
+    fn complementary_bounds_gyro_bias() {
+        let mut filter = Complementary::default();
+        let heading = stationary(&mut filter, 0.01, 60.0);
+        // steady state error is bias * tau
+        let tau = *(COMPLEMENTARY_TIME_CONSTANT / S);
+        assert!((heading - 0.01 * tau).abs() < 1e-3);
+    }
+
+    #[test]
+    fn kalman_learns_gyro_bias() {
+        let mut filter = Kalman::default();
+        let heading = stationary(&mut filter, 0.01, 60.0);
+        assert!(heading.abs() < 1e-3);
+        assert!((*(filter.bias() * S) - 0.01).abs() < 1e-3);
+    }
+
+    #[test]
+    fn filters_agree_when_sensors_do() {
+        let mut complementary = Complementary::default();
+        let mut kalman = Kalman::default();
+        let rate = 1.0;
+        for i in 0..400 {
+            let gyro = rate * f64::from(i) * DT;
+            let encoder_delta = if i == 0 { 0.0 } else { rate * DT };
+            let c = complementary.update(gyro, encoder_delta, DT * S);
+            let k = kalman.update(gyro, encoder_delta, DT * S);
+            assert!((c - gyro).abs() < 1e-9, "{} vs {}", c, gyro);
+            assert!((k - gyro).abs() < 1e-9, "{} vs {}", k, gyro);
+        }
+    }
+}
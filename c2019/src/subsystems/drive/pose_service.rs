@@ -0,0 +1,203 @@
+//! Distributing the drive's pose estimates to the rest of the robot.
+//!
+//! Build a [`PosePublisher`] before spawning the drive, hand out subscribers and handles from it,
+//! then give it to the drive. Subscribers have to be added up front since the publisher moves to
+//! the drive thread; handles can be cloned freely afterwards. Clones of the publisher publish to
+//! the same subscribers, so a restarted drive carries on where the last one left off.
+use super::Pose;
+use crate::config::drive::poses::*;
+use bus::{Bus, BusReader};
+use interp_map::InterpolatingBTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// A pose and when it was estimated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimedPose {
+    pub time: Instant,
+    pub pose: Pose,
+}
+
+type History = InterpolatingBTreeMap<Instant, Pose>;
+
+/// The drive's end: records every pose and broadcasts it to subscribers.
+#[derive(DebugStub, Clone)]
+pub struct PosePublisher {
+    #[debug_stub = "Opaque(Bus<TimedPose>)"]
+    bus: Arc<Mutex<Bus<TimedPose>>>,
+    #[debug_stub = "Opaque(History)"]
+    history: Arc<Mutex<History>>,
+}
+
+impl PosePublisher {
+    pub fn new() -> Self {
+        Self {
+            bus: Arc::new(Mutex::new(Bus::new(SUBSCRIBER_CAPACITY))),
+            history: Arc::new(Mutex::new(History::with_max_age(
+                HISTORY_LENGTH,
+                HISTORY_AGE,
+            ))),
+        }
+    }
+
+    /// Receives every pose published from now on.
+    #[allow(dead_code)]
+    pub fn subscribe(&mut self) -> PoseSubscriber {
+        PoseSubscriber(lock(&self.bus).add_rx())
+    }
+
+    /// Snapshot access to the latest pose and recent history, from any thread.
+    #[allow(dead_code)]
+    pub fn handle(&self) -> PoseHandle {
+        PoseHandle(Arc::clone(&self.history))
+    }
+
+    pub fn publish(&mut self, pose: Pose, time: Instant) {
+        lock(&self.history).insert(time, pose);
+        // A subscriber that has fallen a whole buffer behind misses poses rather than stalling
+        // the drive loop.
+        lock(&self.bus).try_broadcast(TimedPose { time, pose }).ok();
+    }
+}
+
+impl Default for PosePublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receives every pose, in order. If the subscriber falls [`SUBSCRIBER_CAPACITY`] poses behind,
+/// new poses are dropped until it catches up.
+pub struct PoseSubscriber(BusReader<TimedPose>);
+
+#[allow(dead_code)]
+impl PoseSubscriber {
+    /// Blocks for the next pose. `None` once the drive has stopped.
+    pub fn recv(&mut self) -> Option<TimedPose> {
+        self.0.recv().ok()
+    }
+
+    pub fn try_recv(&mut self) -> Option<TimedPose> {
+        self.0.try_recv().ok()
+    }
+
+    /// Discards everything queued but the newest pose.
+    pub fn latest(&mut self) -> Option<TimedPose> {
+        let mut latest = None;
+        while let Some(timed) = self.try_recv() {
+            latest = Some(timed);
+        }
+        latest
+    }
+}
+
+#[derive(Clone)]
+pub struct PoseHandle(Arc<Mutex<History>>);
+
+impl fmt::Debug for PoseHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PoseHandle")
+            .field(&"Opaque(History)")
+            .finish()
+    }
+}
+
+#[allow(dead_code)]
+impl PoseHandle {
+    /// The most recently published pose.
+    pub fn latest(&self) -> Option<TimedPose> {
+        lock(&self.0)
+            .latest()
+            .map(|(&time, &pose)| TimedPose { time, pose })
+    }
+
+    /// Where the robot was at `time`, interpolating between published poses. Times outside the
+    /// retained history get the nearest end.
+    pub fn at(&self, time: Instant) -> Option<Pose> {
+        lock(&self.0).get(time)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    // the publisher never panics while holding a lock
+    mutex.lock().expect("Pose service poisoned")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controls::units::*;
+    use std::time::Duration;
+
+    fn pose_at(x: f64) -> Pose {
+        Pose {
+            x: x * M,
+            ..Pose::origin()
+        }
+    }
+
+    #[test]
+    fn subscribers_get_every_pose() {
+        let mut publisher = PosePublisher::new();
+        let mut a = publisher.subscribe();
+        let mut b = publisher.subscribe();
+        let now = Instant::now();
+        publisher.publish(pose_at(1.0), now);
+        publisher.publish(pose_at(2.0), now + Duration::from_millis(5));
+
+        assert_eq!(a.try_recv().map(|t| t.pose), Some(pose_at(1.0)));
+        assert_eq!(a.try_recv().map(|t| t.pose), Some(pose_at(2.0)));
+        assert_eq!(a.try_recv(), None);
+        assert_eq!(b.latest().map(|t| t.pose), Some(pose_at(2.0)));
+    }
+
+    #[test]
+    fn slow_subscriber_doesnt_block() {
+        let mut publisher = PosePublisher::new();
+        let mut slow = publisher.subscribe();
+        let now = Instant::now();
+        for i in 0..SUBSCRIBER_CAPACITY * 2 {
+            publisher.publish(pose_at(i as f64), now);
+        }
+        assert_eq!(slow.try_recv().map(|t| t.pose), Some(pose_at(0.0)));
+        assert_eq!(
+            publisher.handle().latest().map(|t| t.pose),
+            Some(pose_at((SUBSCRIBER_CAPACITY * 2 - 1) as f64))
+        );
+    }
+
+    #[test]
+    fn looks_up_past_poses() {
+        let mut publisher = PosePublisher::new();
+        let handle = publisher.handle();
+        assert_eq!(handle.at(Instant::now()), None);
+
+        let start = Instant::now();
+        for i in 0..10u32 {
+            let time = start + Duration::from_millis(10 * u64::from(i));
+            publisher.publish(pose_at(f64::from(i)), time);
+        }
+        let x = |time| *(handle.at(time).unwrap().x / M);
+        assert!((x(start + Duration::from_millis(25)) - 2.5).abs() < 1e-9);
+        assert!((x(start + Duration::from_millis(90)) - 9.0).abs() < 1e-9);
+        // clamped to the ends
+        assert!((x(start + Duration::from_secs(1)) - 9.0).abs() < 1e-9);
+        assert!(x(start - Duration::from_secs(1)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut publisher = PosePublisher::new();
+        let handle = publisher.handle();
+        let start = Instant::now();
+        for i in 0..HISTORY_LENGTH as u32 + 10 {
+            publisher.publish(
+                pose_at(f64::from(i)),
+                start + Duration::from_millis(i.into()),
+            );
+        }
+        // the oldest 10 have been forgotten
+        assert!((*(handle.at(start).unwrap().x / M) - 10.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,551 @@
+//! A differential-drive plant for checking [`Drive::generate_pose`] against ground truth.
+//!
+//! Each side's wheels are modelled separately from the chassis and push on it through the tires,
+//! whose traction saturates at the friction limit. Pushing harder than that spins the wheels, so
+//! the encoders count distance the robot never travelled.
+use super::heading::{Complementary, GyroOnly, HeadingFilter};
+use super::*;
+use controls::util::clamp;
+use controls::{HarnessAble, StateShim};
+use crossbeam_channel::unbounded;
+use hardware::fake::{Fake, FakeGyro, FakeSolenoid, FakeTalon};
+use serde_derive::Serialize;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+// CIM
+const MOTOR_STALL_TORQUE: f64 = 2.42; // N*m
+const MOTOR_STALL_CURRENT: f64 = 133.0; // A
+const MOTOR_FREE_SPEED: f64 = 5330.0 / 60.0 * 2.0 * PI; // rad/s
+const MOTOR_RESISTANCE: f64 = 12.0 / MOTOR_STALL_CURRENT; // Ohm
+const MOTOR_KT: f64 = MOTOR_STALL_TORQUE / MOTOR_STALL_CURRENT; // N*m / A
+const MOTOR_KV: f64 = MOTOR_FREE_SPEED / 12.0; // rad/s / V
+const MOTOR_INERTIA: f64 = 7.75e-5; // kg*m^2
+const MOTORS_PER_SIDE: f64 = 2.0;
+const BUS_VOLTAGE: f64 = 12.0;
+
+const LOW_GEAR_RATIO: f64 = 15.0;
+const HIGH_GEAR_RATIO: f64 = 7.0;
+const WHEEL_RADIUS: f64 = 3.0 /*in*/ * 0.0254; // m
+/// Wheels and output shafts of one side, about the axles
+const WHEEL_INERTIA: f64 = 0.005; // kg*m^2
+
+/// A robot like ours, with a 4096 tick encoder on each side's wheel shaft. The real robot's are
+/// in `config::drive`, once they're measured.
+pub const GEOMETRY: Geometry = Geometry {
+    meters_per_tick: const_unit!(2.0 * WHEEL_RADIUS * PI / 4096.0),
+    wheel_width: const_unit!(0.62),
+};
+
+/// With battery and bumpers
+const MASS: f64 = 60.0; // kg
+const MOMENT_OF_INERTIA: f64 = 5.5; // kg*m^2
+const TRACTION_COEFF: f64 = 1.1;
+/// Difference between wheel surface and ground speed at which the tires reach the friction limit
+const SATURATION_SLIP: f64 = 0.1; // m/s
+
+/// The physical state of the robot.
+#[derive(Debug, Copy, Clone)]
+pub struct DriveState {
+    pub x: Meter<f64>,
+    pub y: Meter<f64>,
+    pub heading: f64,
+    pub velocity: MeterPerSecond<f64>,
+    pub angular_velocity: Hertz<f64>,
+    /// Surface speed of the wheels, which only matches the ground while they grip
+    pub left_wheel: MeterPerSecond<f64>,
+    pub right_wheel: MeterPerSecond<f64>,
+    /// Distance turned by the wheels, as seen by the encoders
+    pub left_distance: Meter<f64>,
+    pub right_distance: Meter<f64>,
+}
+
+impl DriveState {
+    pub fn at_rest() -> Self {
+        Self {
+            x: 0.0 * M,
+            y: 0.0 * M,
+            heading: 0.0,
+            velocity: 0.0 * MPS,
+            angular_velocity: 0.0 / S,
+            left_wheel: 0.0 * MPS,
+            right_wheel: 0.0 * MPS,
+            left_distance: 0.0 * M,
+            right_distance: 0.0 * M,
+        }
+    }
+
+    fn step(self, volts: DriveVoltage, dt: f64) -> Self {
+        let ratio = match volts.gear {
+            Gear::High => HIGH_GEAR_RATIO,
+            Gear::Low => LOW_GEAR_RATIO,
+        };
+        let half_width = *(GEOMETRY.wheel_width / M) / 2.0;
+        let velocity = *(self.velocity / MPS);
+        let angular_velocity = *(self.angular_velocity * S);
+        let left_wheel = *(self.left_wheel / MPS);
+        let right_wheel = *(self.right_wheel / MPS);
+
+        let (left_traction, left_acc) = side_forces(
+            *(volts.left / V),
+            left_wheel,
+            velocity - angular_velocity * half_width,
+            ratio,
+        );
+        let (right_traction, right_acc) = side_forces(
+            *(volts.right / V),
+            right_wheel,
+            velocity + angular_velocity * half_width,
+            ratio,
+        );
+
+        let velocity = velocity + (left_traction + right_traction) / MASS * dt;
+        let angular_velocity = angular_velocity
+            + (right_traction - left_traction) * half_width / MOMENT_OF_INERTIA * dt;
+        let left_wheel = left_wheel + left_acc * dt;
+        let right_wheel = right_wheel + right_acc * dt;
+        let heading = self.heading + angular_velocity * dt;
+
+        Self {
+            x: self.x + velocity * heading.cos() * dt * M,
+            y: self.y + velocity * heading.sin() * dt * M,
+            heading,
+            velocity: velocity * MPS,
+            angular_velocity: angular_velocity / S,
+            left_wheel: left_wheel * MPS,
+            right_wheel: right_wheel * MPS,
+            left_distance: self.left_distance + left_wheel * dt * M,
+            right_distance: self.right_distance + right_wheel * dt * M,
+        }
+    }
+}
+
+/// Returns the force the tires put on the chassis and the acceleration of the wheel surface.
+fn side_forces(volts: f64, wheel: f64, ground: f64, ratio: f64) -> (f64, f64) {
+    let motor_speed = wheel / WHEEL_RADIUS * ratio;
+    let limit = f64::from(CURRENT_LIMIT_THRESHOLD);
+    let current = clamp(
+        (volts - motor_speed / MOTOR_KV) / MOTOR_RESISTANCE,
+        -limit,
+        limit,
+    );
+    let motor_force = MOTORS_PER_SIDE * MOTOR_KT * current * ratio / WHEEL_RADIUS;
+
+    let max_traction = TRACTION_COEFF * MASS * 9.81 / 2.0;
+    let traction = clamp(
+        (wheel - ground) / SATURATION_SLIP * max_traction,
+        -max_traction,
+        max_traction,
+    );
+    // rotating inertia on this side, as seen at the wheel surface
+    let side_mass = (MOTORS_PER_SIDE * MOTOR_INERTIA * ratio * ratio + WHEEL_INERTIA)
+        / (WHEEL_RADIUS * WHEEL_RADIUS);
+    (traction, (motor_force - traction) / side_mass)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DriveVoltage {
+    pub left: Volt<f64>,
+    pub right: Volt<f64>,
+    pub gear: Gear,
+}
+
+/// The drive base plant.
+#[derive(Debug)]
+pub struct DrivePlant;
+
+impl HarnessAble for DrivePlant {
+    type State = DriveState;
+    type ControlResponse = DriveVoltage;
+    type LogData = DriveLog;
+
+    fn sim_time(mut s: DriveState, r: DriveVoltage, dur: Second<f64>) -> DriveState {
+        let steps = (*(dur / Self::SIMUL_DT)).round() as u32;
+        let dt = *(Self::SIMUL_DT / S);
+        for _ in 0..steps {
+            s = s.step(r, dt);
+        }
+        s
+    }
+
+    const SIMUL_DT: Second<f64> = const_unit!(0.0001);
+    const CONTROL_DT: Second<f64> = const_unit!(0.005);
+}
+
+#[derive(Debug, Serialize)]
+pub struct DriveLog {
+    time: f64,
+    x: f64,
+    y: f64,
+    heading: f64,
+    est_x: f64,
+    est_y: f64,
+    est_heading: f64,
+}
+
+/// How far the pose estimate has wandered from the truth.
+#[derive(Debug, Copy, Clone)]
+pub struct Drift {
+    pub position: Meter<f64>,
+    pub heading: f64,
+}
+
+/// Feeds an [`DrivePlant`]'s wheel distances and heading through a [`Drive`]'s encoders and pose
+/// estimator, and its motor outputs back to the plant.
+pub struct DriveShim {
+    drive: Drive<Fake>,
+    left: FakeTalon,
+    right: FakeTalon,
+    shifter: FakeSolenoid,
+    gyro: FakeGyro,
+    pose: Pose,
+    /// Gyro bias, rad/s
+    gyro_drift: f64,
+    time: Second<f64>,
+    /// Simulated time zero, for the drive's setpoint timestamps
+    start: Instant,
+    /// Last velocity error of each side, for the talons' derivative term
+    last_err: (f64, f64),
+}
+
+impl DriveShim {
+    pub fn new() -> Self {
+        let left = FakeTalon::new();
+        let right = FakeTalon::new();
+        let shifter = FakeSolenoid::new();
+        let gyro = FakeGyro::new();
+        let drive = Drive::<Fake>::from_hardware(
+            DriveHardware {
+                l_mstr: left.clone(),
+                r_mstr: right.clone(),
+                l_slave: FakeTalon::new(),
+                r_slave: FakeTalon::new(),
+                gyro: gyro.clone(),
+                gear_shifter: shifter.clone(),
+            },
+            PosePublisher::new(),
+            unbounded().1,
+        )
+        .with_geometry(GEOMETRY);
+        Self {
+            drive,
+            left,
+            right,
+            shifter,
+            gyro,
+            pose: Pose::origin(),
+            gyro_drift: 0.0,
+            time: 0.0 * S,
+            start: Instant::now(),
+            last_err: (0.0, 0.0),
+        }
+    }
+
+    pub fn with_gyro_drift(self, rate: Hertz<f64>) -> Self {
+        Self {
+            gyro_drift: *(rate * S),
+            ..self
+        }
+    }
+
+    pub fn with_heading_filter(self, filter: impl HeadingFilter + Send + 'static) -> Self {
+        Self {
+            drive: self.drive.with_heading_filter(filter),
+            ..self
+        }
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) {
+        let now = self.start + Duration::from_secs_f64(*(self.time / S));
+        self.drive.execute(instruction, now);
+    }
+
+    /// The latest estimate
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    pub fn drift(&self, truth: DriveState) -> Drift {
+        let dx = *((self.pose.x - truth.x) / M);
+        let dy = *((self.pose.y - truth.y) / M);
+        Drift {
+            position: (dx * dx + dy * dy).sqrt() * M,
+            heading: self.pose.heading - truth.heading,
+        }
+    }
+
+    /// Percent output the talon firmware would apply
+    fn percent(talon: &FakeTalon, last_err: &mut f64) -> f64 {
+        let state = talon.state();
+        match state.mode {
+            ControlMode::PercentOutput => clamp(state.demand0, -1.0, 1.0),
+            ControlMode::Velocity => {
+                let config = talon_config(&DriveConfig::default()).base;
+                let slot = if state.profile_slot == velocity::high_gear::SLOT_IDX {
+                    config.slot_1
+                } else {
+                    config.slot_0
+                };
+                let err = state.demand0 - f64::from(state.velocity);
+                let pidf = slot.kP * err + slot.kD * (err - *last_err) + slot.kF * state.demand0;
+                *last_err = err;
+                let ff = match state.demand1_type {
+                    DemandType::ArbitraryFeedForward => state.demand1,
+                    _ => 0.0,
+                };
+                let peak = slot.closedLoopPeakOutput;
+                clamp(clamp(pidf / 1023.0, -peak, peak) + ff, -1.0, 1.0)
+            }
+            // anything else the drive doesn't use
+            _ => 0.0,
+        }
+    }
+}
+
+impl StateShim<DrivePlant> for DriveShim {
+    fn update(&mut self, state: DriveState) -> DriveVoltage {
+        for (talon, distance, speed) in &[
+            (&self.left, state.left_distance, state.left_wheel),
+            (&self.right, state.right_distance, state.right_wheel),
+        ] {
+            let mut talon = talon.state();
+            talon.position = (*(*distance / GEOMETRY.meters_per_tick)).round() as i32;
+            talon.velocity = GEOMETRY.to_native_velocity(*speed).round() as i32;
+        }
+        self.gyro
+            .set_heading(state.heading + self.gyro_drift * *(self.time / S));
+        self.pose = self.drive.generate_pose(&self.pose, DrivePlant::CONTROL_DT);
+        self.time += DrivePlant::CONTROL_DT;
+
+        DriveVoltage {
+            left: Self::percent(&self.left, &mut self.last_err.0) * BUS_VOLTAGE * V,
+            right: Self::percent(&self.right, &mut self.last_err.1) * BUS_VOLTAGE * V,
+            gear: if self.shifter.get() == shifter::HIGH_GEAR {
+                Gear::High
+            } else {
+                Gear::Low
+            },
+        }
+    }
+
+    fn log_dat(
+        &mut self,
+        state: DriveState,
+        _response: DriveVoltage,
+        time: Second<f64>,
+    ) -> DriveLog {
+        DriveLog {
+            time: *(time / S),
+            x: *(state.x / M),
+            y: *(state.y / M),
+            heading: state.heading,
+            est_x: *(self.pose.x / M),
+            est_y: *(self.pose.y / M),
+            est_heading: self.pose.heading,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::vision::{Target, TargetPublisher};
+    use controls::SimulationHarness;
+    use std::f64::consts::FRAC_PI_2;
+
+    /// Runs each instruction for the given number of seconds, returning where the robot ended up
+    /// and how far the estimate drifted.
+    fn run_script(shim: DriveShim, script: &[(Instruction, f64)]) -> (DriveState, Drift) {
+        let mut h = SimulationHarness::<DrivePlant, _>::new(shim, DriveState::at_rest(), 20);
+        let mut state = DriveState::at_rest();
+        for &(instruction, secs) in script {
+            h.shim_mut().execute(instruction);
+            state = h.run_time(secs * S);
+        }
+        let drift = h.shim().drift(state);
+        (state, drift)
+    }
+
+    #[test]
+    fn straight_line() {
+        let (state, drift) = run_script(
+            DriveShim::new(),
+            &[
+                (Instruction::GearShift(Gear::High), 0.0),
+                (Instruction::Percentage(0.5, 0.5), 3.0),
+                (Instruction::Percentage(0.0, 0.0), 2.0),
+            ],
+        );
+        assert!(state.x > 3.0 * M);
+        assert!(drift.position < 0.01 * M, "{:?}", drift);
+    }
+
+    #[test]
+    fn arc() {
+        let (state, drift) = run_script(
+            DriveShim::new(),
+            &[
+                (Instruction::GearShift(Gear::High), 0.0),
+                (Instruction::Percentage(0.3, 0.4), 2.5),
+                (Instruction::Percentage(0.0, 0.0), 2.0),
+            ],
+        );
+        assert!(state.heading > PI / 2.0);
+        assert!(drift.position < 0.05 * M, "{:?}", drift);
+    }
+
+    #[test]
+    fn square() {
+        let side = [
+            (Instruction::Percentage(0.4, 0.4), 1.5),
+            (Instruction::Percentage(0.0, 0.0), 0.5),
+            (Instruction::Percentage(-0.3, 0.3), 0.6),
+            (Instruction::Percentage(0.0, 0.0), 0.5),
+        ];
+        let script: Vec<_> = side.iter().cycle().take(4 * side.len()).cloned().collect();
+        let (_, drift) = run_script(DriveShim::new(), &script);
+        assert!(drift.position < 0.05 * M, "{:?}", drift);
+    }
+
+    #[test]
+    fn wheel_slip_overcounts() {
+        let (state, drift) = run_script(
+            DriveShim::new(),
+            &[
+                // launching in low gear asks for more than the tires can give
+                (Instruction::GearShift(Gear::Low), 0.0),
+                (Instruction::Percentage(1.0, 1.0), 0.5),
+                (Instruction::Percentage(0.0, 0.0), 1.0),
+            ],
+        );
+        assert!(state.left_distance > state.x);
+        assert!(drift.position > 0.001 * M);
+    }
+
+    #[test]
+    fn gyro_drift_bends_estimate() {
+        let script = [
+            (Instruction::GearShift(Gear::High), 0.0),
+            (Instruction::Percentage(0.5, 0.5), 5.0),
+        ];
+        let (_, clean) = run_script(DriveShim::new().with_heading_filter(GyroOnly), &script);
+        let (_, biased) = run_script(
+            DriveShim::new()
+                .with_gyro_drift(0.01 / S)
+                .with_heading_filter(GyroOnly),
+            &script,
+        );
+        assert!(biased.position > clean.position + 0.05 * M);
+        assert!((biased.heading - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn filters_reject_gyro_drift() {
+        let script = [
+            (Instruction::GearShift(Gear::High), 0.0),
+            (Instruction::Percentage(0.5, 0.5), 5.0),
+        ];
+        let drifting = || DriveShim::new().with_gyro_drift(0.01 / S);
+        let (_, raw) = run_script(drifting().with_heading_filter(GyroOnly), &script);
+        let (_, complementary) = run_script(
+            drifting().with_heading_filter(Complementary::default()),
+            &script,
+        );
+        let (_, kalman) = run_script(drifting(), &script);
+        assert!(complementary.heading.abs() < raw.heading.abs() / 2.0);
+        assert!(kalman.heading.abs() < raw.heading.abs() / 2.0);
+        assert!(kalman.position < raw.position / 2.0);
+    }
+
+    #[test]
+    fn gyro_corrects_wheel_slip() {
+        // launching one side in low gear at full power breaks its traction
+        let script = [
+            (Instruction::GearShift(Gear::Low), 0.0),
+            (Instruction::Percentage(1.0, 0.0), 0.3),
+        ];
+        let encoders_only = Complementary::new(0.0 * S);
+        let (_, slipping) =
+            run_script(DriveShim::new().with_heading_filter(encoders_only), &script);
+        let (_, fused) = run_script(DriveShim::new(), &script);
+        assert!(slipping.heading.abs() > 0.01);
+        assert!(fused.heading.abs() < slipping.heading.abs() / 2.0);
+    }
+
+    #[test]
+    fn native_velocity_round_trip() {
+        let native = GEOMETRY.to_native_velocity(2.0 * MPS);
+        let back = GEOMETRY.velocity_from_native(native.round() as i32);
+        assert!((*(back / MPS) - 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tuned_gains_reach_talons() {
+        let registry = tunables::Registry::new();
+        let mut shim = DriveShim::new();
+        shim.drive = shim.drive.with_tuning(&registry);
+        registry.set("drive/high_gear/kp", 0.75).unwrap();
+        registry.set("drive/low_gear/ks", 0.5).unwrap();
+        shim.drive.update_gains();
+
+        let slot = velocity::high_gear::SLOT_IDX as usize;
+        for talon in &[&shim.left, &shim.right] {
+            assert!((talon.state().gains[slot].kp - 0.75).abs() < 1e-9);
+        }
+        let ff = shim.drive.gains.for_gear(Gear::Low).feedforward();
+        assert!((*(ff.ks / V) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tracks_velocity() {
+        let (state, _) = run_script(
+            DriveShim::new(),
+            &[
+                (Instruction::GearShift(Gear::High), 0.0),
+                (Instruction::Velocity(2.0 * MPS, 2.0 * MPS), 2.0),
+            ],
+        );
+        assert!((*(state.velocity / MPS) - 2.0).abs() < 0.05);
+        assert!((*(state.angular_velocity * S)).abs() < 0.01);
+    }
+
+    #[test]
+    fn tracks_turning_velocity() {
+        let (state, _) = run_script(
+            DriveShim::new(),
+            &[
+                (Instruction::GearShift(Gear::Low), 0.0),
+                (Instruction::Velocity(0.5 * MPS, 1.5 * MPS), 2.0),
+            ],
+        );
+        assert!((*(state.left_wheel / MPS) - 0.5).abs() < 0.05);
+        assert!((*(state.right_wheel / MPS) - 1.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn locates_targets_from_where_they_were_seen() {
+        let mut shim = DriveShim::new();
+        let mut targets = TargetPublisher::new();
+        shim.drive = shim.drive.with_vision(targets.handle());
+        let seen = shim.start;
+        let now = seen + Duration::from_millis(50);
+        let mut then = Pose::origin();
+        then.heading = FRAC_PI_2;
+        shim.drive.poses.publish(then, seen);
+        shim.drive.poses.publish(Pose::origin(), now);
+        assert!(shim.drive.record(&Pose::origin(), now).target_x_m.is_nan());
+
+        targets.publish(Target {
+            captured: seen,
+            received: now,
+            location: Location {
+                bearing: 0.0,
+                range: 2.0 * M,
+            },
+        });
+        let record = shim.drive.record(&Pose::origin(), now);
+        // straight ahead of where the robot faced then, not where it faces now
+        assert!(record.target_x_m.abs() < 1e-9, "{:?}", record);
+        assert!((record.target_y_m - 2.0).abs() < 1e-9, "{:?}", record);
+    }
+}
@@ -1,6 +1,12 @@
 pub mod controller;
+pub mod dashboard;
 pub mod drive;
+pub mod periodic;
 pub mod superstructure;
+pub mod supervisor;
+pub mod telemetry;
+pub mod tuning;
+pub mod vision;
 
 pub trait Subsystem {
     fn run(self);
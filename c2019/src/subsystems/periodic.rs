@@ -0,0 +1,156 @@
+//! Runs a subsystem's loop at a fixed rate.
+//!
+//! Deadlines are absolute, `period` apart from when the loop started, so the rate doesn't drift
+//! with how long the work takes. A loop that overruns its deadline starts the next one right away,
+//! and the deadlines after it are counted from then rather than bunched up to catch up. Each
+//! [`Tick`] has the time actually measured since the last, for anything that integrates over it.
+use rio_benches::rt::{pin_thread_to_cpu, set_thread_priority};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Realtime scheduling for a loop's thread.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Realtime {
+    /// `SCHED_FIFO` priority, above the RIO's kernel threads
+    pub priority: i32,
+    pub cpu: Option<usize>,
+}
+
+impl Realtime {
+    /// Makes the calling thread realtime. Usually needs root.
+    pub fn apply(&self) -> io::Result<()> {
+        set_thread_priority(self.priority)?;
+        if let Some(cpu) = self.cpu {
+            pin_thread_to_cpu(cpu)?;
+        }
+        Ok(())
+    }
+}
+
+/// One loop's start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tick {
+    /// Since the last loop started, or the period for the first loop
+    pub dt: Duration,
+    /// How long after its deadline the loop started
+    pub jitter: Duration,
+    /// Whether the last loop was still running at this one's deadline
+    pub overran: bool,
+}
+
+/// Jitter and overruns over some loops.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub loops: usize,
+    pub overruns: usize,
+    pub worst_jitter: Duration,
+    total_jitter: Duration,
+}
+
+impl Stats {
+    pub fn record(&mut self, tick: &Tick) {
+        self.loops += 1;
+        if tick.overran {
+            self.overruns += 1;
+        }
+        self.worst_jitter = self.worst_jitter.max(tick.jitter);
+        self.total_jitter += tick.jitter;
+    }
+
+    pub fn mean_jitter(&self) -> Duration {
+        if self.loops == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.total_jitter / self.loops as u32
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Periodic {
+    period: Duration,
+    deadline: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Periodic {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            deadline: None,
+            last: None,
+        }
+    }
+
+    /// Sleeps until the next loop's deadline. The first loop starts right away.
+    pub fn wait(&mut self) -> Tick {
+        let now = Instant::now();
+        let deadline = self.deadline.unwrap_or(now);
+        let overran = now > deadline && self.last.is_some();
+        if now < deadline {
+            thread::sleep(deadline - now);
+        }
+        let start = Instant::now();
+        let jitter = if overran {
+            // the lateness is the last loop's fault, not the scheduler's
+            Duration::from_secs(0)
+        } else {
+            start - deadline
+        };
+        let dt = self.last.map_or(self.period, |last| start - last);
+        self.last = Some(start);
+        let from = if overran { start } else { deadline };
+        self.deadline = Some(from + self.period);
+        Tick {
+            dt,
+            jitter,
+            overran,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn keeps_the_rate_despite_work() {
+        let mut periodic = Periodic::new(PERIOD);
+        let begin = Instant::now();
+        assert_eq!(periodic.wait().dt, PERIOD);
+        let mut stats = Stats::default();
+        for _ in 0..10 {
+            thread::sleep(PERIOD / 2);
+            let tick = periodic.wait();
+            stats.record(&tick);
+        }
+        // sleeping for a whole period after the work would have taken 15
+        let elapsed = begin.elapsed();
+        assert!(
+            elapsed >= PERIOD * 10 && elapsed < PERIOD * 14,
+            "{:?}",
+            elapsed
+        );
+        assert_eq!(stats.loops, 10);
+        assert_eq!(stats.overruns, 0);
+        assert!(stats.mean_jitter() <= stats.worst_jitter);
+    }
+
+    #[test]
+    fn restarts_the_schedule_after_an_overrun() {
+        let mut periodic = Periodic::new(PERIOD);
+        periodic.wait();
+        thread::sleep(PERIOD * 3);
+        let late = periodic.wait();
+        assert!(late.overran);
+        assert!(late.dt >= PERIOD * 3);
+        assert_eq!(late.jitter, Duration::from_secs(0));
+        // not rushed to make up the missed loops
+        let next = periodic.wait();
+        assert!(!next.overran);
+        assert!(next.dt >= PERIOD);
+    }
+}
@@ -1,11 +1,27 @@
+use super::periodic::Periodic;
+use super::supervisor::Heartbeat;
+use super::telemetry::{Log, SuperstructureRecord};
 use super::Subsystem;
-use crossbeam_channel::Receiver;
+use crate::config::telemetry::KEYFRAME_PERIOD;
+use crate::config::SUBSYSTEM_PERIOD;
+use crate::replay::Frame;
+use crossbeam_channel::{Receiver, Sender};
+use hardware::fake::{Fake, FakeAnalogInput, FakeDigitalInput, FakeSolenoid, FakeTalon};
+use hardware::io::{Hardware, Rio, SolenoidOut, Talon};
+use hardware::record::{Playback, Recorded, Recording, Sample, Tape};
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
 
 mod hatch_hardware;
 use hatch_hardware::HatchHardware;
 
 mod channel;
-use ctre_elevator_tuning::Elevator;
+pub use channel::{BallProgress, Fault};
+pub mod machine;
+use ctre_elevator_tuning::{Elevator, ElevatorSnapshot};
+use machine::Command;
+pub use machine::{Mode, Reason, Rejection};
+use tunables::Registry;
 
 #[derive(Debug, Clone)]
 pub struct PeriodicOuts {
@@ -44,7 +60,7 @@ impl From<ctre::ErrorCode> for HalCtreError {
 }
 
 // Utility
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HatchPneumaticExt {
     Extended,
     Retracted,
@@ -65,7 +81,7 @@ impl Into<bool> for IntakeExt {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HatchState {
     pub extend: HatchPneumaticExt,
     pub outtake: HatchPneumaticExt,
@@ -74,8 +90,12 @@ pub struct HatchState {
 // Interface for the controlling channel
 mod interface {
     use super::goal::BallGoalHeight;
+    use super::goal::GoalState;
     use super::goal::HatchGoalHeight;
-    use super::HatchPneumaticExt;
+    use super::{Fault, HatchPneumaticExt, Mode, Rejection};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum UserElevatorHeights {
         Low,
         Med,
@@ -103,6 +123,7 @@ mod interface {
         }
     }
 
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Instruction {
         SetElevatorHeight(UserElevatorHeights),
         Unjam(bool),
@@ -115,20 +136,43 @@ mod interface {
         BeginElevatorPanic,
         ForceElevatorZero,
     }
+
+    /// What the superstructure tells the controller about its commands, and itself.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Feedback {
+        Accepted(Instruction),
+        Rejected(Rejection),
+        /// A command that takes a while is done: the ball an intake was waiting for came in, an
+        /// outtaken ball left, or the elevator reached a new height. Others complete as soon as
+        /// they're accepted.
+        Completed(Instruction),
+        /// Sent whenever it changes
+        Status(Status),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Status {
+        pub mode: Mode,
+        pub goal: GoalState,
+        pub balls: usize,
+        pub fault: Option<Fault>,
+    }
 }
 pub use interface::*;
 
 // Data types for goal states
+pub use goal::{BallGoalHeight, GoalState, HatchGoalHeight};
 mod goal {
     use super::HatchState;
     use controls::units;
-    #[derive(Debug, Clone, PartialEq, Eq)]
+    use serde_derive::{Deserialize, Serialize};
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum GoalState {
         Ball(BallGoalHeight),
         Hatch(HatchGoalHeight, HatchState),
     }
 
-    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum BallGoalHeight {
         None,
         Cargo,
@@ -153,7 +197,7 @@ mod goal {
         }
     }
 
-    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum HatchGoalHeight {
         Low,
         Med,
@@ -184,46 +228,302 @@ mod goal {
 mod unjam;
 
 use ctre::motor_control::{ControlMode, DemandType, MotorController, TalonSRX};
-use wpilib::{pneumatics::Solenoid, HalResult};
+use wpilib::{dio::DigitalInput, pneumatics::Solenoid, HalResult};
 #[derive(Debug)]
-pub struct Superstructure {
+pub struct Superstructure<H: Hardware = Rio> {
     goal: goal::GoalState,
     unjam: unjam::UnjamState,
-    hatch_hardware: HatchHardware,
-    channel: channel::Channel,
-    elevator: Elevator,
-    im: CachingTalon,
-    is: CachingSolenoid,
-    climb: CachingSolenoid,
-    om: CachingTalon,
-    pressure_sensor: Rev111107DS00PressureSensor,
+    hatch_hardware: HatchHardware<H>,
+    channel: channel::Channel<H>,
+    elevator: Elevator<H>,
+    im: CachingTalon<H::Talon>,
+    is: CachingSolenoid<H::Solenoid>,
+    climb: CachingSolenoid<H::Solenoid>,
+    om: CachingTalon<H::Talon>,
+    pressure_sensor: Rev111107DS00PressureSensor<H::AnalogInput>,
     receiver: Receiver<Instruction>,
+    /// Where the controller hears how its commands went
+    feedback: Option<Sender<Feedback>>,
+    /// Accepted commands that haven't completed yet
+    pending: Vec<Instruction>,
+    /// What the controller was last told
+    status: Option<Status>,
+    log: Log,
+    /// Where the hardware records to, if it does
+    tape: Option<Tape>,
+    /// Loops recorded, to snapshot every `KEYFRAME_PERIOD`
+    recorded: u32,
+    heartbeat: Heartbeat,
+}
+
+/// One loop of the superstructure, for replaying: the commands it handled, how long since the
+/// last loop, and what its hardware read and was told.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuperstructureFrame {
+    pub commands: Vec<Instruction>,
+    /// Missing from recordings made before loops were timed, which ran at about this
+    #[serde(default = "nominal_period")]
+    pub dt: Duration,
+    pub hardware: Sample,
+    /// Taken before the loop, every `KEYFRAME_PERIOD` loops
+    #[serde(default)]
+    pub snapshot: Option<SuperstructureSnapshot>,
+}
+
+/// The superstructure's logic between loops, for a replay to pick up from partway through a
+/// match log. The controller's feedback isn't replayed, so the pending commands and the last
+/// status aren't kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuperstructureSnapshot {
+    goal: goal::GoalState,
+    unjam: unjam::UnjamState,
+    hatch: HatchState,
+    channel: channel::ChannelSnapshot,
+    elevator: ElevatorSnapshot,
+    /// What was last sent, since outputs are only sent when they change
+    intake_pct: Option<f64>,
+    outtake_pct: Option<f64>,
+    intake_extended: bool,
+    climb: bool,
 }
-use crate::config::superstructure as config;
+
+fn nominal_period() -> Duration {
+    SUBSYSTEM_PERIOD
+}
+
+/// Every device the superstructure owns, already configured.
+#[derive(Debug)]
+pub struct SuperstructureHardware<H: Hardware> {
+    pub intake_talon: H::Talon,
+    pub outtake_talon: H::Talon,
+    pub intake_solenoid: H::Solenoid,
+    pub climb_solenoid: H::Solenoid,
+    pub hatch_extend: H::Solenoid,
+    pub hatch_outtake: H::Solenoid,
+    pub gates: (H::DigitalInput, H::DigitalInput, H::DigitalInput),
+    pub pressure: H::AnalogInput,
+    pub elevator: Elevator<H>,
+}
+
+use crate::config::robot::SuperstructureConfig;
 impl Superstructure {
-    pub fn new(recv: Receiver<Instruction>) -> HalResult<Self> {
-        let mut im = TalonSRX::new(config::CHANNEL_TALON);
+    pub fn new(config: &SuperstructureConfig, recv: Receiver<Instruction>) -> HalResult<Self> {
+        Self::from_hardware(SuperstructureHardware::new(config)?, recv)
+    }
+}
+
+impl SuperstructureHardware<Rio> {
+    pub fn new(config: &SuperstructureConfig) -> HalResult<Self> {
+        let mut im = TalonSRX::new(config.channel_talon);
         im.config_openloop_ramp(0.1, 100);
+        Ok(SuperstructureHardware {
+            intake_talon: im,
+            outtake_talon: TalonSRX::new(config.outtake_talon),
+            intake_solenoid: Solenoid::new(config.intake_solenoid)?,
+            climb_solenoid: Solenoid::new(config.climb_solenoid)?,
+            hatch_extend: Solenoid::new(config.hatch.extend_solenoid)?,
+            hatch_outtake: Solenoid::new(config.hatch.outtake_solenoid)?,
+            gates: (
+                DigitalInput::new(config.gates[0])?,
+                DigitalInput::new(config.gates[1])?,
+                DigitalInput::new(config.gates[2])?,
+            ),
+            pressure: AnalogInput::new(config.pressure_sensor)?,
+            elevator: Elevator::with_ids(
+                config.elevator.master_talon,
+                config.elevator.slave_talons,
+                config.elevator.limit_switch,
+                &config.elevator.current_limits(),
+            )?,
+        })
+    }
+}
+
+impl<H: Hardware> SuperstructureHardware<H> {
+    /// Records every device to `tape`, under the names the replay tool expects.
+    pub fn recorded(self, tape: &Tape) -> SuperstructureHardware<Recording<H>> {
+        let (gate1, gate2, gate3) = self.gates;
+        SuperstructureHardware {
+            intake_talon: Recorded::new(self.intake_talon, "superstructure/intake_talon", tape),
+            outtake_talon: Recorded::new(self.outtake_talon, "superstructure/outtake_talon", tape),
+            intake_solenoid: Recorded::new(
+                self.intake_solenoid,
+                "superstructure/intake_solenoid",
+                tape,
+            ),
+            climb_solenoid: Recorded::new(
+                self.climb_solenoid,
+                "superstructure/climb_solenoid",
+                tape,
+            ),
+            hatch_extend: Recorded::new(self.hatch_extend, "superstructure/hatch_extend", tape),
+            hatch_outtake: Recorded::new(self.hatch_outtake, "superstructure/hatch_outtake", tape),
+            gates: (
+                Recorded::new(gate1, "superstructure/gate1", tape),
+                Recorded::new(gate2, "superstructure/gate2", tape),
+                Recorded::new(gate3, "superstructure/gate3", tape),
+            ),
+            pressure: Recorded::new(self.pressure, "superstructure/pressure", tape),
+            elevator: self.elevator.recorded(tape),
+        }
+    }
+}
+
+impl SuperstructureHardware<Recording<Fake>> {
+    /// Fakes named like `recorded`'s devices, for replaying a recording.
+    pub fn replaying(playback: &mut Playback, tape: &Tape) -> Self {
+        let talon = |playback: &mut Playback, name| playback.device(FakeTalon::new(), name, tape);
+        let solenoid =
+            |playback: &mut Playback, name| playback.device(FakeSolenoid::new(), name, tape);
+        let gate = |playback: &mut Playback, name| {
+            playback.device(FakeDigitalInput::new(true), name, tape)
+        };
+        SuperstructureHardware {
+            intake_talon: talon(playback, "superstructure/intake_talon"),
+            outtake_talon: talon(playback, "superstructure/outtake_talon"),
+            intake_solenoid: solenoid(playback, "superstructure/intake_solenoid"),
+            climb_solenoid: solenoid(playback, "superstructure/climb_solenoid"),
+            hatch_extend: solenoid(playback, "superstructure/hatch_extend"),
+            hatch_outtake: solenoid(playback, "superstructure/hatch_outtake"),
+            gates: (
+                gate(playback, "superstructure/gate1"),
+                gate(playback, "superstructure/gate2"),
+                gate(playback, "superstructure/gate3"),
+            ),
+            pressure: playback.device(FakeAnalogInput::new(0.0), "superstructure/pressure", tape),
+            elevator: Elevator::replaying(playback, tape),
+        }
+    }
+}
+
+impl<H: Hardware> Superstructure<H> {
+    pub fn from_hardware(
+        hw: SuperstructureHardware<H>,
+        recv: Receiver<Instruction>,
+    ) -> HalResult<Self> {
         Ok(Self {
             goal: goal::GoalState::Hatch(
                 goal::HatchGoalHeight::Low,
                 hatch_hardware::CLOSED_HATCH_STATE,
             ),
             unjam: unjam::UnjamState::Disabled,
-            hatch_hardware: hatch_hardware::HatchHardware::new()?,
-            channel: channel::Channel::new()?,
-            elevator: Elevator::new()?,
-            im: CachingTalon::new(im),
-            is: CachingSolenoid::new(Solenoid::new(config::INTAKE_SOLENOID)?)?,
-            om: CachingTalon::new(TalonSRX::new(config::OUTTAKE_TALON)),
-            climb: CachingSolenoid::new(Solenoid::new(config::CLIMB_SOLENOID)?)?,
-            pressure_sensor: Rev111107DS00PressureSensor::new(AnalogInput::new(3)?, 5.0),
+            hatch_hardware: HatchHardware::new(hw.hatch_extend, hw.hatch_outtake),
+            channel: channel::Channel::new(hw.gates),
+            elevator: hw.elevator,
+            im: CachingTalon::new(hw.intake_talon),
+            is: CachingSolenoid::new(hw.intake_solenoid)?,
+            om: CachingTalon::new(hw.outtake_talon),
+            climb: CachingSolenoid::new(hw.climb_solenoid)?,
+            pressure_sensor: Rev111107DS00PressureSensor::new(hw.pressure, 5.0),
             receiver: recv,
+            feedback: None,
+            pending: Vec::new(),
+            status: None,
+            log: Log::discard(),
+            tape: None,
+            recorded: 0,
+            heartbeat: Heartbeat::new(),
         })
     }
 }
 
-impl Superstructure {
+impl<H: Hardware> Superstructure<H> {
+    /// Makes the channel commands and elevator gains live tunable.
+    pub fn with_tuning(self, registry: &Registry) -> Self {
+        Self {
+            channel: self
+                .channel
+                .with_commands(channel::Commands::register(registry)),
+            elevator: self.elevator.with_tuning(registry),
+            ..self
+        }
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+
+    pub fn with_heartbeat(self, heartbeat: Heartbeat) -> Self {
+        Self { heartbeat, ..self }
+    }
+
+    /// Tells `feedback` how every command went, and the superstructure's status when it changes.
+    pub fn with_feedback(self, feedback: Sender<Feedback>) -> Self {
+        Self {
+            feedback: Some(feedback),
+            ..self
+        }
+    }
+
+    /// Logs a frame for the replay tool every loop, taking it from `tape`. The hardware should
+    /// have been `recorded` to the same tape.
+    pub fn with_recording(self, tape: Tape) -> Self {
+        Self {
+            tape: Some(tape),
+            ..self
+        }
+    }
+
+    /// Records an error that the loop carries on through.
+    fn log_err<T, E: std::fmt::Debug>(&self, doing: &str, result: Result<T, E>) {
+        if let Err(e) = result {
+            self.log
+                .event("superstructure", format!("{}: {:?}", doing, e));
+        }
+    }
+
+    fn record(&self, outs: &PeriodicOuts) -> SuperstructureRecord {
+        SuperstructureRecord {
+            elevator_state: format!("{:?}", self.elevator.state()),
+            elevator_goal_m: *(outs.elev_pos / controls::units::M),
+            mode: format!("{:?}", self.mode()),
+            channel: format!("{:?}", self.channel.state()),
+            balls: self.channel.balls(),
+            intake_pct: outs.intk_pct,
+            outtake_pct: outs.outk_pct,
+            intake_extended: outs.intk_pnm,
+            pressure_psi: self.pressure_sensor.psi().unwrap_or(std::f64::NAN),
+        }
+    }
+
+    pub fn snapshot(&self) -> SuperstructureSnapshot {
+        SuperstructureSnapshot {
+            goal: self.goal.clone(),
+            unjam: self.unjam,
+            hatch: self.hatch_hardware.state().clone(),
+            channel: self.channel.snapshot(),
+            elevator: self.elevator.snapshot(),
+            intake_pct: self.im.sent_pct(),
+            outtake_pct: self.om.sent_pct(),
+            intake_extended: self.is.sent(),
+            climb: self.climb.sent(),
+        }
+    }
+
+    /// Picks up from a snapshot, as if the loops before it had run here.
+    pub fn restore(&mut self, snapshot: &SuperstructureSnapshot) {
+        self.goal = snapshot.goal.clone();
+        self.unjam = snapshot.unjam;
+        self.hatch_hardware.restore(snapshot.hatch.clone());
+        self.channel.restore(&snapshot.channel);
+        self.elevator.restore(&snapshot.elevator);
+        self.im.restore_pct(snapshot.intake_pct);
+        self.om.restore_pct(snapshot.outtake_pct);
+        self.is.restore(snapshot.intake_extended);
+        self.climb.restore(snapshot.climb);
+    }
+
+    /// A snapshot for this loop's frame, if it's recorded and due one.
+    fn keyframe(&mut self) -> Option<SuperstructureSnapshot> {
+        self.tape.as_ref()?;
+        self.recorded += 1;
+        if self.recorded % KEYFRAME_PERIOD == 0 {
+            Some(self.snapshot())
+        } else {
+            None
+        }
+    }
+
     fn flush_outs(&mut self, out: &PeriodicOuts) -> Result<(), HalCtreError> {
         // TODO replace with individual handling
         // or consider using Result::and() to chain these as is
@@ -235,128 +535,274 @@ impl Superstructure {
     }
 }
 
-impl Subsystem for Superstructure {
-    fn run(mut self) {
+impl<H: Hardware> Superstructure<H> {
+    /// Runs one loop, `dt` after the last: handles queued commands, then updates the hardware.
+    pub fn iterate(&mut self, dt: Duration) {
+        let snapshot = self.keyframe();
+        let mut outs = PeriodicOuts::default();
+        let balls = self.channel.balls();
+        let commands: Vec<Instruction> = self.receiver.try_iter().collect();
+        for &command in &commands {
+            match self.handle(command) {
+                Ok(()) => self.accept(command),
+                Err(reason) => self.reject(Rejection {
+                    command,
+                    mode: self.mode(),
+                    reason,
+                }),
+            }
+        }
+
+        let mode = self.mode();
+        self.update(&mut outs);
+        let now = self.mode();
+        if now != mode && machine::event_between(mode, now).is_none() {
+            self.log.event(
+                "superstructure",
+                format!("unexpected transition from {:?} to {:?}", mode, now),
+            );
+        }
+
+        let elevator = self.elevator.iterate(dt.as_secs_f64() * controls::units::S);
+        self.log_err("running the elevator", elevator);
+
+        self.channel.write_outs(&mut outs);
+        // Unjam gets to override everyone else
+        self.unjam.process();
+        self.unjam.write_outs(&mut outs);
+        let flushed = self.flush_outs(&outs);
+        self.log_err("writing outputs", flushed);
+        self.complete(balls);
+        self.report_status();
+        self.log.record(self.record(&outs));
+        if let Some(tape) = &self.tape {
+            self.log.frame(Frame::Superstructure(SuperstructureFrame {
+                commands,
+                dt,
+                hardware: tape.take(),
+                snapshot,
+            }));
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        Mode::of(&self.goal, *self.channel.state())
+    }
+
+    /// Carries out a command, if the transition table allows it in the current mode.
+    fn handle(&mut self, command: Instruction) -> Result<(), Reason> {
         use goal::*;
-        let mut pnm_print_count: u32 = 0;
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(5));
-            if pnm_print_count > 100 {
-                pnm_print_count = 0;
-                println!(
-                    "Pnuematic System PSI: {}",
-                    self.pressure_sensor.psi().unwrap_or(std::f64::NAN)
-                );
-                println!("Elevator State: {:?}", self.elevator.state());
-                println!("Channel State: {:?}", self.channel.state());
+        use Instruction::*;
+        let from = self.mode();
+        let to = machine::target(from, command.into()).ok_or(Reason::Mode)?;
+        match command {
+            BallIntake(true) if to == Mode::Intaking => {
+                self.goal = GoalState::Ball(BallGoalHeight::None);
             }
-            pnm_print_count += 1;
-            let mut outs = PeriodicOuts::default();
-            for msg in self.receiver.try_iter() {
-                use Instruction::*;
-                match msg {
-                    HatchExtend(ext) => match &mut self.goal {
-                        GoalState::Hatch(ref _height, ref mut ext_state) => {
-                            ext_state.extend = ext;
-                        }
-                        _ => (),
-                    },
-                    HatchOuttake(ext) => match &mut self.goal {
-                        GoalState::Hatch(ref _height, ref mut ext_state) => {
-                            ext_state.outtake = ext;
-                        }
-                        _ => (),
-                    },
-                    Unjam(x) => self.unjam.set_enabled(x),
-                    BallIntake(true) => self.goal = GoalState::Ball(BallGoalHeight::None),
-                    BallIntake(false) => {
-                        if self.channel.try_abort_intk() {
-                            self.goal = GoalState::Hatch(
-                                HatchGoalHeight::Low,
-                                hatch_hardware::CLOSED_HATCH_STATE,
-                            );
-                        }
-                    }
-                    SetElevatorHeight(wanted) => match self.goal {
-                        GoalState::Hatch(ref mut height, _) => {
-                            *height = wanted.into_hatch();
-                        }
-                        GoalState::Ball(ref mut height) => {
-                            if self.channel.is_in_carriage() {
-                                *height = wanted.into_ball();
-                            }
-                        }
-                    },
-                    BallOuttake(true) => {
-                        self.channel.try_init_outk();
-                    }
-                    BallOuttake(false) => {
-                        self.channel.try_stop_outk();
-                    }
-                    ForceAbortBall => {
-                        self.channel.force_abort();
-                        self.goal = GoalState::Hatch(
-                            HatchGoalHeight::Low,
-                            hatch_hardware::CLOSED_HATCH_STATE,
-                        );
-                    }
-                    Climb(do_ext) => {
-                        self.climb.set(do_ext).ok();
-                    }
-                    BeginElevatorPanic => {
-                        self.elevator.try_init_panic();
-                    }
-                    ForceElevatorZero => {
-                        self.elevator.force_begin_zero();
-                    }
+            BallIntake(true) => {
+                if !self.channel.try_intake_another() {
+                    return Err(Reason::Full);
+                }
+            }
+            BallIntake(false) => {
+                self.channel.try_abort_intk();
+                if to == Mode::Hatch {
+                    self.pending.clear();
+                    self.goal =
+                        GoalState::Hatch(HatchGoalHeight::Low, hatch_hardware::CLOSED_HATCH_STATE);
+                }
+            }
+            ForceAbortBall => {
+                self.pending.clear();
+                self.channel.force_abort();
+                self.goal =
+                    GoalState::Hatch(HatchGoalHeight::Low, hatch_hardware::CLOSED_HATCH_STATE);
+            }
+            SetElevatorHeight(wanted) => match self.goal {
+                GoalState::Hatch(ref mut height, _) => *height = wanted.into_hatch(),
+                GoalState::Ball(ref mut height) => *height = wanted.into_ball(),
+            },
+            HatchExtend(ext) => {
+                if let GoalState::Hatch(_, ref mut ext_state) = self.goal {
+                    ext_state.extend = ext;
                 }
             }
-            // process desired state
-            match self.goal.clone() {
-                GoalState::Hatch(height, ext_state) => {
-                    // TODO log
-                    self.channel.process_sensors(false).ok();
-                    outs.elev_pos = height.into();
-                    // TODO log
-                    self.hatch_hardware.set(ext_state.clone()).ok();
+            HatchOuttake(ext) => {
+                if let GoalState::Hatch(_, ref mut ext_state) = self.goal {
+                    ext_state.outtake = ext;
                 }
-                GoalState::Ball(goal_height) => {
-                    // TODO log error
-                    self.hatch_hardware.set_closed().ok();
-                    self.channel.idempotent_start();
-                    if self.channel.is_done() {
-                        self.goal = GoalState::Hatch(
+            }
+            BallOuttake(true) => {
+                self.channel.try_init_outk();
+            }
+            BallOuttake(false) => {
+                self.channel.try_stop_outk();
+            }
+            Unjam(x) => self.unjam.set_enabled(x),
+            Climb(do_ext) => {
+                self.climb.set(do_ext).ok();
+            }
+            BeginElevatorPanic => {
+                self.elevator.try_init_panic();
+            }
+            ForceElevatorZero => {
+                self.elevator.force_begin_zero();
+            }
+        }
+        if self.mode() != to {
+            self.log.event(
+                "superstructure",
+                format!(
+                    "{:?} went from {:?} to {:?}, not {:?}",
+                    command,
+                    from,
+                    self.mode(),
+                    to
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Tells the controller, returning whether it heard.
+    fn tell(&self, feedback: Feedback) -> bool {
+        match &self.feedback {
+            Some(sender) => sender.send(feedback).is_ok(),
+            None => false,
+        }
+    }
+
+    fn accept(&mut self, command: Instruction) {
+        use Instruction::*;
+        if let BallIntake(true) | BallOuttake(true) | SetElevatorHeight(_) = command {
+            // a newer command of the same kind replaces the old one
+            let kind = Command::from(command);
+            self.pending.retain(|&c| Command::from(c) != kind);
+            self.pending.push(command);
+        }
+        self.tell(Feedback::Accepted(command));
+    }
+
+    /// Tells the controller, or logs the rejection itself if nobody's listening.
+    fn reject(&self, rejection: Rejection) {
+        if !self.tell(Feedback::Rejected(rejection)) {
+            self.log
+                .event("superstructure", format!("rejected: {}", rejection));
+        }
+    }
+
+    /// Reports pending commands that are done, given the balls in the robot before this loop.
+    fn complete(&mut self, balls_before: usize) {
+        use Instruction::*;
+        if self.pending.is_empty() {
+            return;
+        }
+        let balls = self.channel.balls();
+        let holding = self.elevator.is_holding().unwrap_or(false);
+        let (done, pending) = self.pending.iter().partition(|&&command| match command {
+            BallIntake(_) => balls > balls_before,
+            BallOuttake(_) => balls < balls_before,
+            _ => holding,
+        });
+        self.pending = pending;
+        for command in done {
+            self.tell(Feedback::Completed(command));
+        }
+    }
+
+    fn report_status(&mut self) {
+        let status = Status {
+            mode: self.mode(),
+            goal: self.goal.clone(),
+            balls: self.channel.balls(),
+            fault: self.channel.fault(),
+        };
+        if self.status.as_ref() != Some(&status) {
+            self.tell(Feedback::Status(status.clone()));
+            self.status = Some(status);
+        }
+    }
+
+    /// Works toward the goal, moving the ball along.
+    fn update(&mut self, outs: &mut PeriodicOuts) {
+        use goal::*;
+        let faulted = self.channel.fault();
+        match self.goal.clone() {
+            GoalState::Hatch(height, ext_state) => {
+                let sensors = self.channel.process_sensors(false, self.unjam);
+                self.log_err("reading the channel", sensors);
+                outs.elev_pos = height.into();
+                let hatch = self.hatch_hardware.set(ext_state.clone());
+                self.log_err("setting the hatch", hatch);
+            }
+            GoalState::Ball(goal_height) => {
+                let hatch = self.hatch_hardware.set_closed();
+                self.log_err("closing the hatch", hatch);
+                self.channel.idempotent_start();
+                if self.channel.is_done() {
+                    self.channel.reset();
+                    // any ball left goes to the carriage next
+                    self.goal = match self.channel.state() {
+                        BallProgress::None => GoalState::Hatch(
                             HatchGoalHeight::Low,
                             hatch_hardware::CLOSED_HATCH_STATE,
-                        );
-                        self.channel.reset();
-                    } else {
-                        outs.elev_pos = goal_height.into();
-                        // TODO log the two possible failures here
-                        self.channel
-                            .process_sensors(self.elevator.is_holding().unwrap_or(false))
-                            .ok();
-                    }
+                        ),
+                        _ => GoalState::Ball(BallGoalHeight::None),
+                    };
+                } else {
+                    outs.elev_pos = goal_height.into();
+                    let holding = self.elevator.is_holding();
+                    let sensors = self
+                        .channel
+                        .process_sensors(holding.unwrap_or(false), self.unjam);
+                    self.log_err("checking the elevator", holding);
+                    self.log_err("reading the channel", sensors);
                 }
             }
-            // TODO log
-            self.elevator.iterate().ok();
+        }
+        if let (None, Some(fault)) = (faulted, self.channel.fault()) {
+            self.log
+                .event("superstructure", format!("channel faulted: {:?}", fault));
+        }
+    }
+}
+
+impl<H: Hardware> Superstructure<H> {
+    /// Sends the motor outputs again next loop, even if they haven't changed, after the
+    /// supervisor has neutralized the talons behind the caches' backs.
+    fn resend_outputs(&mut self) {
+        self.im.forget();
+        self.om.forget();
+    }
+}
 
-            self.channel.write_outs(&mut outs);
-            // Unjam gets to override everyone else
-            self.unjam.process();
-            self.unjam.write_outs(&mut outs);
-            // TODO log
-            self.flush_outs(&outs).ok();
+impl<H: Hardware> Subsystem for Superstructure<H> {
+    fn run(mut self) {
+        if let Some(realtime) = crate::config::periodic::SUPERSTRUCTURE {
+            if let Err(e) = realtime.apply() {
+                self.log.event(
+                    "superstructure",
+                    format!("Could not make the superstructure realtime: {}", e),
+                );
+            }
+        }
+        let mut periodic = Periodic::new(SUBSYSTEM_PERIOD);
+        loop {
+            let tick = periodic.wait();
+            if self.heartbeat.was_made_safe() {
+                self.resend_outputs();
+            }
+            self.iterate(tick.dt);
+            self.heartbeat.beat(&tick);
         }
     }
 }
 
 #[derive(Debug)]
-struct CachingTalon(TalonSRX, (ControlMode, f64, DemandType, f64));
+struct CachingTalon<T: Talon>(T, (ControlMode, f64, DemandType, f64));
 
-impl CachingTalon {
-    pub fn new(x: TalonSRX) -> Self {
+impl<T: Talon> CachingTalon<T> {
+    pub fn new(x: T) -> Self {
         Self(
             x,
             (
@@ -389,28 +835,59 @@ impl CachingTalon {
     }
 
     #[allow(dead_code)]
-    pub fn talon(&self) -> &TalonSRX {
+    pub fn talon(&self) -> &T {
         &self.0
     }
 
     #[allow(dead_code)]
-    pub fn talon_mut(&mut self) -> &mut TalonSRX {
+    pub fn talon_mut(&mut self) -> &mut T {
+        self.forget();
+        &mut self.0
+    }
+
+    /// The percent output last sent, if that's what was.
+    pub fn sent_pct(&self) -> Option<f64> {
+        let (mode, pct, demand1_type, _) = self.1;
+        match (mode, demand1_type) {
+            (ControlMode::PercentOutput, DemandType::Neutral) if !pct.is_nan() => Some(pct),
+            _ => None,
+        }
+    }
+
+    /// Takes `pct` to be what was last sent, or forgets what was if it's `None`.
+    pub fn restore_pct(&mut self, pct: Option<f64>) {
+        match pct {
+            Some(pct) => self.1 = (ControlMode::PercentOutput, pct, DemandType::Neutral, 0.0),
+            None => self.forget(),
+        }
+    }
+
+    /// Forgets what was last sent, for when the talon may have been set elsewhere.
+    pub fn forget(&mut self) {
         // set the cache to something that will force a change next time
         self.1 .1 = std::f64::NAN;
         self.1 .3 = std::f64::NAN;
-        &mut self.0
     }
 }
 
 #[derive(Debug)]
-struct CachingSolenoid(Solenoid, bool);
+struct CachingSolenoid<S: SolenoidOut>(S, bool);
 
-impl CachingSolenoid {
-    pub fn new(s: Solenoid) -> HalResult<Self> {
+impl<S: SolenoidOut> CachingSolenoid<S> {
+    pub fn new(mut s: S) -> HalResult<Self> {
         s.set(false)?;
         Ok(Self(s, false))
     }
 
+    pub fn sent(&self) -> bool {
+        self.1
+    }
+
+    /// Takes `b` to be what was last sent.
+    pub fn restore(&mut self, b: bool) {
+        self.1 = b;
+    }
+
     pub fn set(&mut self, b: bool) -> HalResult<()> {
         if b == self.1 {
             return Ok(());
@@ -422,3 +899,228 @@ impl CachingSolenoid {
 
 use hardware::Rev111107DS00PressureSensor;
 use wpilib::AnalogInput;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::telemetry::{queue, Entry, Payload, Record};
+    use controls::approx::assert_abs_diff_eq;
+    use crossbeam_channel::unbounded;
+    use Instruction::*;
+
+    struct Rig {
+        superstructure: Superstructure<Fake>,
+        commands: Sender<Instruction>,
+        feedback: Receiver<Feedback>,
+        gates: [FakeDigitalInput; 3],
+        elevator: FakeTalon,
+        intake: FakeTalon,
+        log: Receiver<Entry>,
+    }
+
+    impl Rig {
+        fn new() -> Self {
+            let gates = [
+                FakeDigitalInput::new(true),
+                FakeDigitalInput::new(true),
+                FakeDigitalInput::new(true),
+            ];
+            let elevator = FakeTalon::new();
+            let intake = FakeTalon::new();
+            let hardware = SuperstructureHardware::<Fake> {
+                intake_talon: intake.clone(),
+                outtake_talon: FakeTalon::new(),
+                intake_solenoid: FakeSolenoid::new(),
+                climb_solenoid: FakeSolenoid::new(),
+                hatch_extend: FakeSolenoid::new(),
+                hatch_outtake: FakeSolenoid::new(),
+                gates: (gates[0].clone(), gates[1].clone(), gates[2].clone()),
+                pressure: FakeAnalogInput::new(2.5),
+                elevator: Elevator::from_hardware(
+                    elevator.clone(),
+                    FakeTalon::new(),
+                    FakeTalon::new(),
+                    FakeDigitalInput::new(false),
+                ),
+            };
+            let (commands, receiver) = unbounded();
+            let (feedback, feedback_recv) = unbounded();
+            let (log, entries) = queue(4096, 0);
+            let superstructure = Superstructure::from_hardware(hardware, receiver)
+                .unwrap()
+                .with_log(log)
+                .with_feedback(feedback);
+            Self {
+                superstructure,
+                commands,
+                feedback: feedback_recv,
+                gates,
+                elevator,
+                intake,
+                log: entries,
+            }
+        }
+
+        /// Runs a loop, with the elevator instantly reaching its setpoint.
+        fn step(&mut self, commands: &[Instruction]) -> Mode {
+            for &command in commands {
+                self.commands.send(command).unwrap();
+            }
+            self.superstructure.iterate(SUBSYSTEM_PERIOD);
+            let mut state = self.elevator.state();
+            state.position = state.demand0 as i32;
+            self.superstructure.mode()
+        }
+
+        fn feedback(&self) -> Vec<Feedback> {
+            self.feedback.try_iter().collect()
+        }
+
+        fn rejected(&self) -> Vec<Rejection> {
+            self.feedback()
+                .into_iter()
+                .filter_map(|feedback| match feedback {
+                    Feedback::Rejected(rejection) => Some(rejection),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.log
+                .try_iter()
+                .filter_map(|entry| match entry.payload {
+                    Payload::Record(Record::EventRecord(event)) => Some(event.message),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn rejects_commands_out_of_mode() {
+        let mut rig = Rig::new();
+        assert_eq!(
+            rig.step(&[HatchExtend(HatchPneumaticExt::Extended)]),
+            Mode::Hatch
+        );
+        assert_eq!(rig.rejected(), Vec::new());
+
+        let med = SetElevatorHeight(UserElevatorHeights::Med);
+        assert_eq!(rig.step(&[BallIntake(true), med]), Mode::Intaking);
+        assert_eq!(
+            rig.rejected(),
+            vec![Rejection {
+                command: med,
+                mode: Mode::Intaking,
+                reason: Reason::Mode,
+            }]
+        );
+        let extend = HatchExtend(HatchPneumaticExt::Extended);
+        rig.step(&[extend, BallOuttake(true)]);
+        let rejected: Vec<Instruction> = rig.rejected().iter().map(|r| r.command).collect();
+        assert_eq!(rejected, vec![extend, BallOuttake(true)]);
+
+        assert_eq!(rig.step(&[ForceAbortBall]), Mode::Hatch);
+        assert_eq!(rig.rejected(), Vec::new());
+        assert_eq!(rig.events(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn follows_the_table() {
+        let mut rig = Rig::new();
+        let mut modes = vec![rig.step(&[BallIntake(true)])];
+        for i in 0..400 {
+            rig.gates[0].set(i != 10);
+            rig.gates[1].set(i != 20);
+            rig.gates[2].set(i < 30);
+            let commands = match i {
+                300 => vec![BallOuttake(true)],
+                310 => vec![BallOuttake(false)],
+                _ => vec![],
+            };
+            let mode = rig.step(&commands);
+            if modes.last() != Some(&mode) {
+                modes.push(mode);
+            }
+        }
+        assert_eq!(
+            modes,
+            vec![
+                Mode::Intaking,
+                Mode::Loading,
+                Mode::Carrying,
+                Mode::Scoring,
+                Mode::Hatch
+            ]
+        );
+        assert_eq!(rig.rejected(), Vec::new());
+        assert_eq!(rig.events(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_progress() {
+        let mut rig = Rig::new();
+        rig.step(&[]);
+        let status = |mode, balls| Status {
+            mode,
+            goal: GoalState::Ball(BallGoalHeight::None),
+            balls,
+            fault: None,
+        };
+        assert_eq!(
+            rig.feedback(),
+            vec![Feedback::Status(Status {
+                mode: Mode::Hatch,
+                goal: GoalState::Hatch(HatchGoalHeight::Low, hatch_hardware::CLOSED_HATCH_STATE),
+                balls: 0,
+                fault: None,
+            })]
+        );
+
+        rig.step(&[BallIntake(true)]);
+        assert_eq!(
+            rig.feedback(),
+            vec![
+                Feedback::Accepted(BallIntake(true)),
+                Feedback::Status(status(Mode::Intaking, 0)),
+            ]
+        );
+        rig.step(&[]);
+        assert_eq!(rig.feedback(), Vec::new());
+
+        rig.gates[0].set(false);
+        rig.step(&[]);
+        assert_eq!(
+            rig.feedback(),
+            vec![
+                Feedback::Completed(BallIntake(true)),
+                Feedback::Status(status(Mode::Loading, 1)),
+            ]
+        );
+
+        rig.step(&[ForceAbortBall]);
+        let feedback = rig.feedback();
+        assert_eq!(feedback[0], Feedback::Accepted(ForceAbortBall));
+        assert!(!feedback.iter().any(|f| match f {
+            Feedback::Completed(_) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn resends_outputs_after_being_made_safe() {
+        let mut rig = Rig::new();
+        rig.step(&[BallIntake(true)]);
+        let intaking = rig.intake.state().demand0;
+        assert!(intaking != 0.0);
+
+        // the supervisor neutralizes the talon through its own handle
+        rig.intake.state().demand0 = 0.0;
+        rig.step(&[]);
+        assert_abs_diff_eq!(rig.intake.state().demand0, 0.0);
+        rig.superstructure.resend_outputs();
+        rig.step(&[]);
+        assert_abs_diff_eq!(rig.intake.state().demand0, intaking);
+    }
+}
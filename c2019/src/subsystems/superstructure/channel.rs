@@ -1,8 +1,11 @@
+use super::unjam::UnjamState;
 use super::HalCtreError;
 use super::IntakeExt;
 use super::PeriodicOuts;
-use hardware::Qmic0N0A;
-use wpilib::HalResult;
+use crate::subsystems::controller::edge_detect::{Edge, EdgeDetector};
+use hardware::io::{DigitalIn, Hardware};
+use serde_derive::{Deserialize, Serialize};
+use tunables::{Registry, Tunable};
 
 // TODO tune
 const CHAN_INTAKE_COMMAND: f64 = 0.8;
@@ -11,7 +14,59 @@ const CHAN_TRANSFER_COMMAND: f64 = 1.0;
 const OUTK_INTK_COMMAND: f64 = 0.5;
 const OUTK_OUTK_COMMAND: f64 = 1.0;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+// Loops a ball gets to reach the next gate before the channel faults, at 5ms a loop. Intaking
+// waits on the driver finding a ball, so it gets much longer.
+const INTAKING_TIMEOUT_CYCLES: u32 = 3000;
+const INSIDE_TIMEOUT_CYCLES: u32 = 400;
+const TRANSFER_TIMEOUT_CYCLES: u32 = 400;
+
+/// Balls the robot can hold: one in the carriage and one waiting in the channel
+pub const CAPACITY: usize = 2;
+
+/// Motor commands for each step of moving a ball, live tunable as `channel/*`.
+#[derive(Debug, Clone)]
+pub struct Commands {
+    intake: Tunable,
+    convey: Tunable,
+    transfer: Tunable,
+    outtake_intake: Tunable,
+    outtake: Tunable,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self {
+            intake: Tunable::fixed(CHAN_INTAKE_COMMAND),
+            convey: Tunable::fixed(CHAN_CONVEY_COMMAND),
+            transfer: Tunable::fixed(CHAN_TRANSFER_COMMAND),
+            outtake_intake: Tunable::fixed(OUTK_INTK_COMMAND),
+            outtake: Tunable::fixed(OUTK_OUTK_COMMAND),
+        }
+    }
+}
+
+impl Commands {
+    pub fn register(registry: &Registry) -> Self {
+        Self {
+            intake: registry.register("channel/intake", CHAN_INTAKE_COMMAND),
+            convey: registry.register("channel/convey", CHAN_CONVEY_COMMAND),
+            transfer: registry.register("channel/transfer", CHAN_TRANSFER_COMMAND),
+            outtake_intake: registry.register("channel/outtake_intake", OUTK_INTK_COMMAND),
+            outtake: registry.register("channel/outtake", OUTK_OUTK_COMMAND),
+        }
+    }
+}
+
+/// Why the channel gave up on a ball. Gates are numbered 1 to 3 from the intake.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// The ball never reached `gate`
+    TimedOut { gate: u8 },
+    /// `gate` tripped before the ball reached `expected`, which it has to pass first
+    OutOfOrder { gate: u8, expected: u8 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BallProgress {
     None,
     Intaking,
@@ -21,32 +76,88 @@ pub enum BallProgress {
     CarriageSecure,
     Outtaking,
     Done,
+    /// Stopped until the ball is aborted, or unjamming pushes it out of the intake
+    Faulted(Fault),
 }
 
+/// Gates are beam breaks, which read false while a ball is in front of them.
+///
+/// `state` follows the lead ball, the next one to reach the carriage. Balls that come in behind
+/// it are counted as they pass gate 1, and each takes the lead in turn once the one ahead of it
+/// is outtaken.
 #[derive(Debug)]
-pub struct Channel {
+pub struct Channel<H: Hardware> {
+    state: BallProgress,
+    gates: (H::DigitalInput, H::DigitalInput, H::DigitalInput),
+    commands: Commands,
+    /// The state being timed, and how many loops it's lasted
+    timed: BallProgress,
+    cycles: u32,
+    /// Sees balls come in and leave through gate 1
+    gate1: EdgeDetector,
+    /// Balls past gate 1 behind the lead ball
+    behind: usize,
+    /// Whether to intake another ball behind the one in the carriage
+    intake_another: bool,
+}
+
+/// A [`Channel`] between loops, for a replay to pick up from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSnapshot {
     state: BallProgress,
-    gates: (Qmic0N0A, Qmic0N0A, Qmic0N0A),
+    timed: BallProgress,
+    cycles: u32,
+    gate1: EdgeDetector,
+    behind: usize,
+    intake_another: bool,
 }
 
-use crate::config::superstructure::{GATE1, GATE2, GATE3};
-impl Channel {
-    pub fn new() -> HalResult<Self> {
-        Ok(Self {
+impl<H: Hardware> Channel<H> {
+    pub fn new(gates: (H::DigitalInput, H::DigitalInput, H::DigitalInput)) -> Self {
+        Self {
             state: BallProgress::None,
-            gates: (
-                Qmic0N0A::new(GATE1)?,
-                Qmic0N0A::new(GATE2)?,
-                Qmic0N0A::new(GATE3)?,
-            ),
-        })
+            gates,
+            commands: Commands::default(),
+            timed: BallProgress::None,
+            cycles: 0,
+            gate1: EdgeDetector::new(),
+            behind: 0,
+            intake_another: false,
+        }
+    }
+
+    pub fn with_commands(self, commands: Commands) -> Self {
+        Self { commands, ..self }
+    }
+
+    pub fn snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            state: self.state,
+            timed: self.timed,
+            cycles: self.cycles,
+            gate1: self.gate1.clone(),
+            behind: self.behind,
+            intake_another: self.intake_another,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &ChannelSnapshot) {
+        self.state = snapshot.state;
+        self.timed = snapshot.timed;
+        self.cycles = snapshot.cycles;
+        self.gate1 = snapshot.gate1.clone();
+        self.behind = snapshot.behind;
+        self.intake_another = snapshot.intake_another;
     }
 
+    /// Stops intaking. Returns whether the channel was left empty.
     pub fn try_abort_intk(&mut self) -> bool {
         use BallProgress::*;
+        self.intake_another = false;
         return match self.state {
-            Intaking => {
+            Intaking | Faulted(_) => {
                 self.state = None;
+                self.behind = 0;
                 true
             }
             _ => false,
@@ -75,8 +186,18 @@ impl Channel {
         };
     }
 
+    /// After an outtake, hands the lead to the next ball, if there is one.
     pub fn reset(&mut self) {
         self.state = match self.state {
+            BallProgress::Done if self.behind > 0 => {
+                self.behind -= 1;
+                BallProgress::Inside
+            }
+            // the second ball wasn't in yet, so keep intaking it
+            BallProgress::Done if self.intake_another => {
+                self.intake_another = false;
+                BallProgress::Intaking
+            }
             BallProgress::Done => BallProgress::None,
             x => x,
         }
@@ -97,6 +218,29 @@ impl Channel {
         }
     }
 
+    /// Intakes a second ball while the first waits in the carriage. Returns false, and doesn't,
+    /// if the carriage isn't holding a ball yet or the robot is full.
+    pub fn try_intake_another(&mut self) -> bool {
+        if self.is_in_carriage() && !self.is_full() {
+            self.intake_another = true;
+        }
+        self.intake_another
+    }
+
+    /// How many balls are in the robot. A faulted ball isn't counted, since it may not be there.
+    pub fn balls(&self) -> usize {
+        use BallProgress::*;
+        let lead = match self.state {
+            Inside | Queued | CarriageVolatile | CarriageSecure | Outtaking => 1,
+            None | Intaking | Done | Faulted(_) => 0,
+        };
+        lead + self.behind
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.balls() >= CAPACITY
+    }
+
     pub fn is_in_carriage(&self) -> bool {
         match self.state {
             BallProgress::CarriageSecure | BallProgress::Outtaking => true,
@@ -106,69 +250,112 @@ impl Channel {
 
     pub fn force_abort(&mut self) {
         self.state = BallProgress::None;
+        self.behind = 0;
+        self.intake_another = false;
+    }
+
+    /// Advances the ball by the gates. `unjam` is what the motors were last doing: while
+    /// unjamming, the ball can go anywhere, so it's followed rather than timed or checked.
+    pub fn process_sensors(
+        &mut self,
+        elev_ready: bool,
+        unjam: UnjamState,
+    ) -> Result<(), HalCtreError> {
+        let blocked = [
+            !self.gates.0.get()?,
+            !self.gates.1.get()?,
+            !self.gates.2.get()?,
+        ];
+        let gate1 = self.gate1.get(blocked[0]);
+        if self.state != self.timed {
+            self.timed = self.state;
+            self.cycles = 0;
+        } else if unjam == UnjamState::Disabled {
+            self.cycles = self.cycles.saturating_add(1);
+        }
+        let past_gate1 = match self.state {
+            BallProgress::None | BallProgress::Intaking | BallProgress::Faulted(_) => false,
+            _ => true,
+        };
+        // the lead ball's own arrival is handled by the state machine
+        if gate1.rising() && past_gate1 && unjam == UnjamState::Disabled {
+            self.behind += 1;
+            self.intake_another = false;
+        }
+        self.state = match unjam {
+            UnjamState::Reverse(_) => self.backward(blocked, gate1),
+            UnjamState::Forward(_) => self.forward(blocked, elev_ready),
+            UnjamState::Disabled => match self.check(blocked) {
+                Some(fault) => BallProgress::Faulted(fault),
+                None => self.forward(blocked, elev_ready),
+            },
+        };
+        Ok(())
     }
 
-    pub fn process_sensors(&mut self, elev_ready: bool) -> Result<(), HalCtreError> {
-        // dbg!(self.state);
+    fn forward(&self, blocked: [bool; 3], elev_ready: bool) -> BallProgress {
         use BallProgress::*;
-        // TODO add backwards transitions for unjam
-        // dbg!((
-        //     self.gates.0.get()?,
-        //     self.gates.1.get()?,
-        //     self.gates.2.get()?
-        // ));
-        self.state = match self.state {
-            None => None,
-            Intaking => {
-                if !self.gates.0.get()? {
-                    Inside
-                } else {
-                    Intaking
-                }
-            }
+        match self.state {
+            Intaking if blocked[0] => Inside,
             // TODO should be handled here to be on the safer side?
-            Inside => {
-                if !self.gates.1.get()? {
-                    Queued
-                } else {
-                    Inside
-                }
-            }
-            Queued => {
-                if elev_ready {
-                    CarriageVolatile
-                } else {
-                    Queued
-                }
-            }
-            CarriageVolatile => {
-                if !self.gates.2.get()? {
-                    CarriageSecure
-                } else {
-                    CarriageVolatile
-                }
+            Inside if blocked[1] => Queued,
+            Queued if elev_ready => CarriageVolatile,
+            CarriageVolatile if blocked[2] => CarriageSecure,
+            state => state,
+        }
+    }
+
+    /// Follows balls being pushed back out of the intake, the last one in leaving first.
+    fn backward(&mut self, blocked: [bool; 3], gate1: Edge) -> BallProgress {
+        use BallProgress::*;
+        if gate1.falling() && self.behind > 0 {
+            self.behind -= 1;
+        } else if gate1.falling() {
+            if let Inside | Faulted(_) = self.state {
+                return None;
             }
-            CarriageSecure => CarriageSecure,
-            Outtaking => Outtaking,
-            Done => Done,
+        }
+        match self.state {
+            Queued | CarriageVolatile if blocked[1] => Inside,
+            state => state,
+        }
+    }
+
+    /// Faults if the ball is taking too long to reach the next gate, or a gate past it tripped.
+    fn check(&self, blocked: [bool; 3]) -> Option<Fault> {
+        use BallProgress::*;
+        let (expected, timeout) = match self.state {
+            Intaking => (1, INTAKING_TIMEOUT_CYCLES),
+            Inside => (2, INSIDE_TIMEOUT_CYCLES),
+            CarriageVolatile => (3, TRANSFER_TIMEOUT_CYCLES),
+            _ => return Option::None,
         };
-        Ok(())
+        if blocked[expected as usize - 1] {
+            return Option::None;
+        }
+        if let Some(gate) = (expected + 1..=3).find(|&gate| blocked[gate as usize - 1]) {
+            Some(Fault::OutOfOrder { gate, expected })
+        } else if self.cycles >= timeout {
+            Some(Fault::TimedOut { gate: expected })
+        } else {
+            Option::None
+        }
     }
 
     pub fn write_outs(&self, outs: &mut PeriodicOuts) {
         use BallProgress::*;
         match self.state {
-            None | Done => {
+            None | Done | Faulted(_) => {
                 outs.intk_pnm = IntakeExt::Retr.into();
                 outs.intk_pct = 0.0;
             }
             Intaking => {
                 outs.intk_pnm = IntakeExt::Ext.into();
-                outs.intk_pct = CHAN_INTAKE_COMMAND;
+                outs.intk_pct = self.commands.intake.get();
             }
             Inside => {
                 outs.intk_pnm = IntakeExt::Retr.into();
-                outs.intk_pct = CHAN_CONVEY_COMMAND;
+                outs.intk_pct = self.commands.convey.get();
             }
             Queued => {
                 outs.intk_pnm = IntakeExt::Retr.into();
@@ -176,8 +363,8 @@ impl Channel {
             }
             CarriageVolatile => {
                 outs.intk_pnm = IntakeExt::Retr.into();
-                outs.intk_pct = CHAN_TRANSFER_COMMAND;
-                outs.outk_pct = OUTK_INTK_COMMAND;
+                outs.intk_pct = self.commands.transfer.get();
+                outs.outk_pct = self.commands.outtake_intake.get();
             }
             CarriageSecure => {
                 outs.intk_pnm = IntakeExt::Retr.into();
@@ -185,12 +372,317 @@ impl Channel {
             }
             Outtaking => {
                 outs.intk_pnm = IntakeExt::Retr.into();
-                outs.outk_pct = OUTK_OUTK_COMMAND;
+                outs.outk_pct = self.commands.outtake.get();
             }
         }
+        // only asked for while the carriage holds the lead ball, when the channel is idle
+        if self.intake_another {
+            outs.intk_pnm = IntakeExt::Ext.into();
+            outs.intk_pct = self.commands.intake.get();
+        }
     }
 
     pub fn state(&self) -> &BallProgress {
         &self.state
     }
+
+    pub fn fault(&self) -> Option<Fault> {
+        match self.state {
+            BallProgress::Faulted(fault) => Some(fault),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hardware::fake::{Fake, FakeDigitalInput};
+
+    fn fake_channel() -> (Channel<Fake>, [FakeDigitalInput; 3]) {
+        let gates = [
+            FakeDigitalInput::new(true),
+            FakeDigitalInput::new(true),
+            FakeDigitalInput::new(true),
+        ];
+        let chan = Channel::new((gates[0].clone(), gates[1].clone(), gates[2].clone()));
+        (chan, gates)
+    }
+
+    #[test]
+    fn full_cycle() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), None);
+
+        chan.idempotent_start();
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), Intaking);
+
+        gates[0].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), Inside);
+        gates[0].set(true);
+
+        gates[1].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), Queued);
+        gates[1].set(true);
+
+        // waits on the elevator
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), Queued);
+        chan.process_sensors(true, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), CarriageVolatile);
+        assert!(!chan.is_in_carriage());
+
+        gates[2].set(false);
+        chan.process_sensors(true, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), CarriageSecure);
+        assert!(chan.is_in_carriage());
+
+        assert!(chan.try_init_outk());
+        assert_eq!(*chan.state(), Outtaking);
+        assert!(chan.try_stop_outk());
+        assert!(chan.is_done());
+        chan.reset();
+        assert_eq!(*chan.state(), None);
+    }
+
+    #[test]
+    fn intake_abort() {
+        let (mut chan, gates) = fake_channel();
+        assert!(!chan.try_abort_intk());
+        chan.idempotent_start();
+        assert!(chan.try_abort_intk());
+        assert_eq!(*chan.state(), BallProgress::None);
+
+        // can't abort once the ball is inside
+        chan.idempotent_start();
+        gates[0].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert!(!chan.try_abort_intk());
+        assert_eq!(*chan.state(), BallProgress::Inside);
+    }
+
+    #[test]
+    fn outputs() {
+        let (mut chan, _gates) = fake_channel();
+        let mut outs = PeriodicOuts::default();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pct, 0.0);
+        assert_eq!(outs.intk_pnm, IntakeExt::Retr.into());
+
+        chan.idempotent_start();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pct, CHAN_INTAKE_COMMAND);
+        assert_eq!(outs.intk_pnm, IntakeExt::Ext.into());
+    }
+
+    /// Runs the channel for `cycles` loops with no unjam, returning where it ended up.
+    fn run(chan: &mut Channel<Fake>, cycles: u32) -> BallProgress {
+        for _ in 0..cycles {
+            chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        }
+        *chan.state()
+    }
+
+    #[test]
+    fn times_out() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        assert_eq!(run(&mut chan, INTAKING_TIMEOUT_CYCLES), Intaking);
+        assert_eq!(run(&mut chan, 1), Faulted(Fault::TimedOut { gate: 1 }));
+
+        let mut outs = PeriodicOuts::default();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pnm, IntakeExt::Retr.into());
+        // the driver aborting clears it
+        assert!(chan.try_abort_intk());
+        chan.idempotent_start();
+        gates[0].set(false);
+        assert_eq!(run(&mut chan, 1), Inside);
+        assert_eq!(run(&mut chan, INSIDE_TIMEOUT_CYCLES), Inside);
+        assert_eq!(run(&mut chan, 1), Faulted(Fault::TimedOut { gate: 2 }));
+        assert_eq!(chan.fault(), Some(Fault::TimedOut { gate: 2 }));
+    }
+
+    #[test]
+    fn faults_on_gates_out_of_order() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        gates[2].set(false);
+        let fault = Fault::OutOfOrder {
+            gate: 3,
+            expected: 1,
+        };
+        assert_eq!(run(&mut chan, 1), Faulted(fault));
+        // stays faulted once the gate clears
+        gates[2].set(true);
+        assert_eq!(run(&mut chan, 1), Faulted(fault));
+        chan.idempotent_start();
+        assert_eq!(*chan.state(), Faulted(fault));
+        chan.force_abort();
+        assert_eq!(*chan.state(), None);
+
+        // a ball long enough to block the next gate too is fine
+        chan.idempotent_start();
+        gates[0].set(false);
+        gates[1].set(false);
+        assert_eq!(run(&mut chan, 1), Inside);
+    }
+
+    #[test]
+    fn follows_unjam() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        gates[0].set(false);
+        run(&mut chan, 1);
+        gates[0].set(true);
+        gates[1].set(false);
+        assert_eq!(run(&mut chan, 1), Queued);
+
+        let reverse = UnjamState::Reverse(10);
+        chan.process_sensors(false, reverse).unwrap();
+        assert_eq!(*chan.state(), Inside);
+        // unjamming doesn't time out
+        gates[1].set(true);
+        for _ in 0..INSIDE_TIMEOUT_CYCLES * 2 {
+            chan.process_sensors(false, reverse).unwrap();
+        }
+        assert_eq!(*chan.state(), Inside);
+        gates[0].set(false);
+        chan.process_sensors(false, reverse).unwrap();
+        assert_eq!(*chan.state(), Inside);
+        gates[0].set(true);
+        chan.process_sensors(false, reverse).unwrap();
+        assert_eq!(*chan.state(), None);
+    }
+
+    #[test]
+    fn unjams_out_of_a_fault() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        gates[1].set(false);
+        assert_eq!(
+            run(&mut chan, 1),
+            Faulted(Fault::OutOfOrder {
+                gate: 2,
+                expected: 1
+            })
+        );
+        // forward unjamming leaves it alone
+        chan.process_sensors(false, UnjamState::Forward(10))
+            .unwrap();
+        assert!(chan.fault().is_some());
+        gates[1].set(true);
+        gates[0].set(false);
+        chan.process_sensors(false, UnjamState::Reverse(10))
+            .unwrap();
+        gates[0].set(true);
+        chan.process_sensors(false, UnjamState::Reverse(10))
+            .unwrap();
+        assert_eq!(*chan.state(), None);
+    }
+
+    /// Takes one ball from the intake into the carriage.
+    fn load(chan: &mut Channel<Fake>, gates: &[FakeDigitalInput; 3]) {
+        for (gate, elev_ready) in gates.iter().zip(&[false, false, true]) {
+            gate.set(false);
+            chan.process_sensors(*elev_ready, UnjamState::Disabled)
+                .unwrap();
+            chan.process_sensors(*elev_ready, UnjamState::Disabled)
+                .unwrap();
+            gate.set(true);
+            chan.process_sensors(*elev_ready, UnjamState::Disabled)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn counts_balls() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        assert!(!chan.try_intake_another());
+        chan.idempotent_start();
+        load(&mut chan, &gates);
+        assert_eq!(*chan.state(), CarriageSecure);
+        assert_eq!(chan.balls(), 1);
+
+        assert!(chan.try_intake_another());
+        let mut outs = PeriodicOuts::default();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pnm, IntakeExt::Ext.into());
+        gates[0].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        gates[0].set(true);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(chan.balls(), 2);
+        assert!(chan.is_full());
+        assert!(!chan.try_intake_another());
+        let mut outs = PeriodicOuts::default();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pnm, IntakeExt::Retr.into());
+
+        // outtaking the first hands the lead to the second
+        assert!(chan.try_init_outk());
+        assert!(chan.try_stop_outk());
+        chan.reset();
+        assert_eq!(*chan.state(), Inside);
+        assert_eq!(chan.balls(), 1);
+        gates[1].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), Queued);
+        gates[1].set(true);
+        chan.process_sensors(true, UnjamState::Disabled).unwrap();
+        gates[2].set(false);
+        chan.process_sensors(true, UnjamState::Disabled).unwrap();
+        assert_eq!(*chan.state(), CarriageSecure);
+
+        assert!(chan.try_init_outk());
+        assert!(chan.try_stop_outk());
+        chan.reset();
+        assert_eq!(*chan.state(), None);
+        assert_eq!(chan.balls(), 0);
+    }
+
+    #[test]
+    fn unjams_the_last_ball_in_first() {
+        use BallProgress::*;
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        load(&mut chan, &gates);
+        assert!(chan.try_intake_another());
+        gates[0].set(false);
+        chan.process_sensors(false, UnjamState::Disabled).unwrap();
+        assert_eq!(chan.balls(), 2);
+
+        gates[0].set(true);
+        chan.process_sensors(false, UnjamState::Reverse(10))
+            .unwrap();
+        assert_eq!(chan.balls(), 1);
+        assert_eq!(*chan.state(), CarriageSecure);
+    }
+
+    #[test]
+    fn keeps_intaking_after_an_outtake() {
+        let (mut chan, gates) = fake_channel();
+        chan.idempotent_start();
+        load(&mut chan, &gates);
+        assert!(chan.try_intake_another());
+        assert!(chan.try_init_outk());
+        assert!(chan.try_stop_outk());
+        chan.reset();
+        assert_eq!(*chan.state(), BallProgress::Intaking);
+        // aborting stops it like any other intake
+        assert!(chan.try_abort_intk());
+        let mut outs = PeriodicOuts::default();
+        chan.write_outs(&mut outs);
+        assert_eq!(outs.intk_pnm, IntakeExt::Retr.into());
+    }
 }
@@ -1,13 +1,12 @@
 use super::HatchPneumaticExt;
 use super::HatchState;
-use crate::config::superstructure::hatch as config;
-use wpilib::pneumatics::Solenoid;
+use hardware::io::{Hardware, SolenoidOut};
 use wpilib::HalResult;
 
 #[derive(Debug)]
-pub struct HatchHardware {
-    extend: Solenoid,
-    outtake: Solenoid,
+pub struct HatchHardware<H: Hardware> {
+    extend: H::Solenoid,
+    outtake: H::Solenoid,
     state: HatchState,
 }
 
@@ -16,16 +15,16 @@ pub const CLOSED_HATCH_STATE: HatchState = HatchState {
     outtake: HatchPneumaticExt::Retracted,
 };
 
-impl HatchHardware {
-    pub fn new() -> HalResult<Self> {
-        Ok(Self {
-            extend: Solenoid::new(config::EXTEND_PNEUMATICS_ID)?,
-            outtake: Solenoid::new(config::OUTTAKE_PNEUMATICS_ID)?,
+impl<H: Hardware> HatchHardware<H> {
+    pub fn new(extend: H::Solenoid, outtake: H::Solenoid) -> Self {
+        Self {
+            extend,
+            outtake,
             state: HatchState {
                 extend: HatchPneumaticExt::Retracted,
                 outtake: HatchPneumaticExt::Retracted,
             },
-        })
+        }
     }
 
     pub fn set(&mut self, new: HatchState) -> HalResult<()> {
@@ -45,6 +44,15 @@ impl HatchHardware {
         Ok(())
     }
 
+    pub fn state(&self) -> &HatchState {
+        &self.state
+    }
+
+    /// Takes the solenoids to already be in `state`, so only changes from it are sent.
+    pub fn restore(&mut self, state: HatchState) {
+        self.state = state;
+    }
+
     pub fn set_closed(&mut self) -> HalResult<()> {
         self.set(HatchState {
             extend: HatchPneumaticExt::Retracted,
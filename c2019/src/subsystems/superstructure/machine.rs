@@ -0,0 +1,383 @@
+//! The superstructure as a state machine: its modes, and what moves it between them.
+//!
+//! [`TRANSITIONS`] is the whole graph. A command is only carried out if the table has a row for
+//! it in the current mode, and the row says which mode it leads to; anything else is rejected and
+//! reported back to the controller. Events are the transitions the superstructure makes on its
+//! own as the ball moves, and a change of mode the table doesn't list is logged as a bug.
+//!
+//! The `superstructure_graph` binary prints the table for Graphviz or Mermaid, to teach drivers
+//! what each button does when.
+use super::goal::GoalState;
+use super::{BallProgress, Instruction};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// No ball, and the elevator holds the hatch mechanism at the driver's height
+    Hatch,
+    /// The intake is out, waiting for a ball
+    Intaking,
+    /// A ball is on its way up the channel to the carriage
+    Loading,
+    /// A ball is secure in the carriage, at the driver's height
+    Carrying,
+    /// The carriage is pushing the ball out
+    Scoring,
+    /// The channel lost track of a ball, until it's aborted or unjammed out
+    Faulted,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 6] = [
+        Mode::Hatch,
+        Mode::Intaking,
+        Mode::Loading,
+        Mode::Carrying,
+        Mode::Scoring,
+        Mode::Faulted,
+    ];
+
+    pub(super) fn of(goal: &GoalState, ball: BallProgress) -> Self {
+        use BallProgress::*;
+        match (goal, ball) {
+            (GoalState::Hatch(..), _) => Mode::Hatch,
+            (_, None) | (_, Intaking) => Mode::Intaking,
+            (_, Inside) | (_, Queued) | (_, CarriageVolatile) => Mode::Loading,
+            (_, CarriageSecure) => Mode::Carrying,
+            // the next ball takes over from a finished one the same loop
+            (_, Outtaking) | (_, Done) => Mode::Scoring,
+            (_, Faulted(_)) => Mode::Faulted,
+        }
+    }
+}
+
+/// The kinds of [`Instruction`], without their arguments
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    StartIntake,
+    StopIntake,
+    AbortBall,
+    SetHeight,
+    HatchExtend,
+    HatchOuttake,
+    StartOuttake,
+    StopOuttake,
+    Unjam,
+    Climb,
+    ElevatorPanic,
+    ZeroElevator,
+}
+
+impl From<Instruction> for Command {
+    fn from(instruction: Instruction) -> Self {
+        use Instruction::*;
+        match instruction {
+            BallIntake(true) => Command::StartIntake,
+            BallIntake(false) => Command::StopIntake,
+            ForceAbortBall => Command::AbortBall,
+            SetElevatorHeight(_) => Command::SetHeight,
+            HatchExtend(_) => Command::HatchExtend,
+            HatchOuttake(_) => Command::HatchOuttake,
+            BallOuttake(true) => Command::StartOuttake,
+            BallOuttake(false) => Command::StopOuttake,
+            Unjam(_) => Command::Unjam,
+            Climb(_) => Command::Climb,
+            BeginElevatorPanic => Command::ElevatorPanic,
+            ForceElevatorZero => Command::ZeroElevator,
+        }
+    }
+}
+
+/// What the ball does to move the superstructure on its own
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    BallEntered,
+    BallInCarriage,
+    Jammed,
+    /// Unjamming pushed the ball back out of the intake
+    PushedOut,
+    /// The last ball left the robot
+    Scored,
+    /// The ball left, and the one behind it goes up next
+    NextBall,
+    /// The ball left before another was intaken, so the intake stays out
+    ScoredIntaking,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    Command(Command),
+    Event(Event),
+}
+
+impl Trigger {
+    /// For drivers, in the graph
+    pub fn label(self) -> &'static str {
+        match self {
+            Trigger::Command(command) => match command {
+                Command::StartIntake => "intake",
+                Command::StopIntake => "stop intake",
+                Command::AbortBall => "abort ball",
+                Command::SetHeight => "set height",
+                Command::HatchExtend => "hatch extend",
+                Command::HatchOuttake => "hatch outtake",
+                Command::StartOuttake => "outtake",
+                Command::StopOuttake => "stop outtake",
+                Command::Unjam => "unjam",
+                Command::Climb => "climb",
+                Command::ElevatorPanic => "elevator panic",
+                Command::ZeroElevator => "zero elevator",
+            },
+            Trigger::Event(event) => match event {
+                Event::BallEntered => "ball enters",
+                Event::BallInCarriage => "ball in carriage",
+                Event::Jammed => "jammed",
+                Event::PushedOut => "pushed out",
+                Event::Scored => "scored",
+                Event::NextBall => "scored, ball behind",
+                Event::ScoredIntaking => "scored, intaking another",
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: &'static [Mode],
+    pub on: Trigger,
+    /// None stays in the mode it came from
+    pub to: Option<Mode>,
+}
+
+const fn command(from: &'static [Mode], command: Command, to: Option<Mode>) -> Transition {
+    Transition {
+        from,
+        on: Trigger::Command(command),
+        to,
+    }
+}
+
+const fn event(from: &'static [Mode], event: Event, to: Mode) -> Transition {
+    Transition {
+        from,
+        on: Trigger::Event(event),
+        to: Some(to),
+    }
+}
+
+const EVERY: &[Mode] = &Mode::ALL;
+const HOLDING_BALL: &[Mode] = &[Mode::Carrying, Mode::Scoring];
+
+pub const TRANSITIONS: &[Transition] = &[
+    command(&[Mode::Hatch], Command::StartIntake, Some(Mode::Intaking)),
+    // takes another ball behind the one in the carriage
+    command(HOLDING_BALL, Command::StartIntake, None),
+    command(
+        &[Mode::Intaking, Mode::Faulted],
+        Command::StopIntake,
+        Some(Mode::Hatch),
+    ),
+    // only stops intaking another ball
+    command(
+        &[Mode::Hatch, Mode::Loading, Mode::Carrying, Mode::Scoring],
+        Command::StopIntake,
+        None,
+    ),
+    command(EVERY, Command::AbortBall, Some(Mode::Hatch)),
+    command(&[Mode::Hatch], Command::SetHeight, None),
+    command(HOLDING_BALL, Command::SetHeight, None),
+    command(&[Mode::Hatch], Command::HatchExtend, None),
+    command(&[Mode::Hatch], Command::HatchOuttake, None),
+    command(
+        &[Mode::Carrying],
+        Command::StartOuttake,
+        Some(Mode::Scoring),
+    ),
+    // a scored ball leaves by itself, once the outtake stops
+    command(EVERY, Command::StopOuttake, None),
+    command(EVERY, Command::Unjam, None),
+    command(EVERY, Command::Climb, None),
+    command(EVERY, Command::ElevatorPanic, None),
+    command(EVERY, Command::ZeroElevator, None),
+    event(&[Mode::Intaking], Event::BallEntered, Mode::Loading),
+    event(&[Mode::Loading], Event::BallInCarriage, Mode::Carrying),
+    event(
+        &[Mode::Intaking, Mode::Loading],
+        Event::Jammed,
+        Mode::Faulted,
+    ),
+    event(
+        &[Mode::Loading, Mode::Faulted],
+        Event::PushedOut,
+        Mode::Intaking,
+    ),
+    event(&[Mode::Scoring], Event::Scored, Mode::Hatch),
+    event(&[Mode::Scoring], Event::NextBall, Mode::Loading),
+    event(&[Mode::Scoring], Event::ScoredIntaking, Mode::Intaking),
+];
+
+/// The mode `command` takes the superstructure to from `mode`, or None if it can't be done there.
+pub fn target(mode: Mode, command: Command) -> Option<Mode> {
+    TRANSITIONS
+        .iter()
+        .find(|t| t.on == Trigger::Command(command) && t.from.contains(&mode))
+        .map(|t| t.to.unwrap_or(mode))
+}
+
+/// The event that moves the superstructure from one mode to another, if it's allowed to.
+pub fn event_between(from: Mode, to: Mode) -> Option<Event> {
+    TRANSITIONS.iter().find_map(|t| match t.on {
+        Trigger::Event(event) if t.from.contains(&from) && t.to == Some(to) => Some(event),
+        _ => None,
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The table has no row for the command in the mode
+    Mode,
+    /// There's no room for another ball
+    Full,
+}
+
+/// A command the superstructure didn't carry out, sent back to whoever gave it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub command: Instruction,
+    pub mode: Mode,
+    pub reason: Reason,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            Reason::Mode => write!(f, "{:?} can't be done in {:?}", self.command, self.mode),
+            Reason::Full => write!(f, "{:?} refused, the robot is full", self.command),
+        }
+    }
+}
+
+/// Every transition as `(from, to, triggers)`, with the triggers between the same two modes
+/// merged, commands first.
+fn edges() -> Vec<(Mode, Mode, Vec<Trigger>)> {
+    let mut edges: Vec<(Mode, Mode, Vec<Trigger>)> = Vec::new();
+    for transition in TRANSITIONS {
+        for &from in transition.from {
+            let to = transition.to.unwrap_or(from);
+            match edges.iter_mut().find(|(f, t, _)| (*f, *t) == (from, to)) {
+                Some((_, _, triggers)) => triggers.push(transition.on),
+                None => edges.push((from, to, vec![transition.on])),
+            }
+        }
+    }
+    edges
+}
+
+fn labels(triggers: &[Trigger], separator: &str) -> String {
+    let labels: Vec<&str> = triggers.iter().map(|t| t.label()).collect();
+    labels.join(separator)
+}
+
+/// The graph in Graphviz's dot language. Events are dashed.
+pub fn dot() -> String {
+    let mut dot = String::from("digraph superstructure {\n    Hatch [shape=doublecircle];\n");
+    for (from, to, triggers) in edges() {
+        let (commands, events): (Vec<Trigger>, Vec<Trigger>) =
+            triggers.into_iter().partition(|t| {
+                if let Trigger::Command(_) = t {
+                    true
+                } else {
+                    false
+                }
+            });
+        if !commands.is_empty() {
+            dot += &format!(
+                "    {:?} -> {:?} [label=\"{}\"];\n",
+                from,
+                to,
+                labels(&commands, "\\n")
+            );
+        }
+        if !events.is_empty() {
+            dot += &format!(
+                "    {:?} -> {:?} [label=\"{}\", style=dashed];\n",
+                from,
+                to,
+                labels(&events, "\\n")
+            );
+        }
+    }
+    dot + "}\n"
+}
+
+/// The graph as a Mermaid state diagram.
+pub fn mermaid() -> String {
+    let mut mermaid = String::from("stateDiagram-v2\n    [*] --> Hatch\n");
+    for (from, to, triggers) in edges() {
+        mermaid += &format!(
+            "    {:?} --> {:?}: {}\n",
+            from,
+            to,
+            labels(&triggers, "<br/>")
+        );
+    }
+    mermaid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_deterministic() {
+        for transition in TRANSITIONS {
+            let command = match transition.on {
+                Trigger::Command(command) => command,
+                Trigger::Event(_) => continue,
+            };
+            for &mode in transition.from {
+                let rows = TRANSITIONS
+                    .iter()
+                    .filter(|t| t.on == transition.on && t.from.contains(&mode))
+                    .count();
+                assert_eq!(rows, 1, "{:?} in {:?}", command, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn looks_up_transitions() {
+        assert_eq!(
+            target(Mode::Hatch, Command::StartIntake),
+            Some(Mode::Intaking)
+        );
+        assert_eq!(
+            target(Mode::Carrying, Command::StartIntake),
+            Some(Mode::Carrying)
+        );
+        assert_eq!(target(Mode::Loading, Command::SetHeight), None);
+        assert_eq!(target(Mode::Faulted, Command::AbortBall), Some(Mode::Hatch));
+        assert_eq!(
+            event_between(Mode::Scoring, Mode::Loading),
+            Some(Event::NextBall)
+        );
+        assert_eq!(event_between(Mode::Hatch, Mode::Carrying), None);
+    }
+
+    #[test]
+    fn exports_graphs() {
+        let dot = dot();
+        assert!(dot.starts_with("digraph superstructure {\n"));
+        assert!(dot.contains("    Hatch -> Intaking [label=\"intake\"];\n"));
+        assert!(
+            dot.contains("    Loading -> Carrying [label=\"ball in carriage\", style=dashed];\n")
+        );
+        assert!(dot.ends_with("}\n"));
+
+        let mermaid = mermaid();
+        assert!(mermaid.starts_with("stateDiagram-v2\n"));
+        assert!(mermaid.contains("    Carrying --> Scoring: outtake\n"));
+        assert!(mermaid.contains("    Intaking --> Hatch: stop intake<br/>abort ball\n"));
+    }
+}
@@ -1,9 +1,10 @@
 use super::IntakeExt;
+use serde_derive::{Deserialize, Serialize};
 // TODO tune
 const UNJAM_CHAN_COMMAND: f64 = 1.0;
 const UNJAM_OUTK_COMMAND: f64 = 1.0;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UnjamState {
     Disabled,
     Forward(u16),
@@ -0,0 +1,515 @@
+//! Runs the other subsystems' threads, and keeps an eye on them.
+//!
+//! Looping subsystems get a [`Heartbeat`] and beat it every loop with the loop's [`Tick`]. The
+//! supervisor checks them every `CHECK_PERIOD`: one that stops beating, or never starts, is
+//! stalled. A subsystem that panics or stalls is made safe, which usually means neutral motors,
+//! and one that panicked is restarted a few times before it's left stopped. Health is logged as a [`HealthRecord`] per
+//! subsystem every check, with events when anything changes.
+use super::periodic::{Stats, Tick};
+use super::telemetry::{HealthRecord, Log};
+use super::Subsystem;
+use crate::config::supervisor::*;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A subsystem's pulse, beaten once a loop. Clones share the same pulse.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat(Arc<Mutex<Pulse>>);
+
+#[derive(Debug, Clone, Default)]
+struct Pulse {
+    last: Option<Instant>,
+    /// Since the supervisor last looked
+    stats: Stats,
+    /// Whether the supervisor made the subsystem safe since it last asked
+    made_safe: bool,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pulse(&self) -> MutexGuard<'_, Pulse> {
+        // a panic while beating can't leave the pulse half updated
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Marks a loop done.
+    pub fn beat(&self, tick: &Tick) {
+        self.beat_at(tick, Instant::now());
+    }
+
+    fn beat_at(&self, tick: &Tick, now: Instant) {
+        let mut pulse = self.pulse();
+        pulse.last = Some(now);
+        pulse.stats.record(tick);
+    }
+
+    /// Whether the subsystem was made safe since this was last asked. Safing goes around the
+    /// subsystem, so outputs it only sends when they change have to be sent again.
+    pub fn was_made_safe(&self) -> bool {
+        let mut pulse = self.pulse();
+        let made_safe = pulse.made_safe;
+        pulse.made_safe = false;
+        made_safe
+    }
+
+    /// The pulse so far, starting the stats over.
+    fn take(&self) -> Pulse {
+        let mut pulse = self.pulse();
+        let taken = pulse.clone();
+        pulse.stats = Stats::default();
+        taken
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Started, and hasn't looped yet
+    Starting,
+    Running,
+    /// Hasn't looped in `STALL_TIMEOUT`, and was made safe
+    Stalled,
+    /// Panicked, and will be started again after `RESTART_DELAY`
+    Restarting,
+    /// Returned, or panicked with no restarts left, and was made safe
+    Stopped,
+}
+
+// debug_stub_derive can't parse `dyn`
+type Start = Arc<dyn Fn(Heartbeat) + Send + Sync>;
+type Safe = Box<dyn Fn() + Send>;
+
+/// A subsystem thread, and what to do when it goes wrong.
+#[derive(DebugStub)]
+pub struct Supervised {
+    name: &'static str,
+    /// Builds and runs the subsystem. Dropped once it won't be started again, so the channels
+    /// it holds close and other subsystems can tell it's gone.
+    #[debug_stub = "Opaque(Start)"]
+    start: Option<Start>,
+    #[debug_stub = "Opaque(Safe)"]
+    safe: Option<Safe>,
+    /// Whether it loops, and so should keep beating its heartbeat
+    loops: bool,
+    max_restarts: usize,
+    restarts: usize,
+    heartbeat: Heartbeat,
+    state: State,
+    /// When it was last started, so one that hangs before its first beat stalls too
+    started_at: Option<Instant>,
+    restart_at: Option<Instant>,
+}
+
+impl Supervised {
+    /// A subsystem that loops, built and run by `start` on its own thread, every time it's
+    /// started. `start` should beat the heartbeat it's given every loop.
+    pub fn looping(name: &'static str, start: impl Fn(Heartbeat) + Send + Sync + 'static) -> Self {
+        Self {
+            name,
+            start: Some(Arc::new(start)),
+            safe: None,
+            loops: true,
+            max_restarts: MAX_RESTARTS,
+            restarts: 0,
+            heartbeat: Heartbeat::new(),
+            state: State::Starting,
+            started_at: None,
+            restart_at: None,
+        }
+    }
+
+    /// A subsystem that's already built, so it only runs once, and isn't expected to beat.
+    pub fn once(name: &'static str, subsystem: impl Subsystem + Send + 'static) -> Self {
+        let subsystem = Mutex::new(Some(subsystem));
+        let start = move |_| {
+            let taken = subsystem.lock().ok().and_then(|mut s| s.take());
+            if let Some(subsystem) = taken {
+                subsystem.run();
+            }
+        };
+        Self {
+            loops: false,
+            max_restarts: 0,
+            ..Self::looping(name, start)
+        }
+    }
+
+    /// Runs `safe` when the subsystem panics, stalls or stops, to put its hardware in a safe
+    /// state. It runs on the supervisor's thread, so it has to make its own handles to the
+    /// hardware.
+    pub fn with_safe(self, safe: impl Fn() + Send + 'static) -> Self {
+        Self {
+            safe: Some(Box::new(safe)),
+            ..self
+        }
+    }
+
+    pub fn with_restarts(self, max_restarts: usize) -> Self {
+        Self {
+            max_restarts,
+            ..self
+        }
+    }
+
+    fn make_safe(&self) {
+        if let Some(safe) = &self.safe {
+            safe();
+            self.heartbeat.pulse().made_safe = true;
+        }
+    }
+}
+
+/// A subsystem's thread ended, with the panic message if it panicked.
+#[derive(Debug)]
+struct Exit {
+    child: usize,
+    panic: Option<String>,
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct Supervisor {
+    children: Vec<Supervised>,
+    exits: (Sender<Exit>, Receiver<Exit>),
+    log: Log,
+}
+
+impl Supervisor {
+    pub fn new(log: Log) -> Self {
+        Self {
+            children: Vec::new(),
+            exits: unbounded(),
+            log,
+        }
+    }
+
+    pub fn supervise(mut self, child: Supervised) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Each subsystem's name and state.
+    pub fn states(&self) -> Vec<(&'static str, State)> {
+        self.children.iter().map(|c| (c.name, c.state)).collect()
+    }
+
+    fn event(&self, message: String) {
+        self.log.event("supervisor", message);
+    }
+
+    fn start(&mut self, i: usize, now: Instant) {
+        let child = &mut self.children[i];
+        let start = match &child.start {
+            Some(start) => start.clone(),
+            None => return,
+        };
+        child.heartbeat = Heartbeat::new();
+        child.state = State::Starting;
+        child.started_at = Some(now);
+        child.restart_at = None;
+        let heartbeat = child.heartbeat.clone();
+        let exits = self.exits.0.clone();
+        let spawned = thread::Builder::new()
+            .name(child.name.to_string())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| start(heartbeat)));
+                let panic = result.err().map(|payload| panic_message(&*payload));
+                exits.send(Exit { child: i, panic }).ok();
+            });
+        if let Err(e) = spawned {
+            let name = child.name;
+            self.stop(i);
+            self.event(format!("Could not start {}: {}", name, e));
+        }
+    }
+
+    /// Leaves a subsystem stopped for good.
+    fn stop(&mut self, i: usize) {
+        let child = &mut self.children[i];
+        child.make_safe();
+        child.state = State::Stopped;
+        child.start = None;
+    }
+
+    fn exited(&mut self, exit: Exit, now: Instant) {
+        let child = &mut self.children[exit.child];
+        let name = child.name;
+        let what = match &exit.panic {
+            Some(message) => format!("{} panicked: {}", name, message),
+            None => format!("{} returned", name),
+        };
+        if exit.panic.is_some() && child.restarts < child.max_restarts {
+            child.make_safe();
+            child.restarts += 1;
+            child.state = State::Restarting;
+            child.restart_at = Some(now + RESTART_DELAY);
+            let restart = child.restarts;
+            let max = child.max_restarts;
+            self.event(format!("{}, restarting ({} of {})", what, restart, max));
+        } else {
+            self.stop(exit.child);
+            self.event(format!("{}, left stopped", what));
+        }
+    }
+
+    /// Restarts what's due, looks for stalls, and logs everyone's health, as of `now`.
+    fn check(&mut self, now: Instant) {
+        for i in 0..self.children.len() {
+            let child = &mut self.children[i];
+            let pulse = child.heartbeat.take();
+            match child.state {
+                State::Restarting if child.restart_at.map_or(true, |at| at <= now) => {
+                    self.start(i, now);
+                }
+                State::Starting | State::Running | State::Stalled if child.loops => {
+                    let since = pulse.last.or(child.started_at);
+                    let stalled = since.map_or(false, |since| now > since + STALL_TIMEOUT);
+                    match (child.state, stalled) {
+                        (State::Stalled, false) => {
+                            child.state = State::Running;
+                            let name = child.name;
+                            self.event(format!("{} recovered", name));
+                        }
+                        (State::Stalled, true) => (),
+                        (_, true) => {
+                            child.make_safe();
+                            child.state = State::Stalled;
+                            let name = child.name;
+                            let what = if pulse.last.is_some() {
+                                "stalled"
+                            } else {
+                                "stalled starting"
+                            };
+                            self.event(format!("{} {}, made safe", name, what));
+                        }
+                        (_, false) if pulse.last.is_some() => child.state = State::Running,
+                        (_, false) => (),
+                    }
+                }
+                _ => (),
+            }
+            let child = &self.children[i];
+            self.log.record(HealthRecord {
+                subsystem: child.name,
+                state: format!("{:?}", child.state),
+                restarts: child.restarts,
+                overruns: pulse.stats.overruns,
+                worst_jitter_ms: pulse.stats.worst_jitter.as_secs_f64() * 1000.0,
+                mean_jitter_ms: pulse.stats.mean_jitter().as_secs_f64() * 1000.0,
+            });
+        }
+    }
+}
+
+impl Subsystem for Supervisor {
+    /// Starts every subsystem, then supervises them forever.
+    fn run(mut self) {
+        let now = Instant::now();
+        for i in 0..self.children.len() {
+            self.start(i, now);
+        }
+        let mut next_check = Instant::now() + CHECK_PERIOD;
+        loop {
+            let now = Instant::now();
+            let timeout = if next_check > now {
+                next_check - now
+            } else {
+                Duration::from_secs(0)
+            };
+            match self.exits.1.recv_timeout(timeout) {
+                Ok(exit) => self.exited(exit, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.check(Instant::now());
+                    next_check += CHECK_PERIOD;
+                }
+                // the supervisor holds a sender
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::telemetry::{queue, Payload, Record};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn events(entries: &Receiver<crate::subsystems::telemetry::Entry>) -> Vec<String> {
+        entries
+            .try_iter()
+            .filter_map(|entry| match entry.payload {
+                Payload::Record(Record::EventRecord(event)) => Some(event.message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn tick(jitter_ms: u64, overran: bool) -> Tick {
+        Tick {
+            dt: Duration::from_millis(5),
+            jitter: Duration::from_millis(jitter_ms),
+            overran,
+        }
+    }
+
+    impl Supervisor {
+        /// Waits for a subsystem's thread to end, and handles it as of `now`.
+        fn wait_for_exit(&mut self, now: Instant) {
+            let exit = self.exits.1.recv_timeout(Duration::from_secs(5)).unwrap();
+            self.exited(exit, now);
+        }
+    }
+
+    #[test]
+    fn restarts_after_a_panic() {
+        let (log, entries) = queue(1024, 0);
+        let starts = Arc::new(AtomicUsize::new(0));
+        let safed = Arc::new(AtomicUsize::new(0));
+        let (started, safe) = (starts.clone(), safed.clone());
+        // the restarted subsystem runs until the test is done with it
+        let (running, restarted) = unbounded();
+        let (_release, released) = unbounded::<()>();
+        let child = Supervised::looping("flaky", move |_| {
+            if started.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("CONFIG ALL FAILED");
+            }
+            running.send(()).ok();
+            released.recv().ok();
+        })
+        .with_safe(move || {
+            safe.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut supervisor = Supervisor::new(log).supervise(child);
+        let now = Instant::now();
+        supervisor.start(0, now);
+        supervisor.wait_for_exit(now);
+        assert_eq!(supervisor.states(), vec![("flaky", State::Restarting)]);
+        assert_eq!(safed.load(Ordering::SeqCst), 1);
+
+        supervisor.check(now + RESTART_DELAY / 2);
+        assert_eq!(supervisor.states(), vec![("flaky", State::Restarting)]);
+        supervisor.check(now + RESTART_DELAY);
+        restarted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+        assert_eq!(supervisor.states(), vec![("flaky", State::Starting)]);
+
+        let beat = now + RESTART_DELAY + CHECK_PERIOD;
+        supervisor.children[0]
+            .heartbeat
+            .beat_at(&tick(0, false), beat);
+        supervisor.check(beat);
+        assert_eq!(supervisor.states(), vec![("flaky", State::Running)]);
+        assert_eq!(
+            events(&entries),
+            vec!["flaky panicked: CONFIG ALL FAILED, restarting (1 of 3)".to_string()]
+        );
+    }
+
+    #[test]
+    fn safes_stalls_and_stops() {
+        let (log, entries) = queue(1024, 0);
+        let safed = Arc::new(AtomicUsize::new(0));
+        let safe = safed.clone();
+        let (_release, released) = unbounded::<()>();
+        let stalling = Supervised::looping("stalling", move |_| {
+            released.recv().ok();
+        })
+        .with_safe(move || {
+            safe.fetch_add(1, Ordering::SeqCst);
+        });
+        let crashing = Supervised::looping("crashing", |_| panic!("oops")).with_restarts(0);
+        let mut supervisor = Supervisor::new(log).supervise(stalling).supervise(crashing);
+        let now = Instant::now();
+        supervisor.start(0, now);
+        supervisor.start(1, now);
+        supervisor.wait_for_exit(now);
+
+        let heartbeat = supervisor.children[0].heartbeat.clone();
+        heartbeat.beat_at(&tick(0, false), now);
+        supervisor.check(now + STALL_TIMEOUT);
+        assert_eq!(
+            supervisor.states(),
+            vec![("stalling", State::Running), ("crashing", State::Stopped)]
+        );
+        assert!(!heartbeat.was_made_safe());
+        supervisor.check(now + STALL_TIMEOUT + CHECK_PERIOD);
+        assert_eq!(safed.load(Ordering::SeqCst), 1);
+        assert!(heartbeat.was_made_safe());
+        assert!(!heartbeat.was_made_safe());
+        assert_eq!(
+            supervisor.states(),
+            vec![("stalling", State::Stalled), ("crashing", State::Stopped)]
+        );
+
+        let later = now + STALL_TIMEOUT + 2 * CHECK_PERIOD;
+        heartbeat.beat_at(&tick(0, false), later);
+        supervisor.check(later);
+        assert_eq!(supervisor.states()[0], ("stalling", State::Running));
+        assert_eq!(
+            events(&entries),
+            vec![
+                "crashing panicked: oops, left stopped".to_string(),
+                "stalling stalled, made safe".to_string(),
+                "stalling recovered".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn safes_hangs_before_the_first_beat() {
+        let (log, entries) = queue(1024, 0);
+        let safed = Arc::new(AtomicUsize::new(0));
+        let safe = safed.clone();
+        let (_release, released) = unbounded::<()>();
+        let hanging = Supervised::looping("hanging", move |_| {
+            released.recv().ok();
+        })
+        .with_safe(move || {
+            safe.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut supervisor = Supervisor::new(log).supervise(hanging);
+        let now = Instant::now();
+        supervisor.start(0, now);
+
+        supervisor.check(now + STALL_TIMEOUT);
+        assert_eq!(supervisor.states(), vec![("hanging", State::Starting)]);
+        supervisor.check(now + STALL_TIMEOUT + CHECK_PERIOD);
+        assert_eq!(supervisor.states(), vec![("hanging", State::Stalled)]);
+        assert_eq!(safed.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            events(&entries),
+            vec!["hanging stalled starting, made safe".to_string()]
+        );
+    }
+
+    #[test]
+    fn counts_overruns() {
+        let heartbeat = Heartbeat::new();
+        heartbeat.beat(&tick(1, false));
+        heartbeat.beat(&tick(0, true));
+        heartbeat.beat(&tick(2, false));
+        let pulse = heartbeat.take();
+        assert_eq!(pulse.stats.loops, 3);
+        assert_eq!(pulse.stats.overruns, 1);
+        assert_eq!(pulse.stats.worst_jitter, Duration::from_millis(2));
+        assert_eq!(pulse.stats.mean_jitter(), Duration::from_millis(1));
+        assert_eq!(heartbeat.take().stats, Stats::default());
+        assert!(heartbeat.take().last.is_some());
+    }
+}
@@ -0,0 +1,945 @@
+//! Timestamped records from every subsystem, written to CSV files on the RIO.
+//!
+//! Subsystems hold a [`Log`] and hand it a typed record every loop. Logging never blocks: records
+//! go into a queue, and if the writer falls behind by `QUEUE_LENGTH` they are dropped and counted
+//! instead. The [`Telemetry`] subsystem drains the queue on a low priority thread.
+//!
+//! Each boot gets a new numbered directory under `config::telemetry::DIR`, with one series of
+//! files per kind of record (`drive-000.csv`, `drive-001.csv`, ...). A file is closed once it
+//! reaches `MAX_FILE_BYTES`, and only the newest `MAX_FILES` of each kind are kept. Every row
+//! starts with `time`, seconds since the robot code started.
+//!
+//! The match log for the replay tool goes through here too, as CBOR frames in `match-000.cbor`
+//! and so on, each a `(sequence number, time, frame)` triple. Frames get `FRAME_QUEUE_LENGTH` more
+//! room in the queue than records before they're dropped. Subsystems snapshot their logic into a
+//! frame every `KEYFRAME_PERIOD` loops, and once frames are missing [`read_frames`] starts each
+//! subsystem again from its next snapshot. No file is deleted from the latest enabled period, or
+//! from each subsystem's last snapshot before it, so the period can be replayed whole. Before that,
+//! only the newest `MAX_FRAME_FILES` are kept.
+use super::{dashboard, Subsystem};
+use crate::config::telemetry::*;
+use crate::replay::Frame;
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use networktables::{Table, Value};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Declares each kind of record, the [`Record`] enum of all of them, and the CSV header for each.
+macro_rules! records {
+    ($($(#[$meta:meta])* $name:ident($kind:expr) {
+        $($(#[$field_meta:meta])* $field:ident: $ty:ty,)*
+    })*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Serialize)]
+            pub struct $name {
+                $($(#[$field_meta])* pub $field: $ty,)*
+            }
+
+            impl From<$name> for Record {
+                fn from(record: $name) -> Self {
+                    Record::$name(record)
+                }
+            }
+        )*
+
+        #[derive(Debug, Clone)]
+        pub enum Record {
+            $($name($name),)*
+        }
+
+        impl Record {
+            /// Names the files this record goes in
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $(Record::$name(_) => $kind,)*
+                }
+            }
+
+            fn header(&self) -> &'static [&'static str] {
+                match self {
+                    $(Record::$name(_) => &["time", $(stringify!($field)),*],)*
+                }
+            }
+
+            /// Each field's name and value, for the dashboard
+            pub fn fields(&self) -> Vec<(&'static str, Value)> {
+                match self {
+                    $(Record::$name(record) => vec![
+                        $((stringify!($field), record.$field.clone().into()),)*
+                    ],)*
+                }
+            }
+
+            fn write_row<W: Write>(&self, time: f64, csv: &mut csv::Writer<W>) -> csv::Result<()> {
+                match self {
+                    $(Record::$name(record) => csv.serialize((time, record)),)*
+                }
+            }
+        }
+    };
+}
+
+records! {
+    /// The drive's estimated pose and what it last told the talons
+    DriveRecord("drive") {
+        x_m: f64,
+        y_m: f64,
+        heading_rad: f64,
+        velocity_mps: f64,
+        angular_velocity_radps: f64,
+        high_gear: bool,
+        /// `percent` or `velocity`, or `none` before the first command
+        mode: &'static str,
+        /// Percent output or meters per second, depending on `mode`
+        left_demand: f64,
+        right_demand: f64,
+        /// Where the latest fresh vision target is on the field, NaN without one
+        target_x_m: f64,
+        target_y_m: f64,
+    }
+
+    SuperstructureRecord("superstructure") {
+        elevator_state: String,
+        elevator_goal_m: f64,
+        /// See `superstructure::Mode`
+        mode: String,
+        channel: String,
+        /// Balls in the robot, see `superstructure::BallProgress`
+        balls: usize,
+        intake_pct: f64,
+        outtake_pct: f64,
+        intake_extended: bool,
+        /// NaN if the sensor couldn't be read
+        pressure_psi: f64,
+    }
+
+    /// A subsystem's health, from the supervisor
+    HealthRecord("health") {
+        subsystem: &'static str,
+        /// See `supervisor::State`
+        state: String,
+        restarts: usize,
+        /// Loops that ran past the next one's deadline, since the last record
+        overruns: usize,
+        /// Latest a loop started after its deadline, since the last record
+        worst_jitter_ms: f64,
+        mean_jitter_ms: f64,
+    }
+
+    /// A target from the TX1, see `subsystems::vision`
+    VisionRecord("vision") {
+        /// Counterclockwise from straight ahead
+        bearing_rad: f64,
+        range_m: f64,
+        /// From the frame's capture to the target arriving
+        latency_ms: f64,
+    }
+
+    /// Something that happened once, like a driver command or an error
+    EventRecord("events") {
+        source: &'static str,
+        message: String,
+    }
+}
+
+/// Names the match log's files
+pub const FRAMES: &str = "match";
+
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Record(Record),
+    /// Numbered from 0, so a missing frame shows
+    Frame(u64, Frame),
+}
+
+impl Payload {
+    fn kind(&self) -> &'static str {
+        match self {
+            Payload::Record(record) => record.kind(),
+            Payload::Frame(..) => FRAMES,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Since the robot code started
+    pub time: Duration,
+    pub payload: Payload,
+}
+
+/// Hands records to the telemetry thread. Clones share the same queue and clock.
+#[derive(Debug, Clone)]
+pub struct Log {
+    /// Frames are dropped once it's full
+    sender: Option<Sender<Entry>>,
+    /// Records are dropped once this many entries are waiting
+    capacity: usize,
+    start: Instant,
+    dropped: Arc<AtomicUsize>,
+    dropped_frames: Arc<AtomicUsize>,
+    /// The next frame's sequence number
+    frames: Arc<Mutex<u64>>,
+}
+
+impl Log {
+    /// A log that throws everything away, for tests and subsystems nobody is watching.
+    pub fn discard() -> Self {
+        Self {
+            sender: None,
+            capacity: 0,
+            start: Instant::now(),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            dropped_frames: Arc::new(AtomicUsize::new(0)),
+            frames: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn record(&self, record: impl Into<Record>) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        if sender.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.send(sender, Payload::Record(record.into()), &self.dropped);
+    }
+
+    /// Adds a frame to the match log. Frames still have room once records are being dropped, and
+    /// are only dropped once the whole queue is full.
+    pub fn frame(&self, frame: Frame) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        // held while sending, so frames from different threads queue in order. A dropped frame
+        // keeps its number, so the replay can tell it's missing.
+        let mut next = self.frames.lock().expect("Frame count poisoned");
+        self.send(sender, Payload::Frame(*next, frame), &self.dropped_frames);
+        *next += 1;
+    }
+
+    fn send(&self, sender: &Sender<Entry>, payload: Payload, dropped: &AtomicUsize) {
+        let entry = Entry {
+            time: self.start.elapsed(),
+            payload,
+        };
+        match sender.try_send(entry) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            // the writer gave up, and it already said why
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    pub fn event(&self, source: &'static str, message: impl Into<String>) {
+        self.record(EventRecord {
+            source,
+            message: message.into(),
+        })
+    }
+}
+
+/// Counts bytes on their way to a file, so it can be rotated by size. The count is shared since
+/// the csv writer owns this.
+#[derive(Debug)]
+struct Counted<W> {
+    inner: W,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug)]
+enum Format {
+    Csv(Box<csv::Writer<Counted<File>>>),
+    Cbor(BufWriter<Counted<File>>),
+}
+
+#[derive(Debug)]
+struct Series {
+    format: Format,
+    bytes: Arc<AtomicU64>,
+    index: usize,
+}
+
+impl Series {
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.format {
+            Format::Csv(csv) => csv.flush(),
+            Format::Cbor(cbor) => cbor.flush(),
+        }
+    }
+}
+
+fn cbor_error(e: serde_cbor::error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Writes records to size capped CSV files in one directory.
+#[derive(Debug)]
+pub struct Writer {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    max_frame_files: usize,
+    series: HashMap<&'static str, Series>,
+    /// The match log file a replay of the latest enabled period would start from. It and every
+    /// file after it are kept.
+    period_start: Option<usize>,
+    enabled: bool,
+    /// The match log file each subsystem's latest snapshot is in, or `None` before it's had one
+    keyframes: HashMap<&'static str, Option<usize>>,
+}
+
+impl Writer {
+    pub fn new(dir: impl Into<PathBuf>, max_file_bytes: u64, max_files: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            max_file_bytes,
+            max_files,
+            max_frame_files: max_files,
+            series: HashMap::new(),
+            period_start: None,
+            enabled: false,
+            keyframes: HashMap::new(),
+        }
+    }
+
+    /// Keeps a different number of match log files from before the robot was last enabled than
+    /// of each kind of record.
+    pub fn with_frame_files(self, max_frame_files: usize) -> Self {
+        Self {
+            max_frame_files,
+            ..self
+        }
+    }
+
+    /// A writer in a new numbered directory under `root`, deleting all but the newest
+    /// `max_sessions` directories.
+    pub fn new_session(
+        root: impl AsRef<Path>,
+        max_sessions: usize,
+        max_file_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let root = root.as_ref();
+        fs::create_dir_all(root)?;
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(root)? {
+            let entry = entry?;
+            if let Some(n) = entry
+                .file_name()
+                .to_str()
+                .and_then(|s| s.parse::<u32>().ok())
+            {
+                sessions.push(n);
+            }
+        }
+        sessions.sort();
+        let next = sessions.last().map_or(0, |n| n + 1);
+        let keep = max_sessions.saturating_sub(1);
+        for old in &sessions[..sessions.len().saturating_sub(keep)] {
+            fs::remove_dir_all(root.join(old.to_string()))?;
+        }
+        let dir = root.join(next.to_string());
+        fs::create_dir(&dir)?;
+        Ok(Self::new(dir, max_file_bytes, max_files))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, kind: &str, index: usize) -> PathBuf {
+        file_path(&self.dir, kind, index)
+    }
+
+    fn open(&self, payload: &Payload, index: usize) -> io::Result<Series> {
+        let kind = payload.kind();
+        let (max_files, keep_from) = match payload {
+            Payload::Record(_) => (self.max_files, None),
+            Payload::Frame(..) => (self.max_frame_files, self.period_start),
+        };
+        if index >= max_files && keep_from.map_or(true, |start| index - max_files < start) {
+            let oldest = self.path(kind, index - max_files);
+            if let Err(e) = fs::remove_file(oldest) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        let bytes = Arc::new(AtomicU64::new(0));
+        let file = Counted {
+            inner: File::create(self.path(kind, index))?,
+            bytes: Arc::clone(&bytes),
+        };
+        let format = match payload {
+            Payload::Record(record) => {
+                // the header is written by hand since csv can't derive one from a tuple
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(file);
+                csv.write_record(record.header())?;
+                Format::Csv(Box::new(csv))
+            }
+            Payload::Frame(..) => Format::Cbor(BufWriter::new(file)),
+        };
+        Ok(Series {
+            format,
+            bytes,
+            index,
+        })
+    }
+
+    /// Notes where a replay of the enabled period would have to start, before `frame` is written
+    /// to file `index`.
+    fn track(&mut self, frame: &Frame, index: usize) {
+        let keyframe = self.keyframes.entry(frame.subsystem()).or_insert(None);
+        if frame.is_keyframe() {
+            *keyframe = Some(index);
+        }
+        if let Some(enabled) = frame.enabled() {
+            if enabled && !self.enabled {
+                // a subsystem without a snapshot yet replays from the start
+                let start = self
+                    .keyframes
+                    .values()
+                    .map(|index| index.unwrap_or(0))
+                    .min();
+                self.period_start = Some(start.unwrap_or(index));
+            }
+            self.enabled = enabled;
+        }
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let kind = entry.payload.kind();
+        let next = match self.series.get(kind) {
+            Some(series) if series.bytes.load(Ordering::Relaxed) < self.max_file_bytes => None,
+            Some(series) => Some(series.index + 1),
+            None => Some(0),
+        };
+        if let Payload::Frame(_, frame) = &entry.payload {
+            let index = next.or_else(|| self.series.get(kind).map(|series| series.index));
+            self.track(frame, index.expect("either open or opening"));
+        }
+        if let Some(index) = next {
+            let series = self.open(&entry.payload, index)?;
+            if let Some(mut full) = self.series.insert(kind, series) {
+                full.flush()?;
+            }
+        }
+        let series = self.series.get_mut(kind).expect("opened above");
+        let time = entry.time.as_secs_f64();
+        match (&mut series.format, &entry.payload) {
+            (Format::Csv(csv), Payload::Record(record)) => record.write_row(time, csv)?,
+            (Format::Cbor(cbor), Payload::Frame(seq, frame)) => {
+                serde_cbor::to_writer(cbor, &(seq, time, frame)).map_err(cbor_error)?
+            }
+            _ => unreachable!("each kind is always written the same way"),
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for series in self.series.values_mut() {
+            series.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn file_path(dir: &Path, kind: &str, index: usize) -> PathBuf {
+    let extension = if kind == FRAMES { "cbor" } else { "csv" };
+    dir.join(format!("{}-{:03}.{}", kind, index, extension))
+}
+
+/// A match log, read back to replay
+#[derive(Debug)]
+pub struct MatchLog {
+    /// Oldest first, with the time of each
+    pub frames: Vec<(f64, Frame)>,
+    /// Frames missing from the log, and frames left out until their subsystem's next snapshot
+    pub skipped: u64,
+}
+
+/// Reads back the match log in a session directory. Once frames are missing, whether deleted with
+/// the oldest files or dropped from a full queue, each subsystem's frames are left out until its
+/// next snapshot, so that what's left can be replayed. A frame cut short by the robot turning off
+/// is left out too.
+pub fn read_frames(dir: impl AsRef<Path>) -> io::Result<MatchLog> {
+    let dir = dir.as_ref();
+    let prefix = format!("{}-", FRAMES);
+    let mut indexes = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let index = name
+            .to_str()
+            .filter(|name| name.starts_with(&prefix) && name.ends_with(".cbor"))
+            .and_then(|name| {
+                name[prefix.len()..name.len() - ".cbor".len()]
+                    .parse::<usize>()
+                    .ok()
+            });
+        indexes.extend(index);
+    }
+    indexes.sort();
+    let mut bytes = Vec::new();
+    // frames never straddle files, so the files can be read as one, and a missing file shows as
+    // missing frames
+    for index in indexes {
+        bytes.extend(fs::read(file_path(dir, FRAMES, index))?);
+    }
+    if bytes.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "match log is empty",
+        ));
+    }
+    let mut log = MatchLog {
+        frames: Vec::new(),
+        skipped: 0,
+    };
+    let mut next = 0;
+    // since frames went missing, the subsystems that have had a snapshot
+    let mut resumed: Option<HashSet<&'static str>> = None;
+    let stream = serde_cbor::Deserializer::from_slice(&bytes).into_iter::<(u64, f64, Frame)>();
+    for frame in stream {
+        let (seq, time, frame) = match frame {
+            Ok(frame) => frame,
+            Err(ref e) if e.is_eof() => break,
+            Err(e) => return Err(cbor_error(e)),
+        };
+        if seq != next {
+            log.skipped += seq.saturating_sub(next);
+            resumed = Some(HashSet::new());
+        }
+        next = seq + 1;
+        if let Some(resumed) = &mut resumed {
+            if frame.is_keyframe() {
+                resumed.insert(frame.subsystem());
+            }
+            if !resumed.contains(frame.subsystem()) {
+                log.skipped += 1;
+                continue;
+            }
+        }
+        log.frames.push((time, frame));
+    }
+    Ok(log)
+}
+
+/// Creates a log and the receiving end of its queue, which drops records past `length` and
+/// frames past `frame_length` more.
+pub(crate) fn queue(length: usize, frame_length: usize) -> (Log, Receiver<Entry>) {
+    let (sender, receiver) = bounded(length + frame_length);
+    let log = Log {
+        sender: Some(sender),
+        capacity: length,
+        start: Instant::now(),
+        dropped: Arc::new(AtomicUsize::new(0)),
+        dropped_frames: Arc::new(AtomicUsize::new(0)),
+        frames: Arc::new(Mutex::new(0)),
+    };
+    (log, receiver)
+}
+
+#[derive(Debug)]
+pub struct Telemetry {
+    writer: Writer,
+    receiver: Receiver<Entry>,
+    log: Log,
+    dashboard: Option<Table>,
+}
+
+impl Telemetry {
+    /// Starts a new session under `config::telemetry::DIR`. The returned log is what subsystems
+    /// record to.
+    pub fn new() -> io::Result<(Self, Log)> {
+        let writer = Writer::new_session(DIR, MAX_SESSIONS, MAX_FILE_BYTES, MAX_FILES)?
+            .with_frame_files(MAX_FRAME_FILES);
+        let (log, receiver) = queue(QUEUE_LENGTH, FRAME_QUEUE_LENGTH);
+        let telemetry = Self {
+            writer,
+            receiver,
+            log: log.clone(),
+            dashboard: None,
+        };
+        Ok((telemetry, log))
+    }
+
+    /// Also publishes every record to a dashboard table, see `dashboard::publish`.
+    pub fn with_dashboard(self, table: Table) -> Self {
+        Self {
+            dashboard: Some(table),
+            ..self
+        }
+    }
+
+    /// Notes how many records and frames were dropped since the last report, if any were.
+    fn report_dropped(&self) {
+        let dropped = self.log.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            self.log
+                .event("telemetry", format!("dropped {} records", dropped));
+        }
+        let dropped = self.log.dropped_frames.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            self.log
+                .event("telemetry", format!("dropped {} match log frames", dropped));
+        }
+    }
+
+    fn write_queued(&mut self) -> io::Result<()> {
+        let mut last_flush = Instant::now();
+        loop {
+            match self.receiver.recv_timeout(FLUSH_PERIOD) {
+                Ok(entry) => {
+                    if let (Some(table), Payload::Record(record)) =
+                        (&self.dashboard, &entry.payload)
+                    {
+                        dashboard::publish(table, record);
+                    }
+                    self.writer.write(&entry)?
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return self.writer.flush(),
+            }
+            if last_flush.elapsed() >= FLUSH_PERIOD {
+                self.report_dropped();
+                self.writer.flush()?;
+                last_flush = Instant::now();
+            }
+        }
+    }
+}
+
+/// Makes the calling thread yield to every normal priority thread. On Linux, niceness is per
+/// thread rather than per process.
+fn lower_priority() -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, NICENESS) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+impl Subsystem for Telemetry {
+    fn run(mut self) {
+        if let Err(e) = lower_priority() {
+            self.log.event(
+                "telemetry",
+                format!("Could not lower the telemetry priority: {}", e),
+            );
+        }
+        self.log.event(
+            "telemetry",
+            format!("Logging telemetry to {}", self.writer.dir().display()),
+        );
+        if let Err(e) = self.write_queued() {
+            // the robot runs fine without it, and logs stop queueing once this is dropped, so
+            // this goes straight to the files, if they still take it
+            let entry = Entry {
+                time: self.log.start.elapsed(),
+                payload: Payload::Record(
+                    EventRecord {
+                        source: "telemetry",
+                        message: format!("Telemetry stopped: {}", e),
+                    }
+                    .into(),
+                ),
+            };
+            self.writer
+                .write(&entry)
+                .and_then(|()| self.writer.flush())
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::replay;
+    use crate::subsystems::controller::recording::{
+        ControllerFrame, ControlsTape, ReplayedControls,
+    };
+    use crate::subsystems::controller::Bindings;
+    use crossbeam_channel::unbounded;
+    use tunables::Registry;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("telemetry-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn event(millis: u64, message: &str) -> Entry {
+        Entry {
+            time: Duration::from_millis(millis),
+            payload: Payload::Record(
+                EventRecord {
+                    source: "test",
+                    message: message.to_string(),
+                }
+                .into(),
+            ),
+        }
+    }
+
+    #[test]
+    fn rotates_files() {
+        let dir = temp_dir("rotate");
+        fs::create_dir_all(&dir).unwrap();
+        let mut writer = Writer::new(&dir, 40, 2);
+        for i in 0..6 {
+            writer.write(&event(i * 1000 + 500, "hello")).unwrap();
+            // the size is only known once the csv buffer reaches the file
+            writer.flush().unwrap();
+        }
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["events-001.csv", "events-002.csv"]);
+        let newest = fs::read_to_string(dir.join("events-002.csv")).unwrap();
+        assert_eq!(
+            newest,
+            "time,source,message\n4.5,test,hello\n5.5,test,hello\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_typed_records() {
+        let dir = temp_dir("typed");
+        fs::create_dir_all(&dir).unwrap();
+        let mut writer = Writer::new(&dir, MAX_FILE_BYTES, MAX_FILES);
+        let record = SuperstructureRecord {
+            elevator_state: "Holding".to_string(),
+            elevator_goal_m: 0.5,
+            mode: "Loading".to_string(),
+            channel: "Queued".to_string(),
+            balls: 1,
+            intake_pct: 0.8,
+            outtake_pct: 0.25,
+            intake_extended: true,
+            pressure_psi: 110.5,
+        };
+        let entry = Entry {
+            time: Duration::from_millis(1500),
+            payload: Payload::Record(record.into()),
+        };
+        writer.write(&entry).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("superstructure-000.csv")).unwrap(),
+            "time,elevator_state,elevator_goal_m,mode,channel,balls,intake_pct,outtake_pct,\
+             intake_extended,pressure_psi\n1.5,Holding,0.5,Loading,Queued,1,0.8,0.25,true,110.5\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbers_sessions() {
+        let root = temp_dir("sessions");
+        for _ in 0..4 {
+            Writer::new_session(&root, 2, MAX_FILE_BYTES, MAX_FILES).unwrap();
+        }
+        let mut sessions: Vec<_> = fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        sessions.sort();
+        assert_eq!(sessions, vec!["2", "3"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn controller_frame(robot_state: &str) -> Frame {
+        Frame::Controller(ControllerFrame {
+            robot_state: robot_state.to_string(),
+            controls: None,
+            drive: Vec::new(),
+            superstructure: Vec::new(),
+            aim: None,
+            snapshot: None,
+        })
+    }
+
+    fn frame(seq: u64, robot_state: &str) -> Entry {
+        Entry {
+            time: Duration::from_millis(seq * 20),
+            payload: Payload::Frame(seq, controller_frame(robot_state)),
+        }
+    }
+
+    /// A frame with a snapshot of freshly made bindings.
+    fn keyframe(seq: u64, robot_state: &str) -> Entry {
+        let mut entry = frame(seq, robot_state);
+        if let Payload::Frame(_, Frame::Controller(frame)) = &mut entry.payload {
+            let bindings = Bindings::new(
+                ReplayedControls::new(&ControlsTape::new()),
+                unbounded().0,
+                unbounded().0,
+            );
+            frame.snapshot = Some(bindings.snapshot());
+        }
+        entry
+    }
+
+    fn write_frames(dir: &Path, frames: &[Entry], max_frame_files: usize) {
+        fs::create_dir_all(dir).unwrap();
+        // a file per frame
+        let mut writer = Writer::new(dir, 1, MAX_FILES).with_frame_files(max_frame_files);
+        for frame in frames {
+            writer.write(frame).unwrap();
+            writer.flush().unwrap();
+        }
+    }
+
+    /// The times of the frames read back, in loops.
+    fn loops(log: &MatchLog) -> Vec<u64> {
+        log.frames
+            .iter()
+            .map(|(time, _)| (time * 50.0).round() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_frames() {
+        let dir = temp_dir("frames");
+        let frames: Vec<_> = (0..4).map(|seq| frame(seq, "Teleop")).collect();
+        write_frames(&dir, &frames, MAX_FRAME_FILES);
+        let log = read_frames(&dir).unwrap();
+        assert_eq!(log.frames.len(), 4);
+        assert_eq!(log.skipped, 0);
+        assert_eq!(log.frames[3].0.to_bits(), 0.06f64.to_bits());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resumes_after_gaps() {
+        let dir = temp_dir("skipped");
+        let frames = vec![
+            frame(0, "Teleop"),
+            frame(1, "Teleop"),
+            frame(3, "Teleop"),
+            keyframe(4, "Teleop"),
+            frame(5, "Teleop"),
+        ];
+        write_frames(&dir, &frames, MAX_FRAME_FILES);
+        let log = read_frames(&dir).unwrap();
+        assert_eq!(loops(&log), vec![0, 1, 4, 5]);
+        assert_eq!(log.skipped, 2);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = temp_dir("missing");
+        let frames = vec![
+            frame(0, "Teleop"),
+            frame(1, "Teleop"),
+            keyframe(2, "Teleop"),
+        ];
+        write_frames(&dir, &frames, MAX_FRAME_FILES);
+        fs::remove_file(file_path(&dir, FRAMES, 1)).unwrap();
+        let log = read_frames(&dir).unwrap();
+        assert_eq!(loops(&log), vec![0, 2]);
+        assert_eq!(log.skipped, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_enabled_period() {
+        let dir = temp_dir("period");
+        let frames: Vec<_> = (0..12)
+            .map(|seq| {
+                let state = match seq {
+                    5..=8 => "Autonomous",
+                    _ => "Disabled",
+                };
+                match seq {
+                    3 | 9 => keyframe(seq, state),
+                    _ => frame(seq, state),
+                }
+            })
+            .collect();
+        write_frames(&dir, &frames, 2);
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        // back to the snapshot before the robot was enabled
+        let kept: Vec<_> = (3..12)
+            .map(|index| format!("match-{:03}.cbor", index))
+            .collect();
+        assert_eq!(files, kept);
+        let log = read_frames(&dir).unwrap();
+        assert_eq!(loops(&log), (3..12).collect::<Vec<_>>());
+        assert_eq!(log.skipped, 3);
+        assert_eq!(replay(&log.frames, &Registry::new()), Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drops_records_when_full() {
+        let (log, receiver) = queue(2, 3);
+        for i in 0..5 {
+            log.event("test", i.to_string());
+        }
+        assert_eq!(receiver.try_iter().count(), 2);
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 3);
+
+        // frames have room past the records, and only fill what's left
+        log.event("test", "queued");
+        for _ in 0..5 {
+            log.frame(controller_frame("Teleop"));
+        }
+        log.event("test", "dropped");
+        let frames: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|entry| match entry.payload {
+                Payload::Frame(seq, _) => Some(seq),
+                Payload::Record(_) => None,
+            })
+            .collect();
+        assert_eq!(frames, vec![0, 1, 2, 3]);
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 4);
+        assert_eq!(log.dropped_frames.load(Ordering::Relaxed), 1);
+
+        // numbering carries on past the dropped frame, so the gap shows
+        log.frame(controller_frame("Teleop"));
+        match receiver.try_recv().unwrap().payload {
+            Payload::Frame(seq, _) => assert_eq!(seq, 5),
+            Payload::Record(_) => panic!("Expected a frame"),
+        }
+
+        drop(receiver);
+        log.event("test", "nobody listening");
+        Log::discard().event("test", "nobody listening");
+    }
+}
@@ -0,0 +1,42 @@
+//! Lets a laptop read and set the robot's tunable parameters while it runs, see the `tunables`
+//! crate and its `tune` tool.
+use super::telemetry::Log;
+use super::Subsystem;
+use crate::config::tuning::*;
+use std::io;
+use std::net::UdpSocket;
+use tunables::server::Server;
+use tunables::Registry;
+
+#[derive(Debug)]
+pub struct Tuning {
+    server: Server,
+    socket: UdpSocket,
+    log: Log,
+}
+
+impl Tuning {
+    pub fn new(registry: Registry) -> io::Result<Self> {
+        Ok(Self {
+            server: Server::new(registry, SAVE_PATH),
+            socket: UdpSocket::bind(("0.0.0.0", PORT))?,
+            log: Log::discard(),
+        })
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+}
+
+impl Subsystem for Tuning {
+    fn run(self) {
+        let log = self.log.clone();
+        let skipped = move |message| log.event("tuning", message);
+        if let Err(e) = self.server.serve(self.socket, skipped) {
+            // the robot still runs on whatever was tuned so far
+            self.log
+                .event("tuning", format!("Tuning server stopped: {:?}", e));
+        }
+    }
+}
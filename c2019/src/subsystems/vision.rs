@@ -0,0 +1,221 @@
+//! Receives the targets the TX1 finds, and hands them to the rest of the robot.
+//!
+//! The TX1 sends a `copcomp::c2019::Packet` to `config::vision::PORT` for every frame it finds a
+//! target in, with the target's center in pixels. [`Vision`] locates each relative to the robot
+//! and works out when the frame was captured, then keeps it as the latest. Build a
+//! [`TargetPublisher`] first, hand out handles to the latest target, then give it to [`Vision`].
+use super::telemetry::{Log, VisionRecord};
+use super::Subsystem;
+use crate::config::vision::*;
+use controls::units::*;
+use copcomp::c2019::Packet;
+use copcomp::Connection;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+pub mod camera;
+
+use camera::{Camera, Clock, Location};
+
+/// A target, and when it was seen.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Target {
+    /// When the frame was captured. Look up the robot's pose then to find the target on the field.
+    pub captured: Instant,
+    pub received: Instant,
+    pub location: Location,
+}
+
+/// The vision subsystem's end: keeps the latest target.
+#[derive(Debug, Clone)]
+pub struct TargetPublisher {
+    latest: Arc<Mutex<Option<Target>>>,
+}
+
+impl TargetPublisher {
+    pub fn new() -> Self {
+        Self {
+            latest: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn handle(&self) -> TargetHandle {
+        TargetHandle(Arc::clone(&self.latest))
+    }
+
+    pub fn publish(&mut self, target: Target) {
+        *lock(&self.latest) = Some(target);
+    }
+}
+
+impl Default for TargetPublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The latest target, from any thread.
+#[derive(Debug, Clone)]
+pub struct TargetHandle(Arc<Mutex<Option<Target>>>);
+
+impl TargetHandle {
+    pub fn latest(&self) -> Option<Target> {
+        *lock(&self.0)
+    }
+
+    /// The latest target, if it was captured no more than `MAX_AGE` before `now`.
+    pub fn fresh(&self, now: Instant) -> Option<Target> {
+        self.latest()
+            .filter(|target| now <= target.captured || now - target.captured <= MAX_AGE)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    // nothing panics while holding a lock
+    mutex.lock().expect("Vision targets poisoned")
+}
+
+#[derive(DebugStub)]
+pub struct Vision {
+    #[debug_stub = "Opaque(Connection)"]
+    connection: Connection,
+    camera: Camera,
+    clock: Clock,
+    targets: TargetPublisher,
+    log: Log,
+}
+
+impl Vision {
+    pub fn new(targets: TargetPublisher) -> io::Result<Self> {
+        Self::from_socket(UdpSocket::bind(("0.0.0.0", PORT))?, targets)
+    }
+
+    fn from_socket(socket: UdpSocket, targets: TargetPublisher) -> io::Result<Self> {
+        let connection = Connection::from_udp(socket, None, None).map_err(|e| match e {
+            copcomp::Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::Other, format!("{:?}", e)),
+        })?;
+        Ok(Self {
+            connection,
+            camera: Camera::default(),
+            clock: Clock::new(Instant::now()),
+            targets,
+            log: Log::discard(),
+        })
+    }
+
+    pub fn with_camera(self, camera: Camera) -> Self {
+        Self { camera, ..self }
+    }
+
+    pub fn with_log(self, log: Log) -> Self {
+        Self { log, ..self }
+    }
+
+    /// Waits for the next packet and publishes its target. Only socket errors are returned;
+    /// packets that don't make sense are logged and skipped.
+    fn receive(&mut self) -> io::Result<()> {
+        let packet = match self.connection.read_item::<Packet>() {
+            Ok(packet) => packet,
+            Err(copcomp::Error::Io(e)) => return Err(e),
+            Err(e) => {
+                self.log.event("vision", format!("Bad packet: {:?}", e));
+                return Ok(());
+            }
+        };
+        let received = Instant::now();
+        let captured = self.clock.captured(packet.micros, received);
+        let (x, y) = (f64::from(packet.x), f64::from(packet.y));
+        let location = match self.camera.locate(x, y, TARGET_HEIGHT) {
+            Some(location) => location,
+            None => {
+                let message = format!("No target could be at {:?}", packet);
+                self.log.event("vision", message);
+                return Ok(());
+            }
+        };
+        self.targets.publish(Target {
+            captured,
+            received,
+            location,
+        });
+        self.log.record(VisionRecord {
+            bearing_rad: location.bearing,
+            range_m: *(location.range / M),
+            latency_ms: received.duration_since(captured).as_secs_f64() * 1000.0,
+        });
+        Ok(())
+    }
+}
+
+impl Subsystem for Vision {
+    fn run(mut self) {
+        loop {
+            if let Err(e) = self.receive() {
+                // the robot drives without vision in the meantime
+                let message = format!("Receiving failed, retrying: {}", e);
+                self.log.event("vision", message);
+                thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn vision() -> (Vision, Connection, TargetHandle) {
+        let targets = TargetPublisher::new();
+        let handle = targets.handle();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let vision = Vision::from_socket(socket, targets).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(address).unwrap();
+        (
+            vision,
+            Connection::from_udp(sender, None, None).unwrap(),
+            handle,
+        )
+    }
+
+    #[test]
+    fn publishes_targets() {
+        let (mut vision, mut tx1, targets) = vision();
+        assert_eq!(targets.latest(), None);
+
+        let (x, y) = (CENTER_X as f32 - 50.0, CENTER_Y as f32);
+        tx1.write_item(&Packet { micros: 1, x, y }).unwrap();
+        vision.receive().unwrap();
+        let target = targets.latest().unwrap();
+        assert!(target.location.bearing > 0.0, "{:?}", target);
+        assert!(target.captured < target.received);
+
+        assert_eq!(targets.fresh(target.received), Some(target));
+        assert_eq!(
+            targets.fresh(target.captured + MAX_AGE + Duration::from_millis(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn skips_nonsense() {
+        let (mut vision, mut tx1, targets) = vision();
+        // far below the horizon, where a target higher than the camera can't be
+        tx1.write_item(&Packet {
+            micros: 1,
+            x: CENTER_X as f32,
+            y: 1000.0,
+        })
+        .unwrap();
+        vision.receive().unwrap();
+        tx1.write_item(&"not a packet").unwrap();
+        vision.receive().unwrap();
+        assert_eq!(targets.latest(), None);
+    }
+}
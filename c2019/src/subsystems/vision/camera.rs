@@ -0,0 +1,181 @@
+//! Turning where the TX1 saw a target into where it is, and when it saw it.
+use crate::config::vision::*;
+use controls::units::*;
+use std::time::{Duration, Instant};
+
+/// Where a target is from the middle of the drive base, along the floor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Location {
+    /// Radians, counterclockwise from straight ahead
+    pub bearing: f64,
+    pub range: Meter<f64>,
+}
+
+/// A pinhole camera's intrinsics, and where it's mounted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    /// Pixels
+    pub focal_length: (f64, f64),
+    pub center: (f64, f64),
+    /// Forward and left of the middle of the drive base
+    pub position: (Meter<f64>, Meter<f64>),
+    pub height: Meter<f64>,
+    /// Radians up from level
+    pub pitch: f64,
+}
+
+impl Camera {
+    /// Finds a target at `target_height` seen at pixel `(x, y)`, or None if the camera couldn't
+    /// have seen one at that height there.
+    pub fn locate(&self, x: f64, y: f64, target_height: Meter<f64>) -> Option<Location> {
+        // the ray through the pixel, forward, left and up from the camera
+        let left = -(x - self.center.0) / self.focal_length.0;
+        let up = -(y - self.center.1) / self.focal_length.1;
+        let (sin, cos) = self.pitch.sin_cos();
+        let (forward, up) = (cos - up * sin, sin + up * cos);
+
+        let rise = *((target_height - self.height) / M);
+        let scale = rise / up;
+        // at or above the horizon for a target below the camera, or the reverse
+        if !(scale > 0.0 && scale.is_finite() && forward > 0.0) {
+            return None;
+        }
+        let x = *(self.position.0 / M) + forward * scale;
+        let y = *(self.position.1 / M) + left * scale;
+        Some(Location {
+            bearing: y.atan2(x),
+            range: x.hypot(y) * M,
+        })
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            focal_length: (FOCAL_LENGTH_X, FOCAL_LENGTH_Y),
+            center: (CENTER_X, CENTER_Y),
+            position: (CAMERA_X, CAMERA_Y),
+            height: CAMERA_HEIGHT,
+            pitch: CAMERA_PITCH,
+        }
+    }
+}
+
+/// Estimates when frames were captured on the RIO's clock, from the TX1's timestamps.
+///
+/// The clocks aren't synchronized, so only differences between the TX1's timestamps mean
+/// anything here. The frame that arrived soonest after its timestamp sets the offset between the
+/// clocks, and is assumed to have taken `MIN_LATENCY`; the rest took that much more as they
+/// arrived later.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    start: Instant,
+    /// Microseconds from the TX1's clock to ours, for the fastest frame
+    offset: Option<i64>,
+    last: Option<u64>,
+}
+
+impl Clock {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            offset: None,
+            last: None,
+        }
+    }
+
+    /// When the frame stamped `micros` that arrived at `received` was captured.
+    pub fn captured(&mut self, micros: u64, received: Instant) -> Instant {
+        if self.last.map_or(false, |last| micros < last) {
+            // the TX1 restarted, and its clock with it
+            self.offset = None;
+        }
+        self.last = Some(micros);
+
+        let since_start = received.duration_since(self.start);
+        let offset = since_start.as_micros() as i64 - micros as i64;
+        let fastest = self.offset.map_or(offset, |fastest| fastest.min(offset));
+        self.offset = Some(fastest);
+        let delayed = Duration::from_micros((offset - fastest) as u64) + MIN_LATENCY;
+        received.checked_sub(delayed).unwrap_or(self.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_4;
+
+    fn camera() -> Camera {
+        Camera {
+            focal_length: (500.0, 500.0),
+            center: (320.0, 240.0),
+            position: (0.0 * M, 0.0 * M),
+            height: 0.5 * M,
+            pitch: FRAC_PI_4,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn locates_targets() {
+        // straight out of the middle of the lens, 45 degrees up
+        let ahead = camera().locate(320.0, 240.0, 1.5 * M).unwrap();
+        assert_close(ahead.bearing, 0.0);
+        assert_close(*(ahead.range / M), 1.0);
+
+        // to the right, at the same height in the frame
+        let right = camera().locate(820.0, 240.0, 1.5 * M).unwrap();
+        assert!(right.bearing < 0.0);
+        let left = camera().locate(-180.0, 240.0, 1.5 * M).unwrap();
+        assert_close(left.bearing, -right.bearing);
+        assert_close(*(left.range / M), *(right.range / M));
+
+        // lower in the frame is further, for a target higher than the camera
+        let below = camera().locate(320.0, 340.0, 1.5 * M).unwrap();
+        assert!(below.range > ahead.range);
+    }
+
+    #[test]
+    fn measures_from_the_middle_of_the_robot() {
+        let mounted = Camera {
+            position: (0.5 * M, 1.0 * M),
+            ..camera()
+        };
+        let ahead = mounted.locate(320.0, 240.0, 1.5 * M).unwrap();
+        assert_close(*(ahead.range / M), 1.5f64.hypot(1.0));
+        assert_close(ahead.bearing, (1.0f64).atan2(1.5));
+    }
+
+    #[test]
+    fn rejects_impossible_targets() {
+        // above the horizon, for a target below the camera
+        assert_eq!(camera().locate(320.0, 240.0, 0.2 * M), None);
+        // on the horizon, which never gets up to the target
+        let level = Camera {
+            pitch: 0.0,
+            ..camera()
+        };
+        assert_eq!(level.locate(320.0, 240.0, 1.5 * M), None);
+    }
+
+    #[test]
+    fn estimates_capture_times() {
+        let start = Instant::now();
+        let mut clock = Clock::new(start);
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // the TX1's clock is 5s ahead, and the second frame took 20ms longer than the first
+        assert_eq!(clock.captured(5_100_000, at(100)), at(100) - MIN_LATENCY);
+        assert_eq!(clock.captured(5_200_000, at(220)), at(200) - MIN_LATENCY);
+        // a faster frame resets what's fastest
+        assert_eq!(clock.captured(5_300_000, at(290)), at(290) - MIN_LATENCY);
+        assert_eq!(clock.captured(5_400_000, at(400)), at(390) - MIN_LATENCY);
+
+        // the TX1 restarted
+        assert_eq!(clock.captured(1_000, at(500)), at(500) - MIN_LATENCY);
+    }
+}
@@ -1,6 +1,9 @@
+/// A vision target the TX1 found in a frame.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Packet {
-    micros: u64,
-    x: f32,
-    y: f32,
+    /// When the frame was captured, on the TX1's clock
+    pub micros: u64,
+    /// Center of the target in pixels, from the top left of the frame
+    pub x: f32,
+    pub y: f32,
 }
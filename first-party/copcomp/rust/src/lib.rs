@@ -3,7 +3,7 @@ use serde_cbor;
 use std::borrow::{Borrow, BorrowMut};
 use std::io;
 use std::io::Cursor;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
 #[macro_use]
 extern crate serde_derive;
 
@@ -73,6 +73,28 @@ impl Connection {
         let result: R = serde_cbor::from_reader(&slice[..bytes])?;
         Ok(result)
     }
+
+    /// Like `write_item`, for an unconnected socket.
+    pub fn write_item_to<W: Serialize>(&mut self, item: &W, addr: SocketAddr) -> Result<()> {
+        let slice: &mut [u8] = self.data.borrow_mut();
+        let mut cursor = Cursor::new(slice);
+        serde_cbor::to_writer(&mut cursor, item)?;
+        let idx = cursor.position() as usize;
+        let slice: &[u8] = self.data.borrow();
+        self.udp.send_to(&slice[..idx], addr)?;
+        Ok(())
+    }
+
+    /// Like `read_item`, also giving who sent the item so it can be answered.
+    pub fn read_item_from<R>(&mut self) -> Result<(R, SocketAddr)>
+    where
+        R: for<'de> Deserialize<'de>,
+    {
+        let (bytes, addr) = self.udp.recv_from(self.data.borrow_mut())?;
+        let slice: &[u8] = self.data.borrow();
+        let result: R = serde_cbor::from_reader(&slice[..bytes])?;
+        Ok((result, addr))
+    }
 }
 
 #[cfg(test)]
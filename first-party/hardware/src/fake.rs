@@ -0,0 +1,246 @@
+//! In-memory stand-ins for robot hardware.
+//!
+//! Each fake is a cheap handle around shared state: clone it before handing it to a subsystem and
+//! keep the clone to drive inputs or inspect outputs from a test.
+use crate::io::{AnalogIn, DigitalIn, Gyro, Hardware, SolenoidOut, Talon};
+use ctre::motor_control::{ControlMode, DemandType};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use wpilib::HalResult;
+
+#[derive(Debug)]
+pub struct Fake;
+
+impl Hardware for Fake {
+    type Talon = FakeTalon;
+    type Solenoid = FakeSolenoid;
+    type DigitalInput = FakeDigitalInput;
+    type AnalogInput = FakeAnalogInput;
+    type Gyro = FakeGyro;
+}
+
+/// Everything a [`FakeTalon`] remembers. Sensor fields are written by tests (or a physics model),
+/// the rest by whatever owns the talon.
+#[derive(Debug, Copy, Clone)]
+pub struct TalonState {
+    pub mode: ControlMode,
+    pub demand0: f64,
+    pub demand1_type: DemandType,
+    pub demand1: f64,
+    /// Selected sensor position, ticks
+    pub position: i32,
+    /// Selected sensor velocity, ticks per 100ms
+    pub velocity: i32,
+    pub closed_loop_target: i32,
+    pub profile_slot: i32,
+    pub soft_limits_enabled: bool,
+    /// Number of calls to `set`, to check that callers aren't spamming the CAN bus
+    pub set_count: u32,
+    /// Gains set with `config_k*`, by slot
+    pub gains: [SlotGains; 4],
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SlotGains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    pub kf: f64,
+}
+
+impl Default for TalonState {
+    fn default() -> Self {
+        Self {
+            mode: ControlMode::Disabled,
+            demand0: 0.0,
+            demand1_type: DemandType::Neutral,
+            demand1: 0.0,
+            position: 0,
+            velocity: 0,
+            closed_loop_target: 0,
+            profile_slot: 0,
+            soft_limits_enabled: true,
+            set_count: 0,
+            gains: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeTalon(Arc<Mutex<TalonState>>);
+
+impl FakeTalon {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, TalonState> {
+        self.0.lock().expect("FakeTalon poisoned")
+    }
+
+    fn config_gain(&self, slot_idx: i32, f: impl FnOnce(&mut SlotGains)) -> ctre::Result<()> {
+        let mut state = self.state();
+        let slot = state
+            .gains
+            .get_mut(slot_idx as usize)
+            .ok_or(ctre::ErrorCode::GeneralError)?;
+        f(slot);
+        Ok(())
+    }
+}
+
+impl Talon for FakeTalon {
+    fn set(
+        &mut self,
+        mode: ControlMode,
+        demand0: f64,
+        demand1_type: DemandType,
+        demand1: f64,
+    ) -> ctre::Result<()> {
+        let mut state = self.state();
+        state.mode = mode;
+        state.demand0 = demand0;
+        state.demand1_type = demand1_type;
+        state.demand1 = demand1;
+        state.set_count += 1;
+        match mode {
+            ControlMode::Position | ControlMode::Velocity | ControlMode::MotionMagic => {
+                state.closed_loop_target = demand0 as i32
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn get_selected_sensor_position(&self, _pid_idx: i32) -> ctre::Result<i32> {
+        Ok(self.state().position)
+    }
+
+    fn get_selected_sensor_velocity(&self, _pid_idx: i32) -> ctre::Result<i32> {
+        Ok(self.state().velocity)
+    }
+
+    fn set_selected_sensor_position(
+        &mut self,
+        sensor_pos: i32,
+        _pid_idx: i32,
+        _timeout_ms: i32,
+    ) -> ctre::Result<()> {
+        self.state().position = sensor_pos;
+        Ok(())
+    }
+
+    fn get_closed_loop_target(&self, _pid_idx: i32) -> ctre::Result<i32> {
+        Ok(self.state().closed_loop_target)
+    }
+
+    fn select_profile_slot(&mut self, slot_idx: i32, _pid_idx: i32) -> ctre::Result<()> {
+        self.state().profile_slot = slot_idx;
+        Ok(())
+    }
+
+    fn override_soft_limits_enable(&mut self, enable: bool) {
+        self.state().soft_limits_enabled = enable;
+    }
+
+    fn get_quadrature_position(&self) -> ctre::Result<i32> {
+        Ok(self.state().position)
+    }
+
+    fn get_quadrature_velocity(&self) -> ctre::Result<i32> {
+        Ok(self.state().velocity)
+    }
+
+    fn config_kp(&mut self, slot_idx: i32, value: f64, _timeout_ms: i32) -> ctre::Result<()> {
+        self.config_gain(slot_idx, |slot| slot.kp = value)
+    }
+
+    fn config_ki(&mut self, slot_idx: i32, value: f64, _timeout_ms: i32) -> ctre::Result<()> {
+        self.config_gain(slot_idx, |slot| slot.ki = value)
+    }
+
+    fn config_kd(&mut self, slot_idx: i32, value: f64, _timeout_ms: i32) -> ctre::Result<()> {
+        self.config_gain(slot_idx, |slot| slot.kd = value)
+    }
+
+    fn config_kf(&mut self, slot_idx: i32, value: f64, _timeout_ms: i32) -> ctre::Result<()> {
+        self.config_gain(slot_idx, |slot| slot.kf = value)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeSolenoid(Arc<AtomicBool>);
+
+impl FakeSolenoid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl SolenoidOut for FakeSolenoid {
+    fn set(&mut self, on: bool) -> HalResult<()> {
+        self.0.store(on, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeDigitalInput(Arc<AtomicBool>);
+
+impl FakeDigitalInput {
+    pub fn new(value: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(value)))
+    }
+
+    pub fn set(&self, value: bool) {
+        self.0.store(value, Ordering::SeqCst);
+    }
+}
+
+impl DigitalIn for FakeDigitalInput {
+    fn get(&self) -> HalResult<bool> {
+        Ok(self.0.load(Ordering::SeqCst))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeAnalogInput(Arc<Mutex<f64>>);
+
+impl FakeAnalogInput {
+    pub fn new(voltage: f64) -> Self {
+        Self(Arc::new(Mutex::new(voltage)))
+    }
+
+    pub fn set_voltage(&self, voltage: f64) {
+        *self.0.lock().expect("FakeAnalogInput poisoned") = voltage;
+    }
+}
+
+impl AnalogIn for FakeAnalogInput {
+    fn voltage(&self) -> HalResult<f64> {
+        Ok(*self.0.lock().expect("FakeAnalogInput poisoned"))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeGyro(Arc<Mutex<f64>>);
+
+impl FakeGyro {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_heading(&self, heading: f64) {
+        *self.0.lock().expect("FakeGyro poisoned") = heading;
+    }
+}
+
+impl Gyro for FakeGyro {
+    fn heading(&self) -> f64 {
+        *self.0.lock().expect("FakeGyro poisoned")
+    }
+}
@@ -0,0 +1,170 @@
+//! Traits abstracting the hardware our subsystems talk to, so the same logic can run against the
+//! real robot or against the fakes in [`crate::fake`].
+use ctre::motor_control::{ControlMode, DemandType, MotorController, TalonSRX};
+use navx::AHRS;
+use std::fmt::Debug;
+use wpilib::dio::DigitalInput;
+use wpilib::pneumatics::Solenoid;
+use wpilib::{AnalogInput, HalResult};
+
+/// The subset of the Talon SRX interface used by the control loops.
+///
+/// Configuration (`config_all`, following, inversion) is left to the constructors that build real
+/// hardware; only what is needed while running lives here.
+pub trait Talon {
+    fn set(
+        &mut self,
+        mode: ControlMode,
+        demand0: f64,
+        demand1_type: DemandType,
+        demand1: f64,
+    ) -> ctre::Result<()>;
+    fn get_selected_sensor_position(&self, pid_idx: i32) -> ctre::Result<i32>;
+    fn get_selected_sensor_velocity(&self, pid_idx: i32) -> ctre::Result<i32>;
+    fn set_selected_sensor_position(
+        &mut self,
+        sensor_pos: i32,
+        pid_idx: i32,
+        timeout_ms: i32,
+    ) -> ctre::Result<()>;
+    fn get_closed_loop_target(&self, pid_idx: i32) -> ctre::Result<i32>;
+    fn select_profile_slot(&mut self, slot_idx: i32, pid_idx: i32) -> ctre::Result<()>;
+    fn override_soft_limits_enable(&mut self, enable: bool);
+    fn get_quadrature_position(&self) -> ctre::Result<i32>;
+    fn get_quadrature_velocity(&self) -> ctre::Result<i32>;
+    /// Closed loop gains can change while running, when they're being tuned live.
+    fn config_kp(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()>;
+    fn config_ki(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()>;
+    fn config_kd(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()>;
+    fn config_kf(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()>;
+}
+
+pub trait SolenoidOut {
+    fn set(&mut self, on: bool) -> HalResult<()>;
+}
+
+pub trait DigitalIn {
+    fn get(&self) -> HalResult<bool>;
+}
+
+pub trait AnalogIn {
+    fn voltage(&self) -> HalResult<f64>;
+}
+
+/// A source of robot heading.
+pub trait Gyro {
+    /// Continuous (unwrapped) heading in radians, counterclockwise positive like the pose.
+    fn heading(&self) -> f64;
+}
+
+/// A family of hardware implementations. Subsystems are generic over this rather than over each
+/// device type individually.
+pub trait Hardware {
+    type Talon: Talon + Debug;
+    type Solenoid: SolenoidOut + Debug;
+    type DigitalInput: DigitalIn + Debug;
+    type AnalogInput: AnalogIn + Debug;
+    type Gyro: Gyro;
+}
+
+/// The real thing: CTRE and WPILib devices on the RoboRIO.
+#[derive(Debug)]
+pub struct Rio;
+
+impl Hardware for Rio {
+    type Talon = TalonSRX;
+    type Solenoid = Solenoid;
+    type DigitalInput = DigitalInput;
+    type AnalogInput = AnalogInput;
+    type Gyro = AHRS;
+}
+
+impl Talon for TalonSRX {
+    fn set(
+        &mut self,
+        mode: ControlMode,
+        demand0: f64,
+        demand1_type: DemandType,
+        demand1: f64,
+    ) -> ctre::Result<()> {
+        MotorController::set(self, mode, demand0, demand1_type, demand1)
+    }
+
+    fn get_selected_sensor_position(&self, pid_idx: i32) -> ctre::Result<i32> {
+        MotorController::get_selected_sensor_position(self, pid_idx)
+    }
+
+    fn get_selected_sensor_velocity(&self, pid_idx: i32) -> ctre::Result<i32> {
+        MotorController::get_selected_sensor_velocity(self, pid_idx)
+    }
+
+    fn set_selected_sensor_position(
+        &mut self,
+        sensor_pos: i32,
+        pid_idx: i32,
+        timeout_ms: i32,
+    ) -> ctre::Result<()> {
+        MotorController::set_selected_sensor_position(self, sensor_pos, pid_idx, timeout_ms)
+    }
+
+    fn get_closed_loop_target(&self, pid_idx: i32) -> ctre::Result<i32> {
+        MotorController::get_closed_loop_target(self, pid_idx)
+    }
+
+    fn select_profile_slot(&mut self, slot_idx: i32, pid_idx: i32) -> ctre::Result<()> {
+        MotorController::select_profile_slot(self, slot_idx, pid_idx)
+    }
+
+    fn override_soft_limits_enable(&mut self, enable: bool) {
+        MotorController::override_soft_limits_enable(self, enable);
+    }
+
+    fn get_quadrature_position(&self) -> ctre::Result<i32> {
+        MotorController::get_quadrature_position(self)
+    }
+
+    fn get_quadrature_velocity(&self) -> ctre::Result<i32> {
+        MotorController::get_quadrature_velocity(self)
+    }
+
+    fn config_kp(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        MotorController::config_kp(self, slot_idx, value, timeout_ms)
+    }
+
+    fn config_ki(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        MotorController::config_ki(self, slot_idx, value, timeout_ms)
+    }
+
+    fn config_kd(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        MotorController::config_kd(self, slot_idx, value, timeout_ms)
+    }
+
+    fn config_kf(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        MotorController::config_kf(self, slot_idx, value, timeout_ms)
+    }
+}
+
+impl SolenoidOut for Solenoid {
+    fn set(&mut self, on: bool) -> HalResult<()> {
+        Solenoid::set(self, on)
+    }
+}
+
+impl DigitalIn for DigitalInput {
+    fn get(&self) -> HalResult<bool> {
+        DigitalInput::get(self)
+    }
+}
+
+impl AnalogIn for AnalogInput {
+    fn voltage(&self) -> HalResult<f64> {
+        AnalogInput::voltage(self)
+    }
+}
+
+impl Gyro for AHRS {
+    fn heading(&self) -> f64 {
+        // the navX reads clockwise positive, in degrees
+        -self.angle().to_radians()
+    }
+}
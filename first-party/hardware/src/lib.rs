@@ -1,10 +1,15 @@
+pub mod fake;
+pub mod io;
+pub mod record;
+
+use io::AnalogIn;
 use wpilib::{AnalogInput, HalResult};
 
 #[derive(Debug)]
-pub struct Rev111107DS00PressureSensor(AnalogInput, f64);
+pub struct Rev111107DS00PressureSensor<A: AnalogIn = AnalogInput>(A, f64);
 
-impl Rev111107DS00PressureSensor {
-    pub fn new(ain: AnalogInput, supply_voltage: f64) -> Self {
+impl<A: AnalogIn> Rev111107DS00PressureSensor<A> {
+    pub fn new(ain: A, supply_voltage: f64) -> Self {
         Self(ain, supply_voltage)
     }
 
@@ -12,7 +17,7 @@ impl Rev111107DS00PressureSensor {
         self.0.voltage().map(|v| (250.0 * v / self.1) - 25.0)
     }
 
-    pub fn into_inner(self) -> AnalogInput {
+    pub fn into_inner(self) -> A {
         self.0
     }
 }
@@ -0,0 +1,481 @@
+//! Recording what hardware reads and is told, so a run can be replayed against the fakes.
+//!
+//! Wrap each device in a [`Recorded`] with a name and a shared [`Tape`], and use [`Recording`] as
+//! the subsystem's hardware. Every successful read and every output lands on the tape, in order,
+//! until the subsystem takes it at the end of its loop. Failed reads aren't recorded, so on replay
+//! the fake keeps returning the last good value.
+//!
+//! To replay, build the same subsystem from fakes wrapped with the same names, hand the fakes to a
+//! [`Playback`], and before each loop [`Playback::apply`] the recorded sample. Each device's
+//! readings are queued in the order they were read, and every read of the fake plays the next one
+//! first, so a device read twice in a loop gets both values back. The outputs on the replay's own
+//! tape should then match the recording's.
+use crate::fake::{FakeAnalogInput, FakeDigitalInput, FakeGyro, FakeSolenoid, FakeTalon};
+use crate::io::{AnalogIn, DigitalIn, Gyro, Hardware, SolenoidOut, Talon};
+use ctre::motor_control::{ControlMode, DemandType};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use wpilib::HalResult;
+
+/// A value a device returned
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Reading {
+    Position(i32),
+    Velocity(i32),
+    ClosedLoopTarget(i32),
+    QuadraturePosition(i32),
+    QuadratureVelocity(i32),
+    Digital(bool),
+    Voltage(f64),
+    Heading(f64),
+}
+
+/// Something a device was told to do
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Output {
+    /// Modes are kept by name, since ctre's enums can't be serialized
+    Talon {
+        mode: String,
+        demand0: f64,
+        demand1_type: String,
+        demand1: f64,
+    },
+    SensorPosition(i32),
+    ProfileSlot(i32),
+    SoftLimits(bool),
+    Gain {
+        slot: i32,
+        /// `"kp"`, `"ki"`, `"kd"` or `"kf"`
+        term: String,
+        value: f64,
+    },
+    Solenoid(bool),
+}
+
+/// Everything recorded from a set of devices over one loop, by device name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub readings: Vec<(String, Reading)>,
+    pub outputs: Vec<(String, Output)>,
+}
+
+/// Where recorded devices write. Clones share the same sample.
+#[derive(Debug, Clone, Default)]
+pub struct Tape(Arc<Mutex<Sample>>);
+
+impl Tape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sample(&self) -> MutexGuard<'_, Sample> {
+        self.0.lock().expect("Tape poisoned")
+    }
+
+    /// Everything recorded since the last take.
+    pub fn take(&self) -> Sample {
+        mem::replace(&mut *self.sample(), Sample::default())
+    }
+
+    fn read(&self, device: &str, reading: Reading) {
+        self.sample().readings.push((device.to_string(), reading));
+    }
+
+    fn wrote(&self, device: &str, output: Output) {
+        self.sample().outputs.push((device.to_string(), output));
+    }
+}
+
+/// A device that records to a tape.
+#[derive(Debug)]
+pub struct Recorded<D> {
+    inner: D,
+    name: String,
+    tape: Tape,
+    /// Readings to play into `inner` before it's read, when it's a fake being played back into
+    cue: Option<Cue>,
+}
+
+impl<D> Recorded<D> {
+    pub fn new(inner: D, name: impl Into<String>, tape: &Tape) -> Self {
+        Self {
+            inner,
+            name: name.into(),
+            tape: tape.clone(),
+            cue: None,
+        }
+    }
+
+    /// Plays the next queued reading into the fake, if there is one.
+    fn cue(&self) {
+        if let Some(cue) = &self.cue {
+            cue.next();
+        }
+    }
+
+    fn read<T: Copy, E>(&self, result: &Result<T, E>, reading: impl FnOnce(T) -> Reading) {
+        if let Ok(value) = result {
+            self.tape.read(&self.name, reading(*value));
+        }
+    }
+
+    fn wrote(&self, output: Output) {
+        self.tape.wrote(&self.name, output);
+    }
+
+    fn gain(&self, slot: i32, term: &str, value: f64) {
+        self.wrote(Output::Gain {
+            slot,
+            term: term.to_string(),
+            value,
+        });
+    }
+}
+
+impl<T: Talon> Talon for Recorded<T> {
+    fn set(
+        &mut self,
+        mode: ControlMode,
+        demand0: f64,
+        demand1_type: DemandType,
+        demand1: f64,
+    ) -> ctre::Result<()> {
+        self.wrote(Output::Talon {
+            mode: format!("{:?}", mode),
+            demand0,
+            demand1_type: format!("{:?}", demand1_type),
+            demand1,
+        });
+        self.inner.set(mode, demand0, demand1_type, demand1)
+    }
+
+    fn get_selected_sensor_position(&self, pid_idx: i32) -> ctre::Result<i32> {
+        self.cue();
+        let result = self.inner.get_selected_sensor_position(pid_idx);
+        self.read(&result, Reading::Position);
+        result
+    }
+
+    fn get_selected_sensor_velocity(&self, pid_idx: i32) -> ctre::Result<i32> {
+        self.cue();
+        let result = self.inner.get_selected_sensor_velocity(pid_idx);
+        self.read(&result, Reading::Velocity);
+        result
+    }
+
+    fn set_selected_sensor_position(
+        &mut self,
+        sensor_pos: i32,
+        pid_idx: i32,
+        timeout_ms: i32,
+    ) -> ctre::Result<()> {
+        self.wrote(Output::SensorPosition(sensor_pos));
+        self.inner
+            .set_selected_sensor_position(sensor_pos, pid_idx, timeout_ms)
+    }
+
+    fn get_closed_loop_target(&self, pid_idx: i32) -> ctre::Result<i32> {
+        self.cue();
+        let result = self.inner.get_closed_loop_target(pid_idx);
+        self.read(&result, Reading::ClosedLoopTarget);
+        result
+    }
+
+    fn select_profile_slot(&mut self, slot_idx: i32, pid_idx: i32) -> ctre::Result<()> {
+        self.wrote(Output::ProfileSlot(slot_idx));
+        self.inner.select_profile_slot(slot_idx, pid_idx)
+    }
+
+    fn override_soft_limits_enable(&mut self, enable: bool) {
+        self.wrote(Output::SoftLimits(enable));
+        self.inner.override_soft_limits_enable(enable)
+    }
+
+    fn get_quadrature_position(&self) -> ctre::Result<i32> {
+        self.cue();
+        let result = self.inner.get_quadrature_position();
+        self.read(&result, Reading::QuadraturePosition);
+        result
+    }
+
+    fn get_quadrature_velocity(&self) -> ctre::Result<i32> {
+        self.cue();
+        let result = self.inner.get_quadrature_velocity();
+        self.read(&result, Reading::QuadratureVelocity);
+        result
+    }
+
+    fn config_kp(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        self.gain(slot_idx, "kp", value);
+        self.inner.config_kp(slot_idx, value, timeout_ms)
+    }
+
+    fn config_ki(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        self.gain(slot_idx, "ki", value);
+        self.inner.config_ki(slot_idx, value, timeout_ms)
+    }
+
+    fn config_kd(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        self.gain(slot_idx, "kd", value);
+        self.inner.config_kd(slot_idx, value, timeout_ms)
+    }
+
+    fn config_kf(&mut self, slot_idx: i32, value: f64, timeout_ms: i32) -> ctre::Result<()> {
+        self.gain(slot_idx, "kf", value);
+        self.inner.config_kf(slot_idx, value, timeout_ms)
+    }
+}
+
+impl<S: SolenoidOut> SolenoidOut for Recorded<S> {
+    fn set(&mut self, on: bool) -> HalResult<()> {
+        self.wrote(Output::Solenoid(on));
+        self.inner.set(on)
+    }
+}
+
+impl<D: DigitalIn> DigitalIn for Recorded<D> {
+    fn get(&self) -> HalResult<bool> {
+        self.cue();
+        let result = self.inner.get();
+        self.read(&result, Reading::Digital);
+        result
+    }
+}
+
+impl<A: AnalogIn> AnalogIn for Recorded<A> {
+    fn voltage(&self) -> HalResult<f64> {
+        self.cue();
+        let result = self.inner.voltage();
+        self.read(&result, Reading::Voltage);
+        result
+    }
+}
+
+impl<G: Gyro> Gyro for Recorded<G> {
+    fn heading(&self) -> f64 {
+        self.cue();
+        let heading = self.inner.heading();
+        self.tape.read(&self.name, Reading::Heading(heading));
+        heading
+    }
+}
+
+/// `H`'s devices, each recording to a tape.
+#[derive(Debug)]
+pub struct Recording<H>(PhantomData<H>);
+
+impl<H: Hardware> Hardware for Recording<H> {
+    type Talon = Recorded<H::Talon>;
+    type Solenoid = Recorded<H::Solenoid>;
+    type DigitalInput = Recorded<H::DigitalInput>;
+    type AnalogInput = Recorded<H::AnalogInput>;
+    type Gyro = Recorded<H::Gyro>;
+}
+
+/// A fake device that readings can be played back into
+#[derive(Debug, Clone)]
+pub enum FakeDevice {
+    Talon(FakeTalon),
+    Solenoid(FakeSolenoid),
+    DigitalInput(FakeDigitalInput),
+    AnalogInput(FakeAnalogInput),
+    Gyro(FakeGyro),
+}
+
+impl FakeDevice {
+    /// Whether the device could have read this.
+    fn fits(&self, reading: Reading) -> bool {
+        match (self, reading) {
+            (FakeDevice::Talon(_), Reading::Position(_))
+            | (FakeDevice::Talon(_), Reading::QuadraturePosition(_))
+            | (FakeDevice::Talon(_), Reading::Velocity(_))
+            | (FakeDevice::Talon(_), Reading::QuadratureVelocity(_))
+            | (FakeDevice::Talon(_), Reading::ClosedLoopTarget(_))
+            | (FakeDevice::DigitalInput(_), Reading::Digital(_))
+            | (FakeDevice::AnalogInput(_), Reading::Voltage(_))
+            | (FakeDevice::Gyro(_), Reading::Heading(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Sets the fake to what its device read, if the reading fits it.
+    fn play(&self, reading: Reading) {
+        match (self, reading) {
+            (FakeDevice::Talon(talon), Reading::Position(ticks))
+            | (FakeDevice::Talon(talon), Reading::QuadraturePosition(ticks)) => {
+                talon.state().position = ticks
+            }
+            (FakeDevice::Talon(talon), Reading::Velocity(velocity))
+            | (FakeDevice::Talon(talon), Reading::QuadratureVelocity(velocity)) => {
+                talon.state().velocity = velocity
+            }
+            (FakeDevice::Talon(talon), Reading::ClosedLoopTarget(target)) => {
+                talon.state().closed_loop_target = target
+            }
+            (FakeDevice::DigitalInput(input), Reading::Digital(value)) => input.set(value),
+            (FakeDevice::AnalogInput(input), Reading::Voltage(voltage)) => {
+                input.set_voltage(voltage)
+            }
+            (FakeDevice::Gyro(gyro), Reading::Heading(heading)) => gyro.set_heading(heading),
+            _ => (),
+        }
+    }
+}
+
+/// One fake's readings from a loop, waiting to be played into it a read at a time.
+#[derive(Debug, Clone)]
+struct Cue {
+    fake: FakeDevice,
+    readings: Arc<Mutex<VecDeque<Reading>>>,
+}
+
+impl Cue {
+    fn readings(&self) -> MutexGuard<'_, VecDeque<Reading>> {
+        self.readings.lock().expect("Cue poisoned")
+    }
+
+    /// Plays the next reading, leaving the fake as it is once they run out.
+    fn next(&self) {
+        let next = self.readings().pop_front();
+        if let Some(reading) = next {
+            self.fake.play(reading);
+        }
+    }
+}
+
+/// Plays recorded readings back into fakes, by device name.
+#[derive(Debug, Default)]
+pub struct Playback {
+    devices: HashMap<String, Cue>,
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps a fake for the subsystem being replayed, which reads back what it's played.
+    pub fn device<D: Clone + Into<FakeDevice>>(
+        &mut self,
+        fake: D,
+        name: &str,
+        tape: &Tape,
+    ) -> Recorded<D> {
+        let cue = Cue {
+            fake: fake.clone().into(),
+            readings: Arc::default(),
+        };
+        self.devices.insert(name.to_string(), cue.clone());
+        Recorded {
+            cue: Some(cue),
+            ..Recorded::new(fake, name, tape)
+        }
+    }
+
+    /// Queues each device's readings to be played back one per read, in order, in place of any
+    /// left over from the last sample. Readings of unknown devices, or that don't fit the device,
+    /// are returned.
+    pub fn apply(&self, sample: &Sample) -> Vec<(String, Reading)> {
+        for cue in self.devices.values() {
+            cue.readings().clear();
+        }
+        let mut unused = Vec::new();
+        for (name, reading) in &sample.readings {
+            match self.devices.get(name) {
+                Some(cue) if cue.fake.fits(*reading) => cue.readings().push_back(*reading),
+                _ => unused.push((name.clone(), *reading)),
+            }
+        }
+        unused
+    }
+}
+
+macro_rules! fake_device {
+    ($($fake:ident => $variant:ident,)*) => {
+        $(
+            impl From<$fake> for FakeDevice {
+                fn from(fake: $fake) -> Self {
+                    FakeDevice::$variant(fake)
+                }
+            }
+        )*
+    };
+}
+
+fake_device! {
+    FakeTalon => Talon,
+    FakeSolenoid => Solenoid,
+    FakeDigitalInput => DigitalInput,
+    FakeAnalogInput => AnalogInput,
+    FakeGyro => Gyro,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_readings_into_fakes() {
+        let tape = Tape::new();
+        let talon = FakeTalon::new();
+        talon.state().position = 42;
+        let mut recorded = Recorded::new(talon, "arm", &tape);
+        let limit = Recorded::new(FakeDigitalInput::new(true), "limit", &tape);
+        assert_eq!(recorded.get_selected_sensor_position(0).unwrap(), 42);
+        assert!(limit.get().unwrap());
+        recorded
+            .set(ControlMode::PercentOutput, 0.5, DemandType::Neutral, 0.0)
+            .unwrap();
+        let sample = tape.take();
+        assert_eq!(sample.readings.len(), 2);
+        assert_eq!(tape.take(), Sample::default());
+
+        let replay_tape = Tape::new();
+        let mut playback = Playback::new();
+        let mut talon = playback.device(FakeTalon::new(), "arm", &replay_tape);
+        let limit = playback.device(FakeDigitalInput::new(false), "limit", &replay_tape);
+        assert!(playback.apply(&sample).is_empty());
+        assert_eq!(talon.get_selected_sensor_position(0).unwrap(), 42);
+        assert!(limit.get().unwrap());
+        talon
+            .set(ControlMode::PercentOutput, 0.5, DemandType::Neutral, 0.0)
+            .unwrap();
+        assert_eq!(replay_tape.take(), sample);
+
+        let wrong = Sample {
+            readings: vec![("limit".to_string(), Reading::Voltage(1.0))],
+            outputs: Vec::new(),
+        };
+        assert_eq!(playback.apply(&wrong), wrong.readings);
+    }
+
+    #[test]
+    fn plays_back_each_read() {
+        let tape = Tape::new();
+        let talon = FakeTalon::new();
+        let recorded = Recorded::new(talon.clone(), "arm", &tape);
+        for &ticks in &[10, 20] {
+            talon.state().position = ticks;
+            recorded.get_selected_sensor_position(0).unwrap();
+        }
+        let sample = tape.take();
+
+        let replay_tape = Tape::new();
+        let mut playback = Playback::new();
+        let talon = playback.device(FakeTalon::new(), "arm", &replay_tape);
+        playback.apply(&sample);
+        assert_eq!(talon.get_selected_sensor_position(0).unwrap(), 10);
+        assert_eq!(talon.get_selected_sensor_position(0).unwrap(), 20);
+        // past the recording, the last reading holds
+        assert_eq!(talon.get_selected_sensor_position(0).unwrap(), 20);
+
+        // leftovers don't carry into the next loop
+        playback.apply(&sample);
+        talon.get_selected_sensor_position(0).unwrap();
+        playback.apply(&Sample::default());
+        assert_eq!(talon.get_selected_sensor_position(0).unwrap(), 10);
+    }
+}
@@ -1,7 +1,17 @@
 #[cfg(test)]
 extern crate approx;
 
+pub mod pose;
+mod spline;
+mod table;
+mod time;
+
+pub use spline::{Scalar, Spline};
+pub use table::TableError;
+
+use dimensioned::si::SI;
 use num_traits::Float;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub trait Interpolatable {
     fn interp(low: Self, high: Self, percent: f64) -> Self;
@@ -20,14 +30,71 @@ impl Interpolatable for f32 {
     }
 }
 
+impl<V: Interpolatable, U> Interpolatable for SI<V, U> {
+    fn interp(low: Self, high: Self, percent: f64) -> Self {
+        SI::new(V::interp(low.value_unsafe, high.value_unsafe, percent))
+    }
+}
+
+macro_rules! tuple_interp {
+    ($($idx:tt $name:ident),+) => {
+        impl<$($name: Interpolatable),+> Interpolatable for ($($name,)+) {
+            fn interp(low: Self, high: Self, percent: f64) -> Self {
+                ($($name::interp(low.$idx, high.$idx, percent),)+)
+            }
+        }
+    };
+}
+
+tuple_interp!(0 A);
+tuple_interp!(0 A, 1 B);
+tuple_interp!(0 A, 1 B, 2 C);
+tuple_interp!(0 A, 1 B, 2 C, 3 D);
+tuple_interp!(0 A, 1 B, 2 C, 3 D, 4 E);
+tuple_interp!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+macro_rules! array_interp {
+    ($($len:expr),+) => {
+        $(
+            impl<T: Interpolatable + Copy> Interpolatable for [T; $len] {
+                fn interp(low: Self, high: Self, percent: f64) -> Self {
+                    let mut out = low;
+                    for (out, (&low, &high)) in out.iter_mut().zip(low.iter().zip(high.iter())) {
+                        *out = T::interp(low, high, percent);
+                    }
+                    out
+                }
+            }
+        )+
+    };
+}
+
+array_interp!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16);
+
 pub trait InverseInterpolatable {
     fn percent_to(value: Self, low: Self, high: Self) -> f64;
 }
 
-impl<T: Float + Into<f64>> InverseInterpolatable for T {
-    fn percent_to(value: Self, low: Self, high: Self) -> f64 {
-        ((value - low) / (high - low)).into()
-    }
+// Not a blanket impl over `Float`, which would stop us implementing this for std types
+macro_rules! float_inverse_interp {
+    ($($t:ty),+) => {
+        $(
+            impl InverseInterpolatable for $t {
+                fn percent_to(value: Self, low: Self, high: Self) -> f64 {
+                    ((value - low) / (high - low)).into()
+                }
+            }
+        )+
+    };
+}
+
+float_inverse_interp!(f32, f64);
+
+/// Keys that can be measured apart, so a map can forget keys too far behind the newest.
+pub trait KeyDistance {
+    type Distance: PartialOrd;
+    /// How far `to` is past `from`, zero if it isn't
+    fn distance(from: &Self, to: &Self) -> Self::Distance;
 }
 
 // Float type where NaN is the lowest value and equal to itself
@@ -72,6 +139,18 @@ impl<T: Float> Deref for OrdFloat<T> {
     }
 }
 
+impl<T: Float + Serialize> Serialize for OrdFloat<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Float + Deserialize<'de>> Deserialize<'de> for OrdFloat<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(OrdFloat)
+    }
+}
+
 impl<T: Float> From<T> for OrdFloat<T> {
     fn from(t: T) -> Self {
         OrdFloat(t)
@@ -84,17 +163,80 @@ impl<T: Float + Interpolatable> Interpolatable for OrdFloat<T> {
     }
 }
 
+impl<T: Float> KeyDistance for OrdFloat<T> {
+    type Distance = T;
+    fn distance(from: &Self, to: &Self) -> T {
+        (to.0 - from.0).max(T::zero())
+    }
+}
+
 impl<T: Float + InverseInterpolatable + Into<f64>> InverseInterpolatable for OrdFloat<T> {
     fn percent_to(value: Self, low: Self, high: Self) -> f64 {
         ((value.0 - low.0) / (high.0 - low.0)).into()
     }
 }
 
-use std::collections::BTreeMap;
+use std::collections::btree_map::{BTreeMap, Iter, Range};
+use std::ops::RangeBounds;
+
+/// Decides whether a key has aged out, given the newest key.
+type Expiry<K> = Box<dyn Fn(&K, &K) -> bool + Send + Sync>;
+
+/// Finds the value at a key strictly between two adjacent entries of the map.
+type Interpolator<K, V> = fn(&BTreeMap<K, V>, (&K, &V), (&K, &V), &K) -> V;
+
+/// How `get` fills in between stored keys. See also [`Spline`] for maps of scalars.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// The value of whichever stored key is closer
+    Nearest,
+}
+
+/// What `get` does with keys outside the stored range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Extrapolation {
+    /// The value at the nearest end
+    Clamp,
+    /// Continues the line through the two entries at that end
+    Linear,
+    /// Returns `None`
+    Reject,
+}
+
 pub struct InterpolatingBTreeMap<K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone>
 {
     map: BTreeMap<K, V>,
     max_size: usize,
+    expired: Option<Expiry<K>>,
+    interpolator: Interpolator<K, V>,
+    extrapolation: Extrapolation,
+}
+
+fn linear<K: InverseInterpolatable + Clone, V: Interpolatable + Clone>(
+    _map: &BTreeMap<K, V>,
+    low: (&K, &V),
+    high: (&K, &V),
+    key: &K,
+) -> V {
+    V::interp(
+        low.1.clone(),
+        high.1.clone(),
+        K::percent_to(key.clone(), low.0.clone(), high.0.clone()),
+    )
+}
+
+fn nearest<K: InverseInterpolatable + Clone, V: Clone>(
+    _map: &BTreeMap<K, V>,
+    low: (&K, &V),
+    high: (&K, &V),
+    key: &K,
+) -> V {
+    if K::percent_to(key.clone(), low.0.clone(), high.0.clone()) < 0.5 {
+        low.1.clone()
+    } else {
+        high.1.clone()
+    }
 }
 
 impl<K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone>
@@ -105,6 +247,42 @@ impl<K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone>
         Self {
             map: BTreeMap::new(),
             max_size,
+            expired: None,
+            interpolator: linear,
+            extrapolation: Extrapolation::Clamp,
+        }
+    }
+
+    /// Linear by default
+    pub fn with_interpolation(self, interpolation: Interpolation) -> Self {
+        Self {
+            interpolator: match interpolation {
+                Interpolation::Linear => linear,
+                Interpolation::Nearest => nearest,
+            },
+            ..self
+        }
+    }
+
+    /// Clamps by default
+    pub fn with_extrapolation(self, extrapolation: Extrapolation) -> Self {
+        Self {
+            extrapolation,
+            ..self
+        }
+    }
+
+    /// Like `new`, but also forgets keys more than `max_age` behind the newest.
+    pub fn with_max_age(max_size: usize, max_age: K::Distance) -> Self
+    where
+        K: KeyDistance,
+        K::Distance: Send + Sync + 'static,
+    {
+        Self {
+            expired: Some(Box::new(move |newest, key| {
+                K::distance(key, newest) > max_age
+            })),
+            ..Self::new(max_size)
         }
     }
 
@@ -114,6 +292,52 @@ impl<K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone>
             let first_key = self.map.keys().next().unwrap().clone();
             self.map.remove(&first_key);
         }
+        if let Some(ref expired) = self.expired {
+            let newest = self.map.keys().next_back().unwrap().clone();
+            // the newest key never expires, so this stops before emptying the map
+            while let Some(first_key) = self.map.keys().next().cloned() {
+                if !expired(&newest, &first_key) {
+                    break;
+                }
+                self.map.remove(&first_key);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The entry with the highest key
+    pub fn latest(&self) -> Option<(&K, &V)> {
+        self.map.iter().next_back()
+    }
+
+    /// The entry with the lowest key
+    pub fn earliest(&self) -> Option<(&K, &V)> {
+        self.map.iter().next()
+    }
+
+    /// The stored entries in `range`, in key order. Nothing is interpolated.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        self.map.range(range)
+    }
+
+    /// All stored entries in key order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
     }
 
     pub fn get(&self, key: K) -> Option<V> {
@@ -126,17 +350,61 @@ impl<K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone>
         let ceiling = self.map.range((Included(&key), Unbounded)).next();
         match (floor, ceiling) {
             (None, None) => None,
-            (Some((_low_key, low_value)), None) => Some(low_value.clone()),
-            (None, Some((_high_key, high_value))) => Some(high_value.clone()),
-            (Some((low_key, low_value)), Some((high_key, high_value))) => Some(V::interp(
-                low_value.clone(),
-                high_value.clone(),
-                K::percent_to(key.clone(), low_key.clone(), high_key.clone()),
+            (Some(low), Some(high)) => Some((self.interpolator)(&self.map, low, high, &key)),
+            (Some(end), None) => {
+                let inner = self.map.iter().rev().nth(1);
+                self.extrapolate(end, inner, &key)
+            }
+            (None, Some(end)) => {
+                let inner = self.map.iter().nth(1);
+                self.extrapolate(end, inner, &key)
+            }
+        }
+    }
+
+    /// Looks up a key past `end`, the last entry on that side, with `inner` the one before it.
+    fn extrapolate(&self, end: (&K, &V), inner: Option<(&K, &V)>, key: &K) -> Option<V> {
+        match (self.extrapolation, inner) {
+            (Extrapolation::Reject, _) => None,
+            (Extrapolation::Linear, Some(inner)) => Some(V::interp(
+                inner.1.clone(),
+                end.1.clone(),
+                K::percent_to(key.clone(), inner.0.clone(), end.0.clone()),
             )),
+            // a single point doesn't define a line
+            (Extrapolation::Clamp, _) | (Extrapolation::Linear, None) => Some(end.1.clone()),
+        }
+    }
+}
+
+impl<K: InverseInterpolatable + Ord + Clone, V: Scalar> InterpolatingBTreeMap<K, V> {
+    /// Interpolates with a cubic spline instead. Extrapolation is still linear from the end
+    /// points if enabled.
+    pub fn with_spline(self, spline: Spline) -> Self {
+        Self {
+            interpolator: match spline {
+                Spline::Cubic => {
+                    |map, low, high, key| spline::interp(Spline::Cubic, map, low, high, key)
+                }
+                Spline::MonotoneCubic => {
+                    |map, low, high, key| spline::interp(Spline::MonotoneCubic, map, low, high, key)
+                }
+            },
+            ..self
         }
     }
 }
 
+impl<'a, K: InverseInterpolatable + Ord + Clone, V: Interpolatable + Clone> IntoIterator
+    for &'a InterpolatingBTreeMap<K, V>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -163,4 +431,103 @@ mod tests {
         assert_abs_diff_eq!(map.get(4.9.into()).unwrap(), 20.0);
         assert_abs_diff_eq!(map.get(8.33333.into()).unwrap(), 0.0, epsilon = 0.0001);
     }
+
+    #[test]
+    fn compound_values() {
+        let (a, b) = <(f64, f32)>::interp((0.0, 10.0), (1.0, 20.0), 0.25);
+        assert_abs_diff_eq!(a, 0.25);
+        assert_abs_diff_eq!(b, 12.5);
+
+        let arr = <[f64; 3]>::interp([0.0, 1.0, 2.0], [2.0, 1.0, 0.0], 0.5);
+        assert_abs_diff_eq!(arr[0], 1.0);
+        assert_abs_diff_eq!(arr[1], 1.0);
+        assert_abs_diff_eq!(arr[2], 1.0);
+    }
+
+    #[test]
+    fn unit_values() {
+        use dimensioned::si::{Meter, M};
+        let mut map = InterpolatingBTreeMap::new(2);
+        map.insert(OrdFloat(0.0), 1.0 * M);
+        map.insert(OrdFloat(1.0), 3.0 * M);
+        let length: Meter<f64> = map.get(OrdFloat(0.5)).unwrap();
+        assert_abs_diff_eq!(length.value_unsafe, 2.0);
+    }
+
+    #[test]
+    fn evicts_by_age() {
+        let mut map = InterpolatingBTreeMap::with_max_age(100, 1.0);
+        for i in 0..10 {
+            map.insert(OrdFloat(f64::from(i) * 0.5), f64::from(i));
+        }
+        // 4.5 is newest, so 3.5 and up remain
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.earliest(), Some((&OrdFloat(3.5), &7.0)));
+        assert_eq!(map.latest(), Some((&OrdFloat(4.5), &9.0)));
+
+        // an old key is dropped straight away
+        map.insert(OrdFloat(0.0), 0.0);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn ranges_and_removal() {
+        let mut map = InterpolatingBTreeMap::new(10);
+        for i in 0..5 {
+            map.insert(OrdFloat(f64::from(i)), f64::from(i) * 10.0);
+        }
+        let inner: Vec<_> = map
+            .range(OrdFloat(1.0)..OrdFloat(3.0))
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(inner, vec![10.0, 20.0]);
+
+        assert_eq!(map.remove(&OrdFloat(2.0)), Some(20.0));
+        assert_eq!(map.remove(&OrdFloat(2.0)), None);
+        assert_abs_diff_eq!(map.get(OrdFloat(2.0)).unwrap(), 20.0);
+        assert_eq!(map.iter().count(), 4);
+        assert_abs_diff_eq!((&map).into_iter().map(|(k, _)| **k).sum::<f64>(), 8.0);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.latest(), None);
+    }
+
+    #[test]
+    fn out_of_range_modes() {
+        let points = [(0.0, 0.0), (1.0, 10.0), (2.0, 30.0)];
+        let build = |extrapolation| {
+            let mut map = InterpolatingBTreeMap::new(3).with_extrapolation(extrapolation);
+            for &(x, y) in &points {
+                map.insert(OrdFloat(x), y);
+            }
+            map
+        };
+
+        let clamp = build(Extrapolation::Clamp);
+        assert_abs_diff_eq!(clamp.get(OrdFloat(-1.0)).unwrap(), 0.0);
+        assert_abs_diff_eq!(clamp.get(OrdFloat(3.0)).unwrap(), 30.0);
+
+        let linear = build(Extrapolation::Linear);
+        assert_abs_diff_eq!(linear.get(OrdFloat(-1.0)).unwrap(), -10.0);
+        assert_abs_diff_eq!(linear.get(OrdFloat(3.0)).unwrap(), 50.0);
+
+        let reject = build(Extrapolation::Reject);
+        assert_eq!(reject.get(OrdFloat(-1.0)), None);
+        assert_eq!(reject.get(OrdFloat(3.0)), None);
+        assert_abs_diff_eq!(reject.get(OrdFloat(1.5)).unwrap(), 20.0);
+
+        let mut single = InterpolatingBTreeMap::new(1).with_extrapolation(Extrapolation::Linear);
+        single.insert(OrdFloat(0.0), 5.0);
+        assert_abs_diff_eq!(single.get(OrdFloat(1.0)).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn nearest() {
+        let mut map = InterpolatingBTreeMap::new(2).with_interpolation(Interpolation::Nearest);
+        map.insert(OrdFloat(0.0), 0.0);
+        map.insert(OrdFloat(1.0), 10.0);
+        assert_abs_diff_eq!(map.get(OrdFloat(0.4)).unwrap(), 0.0);
+        assert_abs_diff_eq!(map.get(OrdFloat(0.6)).unwrap(), 10.0);
+    }
 }
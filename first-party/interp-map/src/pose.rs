@@ -0,0 +1,146 @@
+//! 2D rigid transforms, interpolated along the constant-curvature arc between them rather than
+//! component-wise, so a robot turning while it drives is looked up on the path it actually took.
+use crate::Interpolatable;
+use std::f64::consts::PI;
+
+/// Position and heading (radians, counterclockwise) in the plane.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pose2d {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+}
+
+/// Constant-curvature motion, expressed in the frame of the pose it starts from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Twist2d {
+    pub dx: f64,
+    pub dy: f64,
+    pub dtheta: f64,
+}
+
+/// Wraps an angle into (-pi, pi].
+pub fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = (angle + PI) % (2.0 * PI);
+    if wrapped <= 0.0 {
+        wrapped + PI
+    } else {
+        wrapped - PI
+    }
+}
+
+impl Twist2d {
+    pub fn scaled(self, by: f64) -> Self {
+        Self {
+            dx: self.dx * by,
+            dy: self.dy * by,
+            dtheta: self.dtheta * by,
+        }
+    }
+}
+
+impl Pose2d {
+    pub fn new(x: f64, y: f64, heading: f64) -> Self {
+        Self { x, y, heading }
+    }
+
+    /// Follows `twist` from this pose.
+    ///
+    /// The heading is not wrapped, so continuous headings stay continuous.
+    pub fn exp(&self, twist: Twist2d) -> Self {
+        let theta = twist.dtheta;
+        // sin(theta) / theta and (1 - cos(theta)) / theta, by series near zero
+        let (s, c) = if theta.abs() < 1e-9 {
+            (1.0 - theta * theta / 6.0, 0.5 * theta)
+        } else {
+            (theta.sin() / theta, (1.0 - theta.cos()) / theta)
+        };
+        let local_x = twist.dx * s - twist.dy * c;
+        let local_y = twist.dx * c + twist.dy * s;
+        let (sin, cos) = self.heading.sin_cos();
+        Self {
+            x: self.x + local_x * cos - local_y * sin,
+            y: self.y + local_x * sin + local_y * cos,
+            heading: self.heading + theta,
+        }
+    }
+
+    /// The twist that carries this pose to `other`, turning through less than half a revolution.
+    pub fn log(&self, other: &Self) -> Twist2d {
+        let (sin, cos) = self.heading.sin_cos();
+        let (dx, dy) = (other.x - self.x, other.y - self.y);
+        let local_x = dx * cos + dy * sin;
+        let local_y = -dx * sin + dy * cos;
+
+        let theta = wrap_angle(other.heading - self.heading);
+        let half = theta / 2.0;
+        let cos_minus_one = theta.cos() - 1.0;
+        // (theta / 2) * sin(theta) / (1 - cos(theta)), by series near zero
+        let half_cot = if cos_minus_one.abs() < 1e-9 {
+            1.0 - theta * theta / 12.0
+        } else {
+            -(half * theta.sin()) / cos_minus_one
+        };
+        Twist2d {
+            dx: half_cot * local_x + half * local_y,
+            dy: -half * local_x + half_cot * local_y,
+            dtheta: theta,
+        }
+    }
+}
+
+impl Interpolatable for Pose2d {
+    fn interp(low: Self, high: Self, percent: f64) -> Self {
+        low.exp(low.log(&high).scaled(percent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn assert_pose_eq(a: Pose2d, b: Pose2d) {
+        assert_abs_diff_eq!(a.x, b.x, epsilon = 1e-9);
+        assert_abs_diff_eq!(a.y, b.y, epsilon = 1e-9);
+        assert_abs_diff_eq!(a.heading, b.heading, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn wraps_angles() {
+        assert_abs_diff_eq!(wrap_angle(0.5), 0.5);
+        assert_abs_diff_eq!(wrap_angle(PI), PI);
+        assert_abs_diff_eq!(wrap_angle(-PI), PI);
+        assert_abs_diff_eq!(wrap_angle(3.0 * PI / 2.0), -FRAC_PI_2, epsilon = 1e-12);
+        assert_abs_diff_eq!(wrap_angle(-5.0 * PI / 2.0), -FRAC_PI_2, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn exp_inverts_log() {
+        let a = Pose2d::new(1.0, -2.0, 0.3);
+        let b = Pose2d::new(-0.5, 4.0, 2.9);
+        assert_pose_eq(a.exp(a.log(&b)), b);
+        let straight = Pose2d::new(2.0, 1.0, 0.3);
+        assert_pose_eq(a.exp(a.log(&straight)), straight);
+    }
+
+    #[test]
+    fn interpolates_along_arc() {
+        // a quarter circle of radius 1 about (0, 1)
+        let start = Pose2d::new(0.0, 0.0, 0.0);
+        let end = Pose2d::new(1.0, 1.0, FRAC_PI_2);
+        let mid = Pose2d::interp(start, end, 0.5);
+        let angle = FRAC_PI_2 / 2.0;
+        assert_pose_eq(mid, Pose2d::new(angle.sin(), 1.0 - angle.cos(), angle));
+    }
+
+    #[test]
+    fn interpolates_across_wrap() {
+        let low = Pose2d::new(0.0, 0.0, PI - 0.1);
+        let high = Pose2d::new(0.0, 0.0, -PI + 0.1);
+        let mid = Pose2d::interp(low, high, 0.5);
+        // turns the short way, keeping the heading continuous
+        assert_abs_diff_eq!(mid.heading, PI, epsilon = 1e-9);
+    }
+}
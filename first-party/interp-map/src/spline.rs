@@ -0,0 +1,179 @@
+//! Cubic Hermite interpolation for maps of scalar values.
+//!
+//! Tangents are fit from the neighbouring points, so only the stored points are needed. Key
+//! spacing doesn't have to be uniform: everything is worked out in units of the interval being
+//! looked up, which is all [`InverseInterpolatable`] can give us.
+use crate::{Interpolatable, InverseInterpolatable};
+use dimensioned::si::SI;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// Values with a single real coordinate, which spline tangents can be fit through.
+pub trait Scalar: Interpolatable + Clone {
+    fn to_f64(&self) -> f64;
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Scalar for f64 {
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+impl Scalar for f32 {
+    fn to_f64(&self) -> f64 {
+        f64::from(*self)
+    }
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl<V: Scalar, U: Clone> Scalar for SI<V, U> {
+    fn to_f64(&self) -> f64 {
+        self.value_unsafe.to_f64()
+    }
+    fn from_f64(value: f64) -> Self {
+        SI::new(V::from_f64(value))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Spline {
+    /// Tangents average the slopes on either side. Smooth, but can overshoot the data.
+    Cubic,
+    /// Tangents are limited so the curve never overshoots: monotone data gives a monotone curve.
+    MonotoneCubic,
+}
+
+/// Hermite basis evaluation on [0, 1]
+fn hermite(y0: f64, y1: f64, m0: f64, m1: f64, t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+        + (t3 - 2.0 * t2 + t) * m0
+        + (-2.0 * t3 + 3.0 * t2) * y1
+        + (t3 - t2) * m1
+}
+
+/// Tangent at a point from the slopes of the intervals before and after it, of widths `h0`, `h1`.
+fn tangent(spline: Spline, before: Option<(f64, f64)>, after: Option<(f64, f64)>) -> f64 {
+    match (before, after) {
+        (None, None) => 0.0,
+        (Some((_, d)), None) | (None, Some((_, d))) => d,
+        (Some((h0, d0)), Some((h1, d1))) => match spline {
+            Spline::Cubic => (h1 * d0 + h0 * d1) / (h0 + h1),
+            Spline::MonotoneCubic => {
+                if d0 * d1 <= 0.0 {
+                    // a local extremum, which has to be flat to not overshoot
+                    0.0
+                } else {
+                    // Fritsch-Butland weighted harmonic mean, as in PCHIP
+                    3.0 * (h0 + h1) / ((2.0 * h1 + h0) / d0 + (h1 + 2.0 * h0) / d1)
+                }
+            }
+        },
+    }
+}
+
+/// Interpolates at `key`, which must lie strictly between the keys `low` and `high` adjacent in
+/// `map`.
+pub(crate) fn interp<K, V>(
+    spline: Spline,
+    map: &BTreeMap<K, V>,
+    low: (&K, &V),
+    high: (&K, &V),
+    key: &K,
+) -> V
+where
+    K: InverseInterpolatable + Ord + Clone,
+    V: Scalar,
+{
+    let (low_key, high_key) = (low.0.clone(), high.0.clone());
+    // positions in units of this interval, so low is at 0 and high at 1
+    let position = |k: &K| K::percent_to(k.clone(), low_key.clone(), high_key.clone());
+    let before = map.range((Unbounded, Excluded(low.0))).next_back();
+    let after = map.range((Excluded(high.0), Unbounded)).next();
+
+    let (y0, y1) = (low.1.to_f64(), high.1.to_f64());
+    let slope = y1 - y0;
+    let before = before.map(|(k, v)| {
+        let h = -position(k);
+        (h, (y0 - v.to_f64()) / h)
+    });
+    let after = after.map(|(k, v)| {
+        let h = position(k) - 1.0;
+        (h, (v.to_f64() - y1) / h)
+    });
+    let m0 = tangent(spline, before, Some((1.0, slope)));
+    let m1 = tangent(spline, Some((1.0, slope)), after);
+    V::from_f64(hermite(y0, y1, m0, m1, position(key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InterpolatingBTreeMap, OrdFloat, Spline};
+    use approx::assert_abs_diff_eq;
+
+    fn map_of(points: &[(f64, f64)], spline: Spline) -> InterpolatingBTreeMap<OrdFloat<f64>, f64> {
+        let mut map = InterpolatingBTreeMap::new(points.len()).with_spline(spline);
+        for &(x, y) in points {
+            map.insert(OrdFloat(x), y);
+        }
+        map
+    }
+
+    #[test]
+    fn cubic_reproduces_quadratics() {
+        let f = |x: f64| 0.5 * x * x - x + 2.0;
+        // uneven spacing on purpose
+        let xs = [0.0, 0.5, 2.0, 2.5, 4.0];
+        let points: Vec<_> = xs.iter().map(|&x| (x, f(x))).collect();
+        let map = map_of(&points, Spline::Cubic);
+        for &x in &[0.7, 1.3, 2.2, 2.4] {
+            assert_abs_diff_eq!(map.get(OrdFloat(x)).unwrap(), f(x), epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn monotone_doesnt_overshoot() {
+        let points = [(0.0, 0.0), (1.0, 0.0), (2.0, 1.0), (3.0, 1.0), (4.0, 1.0)];
+        let cubic = map_of(&points, Spline::Cubic);
+        let monotone = map_of(&points, Spline::MonotoneCubic);
+        // the plain cubic rings around the step
+        assert!(cubic.get(OrdFloat(2.5)).unwrap() > 1.0);
+
+        let mut last = 0.0;
+        for i in 0..=400 {
+            let y = monotone.get(OrdFloat(f64::from(i) * 0.01)).unwrap();
+            assert!(y >= last && y <= 1.0, "{} after {}", y, last);
+            last = y;
+        }
+    }
+
+    #[test]
+    fn splines_pass_through_points() {
+        let points = [(0.0, 3.0), (1.0, -1.0), (3.0, 2.0)];
+        for &spline in &[Spline::Cubic, Spline::MonotoneCubic] {
+            let map = map_of(&points, spline);
+            for &(x, y) in &points {
+                assert_abs_diff_eq!(map.get(OrdFloat(x)).unwrap(), y);
+                assert_abs_diff_eq!(map.get(OrdFloat(x + 1e-9)).unwrap(), y, epsilon = 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn unit_values() {
+        use dimensioned::si::{Meter, M};
+        let mut map = InterpolatingBTreeMap::new(4).with_spline(Spline::Cubic);
+        for &x in &[0.0, 1.0, 2.0, 3.0] {
+            map.insert(OrdFloat(x), x * x * M);
+        }
+        let length: Meter<f64> = map.get(OrdFloat(1.5)).unwrap();
+        assert_abs_diff_eq!(length.value_unsafe, 2.25, epsilon = 1e-9);
+    }
+}
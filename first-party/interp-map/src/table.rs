@@ -0,0 +1,208 @@
+//! Loading and saving maps as calibration tables, so they can be tuned without recompiling.
+//!
+//! CSV tables are a header row followed by `key,value` rows. TOML tables are a list of points:
+//!
+//! ```toml
+//! points = [
+//!     { key = 0.0, value = 1.5 },
+//!     { key = 2.0, value = 3.0 },
+//! ]
+//! ```
+//!
+//! Loaded maps hold every row, and can be configured further with the usual builder methods.
+use crate::{Interpolatable, InterpolatingBTreeMap, InverseInterpolatable};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum TableError {
+    Io(io::Error),
+    Csv(csv::Error),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    /// The table has no rows
+    Empty,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Io(e) => write!(f, "Could not access table: {}", e),
+            TableError::Csv(e) => write!(f, "Bad CSV table: {}", e),
+            TableError::TomlRead(e) => write!(f, "Bad TOML table: {}", e),
+            TableError::TomlWrite(e) => write!(f, "Could not write TOML table: {}", e),
+            TableError::Empty => write!(f, "Table has no rows"),
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+impl From<io::Error> for TableError {
+    fn from(e: io::Error) -> Self {
+        TableError::Io(e)
+    }
+}
+
+impl From<csv::Error> for TableError {
+    fn from(e: csv::Error) -> Self {
+        TableError::Csv(e)
+    }
+}
+
+impl From<toml::de::Error> for TableError {
+    fn from(e: toml::de::Error) -> Self {
+        TableError::TomlRead(e)
+    }
+}
+
+impl From<toml::ser::Error> for TableError {
+    fn from(e: toml::ser::Error) -> Self {
+        TableError::TomlWrite(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Point<K, V> {
+    key: K,
+    value: V,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TomlTable<K, V> {
+    points: Vec<Point<K, V>>,
+}
+
+impl<K, V> InterpolatingBTreeMap<K, V>
+where
+    K: InverseInterpolatable + Ord + Clone + DeserializeOwned + Serialize,
+    V: Interpolatable + Clone + DeserializeOwned + Serialize,
+{
+    fn from_points(points: Vec<(K, V)>) -> Result<Self, TableError> {
+        if points.is_empty() {
+            return Err(TableError::Empty);
+        }
+        let mut map = Self::new(points.len());
+        for (key, value) in points {
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+
+    pub fn from_csv(reader: impl Read) -> Result<Self, TableError> {
+        let points = csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<(K, V)>, _>>()?;
+        Self::from_points(points)
+    }
+
+    pub fn from_toml(mut reader: impl Read) -> Result<Self, TableError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let table: TomlTable<K, V> = toml::from_str(&text)?;
+        Self::from_points(
+            table
+                .points
+                .into_iter()
+                .map(|point| (point.key, point.value))
+                .collect(),
+        )
+    }
+
+    /// Reads a `.csv` or `.toml` table, by the file's extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TableError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        if path.extension().map_or(false, |ext| ext == "toml") {
+            Self::from_toml(file)
+        } else {
+            Self::from_csv(file)
+        }
+    }
+
+    /// Writes the stored entries, under a header naming the columns.
+    pub fn write_csv(
+        &self,
+        writer: impl Write,
+        key_name: &str,
+        value_name: &str,
+    ) -> Result<(), TableError> {
+        let mut csv = csv::Writer::from_writer(writer);
+        csv.write_record(&[key_name, value_name])?;
+        for entry in self.iter() {
+            csv.serialize(entry)?;
+        }
+        csv.flush()?;
+        Ok(())
+    }
+
+    pub fn write_toml(&self, mut writer: impl Write) -> Result<(), TableError> {
+        let table = TomlTable {
+            points: self
+                .iter()
+                .map(|(key, value)| Point {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        };
+        writer.write_all(toml::to_string(&table)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InterpolatingBTreeMap, OrdFloat};
+    use approx::assert_abs_diff_eq;
+
+    type Table = InterpolatingBTreeMap<OrdFloat<f64>, f64>;
+
+    #[test]
+    fn reads_csv() {
+        let csv = "distance,rpm\n1.0,2000\n3.0,3000\n2.0,2400\n";
+        let table = Table::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(table.len(), 3);
+        assert_abs_diff_eq!(table.get(OrdFloat(2.5)).unwrap(), 2700.0);
+    }
+
+    #[test]
+    fn reads_toml() {
+        let toml = "points = [\n{ key = 0.0, value = 1.5 },\n{ key = 2.0, value = 3.0 },\n]\n";
+        let table = Table::from_toml(toml.as_bytes()).unwrap();
+        assert_abs_diff_eq!(table.get(OrdFloat(1.0)).unwrap(), 2.25);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut table = Table::new(3);
+        table.insert(OrdFloat(0.0), 0.5);
+        table.insert(OrdFloat(1.0), -2.0);
+        table.insert(OrdFloat(2.5), 7.25);
+
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv, "height", "ticks").unwrap();
+        assert!(csv.starts_with(b"height,ticks\n"));
+        let from_csv = Table::from_csv(csv.as_slice()).unwrap();
+
+        let mut toml = Vec::new();
+        table.write_toml(&mut toml).unwrap();
+        let from_toml = Table::from_toml(toml.as_slice()).unwrap();
+
+        for loaded in &[from_csv, from_toml] {
+            assert!(loaded.iter().eq(table.iter()));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        assert!(Table::from_csv(&b"a,b\n"[..]).is_err());
+        assert!(Table::from_csv(&b"a,b\n1.0,x\n"[..]).is_err());
+        assert!(Table::from_toml(&b"points = 3"[..]).is_err());
+    }
+}
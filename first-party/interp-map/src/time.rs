@@ -0,0 +1,72 @@
+//! Time as a key, for buffers of timestamped state.
+use crate::{InverseInterpolatable, KeyDistance};
+use std::time::{Duration, Instant};
+
+/// `to - from` in seconds, which unlike `Duration` may be negative
+fn signed_secs(from: Instant, to: Instant) -> f64 {
+    if to >= from {
+        to.duration_since(from).as_secs_f64()
+    } else {
+        -from.duration_since(to).as_secs_f64()
+    }
+}
+
+impl InverseInterpolatable for Duration {
+    fn percent_to(value: Self, low: Self, high: Self) -> f64 {
+        (value.as_secs_f64() - low.as_secs_f64()) / (high.as_secs_f64() - low.as_secs_f64())
+    }
+}
+
+impl KeyDistance for Duration {
+    type Distance = Duration;
+    fn distance(from: &Self, to: &Self) -> Duration {
+        to.checked_sub(*from).unwrap_or_default()
+    }
+}
+
+impl InverseInterpolatable for Instant {
+    fn percent_to(value: Self, low: Self, high: Self) -> f64 {
+        signed_secs(low, value) / signed_secs(low, high)
+    }
+}
+
+impl KeyDistance for Instant {
+    type Distance = Duration;
+    fn distance(from: &Self, to: &Self) -> Duration {
+        if to > from {
+            to.duration_since(*from)
+        } else {
+            Duration::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::InterpolatingBTreeMap;
+    use approx::assert_abs_diff_eq;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn instant_keys() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let mut map = InterpolatingBTreeMap::with_max_age(100, Duration::from_millis(20));
+        map.insert(ms(0), 0.0);
+        map.insert(ms(10), 1.0);
+        map.insert(ms(20), 2.0);
+        assert_abs_diff_eq!(map.get(ms(15)).unwrap(), 1.5);
+        assert_abs_diff_eq!(map.get(ms(1)).unwrap(), 0.1, epsilon = 1e-9);
+
+        map.insert(ms(25), 2.5);
+        assert_eq!(map.earliest(), Some((&ms(10), &1.0)));
+    }
+
+    #[test]
+    fn duration_keys() {
+        let mut map = InterpolatingBTreeMap::new(10);
+        map.insert(Duration::from_secs(1), 10.0);
+        map.insert(Duration::from_secs(3), 30.0);
+        assert_abs_diff_eq!(map.get(Duration::from_millis(2500)).unwrap(), 25.0);
+    }
+}
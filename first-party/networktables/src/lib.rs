@@ -0,0 +1,274 @@
+//! A NetworkTables server, so dashboards like Shuffleboard and Glass can show the robot's state.
+//!
+//! It speaks protocol revision 3.0 over TCP, which every dashboard since 2017 understands and
+//! NT4 dashboards fall back to. Set values on a [`Table`] from anywhere, and a
+//! [`server::Server`] sends each client whatever changed since it last looked, at most once an
+//! update period, the way ntcore batches updates.
+//!
+//! Clients can change entries and create new ones, but the robot owns the table: deleting
+//! entries, clearing it, flags and RPCs from clients are ignored.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use wire::{Message, NEW_ENTRY_ID};
+
+pub mod server;
+pub mod wire;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Double(f64),
+    String(String),
+    Raw(Vec<u8>),
+    BooleanArray(Vec<bool>),
+    DoubleArray(Vec<f64>),
+    StringArray(Vec<String>),
+    /// An RPC definition
+    Rpc(Vec<u8>),
+}
+
+impl Value {
+    fn same_type(&self, other: &Value) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Double(x)
+    }
+}
+
+/// Counts go out as doubles, the only number NetworkTables has
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Double(n as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(xs: Vec<f64>) -> Self {
+        Value::DoubleArray(xs)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    value: Value,
+    flags: u8,
+    /// Bumped with every change, and sent so clients can order updates
+    seq: u16,
+    /// The table's revision when this last changed
+    revision: u64,
+}
+
+/// Whether sequence number `a` comes after `b`, allowing for wrapping around.
+fn newer(a: u16, b: u16) -> bool {
+    (a < b && b - a > 1 << 15) || (a > b && a - b < 1 << 15)
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// By id, which is the order they were created in
+    entries: Vec<Entry>,
+    ids: HashMap<String, u16>,
+    /// Counts changes to any entry
+    revision: u64,
+}
+
+impl Entries {
+    fn insert(&mut self, name: &str, value: Value, flags: u8) -> bool {
+        // the last id means "new entry" on the wire
+        if self.entries.len() >= NEW_ENTRY_ID as usize {
+            return false;
+        }
+        self.revision += 1;
+        self.ids.insert(name.to_string(), self.entries.len() as u16);
+        self.entries.push(Entry {
+            name: name.to_string(),
+            value,
+            flags,
+            seq: 0,
+            revision: self.revision,
+        });
+        true
+    }
+
+    fn change(&mut self, id: u16, seq: u16, value: Value) {
+        self.revision += 1;
+        let entry = &mut self.entries[id as usize];
+        entry.value = value;
+        entry.seq = seq;
+        entry.revision = self.revision;
+    }
+}
+
+/// How far a client has been told about the table.
+#[derive(Debug, Default)]
+pub(crate) struct Synced {
+    revision: u64,
+    /// Entries from this id on haven't been assigned to the client yet
+    assigned: usize,
+}
+
+/// The entries served to every client. Clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct Table(Arc<Mutex<Entries>>);
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.0.lock().expect("Table poisoned")
+    }
+
+    /// Sets an entry, creating it if needed. Like ntcore, an entry keeps the type it was created
+    /// with, and setting a value of another type is refused, returning false.
+    pub fn set(&self, name: &str, value: impl Into<Value>) -> bool {
+        let value = value.into();
+        let mut entries = self.entries();
+        let id = match entries.ids.get(name) {
+            Some(&id) => id,
+            None => return entries.insert(name, value, 0),
+        };
+        let entry = &entries.entries[id as usize];
+        if !entry.value.same_type(&value) {
+            return false;
+        }
+        if entry.value != value {
+            let seq = entry.seq.wrapping_add(1);
+            entries.change(id, seq, value);
+        }
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let entries = self.entries();
+        let &id = entries.ids.get(name)?;
+        Some(entries.entries[id as usize].value.clone())
+    }
+
+    /// What a client needs to hear to catch up, from where it was.
+    pub(crate) fn changes(&self, synced: &mut Synced) -> Vec<Message> {
+        let entries = self.entries();
+        let mut messages = Vec::new();
+        for (id, entry) in entries.entries.iter().enumerate() {
+            if id >= synced.assigned {
+                messages.push(Message::EntryAssignment {
+                    name: entry.name.clone(),
+                    id: id as u16,
+                    seq: entry.seq,
+                    flags: entry.flags,
+                    value: entry.value.clone(),
+                });
+            } else if entry.revision > synced.revision {
+                messages.push(Message::EntryUpdate {
+                    id: id as u16,
+                    seq: entry.seq,
+                    value: entry.value.clone(),
+                });
+            }
+        }
+        synced.revision = entries.revision;
+        synced.assigned = entries.entries.len();
+        messages
+    }
+
+    /// Applies what a client sent, ignoring anything clients may not do.
+    pub(crate) fn receive(&self, message: Message) {
+        let mut entries = self.entries();
+        match message {
+            // when two clients create the same entry, the first wins
+            Message::EntryAssignment {
+                ref name,
+                id: NEW_ENTRY_ID,
+                flags,
+                ref value,
+                ..
+            } if !entries.ids.contains_key(name) => {
+                entries.insert(name, value.clone(), flags);
+            }
+            Message::EntryUpdate { id, seq, value } => {
+                let accepted = match entries.entries.get(id as usize) {
+                    Some(entry) => entry.value.same_type(&value) && newer(seq, entry.seq),
+                    None => false,
+                };
+                if accepted {
+                    entries.change(id, seq, value);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_changes() {
+        let table = Table::new();
+        let mut synced = Synced::default();
+        assert!(table.set("a", 1.5));
+        assert!(table.set("b", true));
+        assert_eq!(table.changes(&mut synced).len(), 2);
+        assert_eq!(table.changes(&mut synced), Vec::new());
+
+        assert!(!table.set("a", "text"));
+        assert!(table.set("b", true));
+        assert_eq!(table.changes(&mut synced), Vec::new());
+        assert!(table.set("b", false));
+        assert_eq!(
+            table.changes(&mut synced),
+            vec![Message::EntryUpdate {
+                id: 1,
+                seq: 1,
+                value: Value::Boolean(false),
+            }]
+        );
+
+        // a stale update from a client loses to what the robot set since
+        table.receive(Message::EntryUpdate {
+            id: 1,
+            seq: 0,
+            value: Value::Boolean(true),
+        });
+        assert_eq!(table.get("b"), Some(Value::Boolean(false)));
+        table.receive(Message::EntryUpdate {
+            id: 1,
+            seq: 2,
+            value: Value::Boolean(true),
+        });
+        assert_eq!(table.get("b"), Some(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn orders_wrapped_sequence_numbers() {
+        assert!(newer(1, 0));
+        assert!(newer(0, 0xffff));
+        assert!(!newer(0xffff, 0));
+        assert!(!newer(5, 5));
+    }
+}
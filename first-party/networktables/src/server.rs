@@ -0,0 +1,240 @@
+//! Serving a table to dashboards over TCP.
+use crate::wire::{self, Message, REVISION};
+use crate::{Synced, Table};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The port NetworkTables 3 clients connect to
+pub const PORT: u16 = 1735;
+
+/// Longest a client goes without hearing from the server
+const KEEP_ALIVE_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    table: Table,
+    identity: String,
+    update_period: Duration,
+}
+
+impl Server {
+    pub fn new(table: Table, identity: impl Into<String>, update_period: Duration) -> Self {
+        Self {
+            table,
+            identity: identity.into(),
+            update_period,
+        }
+    }
+
+    /// Accepts clients on `listener` forever, each on its own threads. A client that misbehaves
+    /// or goes away only ends its own connection, and `dropped` is told why.
+    pub fn serve<F>(&self, listener: TcpListener, dropped: F) -> io::Result<()>
+    where
+        F: Fn(String) + Clone + Send + 'static,
+    {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            let dropped = dropped.clone();
+            thread::Builder::new()
+                .name("NT client".to_string())
+                .spawn(move || {
+                    let peer = stream.peer_addr();
+                    if let Err(e) = server.talk(stream) {
+                        dropped(format!("NetworkTables client {:?} dropped: {}", peer, e));
+                    }
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Greets a client, then keeps it up to date until it goes away.
+    fn talk(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        // later revisions frame messages differently, so only 3.0 is spoken
+        match wire::read(&mut reader)? {
+            Message::ClientHello { revision, .. } if revision == REVISION => (),
+            Message::ClientHello { .. } => {
+                let unsupported = Message::ProtocolUnsupported { revision: REVISION };
+                wire::write(&mut writer, &unsupported)?;
+                return writer.flush();
+            }
+            other => {
+                let e = format!("expected a client hello, got {:?}", other);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        }
+
+        let mut synced = Synced::default();
+        let hello = Message::ServerHello {
+            flags: 0,
+            identity: self.identity.clone(),
+        };
+        wire::write(&mut writer, &hello)?;
+        for message in self.table.changes(&mut synced) {
+            wire::write(&mut writer, &message)?;
+        }
+        wire::write(&mut writer, &Message::ServerHelloComplete)?;
+        writer.flush()?;
+
+        // the client's own entries, and everything it sends after, are applied as they come
+        let table = self.table.clone();
+        thread::Builder::new()
+            .name("NT client reader".to_string())
+            .spawn(move || loop {
+                match wire::read(&mut reader) {
+                    Ok(message) => table.receive(message),
+                    Err(_) => return,
+                }
+            })?;
+
+        let result = self.update(&mut writer, synced);
+        // unblocks the reader, if the client is still there
+        stream.shutdown(Shutdown::Both).ok();
+        result
+    }
+
+    fn update(&self, writer: &mut impl Write, mut synced: Synced) -> io::Result<()> {
+        let mut last_sent = Instant::now();
+        loop {
+            thread::sleep(self.update_period);
+            let changes = self.table.changes(&mut synced);
+            if changes.is_empty() && last_sent.elapsed() < KEEP_ALIVE_PERIOD {
+                continue;
+            }
+            if changes.is_empty() {
+                wire::write(writer, &Message::KeepAlive)?;
+            }
+            for message in changes {
+                wire::write(writer, &message)?;
+            }
+            writer.flush()?;
+            last_sent = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::NEW_ENTRY_ID;
+    use crate::Value;
+
+    /// The next message that isn't a keep alive.
+    fn next(reader: &mut impl io::Read) -> Message {
+        loop {
+            match wire::read(reader).unwrap() {
+                Message::KeepAlive => (),
+                message => return message,
+            }
+        }
+    }
+
+    fn wait_for(table: &Table, name: &str, value: Value) {
+        let start = Instant::now();
+        while table.get(name) != Some(value.clone()) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "{} never set",
+                name
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn start(table: &Table) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(table.clone(), "robot", Duration::from_millis(5));
+        thread::spawn(move || server.serve(listener, drop));
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    #[test]
+    fn serves_a_client() {
+        let table = Table::new();
+        table.set("/SmartDashboard/psi", 110.5);
+        let mut stream = start(&table);
+        let hello = Message::ClientHello {
+            revision: REVISION,
+            identity: "test".to_string(),
+        };
+        wire::write(&mut stream, &hello).unwrap();
+        match next(&mut stream) {
+            Message::ServerHello { identity, .. } => assert_eq!(identity, "robot"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            next(&mut stream),
+            Message::EntryAssignment {
+                name: "/SmartDashboard/psi".to_string(),
+                id: 0,
+                seq: 0,
+                flags: 0,
+                value: Value::Double(110.5),
+            }
+        );
+        assert_eq!(next(&mut stream), Message::ServerHelloComplete);
+        let created = Message::EntryAssignment {
+            name: "/client/mode".to_string(),
+            id: NEW_ENTRY_ID,
+            seq: 0,
+            flags: 0,
+            value: Value::String("auto".to_string()),
+        };
+        wire::write(&mut stream, &created).unwrap();
+        wire::write(&mut stream, &Message::ClientHelloComplete).unwrap();
+
+        // the server assigns the client's entry an id, and tells it
+        assert_eq!(
+            next(&mut stream),
+            Message::EntryAssignment {
+                name: "/client/mode".to_string(),
+                id: 1,
+                seq: 0,
+                flags: 0,
+                value: Value::String("auto".to_string()),
+            }
+        );
+        table.set("/SmartDashboard/psi", 90.5);
+        assert_eq!(
+            next(&mut stream),
+            Message::EntryUpdate {
+                id: 0,
+                seq: 1,
+                value: Value::Double(90.5),
+            }
+        );
+        let update = Message::EntryUpdate {
+            id: 1,
+            seq: 1,
+            value: Value::String("teleop".to_string()),
+        };
+        wire::write(&mut stream, &update).unwrap();
+        wait_for(&table, "/client/mode", Value::String("teleop".to_string()));
+    }
+
+    #[test]
+    fn refuses_other_revisions() {
+        for &revision in &[0x0200, 0x0400] {
+            let mut stream = start(&Table::new());
+            let hello = Message::ClientHello {
+                revision,
+                identity: String::new(),
+            };
+            wire::write(&mut stream, &hello).unwrap();
+            assert_eq!(
+                next(&mut stream),
+                Message::ProtocolUnsupported { revision: REVISION }
+            );
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! The messages of NetworkTables protocol revision 3.0, and their encoding.
+//!
+//! Integers are big endian, doubles are IEEE 754, and strings and raw bytes are prefixed with
+//! their length as an unsigned LEB128.
+use crate::Value;
+use std::io::{self, Read, Write};
+
+/// The protocol revision this speaks, 3.0
+pub const REVISION: u16 = 0x0300;
+
+/// The id a client gives an entry it's creating, for the server to assign a real one
+pub const NEW_ENTRY_ID: u16 = 0xffff;
+
+/// Guards clear all messages against corruption
+const CLEAR_ALL_MAGIC: u32 = 0xd06c_b27a;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    KeepAlive,
+    /// Revision 2.0 clients send no identity, so theirs is empty
+    ClientHello {
+        revision: u16,
+        identity: String,
+    },
+    ProtocolUnsupported {
+        revision: u16,
+    },
+    ServerHelloComplete,
+    ServerHello {
+        /// Bit 0 is set if the server has seen this client before
+        flags: u8,
+        identity: String,
+    },
+    ClientHelloComplete,
+    EntryAssignment {
+        name: String,
+        id: u16,
+        seq: u16,
+        /// Bit 0 marks the entry persistent
+        flags: u8,
+        value: Value,
+    },
+    EntryUpdate {
+        id: u16,
+        seq: u16,
+        value: Value,
+    },
+    EntryFlagsUpdate {
+        id: u16,
+        flags: u8,
+    },
+    EntryDelete {
+        id: u16,
+    },
+    ClearAll,
+    RpcExecute {
+        id: u16,
+        call: u16,
+        params: Vec<u8>,
+    },
+    RpcResponse {
+        id: u16,
+        call: u16,
+        result: Vec<u8>,
+    },
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn type_id(value: &Value) -> u8 {
+    match value {
+        Value::Boolean(_) => 0x00,
+        Value::Double(_) => 0x01,
+        Value::String(_) => 0x02,
+        Value::Raw(_) => 0x03,
+        Value::BooleanArray(_) => 0x10,
+        Value::DoubleArray(_) => 0x11,
+        Value::StringArray(_) => 0x12,
+        Value::Rpc(_) => 0x20,
+    }
+}
+
+/// Encodes messages into a buffer.
+#[derive(Debug, Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.0.extend_from_slice(&x.to_bits().to_be_bytes());
+    }
+
+    fn leb128(&mut self, mut n: usize) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.u8(byte);
+                return;
+            }
+            self.u8(byte | 0x80);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.leb128(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    /// Arrays are capped at 255 elements, and longer ones are cut short.
+    fn array<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        let items = &items[..items.len().min(255)];
+        self.u8(items.len() as u8);
+        for x in items {
+            item(self, x);
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Boolean(b) => self.u8(*b as u8),
+            Value::Double(x) => self.f64(*x),
+            Value::String(s) => self.string(s),
+            Value::Raw(bytes) | Value::Rpc(bytes) => self.bytes(bytes),
+            Value::BooleanArray(bs) => self.array(bs, |e, b| e.u8(*b as u8)),
+            Value::DoubleArray(xs) => self.array(xs, |e, x| e.f64(*x)),
+            Value::StringArray(ss) => self.array(ss, |e, s| e.string(s)),
+        }
+    }
+
+    fn message(&mut self, message: &Message) {
+        match message {
+            Message::KeepAlive => self.u8(0x00),
+            Message::ClientHello { revision, identity } => {
+                self.u8(0x01);
+                self.u16(*revision);
+                if *revision >= REVISION {
+                    self.string(identity);
+                }
+            }
+            Message::ProtocolUnsupported { revision } => {
+                self.u8(0x02);
+                self.u16(*revision);
+            }
+            Message::ServerHelloComplete => self.u8(0x03),
+            Message::ServerHello { flags, identity } => {
+                self.u8(0x04);
+                self.u8(*flags);
+                self.string(identity);
+            }
+            Message::ClientHelloComplete => self.u8(0x05),
+            Message::EntryAssignment {
+                name,
+                id,
+                seq,
+                flags,
+                value,
+            } => {
+                self.u8(0x10);
+                self.string(name);
+                self.u8(type_id(value));
+                self.u16(*id);
+                self.u16(*seq);
+                self.u8(*flags);
+                self.value(value);
+            }
+            Message::EntryUpdate { id, seq, value } => {
+                self.u8(0x11);
+                self.u16(*id);
+                self.u16(*seq);
+                self.u8(type_id(value));
+                self.value(value);
+            }
+            Message::EntryFlagsUpdate { id, flags } => {
+                self.u8(0x12);
+                self.u16(*id);
+                self.u8(*flags);
+            }
+            Message::EntryDelete { id } => {
+                self.u8(0x13);
+                self.u16(*id);
+            }
+            Message::ClearAll => {
+                self.u8(0x14);
+                self.u32(CLEAR_ALL_MAGIC);
+            }
+            Message::RpcExecute { id, call, params } => {
+                self.u8(0x20);
+                self.u16(*id);
+                self.u16(*call);
+                self.bytes(params);
+            }
+            Message::RpcResponse { id, call, result } => {
+                self.u8(0x21);
+                self.u16(*id);
+                self.u16(*call);
+                self.bytes(result);
+            }
+        }
+    }
+}
+
+/// Decodes messages from a stream.
+struct Decoder<'a, R>(&'a mut R);
+
+impl<'a, R: Read> Decoder<'a, R> {
+    fn array<A: Default + AsMut<[u8]>>(&mut self) -> io::Result<A> {
+        let mut buf = A::default();
+        self.0.read_exact(buf.as_mut())?;
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.array::<[u8; 1]>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(u64::from_be_bytes(self.array()?)))
+    }
+
+    fn leb128(&mut self) -> io::Result<usize> {
+        let mut n = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = self.u8()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("length too long".to_string()))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.leb128()?;
+        // grown as the bytes arrive, rather than trusting the length up front
+        let mut bytes = Vec::new();
+        self.0.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn items<T>(&mut self, mut item: impl FnMut(&mut Self) -> io::Result<T>) -> io::Result<Vec<T>> {
+        let len = self.u8()?;
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    fn value(&mut self, type_id: u8) -> io::Result<Value> {
+        Ok(match type_id {
+            0x00 => Value::Boolean(self.u8()? != 0),
+            0x01 => Value::Double(self.f64()?),
+            0x02 => Value::String(self.string()?),
+            0x03 => Value::Raw(self.bytes()?),
+            0x10 => Value::BooleanArray(self.items(|d| Ok(d.u8()? != 0))?),
+            0x11 => Value::DoubleArray(self.items(Self::f64)?),
+            0x12 => Value::StringArray(self.items(Self::string)?),
+            0x20 => Value::Rpc(self.bytes()?),
+            other => return Err(invalid(format!("unknown value type {:#x}", other))),
+        })
+    }
+
+    fn message(&mut self) -> io::Result<Message> {
+        Ok(match self.u8()? {
+            0x00 => Message::KeepAlive,
+            0x01 => {
+                let revision = self.u16()?;
+                let identity = if revision >= REVISION {
+                    self.string()?
+                } else {
+                    String::new()
+                };
+                Message::ClientHello { revision, identity }
+            }
+            0x02 => Message::ProtocolUnsupported {
+                revision: self.u16()?,
+            },
+            0x03 => Message::ServerHelloComplete,
+            0x04 => Message::ServerHello {
+                flags: self.u8()?,
+                identity: self.string()?,
+            },
+            0x05 => Message::ClientHelloComplete,
+            0x10 => {
+                let name = self.string()?;
+                let type_id = self.u8()?;
+                Message::EntryAssignment {
+                    name,
+                    id: self.u16()?,
+                    seq: self.u16()?,
+                    flags: self.u8()?,
+                    value: self.value(type_id)?,
+                }
+            }
+            0x11 => {
+                let id = self.u16()?;
+                let seq = self.u16()?;
+                let type_id = self.u8()?;
+                Message::EntryUpdate {
+                    id,
+                    seq,
+                    value: self.value(type_id)?,
+                }
+            }
+            0x12 => Message::EntryFlagsUpdate {
+                id: self.u16()?,
+                flags: self.u8()?,
+            },
+            0x13 => Message::EntryDelete { id: self.u16()? },
+            0x14 => {
+                if self.u32()? == CLEAR_ALL_MAGIC {
+                    Message::ClearAll
+                } else {
+                    // the spec says to ignore it
+                    Message::KeepAlive
+                }
+            }
+            0x20 => Message::RpcExecute {
+                id: self.u16()?,
+                call: self.u16()?,
+                params: self.bytes()?,
+            },
+            0x21 => Message::RpcResponse {
+                id: self.u16()?,
+                call: self.u16()?,
+                result: self.bytes()?,
+            },
+            other => return Err(invalid(format!("unknown message type {:#x}", other))),
+        })
+    }
+}
+
+/// Encodes a message, to write in one go.
+pub fn encode(message: &Message) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.message(message);
+    encoder.0
+}
+
+pub fn write(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    writer.write_all(&encode(message))
+}
+
+/// Reads the next message, blocking until it's all there.
+pub fn read(reader: &mut impl Read) -> io::Result<Message> {
+    Decoder(reader).message()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let messages = vec![
+            Message::KeepAlive,
+            Message::ClientHello {
+                revision: REVISION,
+                identity: "Shuffleboard".to_string(),
+            },
+            Message::ClientHello {
+                revision: 0x0200,
+                identity: String::new(),
+            },
+            Message::ProtocolUnsupported { revision: REVISION },
+            Message::ServerHelloComplete,
+            Message::ServerHello {
+                flags: 1,
+                identity: "robot".to_string(),
+            },
+            Message::ClientHelloComplete,
+            Message::EntryAssignment {
+                name: "/SmartDashboard/x".to_string(),
+                id: 3,
+                seq: 9,
+                flags: 0,
+                value: Value::Double(1.5),
+            },
+            Message::EntryUpdate {
+                id: 3,
+                seq: 10,
+                value: Value::StringArray(vec!["a".to_string(), "".to_string()]),
+            },
+            Message::EntryUpdate {
+                id: 4,
+                seq: 0,
+                value: Value::BooleanArray(vec![true, false]),
+            },
+            Message::EntryUpdate {
+                id: 5,
+                seq: 1,
+                value: Value::DoubleArray(vec![0.25, -2.0]),
+            },
+            Message::EntryUpdate {
+                id: 6,
+                seq: 2,
+                value: Value::Raw(vec![0; 300]),
+            },
+            Message::EntryUpdate {
+                id: 7,
+                seq: 3,
+                value: Value::Boolean(true),
+            },
+            Message::EntryFlagsUpdate { id: 3, flags: 1 },
+            Message::EntryDelete { id: 3 },
+            Message::ClearAll,
+            Message::RpcExecute {
+                id: 8,
+                call: 1,
+                params: vec![1, 2],
+            },
+            Message::RpcResponse {
+                id: 8,
+                call: 1,
+                result: vec![],
+            },
+        ];
+        let mut bytes = Vec::new();
+        for message in &messages {
+            write(&mut bytes, message).unwrap();
+        }
+        let mut reader = &bytes[..];
+        for message in &messages {
+            assert_eq!(&read(&mut reader).unwrap(), message);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn matches_the_spec() {
+        let update = Message::EntryUpdate {
+            id: 0x0102,
+            seq: 0x0304,
+            value: Value::Double(1.0),
+        };
+        assert_eq!(
+            encode(&update),
+            [0x11, 1, 2, 3, 4, 0x01, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0]
+        );
+        let mut encoder = Encoder::default();
+        encoder.leb128(624_485);
+        assert_eq!(encoder.0, [0xe5, 0x8e, 0x26]);
+        assert_eq!(
+            read(&mut &[0x14, 0, 0, 0, 0][..]).unwrap(),
+            Message::KeepAlive
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Reads and sets a robot's tunable parameters from a laptop.
+//!
+//! ```sh
+//! tune 10.1.14.2:5805 list
+//! tune 10.1.14.2:5805 set elevator/stage_one/kp 0.2
+//! tune 10.1.14.2:5805 save
+//! ```
+use copcomp::Connection;
+use std::env;
+use std::net::UdpSocket;
+use std::process;
+use std::time::Duration;
+use tunables::protocol::{Request, Response};
+
+const USAGE: &str = "usage: tune <robot address:port> (list | set <name> <value> | save)";
+
+fn parse_request(args: &[String]) -> Option<Request> {
+    match args {
+        [command] if command == "list" => Some(Request::List),
+        [command] if command == "save" => Some(Request::Save),
+        [command, name, value] if command == "set" => {
+            value.parse().ok().map(|value| Request::Set {
+                name: name.clone(),
+                value,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let request = match args.split_first() {
+        Some((_, rest)) => parse_request(rest),
+        None => None,
+    };
+    let request = request.unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(2)
+    });
+
+    let socket = UdpSocket::bind("0.0.0.0:0").expect("Could not bind a socket");
+    socket.connect(&args[0]).expect("Bad robot address");
+    let timeout = Some(Duration::from_secs(1));
+    let mut connection = Connection::from_udp(socket, timeout, timeout).unwrap();
+    connection.write_item(&request).expect("Could not send");
+    match connection.read_item::<Response>() {
+        Ok(Response::Values(values)) => {
+            for (name, value) in values {
+                println!("{} = {}", name, value);
+            }
+        }
+        Ok(Response::Set { name, value }) => println!("{} = {}", name, value),
+        Ok(Response::Saved { path }) => println!("Saved to {}", path),
+        Ok(Response::Error(e)) => {
+            eprintln!("{}", e);
+            process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("No answer from the robot: {:?}", e);
+            process::exit(1)
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Parameters that can be changed while the robot runs.
+//!
+//! Subsystems register each gain with a [`Registry`] under a name like `"elevator/stage_one/kp"`,
+//! keep the returned [`Tunable`], and read it every loop. Reads are a single atomic load, so they
+//! can sit in the tightest loop. A [`server::Server`] exposes the registry over the network, and
+//! the `tune` binary talks to it from a laptop.
+//!
+//! Tuned values are saved as a flat TOML table of names to values, and loaded before or after the
+//! parameters are registered.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub mod protocol;
+pub mod server;
+
+#[derive(Debug)]
+pub enum TuneError {
+    Unknown(String),
+    NotFinite(String, f64),
+    Io(io::Error),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+}
+
+impl fmt::Display for TuneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TuneError::Unknown(name) => write!(f, "No parameter named {:?}", name),
+            TuneError::NotFinite(name, value) => write!(f, "Can't set {} to {}", name, value),
+            TuneError::Io(e) => write!(f, "Could not access tuned values: {}", e),
+            TuneError::TomlRead(e) => write!(f, "Bad tuned values: {}", e),
+            TuneError::TomlWrite(e) => write!(f, "Could not write tuned values: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TuneError {}
+
+impl From<io::Error> for TuneError {
+    fn from(e: io::Error) -> Self {
+        TuneError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for TuneError {
+    fn from(e: toml::de::Error) -> Self {
+        TuneError::TomlRead(e)
+    }
+}
+
+impl From<toml::ser::Error> for TuneError {
+    fn from(e: toml::ser::Error) -> Self {
+        TuneError::TomlWrite(e)
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    bits: AtomicU64,
+    /// Bumped on every set, so readers can tell when to reconfigure hardware
+    version: AtomicUsize,
+}
+
+impl Slot {
+    fn new(value: f64) -> Self {
+        Self {
+            bits: AtomicU64::new(value.to_bits()),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f64) {
+        self.bits.store(value.to_bits(), Ordering::Relaxed);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// A parameter's current value, shared with the [`Registry`] it came from.
+#[derive(Clone)]
+pub struct Tunable {
+    slot: Arc<Slot>,
+    seen: usize,
+}
+
+impl Tunable {
+    /// A parameter that isn't registered anywhere, for tests and defaults.
+    pub fn fixed(value: f64) -> Self {
+        Self {
+            slot: Arc::new(Slot::new(value)),
+            seen: std::usize::MAX,
+        }
+    }
+
+    pub fn get(&self) -> f64 {
+        self.slot.get()
+    }
+
+    /// Hands the value to `apply` if it was set since this handle last applied it. It only counts
+    /// as applied once `apply` succeeds, so a failure is tried again next call. Always applies the
+    /// first time, so hardware configured from it starts out with the tuned value.
+    pub fn apply<E>(&mut self, apply: impl FnOnce(f64) -> Result<(), E>) -> Result<(), E> {
+        let version = self.slot.version.load(Ordering::Acquire);
+        if version != self.seen {
+            apply(self.get())?;
+            self.seen = version;
+        }
+        Ok(())
+    }
+
+    /// Counts the current value as applied without applying it, for a handle taking over hardware
+    /// that already has it.
+    pub fn assume_applied(&mut self) {
+        self.seen = self.slot.version.load(Ordering::Acquire);
+    }
+}
+
+impl fmt::Debug for Tunable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tunable({})", self.get())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Parameters {
+    registered: BTreeMap<String, Arc<Slot>>,
+    /// Loaded values for parameters that haven't been registered yet
+    pending: BTreeMap<String, f64>,
+}
+
+/// Every tunable parameter on the robot, by name. Clones share the same parameters.
+#[derive(Debug, Clone, Default)]
+pub struct Registry(Arc<Mutex<Parameters>>);
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<Parameters> {
+        // nothing panics while holding the lock
+        self.0.lock().expect("Registry poisoned")
+    }
+
+    /// Adds a parameter, starting at `default` unless a value was loaded for it. Registering a
+    /// name again shares the existing parameter.
+    pub fn register(&self, name: &str, default: f64) -> Tunable {
+        let mut parameters = self.lock();
+        let value = parameters.pending.remove(name).unwrap_or(default);
+        let slot = parameters
+            .registered
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Slot::new(value)));
+        Tunable {
+            slot: Arc::clone(slot),
+            seen: std::usize::MAX,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.lock().registered.get(name).map(|slot| slot.get())
+    }
+
+    pub fn set(&self, name: &str, value: f64) -> Result<(), TuneError> {
+        if !value.is_finite() {
+            return Err(TuneError::NotFinite(name.to_string(), value));
+        }
+        self.lock()
+            .registered
+            .get(name)
+            .ok_or_else(|| TuneError::Unknown(name.to_string()))?
+            .set(value);
+        Ok(())
+    }
+
+    /// Every registered parameter's current value.
+    pub fn values(&self) -> BTreeMap<String, f64> {
+        self.lock()
+            .registered
+            .iter()
+            .map(|(name, slot)| (name.clone(), slot.get()))
+            .collect()
+    }
+
+    /// Writes the current values, including loaded values that were never registered so they
+    /// aren't lost by a build that doesn't use them.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TuneError> {
+        let mut values = self.lock().pending.clone();
+        values.extend(self.values());
+        fs::write(path, toml::to_string(&values)?)?;
+        Ok(())
+    }
+
+    /// Applies values saved by [`Registry::save`]. A missing file isn't an error, since nothing
+    /// has been tuned yet.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<(), TuneError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let values: BTreeMap<String, f64> = toml::from_str(&text)?;
+        if let Some((name, &value)) = values.iter().find(|(_, value)| !value.is_finite()) {
+            return Err(TuneError::NotFinite(name.clone(), value));
+        }
+        let mut parameters = self.lock();
+        for (name, value) in values {
+            match parameters.registered.get(&name) {
+                Some(slot) => slot.set(value),
+                None => {
+                    parameters.pending.insert(name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_share_values() {
+        let registry = Registry::new();
+        let a = registry.register("drive/kp", 1.0);
+        let b = registry.register("drive/kp", 2.0);
+        assert_eq!(a.get().to_bits(), 1.0f64.to_bits());
+        registry.set("drive/kp", 3.0).unwrap();
+        assert_eq!(b.get().to_bits(), 3.0f64.to_bits());
+        assert!(registry.set("drive/kq", 3.0).is_err());
+        assert!(registry.set("drive/kp", std::f64::NAN).is_err());
+    }
+
+    /// What `apply` was handed, applying it.
+    fn applied(tunable: &mut Tunable) -> Option<f64> {
+        let mut applied = None;
+        tunable
+            .apply(|value| -> Result<(), ()> {
+                applied = Some(value);
+                Ok(())
+            })
+            .unwrap();
+        applied
+    }
+
+    #[test]
+    fn applies_changes() {
+        let registry = Registry::new();
+        let mut kp = registry.register("kp", 1.0);
+        assert_eq!(applied(&mut kp), Some(1.0));
+        assert_eq!(applied(&mut kp), None);
+        registry.set("kp", 2.0).unwrap();
+        assert_eq!(applied(&mut kp), Some(2.0));
+        assert_eq!(applied(&mut kp), None);
+        assert_eq!(applied(&mut Tunable::fixed(4.0)), Some(4.0));
+
+        // a value that didn't make it is tried again
+        registry.set("kp", 3.0).unwrap();
+        assert_eq!(kp.apply(|_| Err("CAN timeout")), Err("CAN timeout"));
+        assert_eq!(applied(&mut kp), Some(3.0));
+
+        // unless it's already there
+        let mut fixed = Tunable::fixed(5.0);
+        fixed.assume_applied();
+        assert_eq!(applied(&mut fixed), None);
+    }
+
+    #[test]
+    fn persists_values() {
+        let path = std::env::temp_dir().join(format!("tunables-{}.toml", std::process::id()));
+        let registry = Registry::new();
+        registry.register("elevator/kp", 1.0);
+        registry.register("channel/intake", 0.8);
+        registry.set("elevator/kp", 1.5).unwrap();
+        registry.save(&path).unwrap();
+
+        // loading before registering
+        let early = Registry::new();
+        early.load(&path).unwrap();
+        assert_eq!(
+            early.register("elevator/kp", 1.0).get().to_bits(),
+            1.5f64.to_bits()
+        );
+        // and after
+        let late = Registry::new();
+        let mut kp = late.register("elevator/kp", 1.0);
+        applied(&mut kp);
+        late.load(&path).unwrap();
+        assert_eq!(applied(&mut kp), Some(1.5));
+        // values nobody registered survive another save
+        late.save(&path).unwrap();
+        let again = Registry::new();
+        again.load(&path).unwrap();
+        assert_eq!(
+            again.register("channel/intake", 0.0).get().to_bits(),
+            0.8f64.to_bits()
+        );
+
+        // a bad value doesn't load, nor anything alongside it
+        fs::write(&path, "\"elevator/kp\" = 2.0\n\"elevator/kd\" = nan\n").unwrap();
+        match late.load(&path) {
+            Err(TuneError::NotFinite(name, _)) => assert_eq!(name, "elevator/kd"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(kp.get().to_bits(), 1.5f64.to_bits());
+
+        fs::remove_file(&path).unwrap();
+        assert!(Registry::new().load(&path).is_ok());
+    }
+}
@@ -0,0 +1,25 @@
+//! Messages between the robot and a tuning tool, one CBOR item per UDP packet (see `copcomp`).
+//!
+//! The tool sends a [`Request`] and the robot answers every one with a [`Response`].
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// Every parameter and its value
+    List,
+    Set {
+        name: String,
+        value: f64,
+    },
+    /// Write the current values to the robot's save file
+    Save,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Values(BTreeMap<String, f64>),
+    Set { name: String, value: f64 },
+    Saved { path: String },
+    Error(String),
+}
@@ -0,0 +1,107 @@
+//! Answering tuning tools on the robot.
+use crate::protocol::{Request, Response};
+use crate::Registry;
+use copcomp::Connection;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct Server {
+    registry: Registry,
+    save_path: PathBuf,
+}
+
+impl Server {
+    pub fn new(registry: Registry, save_path: impl Into<PathBuf>) -> Self {
+        Self {
+            registry,
+            save_path: save_path.into(),
+        }
+    }
+
+    pub fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::List => Ok(Response::Values(self.registry.values())),
+            Request::Set { name, value } => self
+                .registry
+                .set(&name, value)
+                .map(|()| Response::Set { name, value }),
+            Request::Save => self
+                .registry
+                .save(&self.save_path)
+                .map(|()| Response::Saved {
+                    path: self.save_path.display().to_string(),
+                }),
+        };
+        result.unwrap_or_else(|e| Response::Error(e.to_string()))
+    }
+
+    /// Answers requests on `socket` forever. Bad packets are answered with an error if they can
+    /// be, and otherwise dropped, telling `skipped` why.
+    pub fn serve(&self, socket: UdpSocket, mut skipped: impl FnMut(String)) -> copcomp::Result<()> {
+        let mut connection = Connection::from_udp(socket, None, None)?;
+        loop {
+            match connection.read_item_from::<Request>() {
+                Ok((request, addr)) => {
+                    let response = self.handle(request);
+                    if let Err(e) = connection.write_item_to(&response, addr) {
+                        skipped(format!("Could not answer tuning request: {:?}", e));
+                    }
+                }
+                Err(copcomp::Error::Io(e)) => return Err(copcomp::Error::Io(e)),
+                Err(e) => skipped(format!("Bad tuning request: {:?}", e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn answers_over_udp() {
+        let registry = Registry::new();
+        let kp = registry.register("drive/kp", 1.0);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = Server::new(registry, std::env::temp_dir().join("unused.toml"));
+        thread::spawn(move || server.serve(socket, drop));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(addr).unwrap();
+        let timeout = Some(Duration::from_secs(5));
+        let mut client = Connection::from_udp(client, timeout, timeout).unwrap();
+        let mut ask = |request| {
+            client.write_item(&request).unwrap();
+            client.read_item::<Response>().unwrap()
+        };
+
+        let set = Request::Set {
+            name: "drive/kp".to_string(),
+            value: 2.5,
+        };
+        assert_eq!(
+            ask(set),
+            Response::Set {
+                name: "drive/kp".to_string(),
+                value: 2.5
+            }
+        );
+        assert_eq!(kp.get().to_bits(), 2.5f64.to_bits());
+        match ask(Request::List) {
+            Response::Values(values) => assert_eq!(values.len(), 1),
+            other => panic!("{:?}", other),
+        }
+        let unknown = Request::Set {
+            name: "nope".to_string(),
+            value: 0.0,
+        };
+        match ask(unknown) {
+            Response::Error(_) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+}
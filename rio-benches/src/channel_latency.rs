@@ -1,4 +1,5 @@
 use crossbeam_channel::*;
+use rio_benches::rt::{pin_thread_to_cpu, set_thread_priority};
 use std::convert::TryInto;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -9,7 +10,7 @@ fn main() {
     let iters: u32 = 100;
     let pin_prio = true;
     if pin_prio {
-        set_thread_priority(20);
+        set_thread_priority(20).unwrap();
         pin_thread_to_cpu(0).unwrap();
     }
     let mut v = Vec::with_capacity(iters.try_into().unwrap());
@@ -57,42 +58,12 @@ fn print_rusage() {
     }
 }
 
-fn set_thread_priority(prio: i32) {
-    unsafe {
-        let mut sched: libc::sched_param = std::mem::uninitialized();
-        sched.sched_priority = 30 + prio;
-        let ret = libc::sched_setscheduler(0, libc::SCHED_FIFO, &sched);
-        if ret != 0 {
-            panic!()
-        }
-    }
-}
-
-fn pin_thread_to_cpu(cpu: usize) -> Result<(), libc::c_int> {
-    let res;
-    unsafe {
-        let mut cpuset: libc::cpu_set_t = std::mem::uninitialized();
-        libc::CPU_ZERO(&mut cpuset);
-        libc::CPU_SET(cpu, &mut cpuset);
-        res = libc::pthread_setaffinity_np(
-            libc::pthread_self(),
-            std::mem::size_of::<libc::cpu_set_t>(),
-            &cpuset,
-        );
-    }
-    if res == 0 {
-        Ok(())
-    } else {
-        Err(res)
-    }
-}
-
 fn bench(iters: usize, pin_prio: bool) -> Duration {
     let (s, r) = unbounded();
     let mut v = Vec::with_capacity(iters);
     let handle = thread::spawn(move || {
         if pin_prio {
-            set_thread_priority(10);
+            set_thread_priority(10).unwrap();
             pin_thread_to_cpu(1).unwrap();
         }
         std::thread::sleep(Duration::from_millis(10));
@@ -2,6 +2,8 @@
 #![cfg_attr(feature = "nightly", feature(test))]
 use std::thread;
 
+pub mod rt;
+
 use crossbeam_channel::{bounded, Receiver, Sender};
 #[cfg(feature = "nightly")]
 extern crate test;
@@ -0,0 +1,36 @@
+//! Making a thread realtime: `SCHED_FIFO` priority and pinning to a core. Both usually need root,
+//! or `CAP_SYS_NICE`.
+use std::io;
+
+/// Schedules the calling thread `SCHED_FIFO` at `30 + prio`, above the RIO's kernel threads.
+pub fn set_thread_priority(prio: i32) -> io::Result<()> {
+    let ret = unsafe {
+        let mut sched: libc::sched_param = std::mem::zeroed();
+        sched.sched_priority = 30 + prio;
+        libc::sched_setscheduler(0, libc::SCHED_FIFO, &sched)
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Keeps the calling thread on one CPU.
+pub fn pin_thread_to_cpu(cpu: usize) -> io::Result<()> {
+    let res = unsafe {
+        let mut cpuset: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut cpuset);
+        libc::CPU_SET(cpu, &mut cpuset);
+        libc::pthread_setaffinity_np(
+            libc::pthread_self(),
+            std::mem::size_of::<libc::cpu_set_t>(),
+            &cpuset,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(res))
+    }
+}